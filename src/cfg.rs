@@ -0,0 +1,619 @@
+//! Control-flow graphs over the code of a function.
+//!
+//! Instructions are grouped into basic blocks, straight-line runs entered
+//! only at their first instruction and left only after their last one. An
+//! `ExtraArg` always stays in the block of the `LoadKx` or `SetList` it
+//! belongs to. A `Tailcall` continues with the `Return` after it, which
+//! ends the function when the called function is not a Lua function. On
+//! top of the blocks the graph computes dominators and the natural loops
+//! formed by back edges, and it can be rendered as Graphviz DOT.
+
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter, Write};
+
+use crate::instruction::Instruction;
+use crate::opcode::{Op, Opcode};
+
+/// Code whose control flow does not stay within the function.
+#[derive(Debug, Eq, PartialEq)]
+pub struct CfgError {
+    pub pc: usize,
+    pub message: String,
+}
+
+impl Display for CfgError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pc {}: {}", self.pc, self.message)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct BasicBlock {
+    /// First instruction of the block.
+    pub start: usize,
+    /// One past the last instruction of the block.
+    pub end: usize,
+    /// Blocks control can continue to. For conditional blocks the one the
+    /// next instruction starts comes first.
+    pub successors: Vec<usize>,
+    pub predecessors: Vec<usize>,
+}
+
+/// A natural loop: the blocks that can reach a back edge to the header
+/// without passing through it.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Loop {
+    pub header: usize,
+    /// Blocks with a back edge to the header.
+    pub latches: Vec<usize>,
+    /// All blocks of the loop, the header and nested loops included, in
+    /// code order.
+    pub blocks: Vec<usize>,
+    /// Innermost loop this one is nested in.
+    pub parent: Option<usize>,
+    /// 1 for outermost loops.
+    pub depth: usize,
+}
+
+pub struct Cfg<'a> {
+    code: &'a [Instruction],
+    blocks: Vec<BasicBlock>,
+    /// Block each instruction belongs to.
+    block_of: Vec<usize>,
+    /// Reachable blocks in reverse postorder, starting with the entry.
+    order: Vec<usize>,
+    /// Immediate dominator of every reachable block, the entry dominating
+    /// itself.
+    idom: Vec<Option<usize>>,
+    /// Outer loops come before the loops nested in them.
+    loops: Vec<Loop>,
+    /// Innermost loop of every block.
+    loop_of: Vec<Option<usize>>,
+}
+
+impl<'a> Cfg<'a> {
+    pub fn build(code: &'a [Instruction]) -> Result<Cfg<'a>, CfgError> {
+        if code.is_empty() {
+            return Err(error(0, "function has no code"));
+        }
+        let mut leaders = BTreeSet::from([0]);
+        let mut flow = Vec::with_capacity(code.len());
+        let mut pc = 0;
+        while pc < code.len() {
+            let width = width(code, pc)?;
+            let targets = targets(code, pc)?;
+            match &targets {
+                Some(targets) => {
+                    for &target in targets {
+                        if target >= code.len() {
+                            return Err(error(pc, "jump out of the function"));
+                        }
+                        if code[target].get_op() == Op::ExtraArg {
+                            return Err(error(pc, "jump to an ExtraArg"));
+                        }
+                        leaders.insert(target);
+                    }
+                    leaders.insert(pc + width);
+                }
+                None if pc + width == code.len() => {
+                    return Err(error(pc, "control falls off the end of the function"));
+                }
+                None => (),
+            }
+            flow.push((pc, width, targets));
+            pc += width;
+        }
+        leaders.remove(&code.len());
+
+        let starts: Vec<_> = leaders.into_iter().collect();
+        let mut block_of = vec![0; code.len()];
+        let mut blocks: Vec<_> = starts
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| {
+                let end = starts.get(i + 1).copied().unwrap_or(code.len());
+                block_of[start..end].fill(i);
+                BasicBlock {
+                    start,
+                    end,
+                    successors: Vec::new(),
+                    predecessors: Vec::new(),
+                }
+            })
+            .collect();
+        for (pc, width, targets) in flow {
+            let block = block_of[pc];
+            let targets = match targets {
+                Some(targets) => targets,
+                None if pc + width == blocks[block].end => vec![pc + width],
+                None => continue,
+            };
+            for target in targets {
+                let target = block_of[target];
+                if !blocks[block].successors.contains(&target) {
+                    blocks[block].successors.push(target);
+                    blocks[target].predecessors.push(block);
+                }
+            }
+        }
+
+        let mut cfg = Cfg {
+            code,
+            blocks,
+            block_of,
+            order: Vec::new(),
+            idom: Vec::new(),
+            loops: Vec::new(),
+            loop_of: Vec::new(),
+        };
+        cfg.order = cfg.postorder();
+        cfg.order.reverse();
+        cfg.idom = cfg.dominators();
+        cfg.loops = cfg.natural_loops();
+        cfg.loop_of = (0..cfg.blocks.len())
+            .map(|block| {
+                (0..cfg.loops.len())
+                    .rev()
+                    .find(|&l| cfg.loops[l].blocks.binary_search(&block).is_ok())
+            })
+            .collect();
+        Ok(cfg)
+    }
+
+    pub fn code(&self) -> &'a [Instruction] {
+        self.code
+    }
+
+    /// The blocks in code order. The first one is the entry.
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    pub fn block_at(&self, pc: usize) -> Option<usize> {
+        self.block_of.get(pc).copied()
+    }
+
+    /// Reachable blocks in reverse postorder, so every block comes before
+    /// the blocks it dominates.
+    pub fn reverse_postorder(&self) -> &[usize] {
+        &self.order
+    }
+
+    pub fn is_reachable(&self, block: usize) -> bool {
+        self.idom[block].is_some()
+    }
+
+    /// The closest block other than itself that every path from the entry
+    /// to `block` goes through. None for the entry and unreachable blocks.
+    pub fn immediate_dominator(&self, block: usize) -> Option<usize> {
+        self.idom[block].filter(|&idom| idom != block)
+    }
+
+    /// Whether every path from the entry to `block` goes through
+    /// `dominator`. Blocks dominate themselves.
+    pub fn dominates(&self, dominator: usize, block: usize) -> bool {
+        if !self.is_reachable(block) {
+            return false;
+        }
+        let mut block = block;
+        loop {
+            if block == dominator {
+                return true;
+            }
+            match self.immediate_dominator(block) {
+                Some(idom) => block = idom,
+                None => return false,
+            }
+        }
+    }
+
+    /// Whether leaving `block` ends the function, through a `Return`.
+    pub fn is_exit(&self, block: usize) -> bool {
+        let last = self.code[self.blocks[block].end - 1];
+        last.get_op() == Op::Return
+    }
+
+    pub fn loops(&self) -> &[Loop] {
+        &self.loops
+    }
+
+    /// The innermost loop containing `block`.
+    pub fn innermost_loop(&self, block: usize) -> Option<usize> {
+        self.loop_of[block]
+    }
+
+    /// How many loops `block` is nested in.
+    pub fn loop_depth(&self, block: usize) -> usize {
+        self.loop_of[block].map_or(0, |l| self.loops[l].depth)
+    }
+
+    /// Renders the graph in Graphviz DOT, one box per block listing its
+    /// instructions. Back edges are dashed and exits drawn with a double
+    /// border.
+    pub fn to_dot(&self, name: &str) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "digraph \"{}\" {{", escape(name));
+        out.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        for (i, block) in self.blocks.iter().enumerate() {
+            let _ = write!(out, "    b{} [label=\"", i);
+            for pc in block.start..block.end {
+                let _ = write!(out, "{}: {}\\l", pc, self.code[pc]);
+            }
+            out.push('"');
+            if self.is_exit(i) {
+                out.push_str(", peripheries=2");
+            }
+            if !self.is_reachable(i) {
+                out.push_str(", style=dotted");
+            }
+            out.push_str("];\n");
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            for &succ in &block.successors {
+                let _ = write!(out, "    b{} -> b{}", i, succ);
+                if self.dominates(succ, i) {
+                    out.push_str(" [style=dashed]");
+                }
+                out.push_str(";\n");
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn postorder(&self) -> Vec<usize> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut postorder = Vec::with_capacity(self.blocks.len());
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((block, next)) = stack.pop() {
+            match self.blocks[block].successors.get(next) {
+                Some(&succ) => {
+                    stack.push((block, next + 1));
+                    if !visited[succ] {
+                        visited[succ] = true;
+                        stack.push((succ, 0));
+                    }
+                }
+                None => postorder.push(block),
+            }
+        }
+        postorder
+    }
+
+    /// Cooper, Harvey and Kennedy's iterative algorithm, intersecting the
+    /// dominators of the predecessors in reverse postorder until nothing
+    /// changes.
+    fn dominators(&self) -> Vec<Option<usize>> {
+        let mut rank = vec![usize::MAX; self.blocks.len()];
+        for (i, &block) in self.order.iter().enumerate() {
+            rank[block] = i;
+        }
+        let mut idom = vec![None; self.blocks.len()];
+        idom[0] = Some(0);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &self.order[1..] {
+                let mut preds = self.blocks[block]
+                    .predecessors
+                    .iter()
+                    .copied()
+                    .filter(|&pred| idom[pred].is_some());
+                let Some(first) = preds.next() else { continue };
+                let new = preds.fold(first, |mut a, mut b| {
+                    while a != b {
+                        while rank[a] > rank[b] {
+                            a = idom[a].unwrap();
+                        }
+                        while rank[b] > rank[a] {
+                            b = idom[b].unwrap();
+                        }
+                    }
+                    a
+                });
+                if idom[block] != Some(new) {
+                    idom[block] = Some(new);
+                    changed = true;
+                }
+            }
+        }
+        idom
+    }
+
+    /// Finds the back edges, edges to a block dominating their source, and
+    /// merges the loops sharing a header. Jumps into the middle of a loop
+    /// do not make back edges, so such irreducible cycles are not loops.
+    fn natural_loops(&self) -> Vec<Loop> {
+        let mut loops: Vec<Loop> = Vec::new();
+        for &header in &self.order {
+            let latches: Vec<_> = self.blocks[header]
+                .predecessors
+                .iter()
+                .copied()
+                .filter(|&pred| self.dominates(header, pred))
+                .collect();
+            if latches.is_empty() {
+                continue;
+            }
+            let mut blocks = BTreeSet::from([header]);
+            let mut work = latches.clone();
+            while let Some(block) = work.pop() {
+                if blocks.insert(block) {
+                    work.extend(
+                        self.blocks[block]
+                            .predecessors
+                            .iter()
+                            .filter(|&&pred| self.is_reachable(pred)),
+                    );
+                }
+            }
+            // headers come in reverse postorder, so the loops enclosing
+            // this one are already there
+            let parent = loops
+                .iter()
+                .rposition(|outer| outer.blocks.binary_search(&header).is_ok());
+            loops.push(Loop {
+                header,
+                latches,
+                blocks: blocks.into_iter().collect(),
+                parent,
+                depth: parent.map_or(1, |parent| loops[parent].depth + 1),
+            });
+        }
+        loops
+    }
+}
+
+/// Where control can go after the instruction at `pc`, or None if it simply
+/// continues with the next one.
+fn targets(code: &[Instruction], pc: usize) -> Result<Option<Vec<usize>>, CfgError> {
+    let instr = code[pc];
+    let jump = || {
+        let target = pc as i64 + 1 + instr.get_sbx() as i64;
+        usize::try_from(target).map_err(|_| error(pc, "jump out of the function"))
+    };
+    let targets = match instr.get_op() {
+        Op::Jmp | Op::ForPrep => vec![jump()?],
+        op if op.is_test() => vec![pc + 1, pc + 2],
+        Op::LoadBool if instr.get_c().raw() != 0 => vec![pc + 2],
+        Op::ForLoop | Op::TForLoop => vec![pc + 1, jump()?],
+        Op::TForCall if code.get(pc + 1).map(|next| next.get_op()) != Some(Op::TForLoop) => {
+            return Err(error(pc, "TForCall not followed by TForLoop"));
+        }
+        Op::Return => vec![],
+        Op::ExtraArg => return Err(error(pc, "ExtraArg without an instruction using it")),
+        _ => return Ok(None),
+    };
+    Ok(Some(targets))
+}
+
+/// How many instructions make up the one at `pc`, 2 for those taking their
+/// argument from an `ExtraArg`.
+fn width(code: &[Instruction], pc: usize) -> Result<usize, CfgError> {
+    let instr = code[pc];
+    let extra = match instr.get_op() {
+        Op::LoadKx => true,
+        Op::SetList => instr.get_c().raw() == 0,
+        _ => false,
+    };
+    if !extra {
+        return Ok(1);
+    }
+    match code.get(pc + 1) {
+        Some(next) if next.get_op() == Op::ExtraArg => Ok(2),
+        _ => Err(error(pc, "missing ExtraArg")),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn error(pc: usize, message: &str) -> CfgError {
+    CfgError {
+        pc,
+        message: message.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::file::chunk::Chunk;
+
+    fn chunk(source: &str) -> Chunk {
+        compile(source.as_bytes(), "=test").unwrap()
+    }
+
+    fn ranges(cfg: &Cfg) -> Vec<(usize, usize, Vec<usize>)> {
+        cfg.blocks()
+            .iter()
+            .map(|block| (block.start, block.end, block.successors.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_branches() {
+        let chunk = chunk("if a then b() else c() end");
+        let cfg = Cfg::build(&chunk.code).unwrap();
+        assert_eq!(
+            vec![
+                (0, 2, vec![1, 2]),
+                (2, 3, vec![3]),
+                (3, 6, vec![4]),
+                (6, 8, vec![4]),
+                (8, 9, vec![]),
+            ],
+            ranges(&cfg)
+        );
+        assert_eq!(vec![2, 3], cfg.blocks()[4].predecessors);
+        assert_eq!(None, cfg.immediate_dominator(0));
+        assert_eq!(Some(1), cfg.immediate_dominator(3));
+        assert_eq!(Some(0), cfg.immediate_dominator(4));
+        assert!(cfg.dominates(1, 3));
+        assert!(!cfg.dominates(2, 4));
+        assert!(cfg.is_exit(4));
+        assert!(cfg.loops().is_empty());
+    }
+
+    #[test]
+    fn test_load_bool_skip() {
+        let chunk = chunk("local x = a < b");
+        let cfg = Cfg::build(&chunk.code).unwrap();
+        assert_eq!(
+            vec![
+                (0, 3, vec![1, 2]),
+                (3, 4, vec![3]),
+                (4, 5, vec![4]),
+                (5, 6, vec![4]),
+                (6, 7, vec![]),
+            ],
+            ranges(&cfg)
+        );
+    }
+
+    #[test]
+    fn test_nested_loops() {
+        let chunk = chunk("for i = 1, 3 do while a do if b then break end end end");
+        let cfg = Cfg::build(&chunk.code).unwrap();
+        assert_eq!(
+            vec![
+                (0, 4, vec![6]),
+                (4, 6, vec![2, 3]),
+                (6, 7, vec![6]),
+                (7, 9, vec![4, 5]),
+                (9, 10, vec![6]),
+                (10, 11, vec![1]),
+                (11, 12, vec![7, 1]),
+                (12, 13, vec![]),
+            ],
+            ranges(&cfg)
+        );
+        assert_eq!(
+            vec![
+                Loop {
+                    header: 6,
+                    latches: vec![2, 4],
+                    blocks: vec![1, 2, 3, 4, 5, 6],
+                    parent: None,
+                    depth: 1,
+                },
+                Loop {
+                    header: 1,
+                    latches: vec![5],
+                    blocks: vec![1, 3, 5],
+                    parent: Some(0),
+                    depth: 2,
+                },
+            ],
+            cfg.loops()
+        );
+        assert_eq!(Some(1), cfg.innermost_loop(3));
+        assert_eq!(Some(0), cfg.innermost_loop(4));
+        assert_eq!(2, cfg.loop_depth(5));
+        assert_eq!(0, cfg.loop_depth(7));
+    }
+
+    #[test]
+    fn test_generic_for() {
+        let chunk = chunk("for k, v in pairs(t) do f(k) end");
+        let cfg = Cfg::build(&chunk.code).unwrap();
+        assert_eq!(
+            vec![
+                (0, 4, vec![2]),
+                (4, 7, vec![2]),
+                (7, 9, vec![3, 1]),
+                (9, 10, vec![]),
+            ],
+            ranges(&cfg)
+        );
+        assert_eq!(2, cfg.loops()[0].header);
+        assert_eq!(vec![1, 2], cfg.loops()[0].blocks);
+        assert_eq!(Some(2), cfg.immediate_dominator(1));
+    }
+
+    #[test]
+    fn test_tail_call_falls_through() {
+        // a tail call to a host function continues with the `Return`
+        let chunk = chunk("return f()");
+        let cfg = Cfg::build(&chunk.code).unwrap();
+        assert_eq!(vec![(0, 3, vec![]), (3, 4, vec![])], ranges(&cfg));
+        assert_eq!(Op::Tailcall, chunk.code[1].get_op());
+        assert!(cfg.is_exit(0));
+        assert!(!cfg.is_reachable(1));
+        assert_eq!(&[0], cfg.reverse_postorder());
+    }
+
+    #[test]
+    fn test_extra_arg() {
+        let code = [
+            Instruction::abx(Op::LoadKx, 0, 0),
+            Instruction::ax(Op::ExtraArg, 1),
+            Instruction::asbx(Op::Jmp, 0, -3),
+        ];
+        let cfg = Cfg::build(&code).unwrap();
+        assert_eq!(vec![(0, 3, vec![0])], ranges(&cfg));
+        assert_eq!(vec![0], cfg.loops()[0].latches);
+
+        let error = |code: &[Instruction]| Cfg::build(code).err().unwrap().to_string();
+        assert_eq!(
+            "pc 0: missing ExtraArg",
+            error(&[
+                Instruction::abc(Op::SetList, 0, 1, 0),
+                Instruction::abc(Op::Return, 0, 1, 0),
+            ])
+        );
+        assert_eq!(
+            "pc 2: jump to an ExtraArg",
+            error(&[
+                Instruction::abx(Op::LoadKx, 0, 0),
+                Instruction::ax(Op::ExtraArg, 1),
+                Instruction::asbx(Op::Jmp, 0, -2),
+            ])
+        );
+        assert_eq!(
+            "pc 0: ExtraArg without an instruction using it",
+            error(&[Instruction::ax(Op::ExtraArg, 1)])
+        );
+    }
+
+    #[test]
+    fn test_invalid_flow() {
+        let error = |code: &[Instruction]| Cfg::build(code).err().unwrap().to_string();
+        assert_eq!(
+            "pc 0: control falls off the end of the function",
+            error(&[Instruction::abc(Op::Move, 0, 1, 0)])
+        );
+        assert_eq!(
+            "pc 0: jump out of the function",
+            error(&[Instruction::asbx(Op::Jmp, 0, 1)])
+        );
+        assert_eq!(
+            "pc 0: jump out of the function",
+            error(&[Instruction::asbx(Op::Jmp, 0, -2)])
+        );
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let chunk = chunk("local i = 0 repeat i = i + 1 until i > 2");
+        let cfg = Cfg::build(&chunk.code).unwrap();
+        assert_eq!(
+            r#"digraph "test" {
+    node [shape=box, fontname="monospace"];
+    b0 [label="0: LoadK 0 0\l"];
+    b1 [label="1: Add 0 0 1\l2: Lt 0 2 0\l"];
+    b2 [label="3: Jmp 0 -3\l"];
+    b3 [label="4: Return 0 1 0\l", peripheries=2];
+    b0 -> b1;
+    b1 -> b2;
+    b1 -> b3;
+    b2 -> b1 [style=dashed];
+}
+"#,
+            cfg.to_dot("test")
+        );
+    }
+}
@@ -0,0 +1,274 @@
+/// A region of the source. `line` and `column` are where it starts,
+/// `end_line` and `end_column` where it ends, all 1-based.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: u64,
+    pub column: u64,
+    pub end_line: u64,
+    pub end_column: u64,
+}
+
+impl Span {
+    /// The span from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            end_line: other.end_line,
+            end_column: other.end_column,
+            ..self
+        }
+    }
+}
+
+/// A sequence of statements. Its span runs from the end of the token
+/// opening it to the start of the one closing it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub stats: Vec<Stat>,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Name {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stat {
+    pub kind: StatKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StatKind {
+    Local {
+        names: Vec<Name>,
+        exprs: Vec<Expr>,
+    },
+    Assign {
+        targets: Vec<Expr>,
+        exprs: Vec<Expr>,
+    },
+    Call(Expr),
+    Do(Block),
+    While {
+        cond: Expr,
+        body: Block,
+    },
+    Repeat {
+        body: Block,
+        cond: Expr,
+    },
+    If {
+        clauses: Vec<(Expr, Block)>,
+        else_block: Option<Block>,
+    },
+    NumericFor {
+        var: Name,
+        start: Box<Expr>,
+        limit: Box<Expr>,
+        step: Option<Box<Expr>>,
+        body: Block,
+    },
+    GenericFor {
+        names: Vec<Name>,
+        exprs: Vec<Expr>,
+        body: Block,
+    },
+    Function {
+        name: FunctionName,
+        body: FunctionBody,
+    },
+    LocalFunction {
+        name: Name,
+        body: FunctionBody,
+    },
+    Return(Vec<Expr>),
+    Break,
+    Goto(Name),
+    Label(Name),
+}
+
+/// `a.b.c:m` in `function a.b.c:m() end`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionName {
+    pub path: Vec<Name>,
+    pub method: Option<Name>,
+}
+
+/// Parameters and body of a function, spanning from `(` to `end`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionBody {
+    pub params: Vec<Name>,
+    pub is_vararg: bool,
+    pub body: Block,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExprKind {
+    Nil,
+    True,
+    False,
+    VarArg,
+    Integer(i64),
+    Float(f64),
+    String(Vec<u8>),
+    Function(Box<FunctionBody>),
+    Table(Vec<Field>),
+    Name(String),
+    /// `table.name`
+    Field {
+        table: Box<Expr>,
+        name: Name,
+    },
+    /// `table[key]`
+    Index {
+        table: Box<Expr>,
+        key: Box<Expr>,
+    },
+    Call {
+        func: Box<Expr>,
+        args: Vec<Expr>,
+        style: CallStyle,
+    },
+    /// `object:name(args)`
+    Method {
+        object: Box<Expr>,
+        name: Name,
+        args: Vec<Expr>,
+        style: CallStyle,
+    },
+    /// A parenthesized expression, which truncates multiple results.
+    Paren(Box<Expr>),
+    Binary {
+        op: BinOp,
+        op_line: u64,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    Unary {
+        op: UnOp,
+        op_line: u64,
+        operand: Box<Expr>,
+    },
+}
+
+/// How the arguments of a call were written: `f(a)`, `f{a}` or `f"a"`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CallStyle {
+    Parens,
+    Table,
+    String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Field {
+    /// `value`
+    Positional(Expr),
+    /// `name = value`
+    Named { name: Name, value: Expr },
+    /// `[key] = value`
+    Keyed { key: Expr, value: Expr },
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Mod,
+    Pow,
+    Div,
+    IDiv,
+    BAnd,
+    BOr,
+    BXor,
+    Shl,
+    Shr,
+    Concat,
+    Ne,
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinOp {
+    /// Left and right priorities, as in `lparser.c`.
+    pub fn priority(self) -> (u8, u8) {
+        match self {
+            BinOp::Add | BinOp::Sub => (10, 10),
+            BinOp::Mul | BinOp::Mod => (11, 11),
+            BinOp::Pow => (14, 13),
+            BinOp::Div | BinOp::IDiv => (11, 11),
+            BinOp::BAnd => (6, 6),
+            BinOp::BOr => (4, 4),
+            BinOp::BXor => (5, 5),
+            BinOp::Shl | BinOp::Shr => (7, 7),
+            BinOp::Concat => (9, 8),
+            BinOp::Ne | BinOp::Eq | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => (3, 3),
+            BinOp::And => (2, 2),
+            BinOp::Or => (1, 1),
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Mod => "%",
+            BinOp::Pow => "^",
+            BinOp::Div => "/",
+            BinOp::IDiv => "//",
+            BinOp::BAnd => "&",
+            BinOp::BOr => "|",
+            BinOp::BXor => "~",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+            BinOp::Concat => "..",
+            BinOp::Ne => "~=",
+            BinOp::Eq => "==",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::And => "and",
+            BinOp::Or => "or",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnOp {
+    Minus,
+    BNot,
+    Not,
+    Len,
+}
+
+impl UnOp {
+    pub const PRIORITY: u8 = 12;
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnOp::Minus => "-",
+            UnOp::BNot => "~",
+            UnOp::Not => "not",
+            UnOp::Len => "#",
+        }
+    }
+}
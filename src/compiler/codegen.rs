@@ -0,0 +1,2170 @@
+use std::collections::HashMap;
+
+use crate::compiler::ast::{
+    BinOp, Block, CallStyle, Expr, ExprKind, Field, FunctionBody, FunctionName, Name, Stat,
+    StatKind, UnOp,
+};
+use crate::compiler::CompileError;
+use crate::file::chunk::Chunk;
+use crate::file::{Constant, Local, Upvalue, VarArgInfo};
+use crate::instruction::{Instruction, BIT_RK, MAXARG_AX, MAXARG_BX, MAXARG_C, MAXARG_SBX};
+use crate::number::{ArithOp, Number};
+use crate::opcode::{Op, Opcode};
+
+const NO_JUMP: i32 = -1;
+/// `MAXARG_A`, used as "no register" in `TestSet`.
+const NO_REG: usize = 255;
+const MAX_REGS: usize = 255;
+const MAX_VARS: usize = 200;
+const MAX_UPVALUES: usize = 255;
+const MAX_INDEX_RK: usize = BIT_RK as usize - 1;
+const FIELDS_PER_FLUSH: usize = 50;
+/// `LUA_MULTRET`
+const MULT_RET: i32 = -1;
+
+type CodegenResult<T> = Result<T, CompileError>;
+
+/// Where the value of an expression is, as `expdesc` in `lparser.h`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ExpKind {
+    /// An empty expression list.
+    Void,
+    Nil,
+    True,
+    False,
+    /// A constant in the constant table.
+    K(usize),
+    KFlt(f64),
+    KInt(i64),
+    /// A value in a fixed register.
+    NonReloc(usize),
+    /// A local variable in its register.
+    Local(usize),
+    Upval(usize),
+    /// `table[key]`, where `key` is an RK operand and `table` a register or
+    /// an upvalue.
+    Indexed {
+        table: usize,
+        key: usize,
+        table_is_upvalue: bool,
+    },
+    /// A test followed by the jump at this pc.
+    Jmp(usize),
+    /// An instruction at this pc whose target register can still be set.
+    Relocable(usize),
+    Call(usize),
+    VarArg(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ExpDesc {
+    kind: ExpKind,
+    /// Jumps to patch when the expression is true.
+    t: i32,
+    /// Jumps to patch when the expression is false.
+    f: i32,
+}
+
+impl ExpDesc {
+    fn new(kind: ExpKind) -> ExpDesc {
+        ExpDesc {
+            kind,
+            t: NO_JUMP,
+            f: NO_JUMP,
+        }
+    }
+
+    fn has_jumps(&self) -> bool {
+        self.t != self.f
+    }
+
+    fn has_mult_ret(&self) -> bool {
+        matches!(self.kind, ExpKind::Call(_) | ExpKind::VarArg(_))
+    }
+
+    /// The register of a `NonReloc` or the pc of a pending instruction.
+    fn info(&self) -> usize {
+        match self.kind {
+            ExpKind::NonReloc(i)
+            | ExpKind::Local(i)
+            | ExpKind::Upval(i)
+            | ExpKind::K(i)
+            | ExpKind::Jmp(i)
+            | ExpKind::Relocable(i)
+            | ExpKind::Call(i)
+            | ExpKind::VarArg(i) => i,
+            _ => unreachable!("expression has no info: {:?}", self.kind),
+        }
+    }
+}
+
+/// Keys of the constant cache, which like the table `lcode.c` uses for it
+/// is shared by all functions of a chunk. Both zeros share a key.
+#[derive(Eq, Hash, PartialEq)]
+enum ConstantKey {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Float(u64),
+    String(Vec<u8>),
+}
+
+struct BlockCnt {
+    first_label: usize,
+    first_goto: usize,
+    nactvar: usize,
+    /// Whether some variable of the block is captured as an upvalue.
+    upval: bool,
+    is_loop: bool,
+}
+
+struct LabelDesc {
+    name: String,
+    pc: i32,
+    line: u64,
+    nactvar: usize,
+}
+
+/// The state of the function being compiled, as `FuncState` in
+/// `lparser.h`.
+struct FuncState {
+    f: Chunk,
+    /// The constant cache, held by the innermost function.
+    constant_indices: HashMap<ConstantKey, usize>,
+    /// The last pc that is a jump target.
+    last_target: usize,
+    /// Pending jumps to the current pc.
+    jpc: i32,
+    free_reg: usize,
+    nactvar: usize,
+    /// Declared variables as indices into `f.locals`; the first `nactvar`
+    /// are in scope.
+    active_vars: Vec<usize>,
+    blocks: Vec<BlockCnt>,
+    labels: Vec<LabelDesc>,
+    gotos: Vec<LabelDesc>,
+    /// The line of the last token consumed, given to new instructions.
+    line: u64,
+    /// Errors found while emitting code, reported after the statement.
+    error: Option<(u64, String)>,
+}
+
+impl FuncState {
+    fn new(name: &str, line_defined: u64, line: u64) -> FuncState {
+        FuncState {
+            f: Chunk {
+                name: name.to_owned(),
+                line_defined,
+                last_line_defined: 0,
+                num_upvalues: 0,
+                num_params: 0,
+                vararg_info: None,
+                max_stack: 2,
+                code: vec![],
+                constants: vec![],
+                prototypes: vec![],
+                source_lines: vec![],
+                locals: vec![],
+                upvalues: vec![],
+                upvalue_names: vec![],
+            },
+            constant_indices: HashMap::new(),
+            last_target: 0,
+            jpc: NO_JUMP,
+            free_reg: 0,
+            nactvar: 0,
+            active_vars: vec![],
+            blocks: vec![],
+            labels: vec![],
+            gotos: vec![],
+            line,
+            error: None,
+        }
+    }
+
+    fn pc(&self) -> usize {
+        self.f.code.len()
+    }
+
+    fn set_error(&mut self, message: &str) {
+        if self.error.is_none() {
+            self.error = Some((self.line, message.to_owned()));
+        }
+    }
+
+    fn code(&mut self, instruction: Instruction) -> usize {
+        self.discharge_jpc();
+        self.f.code.push(instruction);
+        self.f.source_lines.push(self.line);
+        self.pc() - 1
+    }
+
+    fn code_abc(&mut self, op: Op, a: usize, b: usize, c: usize) -> usize {
+        self.code(Instruction::abc(op, a as u8, b as u16, c as u16))
+    }
+
+    fn code_abx(&mut self, op: Op, a: usize, bx: usize) -> usize {
+        self.code(Instruction::abx(op, a as u8, bx as u32))
+    }
+
+    fn code_asbx(&mut self, op: Op, a: usize, sbx: i32) -> usize {
+        self.code(Instruction::asbx(op, a as u8, sbx))
+    }
+
+    fn code_k(&mut self, reg: usize, k: usize) -> usize {
+        if k <= MAXARG_BX as usize {
+            return self.code_abx(Op::LoadK, reg, k);
+        }
+        let pc = self.code_abx(Op::LoadKx, reg, 0);
+        self.code(Instruction::ax(Op::ExtraArg, k as u32));
+        pc
+    }
+
+    fn fix_line(&mut self, line: u64) {
+        *self.f.source_lines.last_mut().unwrap() = line;
+    }
+
+    /// Sets registers `from..from + n` to nil, merging with a preceding
+    /// `LoadNil` when possible.
+    fn nil(&mut self, mut from: usize, n: usize) {
+        let mut last = from + n - 1;
+        if self.pc() > self.last_target && self.pc() > 0 {
+            let previous = self.f.code.last_mut().unwrap();
+            if previous.get_op() == Op::LoadNil {
+                let previous_from = previous.get_a() as usize;
+                let previous_last = previous_from + previous.get_b().raw() as usize;
+                if (previous_from <= from && from <= previous_last + 1)
+                    || (from <= previous_from && previous_from <= last + 1)
+                {
+                    from = from.min(previous_from);
+                    last = last.max(previous_last);
+                    previous.set_a(from as u8);
+                    previous.set_b((last - from) as u16);
+                    return;
+                }
+            }
+        }
+        self.code_abc(Op::LoadNil, from, n - 1, 0);
+    }
+
+    fn get_jump(&self, pc: usize) -> i32 {
+        match self.f.code[pc].get_sbx() {
+            NO_JUMP => NO_JUMP,
+            offset => pc as i32 + 1 + offset,
+        }
+    }
+
+    fn fix_jump(&mut self, pc: usize, dest: usize) {
+        let offset = dest as i32 - (pc as i32 + 1);
+        if offset.abs() > MAXARG_SBX {
+            self.set_error("control structure too long");
+        }
+        self.f.code[pc].set_sbx(offset);
+    }
+
+    /// Appends jump list `l2` to `l1`.
+    fn concat(&mut self, l1: &mut i32, l2: i32) {
+        if l2 == NO_JUMP {
+            return;
+        }
+        if *l1 == NO_JUMP {
+            *l1 = l2;
+            return;
+        }
+        let mut list = *l1 as usize;
+        loop {
+            match self.get_jump(list) {
+                NO_JUMP => break,
+                next => list = next as usize,
+            }
+        }
+        self.fix_jump(list, l2 as usize);
+    }
+
+    fn jump(&mut self) -> i32 {
+        let jpc = self.jpc;
+        self.jpc = NO_JUMP;
+        let mut j = self.code_asbx(Op::Jmp, 0, NO_JUMP) as i32;
+        self.concat(&mut j, jpc);
+        j
+    }
+
+    fn jump_to(&mut self, target: usize) {
+        let j = self.jump();
+        self.patch_list(j, target);
+    }
+
+    fn ret(&mut self, first: usize, nret: i32) {
+        self.code_abc(Op::Return, first, (nret + 1) as usize, 0);
+    }
+
+    fn cond_jump(&mut self, op: Op, a: usize, b: usize, c: usize) -> i32 {
+        self.code_abc(op, a, b, c);
+        self.jump()
+    }
+
+    fn get_label(&mut self) -> usize {
+        self.last_target = self.pc();
+        self.pc()
+    }
+
+    /// The test controlling the jump at `pc`, or the jump itself.
+    fn jump_control(&self, pc: usize) -> usize {
+        if pc >= 1 && self.f.code[pc - 1].get_op().is_test() {
+            pc - 1
+        } else {
+            pc
+        }
+    }
+
+    /// Sets the destination register of a `TestSet` controlling the jump
+    /// at `node`, turning it into a `Test` if there is no register.
+    fn patch_test_reg(&mut self, node: usize, reg: usize) -> bool {
+        let i = self.jump_control(node);
+        let instruction = self.f.code[i];
+        if instruction.get_op() != Op::TestSet {
+            return false;
+        }
+        if reg != NO_REG && reg != instruction.get_b().raw() as usize {
+            self.f.code[i].set_a(reg as u8);
+        } else {
+            self.f.code[i] = Instruction::abc(
+                Op::Test,
+                instruction.get_b().raw() as u8,
+                0,
+                instruction.get_c().raw(),
+            );
+        }
+        true
+    }
+
+    fn patch_list_aux(&mut self, mut list: i32, vtarget: usize, reg: usize, dtarget: usize) {
+        while list != NO_JUMP {
+            let next = self.get_jump(list as usize);
+            if self.patch_test_reg(list as usize, reg) {
+                self.fix_jump(list as usize, vtarget);
+            } else {
+                self.fix_jump(list as usize, dtarget);
+            }
+            list = next;
+        }
+    }
+
+    fn discharge_jpc(&mut self) {
+        let pc = self.pc();
+        self.patch_list_aux(self.jpc, pc, NO_REG, pc);
+        self.jpc = NO_JUMP;
+    }
+
+    fn patch_to_here(&mut self, list: i32) {
+        self.get_label();
+        let mut jpc = self.jpc;
+        self.concat(&mut jpc, list);
+        self.jpc = jpc;
+    }
+
+    fn patch_list(&mut self, list: i32, target: usize) {
+        if target == self.pc() {
+            self.patch_to_here(list);
+        } else {
+            self.patch_list_aux(list, target, NO_REG, target);
+        }
+    }
+
+    /// Makes the jumps in `list` close upvalues from register `level` on.
+    fn patch_close(&mut self, mut list: i32, level: usize) {
+        while list != NO_JUMP {
+            self.f.code[list as usize].set_a(level as u8 + 1);
+            list = self.get_jump(list as usize);
+        }
+    }
+
+    fn check_stack(&mut self, n: usize) {
+        let new_stack = self.free_reg + n;
+        if new_stack > self.f.max_stack as usize {
+            if new_stack >= MAX_REGS {
+                self.set_error("function or expression needs too many registers");
+                return;
+            }
+            self.f.max_stack = new_stack as u8;
+        }
+    }
+
+    fn reserve_regs(&mut self, n: usize) {
+        self.check_stack(n);
+        self.free_reg += n;
+    }
+
+    fn free_reg(&mut self, reg: usize) {
+        if reg & BIT_RK as usize == 0 && reg >= self.nactvar {
+            self.free_reg -= 1;
+        }
+    }
+
+    fn free_exp(&mut self, e: &ExpDesc) {
+        if let ExpKind::NonReloc(reg) = e.kind {
+            self.free_reg(reg);
+        }
+    }
+
+    /// Frees the registers of two expressions, the higher one first.
+    fn free_exps(&mut self, e1: &ExpDesc, e2: &ExpDesc) {
+        let r1 = match e1.kind {
+            ExpKind::NonReloc(reg) => reg as isize,
+            _ => -1,
+        };
+        let r2 = match e2.kind {
+            ExpKind::NonReloc(reg) => reg as isize,
+            _ => -1,
+        };
+        if r1 > r2 {
+            self.free_exp(e1);
+            self.free_exp(e2);
+        } else {
+            self.free_exp(e2);
+            self.free_exp(e1);
+        }
+    }
+
+    /// Adds a constant, reusing the index cached for `key` if it still
+    /// holds the same value, as the cache may point into another function.
+    fn add_constant(&mut self, key: ConstantKey, value: Constant) -> usize {
+        if let Some(&index) = self.constant_indices.get(&key) {
+            if self
+                .f
+                .constants
+                .get(index)
+                .is_some_and(|k| same_constant(k, &value))
+            {
+                return index;
+            }
+        }
+        let index = self.f.constants.len();
+        self.f.constants.push(value);
+        self.constant_indices.insert(key, index);
+        index
+    }
+
+    fn string_constant(&mut self, s: &[u8]) -> usize {
+        self.add_constant(
+            ConstantKey::String(s.to_vec()),
+            Constant::String(s.to_vec()),
+        )
+    }
+
+    fn integer_constant(&mut self, i: i64) -> usize {
+        self.add_constant(ConstantKey::Integer(i), Constant::IntegralNumber(i as u64))
+    }
+
+    fn float_constant(&mut self, f: f64) -> usize {
+        let key = if f == 0.0 { 0.0f64 } else { f };
+        self.add_constant(
+            ConstantKey::Float(key.to_bits()),
+            Constant::FloatingNumber(f),
+        )
+    }
+
+    fn bool_constant(&mut self, b: bool) -> usize {
+        self.add_constant(ConstantKey::Boolean(b), Constant::Boolean(b))
+    }
+
+    fn nil_constant(&mut self) -> usize {
+        self.add_constant(ConstantKey::Nil, Constant::Nil)
+    }
+
+    fn set_returns(&mut self, e: &ExpDesc, nresults: i32) {
+        match e.kind {
+            ExpKind::Call(pc) => self.f.code[pc].set_c((nresults + 1) as u16),
+            ExpKind::VarArg(pc) => {
+                self.f.code[pc].set_b((nresults + 1) as u16);
+                self.f.code[pc].set_a(self.free_reg as u8);
+                self.reserve_regs(1);
+            }
+            _ => {}
+        }
+    }
+
+    fn set_mult_ret(&mut self, e: &ExpDesc) {
+        self.set_returns(e, MULT_RET);
+    }
+
+    fn set_one_ret(&mut self, e: &mut ExpDesc) {
+        match e.kind {
+            ExpKind::Call(pc) => e.kind = ExpKind::NonReloc(self.f.code[pc].get_a() as usize),
+            ExpKind::VarArg(pc) => {
+                self.f.code[pc].set_b(2);
+                e.kind = ExpKind::Relocable(pc);
+            }
+            _ => {}
+        }
+    }
+
+    /// Turns variables into values that can be put in a register.
+    fn discharge_vars(&mut self, e: &mut ExpDesc) {
+        match e.kind {
+            ExpKind::Local(reg) => e.kind = ExpKind::NonReloc(reg),
+            ExpKind::Upval(index) => {
+                e.kind = ExpKind::Relocable(self.code_abc(Op::GetUpval, 0, index, 0));
+            }
+            ExpKind::Indexed {
+                table,
+                key,
+                table_is_upvalue,
+            } => {
+                self.free_reg(key);
+                let op = if table_is_upvalue {
+                    Op::GetTabup
+                } else {
+                    self.free_reg(table);
+                    Op::GetTable
+                };
+                e.kind = ExpKind::Relocable(self.code_abc(op, 0, table, key));
+            }
+            ExpKind::VarArg(_) | ExpKind::Call(_) => self.set_one_ret(e),
+            _ => {}
+        }
+    }
+
+    fn discharge_to_reg(&mut self, e: &mut ExpDesc, reg: usize) {
+        self.discharge_vars(e);
+        match e.kind {
+            ExpKind::Nil => self.nil(reg, 1),
+            ExpKind::False => {
+                self.code_abc(Op::LoadBool, reg, 0, 0);
+            }
+            ExpKind::True => {
+                self.code_abc(Op::LoadBool, reg, 1, 0);
+            }
+            ExpKind::K(k) => {
+                self.code_k(reg, k);
+            }
+            ExpKind::KFlt(f) => {
+                let k = self.float_constant(f);
+                self.code_k(reg, k);
+            }
+            ExpKind::KInt(i) => {
+                let k = self.integer_constant(i);
+                self.code_k(reg, k);
+            }
+            ExpKind::Relocable(pc) => self.f.code[pc].set_a(reg as u8),
+            ExpKind::NonReloc(r) => {
+                if reg != r {
+                    self.code_abc(Op::Move, reg, r, 0);
+                }
+            }
+            _ => return,
+        }
+        e.kind = ExpKind::NonReloc(reg);
+    }
+
+    fn discharge_to_any_reg(&mut self, e: &mut ExpDesc) {
+        if !matches!(e.kind, ExpKind::NonReloc(_)) {
+            self.reserve_regs(1);
+            self.discharge_to_reg(e, self.free_reg - 1);
+        }
+    }
+
+    fn code_load_bool(&mut self, a: usize, b: usize, jump: usize) -> usize {
+        self.get_label();
+        self.code_abc(Op::LoadBool, a, b, jump)
+    }
+
+    /// Whether some jump in the list is not a `TestSet`, which produces its
+    /// value itself.
+    fn need_value(&self, mut list: i32) -> bool {
+        while list != NO_JUMP {
+            let control = self.jump_control(list as usize);
+            if self.f.code[control].get_op() != Op::TestSet {
+                return true;
+            }
+            list = self.get_jump(list as usize);
+        }
+        false
+    }
+
+    fn exp_to_reg(&mut self, e: &mut ExpDesc, reg: usize) {
+        self.discharge_to_reg(e, reg);
+        if let ExpKind::Jmp(pc) = e.kind {
+            let mut t = e.t;
+            self.concat(&mut t, pc as i32);
+            e.t = t;
+        }
+        if e.has_jumps() {
+            let mut load_false = NO_JUMP as isize;
+            let mut load_true = NO_JUMP as isize;
+            if self.need_value(e.t) || self.need_value(e.f) {
+                let fj = match e.kind {
+                    ExpKind::Jmp(_) => NO_JUMP,
+                    _ => self.jump(),
+                };
+                load_false = self.code_load_bool(reg, 0, 1) as isize;
+                load_true = self.code_load_bool(reg, 1, 0) as isize;
+                self.patch_to_here(fj);
+            }
+            let end = self.get_label();
+            self.patch_list_aux(e.f, end, reg, load_false as usize);
+            self.patch_list_aux(e.t, end, reg, load_true as usize);
+        }
+        e.t = NO_JUMP;
+        e.f = NO_JUMP;
+        e.kind = ExpKind::NonReloc(reg);
+    }
+
+    fn exp_to_next_reg(&mut self, e: &mut ExpDesc) {
+        self.discharge_vars(e);
+        self.free_exp(e);
+        self.reserve_regs(1);
+        self.exp_to_reg(e, self.free_reg - 1);
+    }
+
+    fn exp_to_any_reg(&mut self, e: &mut ExpDesc) -> usize {
+        self.discharge_vars(e);
+        if let ExpKind::NonReloc(reg) = e.kind {
+            if !e.has_jumps() {
+                return reg;
+            }
+            if reg >= self.nactvar {
+                self.exp_to_reg(e, reg);
+                return reg;
+            }
+        }
+        self.exp_to_next_reg(e);
+        e.info()
+    }
+
+    fn exp_to_any_reg_up(&mut self, e: &mut ExpDesc) {
+        if !matches!(e.kind, ExpKind::Upval(_)) || e.has_jumps() {
+            self.exp_to_any_reg(e);
+        }
+    }
+
+    fn exp_to_val(&mut self, e: &mut ExpDesc) {
+        if e.has_jumps() {
+            self.exp_to_any_reg(e);
+        } else {
+            self.discharge_vars(e);
+        }
+    }
+
+    /// Puts the expression in a register or, if it is a constant with a
+    /// small enough index, returns it as an RK operand.
+    fn exp_to_rk(&mut self, e: &mut ExpDesc) -> usize {
+        self.exp_to_val(e);
+        let k = match e.kind {
+            ExpKind::True => Some(self.bool_constant(true)),
+            ExpKind::False => Some(self.bool_constant(false)),
+            ExpKind::Nil => Some(self.nil_constant()),
+            ExpKind::KInt(i) => Some(self.integer_constant(i)),
+            ExpKind::KFlt(f) => Some(self.float_constant(f)),
+            ExpKind::K(k) => Some(k),
+            _ => None,
+        };
+        if let Some(k) = k {
+            e.kind = ExpKind::K(k);
+            if k <= MAX_INDEX_RK {
+                return k | BIT_RK as usize;
+            }
+        }
+        self.exp_to_any_reg(e)
+    }
+
+    fn store_var(&mut self, var: &ExpDesc, ex: &mut ExpDesc) {
+        match var.kind {
+            ExpKind::Local(reg) => {
+                self.free_exp(ex);
+                self.exp_to_reg(ex, reg);
+                return;
+            }
+            ExpKind::Upval(index) => {
+                let e = self.exp_to_any_reg(ex);
+                self.code_abc(Op::SetUpval, e, index, 0);
+            }
+            ExpKind::Indexed {
+                table,
+                key,
+                table_is_upvalue,
+            } => {
+                let op = match table_is_upvalue {
+                    true => Op::SetTabup,
+                    false => Op::SetTable,
+                };
+                let e = self.exp_to_rk(ex);
+                self.code_abc(op, table, key, e);
+            }
+            _ => unreachable!("cannot store to {:?}", var.kind),
+        }
+        self.free_exp(ex);
+    }
+
+    /// `e:key`, leaving the method and `e` in two consecutive registers.
+    fn self_(&mut self, e: &mut ExpDesc, key: &mut ExpDesc) {
+        self.exp_to_any_reg(e);
+        let ereg = e.info();
+        self.free_exp(e);
+        let base = self.free_reg;
+        e.kind = ExpKind::NonReloc(base);
+        self.reserve_regs(2);
+        let rk = self.exp_to_rk(key);
+        self.code_abc(Op::LuaSelf, base, ereg, rk);
+        self.free_exp(key);
+    }
+
+    fn negate_condition(&mut self, e: &ExpDesc) {
+        let control = self.jump_control(e.info());
+        let a = self.f.code[control].get_a();
+        self.f.code[control].set_a((a == 0) as u8);
+    }
+
+    /// Emits a test jumping if `e` is `cond`, testing the operand of a
+    /// just emitted `not` directly.
+    fn jump_on_cond(&mut self, e: &mut ExpDesc, cond: usize) -> i32 {
+        if let ExpKind::Relocable(pc) = e.kind {
+            let instruction = self.f.code[pc];
+            if instruction.get_op() == Op::Not {
+                self.f.code.pop();
+                self.f.source_lines.pop();
+                let reg = instruction.get_b().raw() as usize;
+                return self.cond_jump(Op::Test, reg, 0, (cond == 0) as usize);
+            }
+        }
+        self.discharge_to_any_reg(e);
+        self.free_exp(e);
+        self.cond_jump(Op::TestSet, NO_REG, e.info(), cond)
+    }
+
+    /// Emits code to go through if `e` is true and jump otherwise.
+    fn go_if_true(&mut self, e: &mut ExpDesc) {
+        self.discharge_vars(e);
+        let pc = match e.kind {
+            ExpKind::Jmp(pc) => {
+                self.negate_condition(e);
+                pc as i32
+            }
+            ExpKind::K(_) | ExpKind::KFlt(_) | ExpKind::KInt(_) | ExpKind::True => NO_JUMP,
+            _ => self.jump_on_cond(e, 0),
+        };
+        let mut f = e.f;
+        self.concat(&mut f, pc);
+        e.f = f;
+        self.patch_to_here(e.t);
+        e.t = NO_JUMP;
+    }
+
+    /// Emits code to go through if `e` is false and jump otherwise.
+    fn go_if_false(&mut self, e: &mut ExpDesc) {
+        self.discharge_vars(e);
+        let pc = match e.kind {
+            ExpKind::Jmp(pc) => pc as i32,
+            ExpKind::Nil | ExpKind::False => NO_JUMP,
+            _ => self.jump_on_cond(e, 1),
+        };
+        let mut t = e.t;
+        self.concat(&mut t, pc);
+        e.t = t;
+        self.patch_to_here(e.f);
+        e.f = NO_JUMP;
+    }
+
+    /// `not e`, folding constants. The jump lists are swapped and no longer
+    /// produce values.
+    fn code_not(&mut self, e: &mut ExpDesc) {
+        self.discharge_vars(e);
+        match e.kind {
+            ExpKind::Nil | ExpKind::False => e.kind = ExpKind::True,
+            ExpKind::K(_) | ExpKind::KFlt(_) | ExpKind::KInt(_) | ExpKind::True => {
+                e.kind = ExpKind::False
+            }
+            ExpKind::Jmp(_) => self.negate_condition(e),
+            ExpKind::Relocable(_) | ExpKind::NonReloc(_) => {
+                self.discharge_to_any_reg(e);
+                self.free_exp(e);
+                e.kind = ExpKind::Relocable(self.code_abc(Op::Not, 0, e.info(), 0));
+            }
+            kind => unreachable!("cannot negate {:?}", kind),
+        }
+        std::mem::swap(&mut e.t, &mut e.f);
+        self.remove_values(e.f);
+        self.remove_values(e.t);
+    }
+
+    fn remove_values(&mut self, mut list: i32) {
+        while list != NO_JUMP {
+            self.patch_test_reg(list as usize, NO_REG);
+            list = self.get_jump(list as usize);
+        }
+    }
+
+    fn indexed(&mut self, t: &mut ExpDesc, k: &mut ExpDesc) {
+        let key = self.exp_to_rk(k);
+        t.kind = match t.kind {
+            ExpKind::Upval(index) => ExpKind::Indexed {
+                table: index,
+                key,
+                table_is_upvalue: true,
+            },
+            ExpKind::Local(reg) | ExpKind::NonReloc(reg) => ExpKind::Indexed {
+                table: reg,
+                key,
+                table_is_upvalue: false,
+            },
+            kind => unreachable!("cannot index {:?}", kind),
+        };
+    }
+
+    fn code_unexp_val(&mut self, op: Op, e: &mut ExpDesc, line: u64) {
+        let r = self.exp_to_any_reg(e);
+        self.free_exp(e);
+        e.kind = ExpKind::Relocable(self.code_abc(op, 0, r, 0));
+        self.fix_line(line);
+    }
+
+    fn code_bin_exp_val(&mut self, op: Op, e1: &mut ExpDesc, e2: &mut ExpDesc, line: u64) {
+        let rk2 = self.exp_to_rk(e2);
+        let rk1 = self.exp_to_rk(e1);
+        self.free_exps(e1, e2);
+        e1.kind = ExpKind::Relocable(self.code_abc(op, 0, rk1, rk2));
+        self.fix_line(line);
+    }
+
+    fn code_comp(&mut self, op: BinOp, e1: &mut ExpDesc, e2: &mut ExpDesc) {
+        let rk1 = match e1.kind {
+            ExpKind::K(k) => k | BIT_RK as usize,
+            ExpKind::NonReloc(reg) => reg,
+            kind => unreachable!("comparison operand in {:?}", kind),
+        };
+        let rk2 = self.exp_to_rk(e2);
+        self.free_exps(e1, e2);
+        let pc = match op {
+            BinOp::Ne => self.cond_jump(Op::Eq, 0, rk1, rk2),
+            BinOp::Gt => self.cond_jump(Op::Lt, 1, rk2, rk1),
+            BinOp::Ge => self.cond_jump(Op::Le, 1, rk2, rk1),
+            BinOp::Eq => self.cond_jump(Op::Eq, 1, rk1, rk2),
+            BinOp::Lt => self.cond_jump(Op::Lt, 1, rk1, rk2),
+            BinOp::Le => self.cond_jump(Op::Le, 1, rk1, rk2),
+            _ => unreachable!("{:?} is not a comparison", op),
+        };
+        e1.kind = ExpKind::Jmp(pc as usize);
+    }
+
+    fn prefix(&mut self, op: UnOp, e: &mut ExpDesc, line: u64) {
+        let zero = ExpDesc::new(ExpKind::KInt(0));
+        match op {
+            UnOp::Minus if const_folding(ArithOp::Unm, e, &zero) => {}
+            UnOp::BNot if const_folding(ArithOp::BNot, e, &zero) => {}
+            UnOp::Minus => self.code_unexp_val(Op::Unm, e, line),
+            UnOp::BNot => self.code_unexp_val(Op::BNot, e, line),
+            UnOp::Len => self.code_unexp_val(Op::Len, e, line),
+            UnOp::Not => self.code_not(e),
+        }
+    }
+
+    /// Prepares the first operand before the second one is compiled.
+    fn infix(&mut self, op: BinOp, v: &mut ExpDesc) {
+        match op {
+            BinOp::And => self.go_if_true(v),
+            BinOp::Or => self.go_if_false(v),
+            BinOp::Concat => self.exp_to_next_reg(v),
+            // a numeral is kept to be folded with the second operand
+            _ if is_arith(op) && to_numeral(v).is_some() => {}
+            _ => {
+                self.exp_to_rk(v);
+            }
+        }
+    }
+
+    fn posfix(&mut self, op: BinOp, e1: &mut ExpDesc, e2: &mut ExpDesc, line: u64) {
+        match op {
+            BinOp::And => {
+                self.discharge_vars(e2);
+                let mut f = e2.f;
+                self.concat(&mut f, e1.f);
+                e2.f = f;
+                *e1 = *e2;
+            }
+            BinOp::Or => {
+                self.discharge_vars(e2);
+                let mut t = e2.t;
+                self.concat(&mut t, e1.t);
+                e2.t = t;
+                *e1 = *e2;
+            }
+            BinOp::Concat => {
+                self.exp_to_val(e2);
+                match e2.kind {
+                    ExpKind::Relocable(pc) if self.f.code[pc].get_op() == Op::Concat => {
+                        self.free_exp(e1);
+                        self.f.code[pc].set_b(e1.info() as u16);
+                        e1.kind = ExpKind::Relocable(pc);
+                    }
+                    _ => {
+                        self.exp_to_next_reg(e2);
+                        self.code_bin_exp_val(Op::Concat, e1, e2, line);
+                    }
+                }
+            }
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => {
+                self.code_comp(op, e1, e2)
+            }
+            _ => {
+                if !const_folding(arith(op), e1, e2) {
+                    self.code_bin_exp_val(arith_op(op), e1, e2, line);
+                }
+            }
+        }
+    }
+
+    fn set_list(&mut self, base: usize, nelems: usize, tostore: i32) {
+        let c = (nelems.wrapping_sub(1)) / FIELDS_PER_FLUSH + 1;
+        let b = match tostore {
+            MULT_RET => 0,
+            n => n as usize,
+        };
+        if c <= MAXARG_C as usize {
+            self.code_abc(Op::SetList, base, b, c);
+        } else if c <= MAXARG_AX as usize {
+            self.code_abc(Op::SetList, base, b, 0);
+            self.code(Instruction::ax(Op::ExtraArg, c as u32));
+        } else {
+            self.set_error("constructor too long");
+        }
+        self.free_reg = base + 1;
+    }
+
+    fn local_var(&mut self, i: usize) -> &mut Local {
+        &mut self.f.locals[self.active_vars[i]]
+    }
+
+    fn search_var(&self, name: &str) -> Option<usize> {
+        (0..self.nactvar)
+            .rev()
+            .find(|&i| self.f.locals[self.active_vars[i]].varname == name)
+    }
+
+    fn search_upvalue(&self, name: &str) -> Option<usize> {
+        self.f.upvalue_names.iter().position(|n| n == name)
+    }
+
+    /// Marks the block declaring the variable at `level` as having an
+    /// upvalue.
+    fn mark_upval(&mut self, level: usize) {
+        let block = self
+            .blocks
+            .iter_mut()
+            .rev()
+            .find(|b| b.nactvar <= level)
+            .unwrap();
+        block.upval = true;
+    }
+
+    fn adjust_local_vars(&mut self, nvars: usize) {
+        self.nactvar += nvars;
+        let pc = self.pc() as u64;
+        for i in self.nactvar - nvars..self.nactvar {
+            self.local_var(i).startpc = pc;
+        }
+    }
+
+    fn remove_vars(&mut self, to_level: usize) {
+        let pc = self.pc() as u64;
+        while self.nactvar > to_level {
+            self.nactvar -= 1;
+            self.local_var(self.nactvar).endpc = pc;
+        }
+        self.active_vars.truncate(to_level);
+    }
+}
+
+fn same_constant(a: &Constant, b: &Constant) -> bool {
+    match (a, b) {
+        (Constant::Nil, Constant::Nil) => true,
+        (Constant::Boolean(a), Constant::Boolean(b)) => a == b,
+        (Constant::IntegralNumber(a), Constant::IntegralNumber(b)) => a == b,
+        (Constant::FloatingNumber(a), Constant::FloatingNumber(b)) => a == b,
+        (Constant::String(a), Constant::String(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn to_numeral(e: &ExpDesc) -> Option<Number> {
+    match e.kind {
+        _ if e.has_jumps() => None,
+        ExpKind::KInt(i) => Some(Number::Integer(i)),
+        ExpKind::KFlt(f) => Some(Number::Float(f)),
+        _ => None,
+    }
+}
+
+/// Replaces `e1` by the result of an operation on two numerals, unless the
+/// operation would raise an error or give NaN or a zero, which could be
+/// -0.
+fn const_folding(op: ArithOp, e1: &mut ExpDesc, e2: &ExpDesc) -> bool {
+    let (Some(v1), Some(v2)) = (to_numeral(e1), to_numeral(e2)) else {
+        return false;
+    };
+    if matches!(op, ArithOp::Div | ArithOp::IDiv | ArithOp::Mod) && v2.to_float() == 0.0 {
+        return false;
+    }
+    match v1.arith(op, v2) {
+        Some(Number::Integer(i)) => e1.kind = ExpKind::KInt(i),
+        Some(Number::Float(f)) if !f.is_nan() && f != 0.0 => e1.kind = ExpKind::KFlt(f),
+        _ => return false,
+    }
+    true
+}
+
+fn is_arith(op: BinOp) -> bool {
+    !matches!(
+        op,
+        BinOp::Concat
+            | BinOp::Ne
+            | BinOp::Eq
+            | BinOp::Lt
+            | BinOp::Le
+            | BinOp::Gt
+            | BinOp::Ge
+            | BinOp::And
+            | BinOp::Or
+    )
+}
+
+fn arith(op: BinOp) -> ArithOp {
+    match op {
+        BinOp::Add => ArithOp::Add,
+        BinOp::Sub => ArithOp::Sub,
+        BinOp::Mul => ArithOp::Mul,
+        BinOp::Mod => ArithOp::Mod,
+        BinOp::Pow => ArithOp::Pow,
+        BinOp::Div => ArithOp::Div,
+        BinOp::IDiv => ArithOp::IDiv,
+        BinOp::BAnd => ArithOp::BAnd,
+        BinOp::BOr => ArithOp::BOr,
+        BinOp::BXor => ArithOp::BXor,
+        BinOp::Shl => ArithOp::Shl,
+        BinOp::Shr => ArithOp::Shr,
+        _ => unreachable!("{:?} is not an arithmetic operator", op),
+    }
+}
+
+fn arith_op(op: BinOp) -> Op {
+    match op {
+        BinOp::Add => Op::Add,
+        BinOp::Sub => Op::Sub,
+        BinOp::Mul => Op::Mul,
+        BinOp::Mod => Op::Mod,
+        BinOp::Pow => Op::Pow,
+        BinOp::Div => Op::Div,
+        BinOp::IDiv => Op::IDiv,
+        BinOp::BAnd => Op::BAnd,
+        BinOp::BOr => Op::BOr,
+        BinOp::BXor => Op::BXor,
+        BinOp::Shl => Op::Shl,
+        BinOp::Shr => Op::Shr,
+        _ => unreachable!("{:?} is not an arithmetic operator", op),
+    }
+}
+
+/// Encodes a table size hint as the "floating point byte" `NewTable`
+/// takes, rounding up.
+fn int_to_fb(mut x: usize) -> usize {
+    if x < 8 {
+        return x;
+    }
+    let mut e = 0;
+    while x >= 8 << 4 {
+        x = (x + 0xf) >> 4;
+        e += 4;
+    }
+    while x >= 8 << 1 {
+        x = (x + 1) >> 1;
+        e += 1;
+    }
+    ((e + 1) << 3) | (x - 8)
+}
+
+/// Walks the syntax tree the way `lparser.c` walks the token stream,
+/// producing the same code as the reference compiler.
+struct CodeGen<'a> {
+    chunk_name: &'a str,
+    states: Vec<FuncState>,
+}
+
+/// Generates the main function of a chunk.
+pub fn generate(block: &Block, chunk_name: &str) -> Result<Chunk, CompileError> {
+    let mut gen = CodeGen {
+        chunk_name,
+        states: vec![],
+    };
+    gen.open_function(0, 1);
+    let fs = gen.fs();
+    fs.f.vararg_info = Some(VarArgInfo {});
+    fs.f.upvalues.push(Upvalue {
+        in_stack: true,
+        index: 0,
+    });
+    fs.f.upvalue_names.push("_ENV".to_owned());
+    gen.stat_list(&block.stats, false)?;
+    gen.set_line(block_end_line(block));
+    gen.close_function()
+}
+
+impl CodeGen<'_> {
+    fn fs(&mut self) -> &mut FuncState {
+        self.states.last_mut().unwrap()
+    }
+
+    fn set_line(&mut self, line: u64) {
+        self.fs().line = line;
+    }
+
+    fn error(&mut self, message: String) -> CompileError {
+        let line = self.fs().line;
+        CompileError::new(self.chunk_name, line, message)
+    }
+
+    /// Reports errors found while emitting code.
+    fn check_error(&mut self) -> CodegenResult<()> {
+        match self.fs().error.take() {
+            Some((line, message)) => Err(CompileError::new(self.chunk_name, line, message)),
+            None => Ok(()),
+        }
+    }
+
+    fn error_limit(&mut self, level: usize, limit: usize, what: &str) -> CompileError {
+        let location = match self.states[level].f.line_defined {
+            0 => "main function".to_owned(),
+            line => format!("function at line {}", line),
+        };
+        self.error(format!(
+            "too many {} (limit is {}) in {}",
+            what, limit, location
+        ))
+    }
+
+    fn open_function(&mut self, line_defined: u64, line: u64) {
+        let mut fs = FuncState::new(self.chunk_name, line_defined, line);
+        if let Some(parent) = self.states.last_mut() {
+            fs.constant_indices = std::mem::take(&mut parent.constant_indices);
+        }
+        self.states.push(fs);
+        self.enter_block(false);
+    }
+
+    fn close_function(&mut self) -> CodegenResult<Chunk> {
+        self.fs().ret(0, 0);
+        self.leave_block()?;
+        self.check_error()?;
+        let mut fs = self.states.pop().unwrap();
+        fs.f.num_upvalues = fs.f.upvalues.len() as u8;
+        if let Some(parent) = self.states.last_mut() {
+            parent.line = fs.line;
+            parent.constant_indices = fs.constant_indices;
+        }
+        Ok(fs.f)
+    }
+
+    fn new_local_var(&mut self, name: &str) -> CodegenResult<()> {
+        let level = self.states.len() - 1;
+        if self.fs().active_vars.len() + 1 > MAX_VARS {
+            return Err(self.error_limit(level, MAX_VARS, "local variables"));
+        }
+        let fs = self.fs();
+        fs.f.locals.push(Local {
+            varname: name.to_owned(),
+            startpc: 0,
+            endpc: 0,
+        });
+        fs.active_vars.push(fs.f.locals.len() - 1);
+        Ok(())
+    }
+
+    fn new_upvalue(&mut self, level: usize, name: &str, v: ExpKind) -> CodegenResult<usize> {
+        if self.states[level].f.upvalues.len() + 1 > MAX_UPVALUES {
+            return Err(self.error_limit(level, MAX_UPVALUES, "upvalues"));
+        }
+        let (in_stack, index) = match v {
+            ExpKind::Local(reg) => (true, reg),
+            ExpKind::Upval(index) => (false, index),
+            _ => unreachable!("upvalue from {:?}", v),
+        };
+        let f = &mut self.states[level].f;
+        f.upvalues.push(Upvalue {
+            in_stack,
+            index: index as u8,
+        });
+        f.upvalue_names.push(name.to_owned());
+        Ok(f.upvalues.len() - 1)
+    }
+
+    /// Finds `name` as a local or upvalue of the function at `level`,
+    /// adding upvalues to the functions in between. `Void` means global.
+    fn single_var_aux(&mut self, level: usize, name: &str, base: bool) -> CodegenResult<ExpKind> {
+        let fs = &mut self.states[level];
+        if let Some(v) = fs.search_var(name) {
+            if !base {
+                fs.mark_upval(v);
+            }
+            return Ok(ExpKind::Local(v));
+        }
+        if let Some(index) = fs.search_upvalue(name) {
+            return Ok(ExpKind::Upval(index));
+        }
+        if level == 0 {
+            return Ok(ExpKind::Void);
+        }
+        match self.single_var_aux(level - 1, name, false)? {
+            ExpKind::Void => Ok(ExpKind::Void),
+            v => Ok(ExpKind::Upval(self.new_upvalue(level, name, v)?)),
+        }
+    }
+
+    fn single_var(&mut self, name: &str) -> CodegenResult<ExpDesc> {
+        let level = self.states.len() - 1;
+        let mut var = match self.single_var_aux(level, name, true)? {
+            ExpKind::Void => {
+                let env = self.single_var_aux(level, "_ENV", true)?;
+                let mut var = ExpDesc::new(env);
+                let mut key = ExpDesc::new(ExpKind::K(self.fs().string_constant(name.as_bytes())));
+                self.fs().indexed(&mut var, &mut key);
+                var
+            }
+            kind => ExpDesc::new(kind),
+        };
+        var.t = NO_JUMP;
+        Ok(var)
+    }
+
+    fn enter_block(&mut self, is_loop: bool) {
+        let fs = self.fs();
+        fs.blocks.push(BlockCnt {
+            first_label: fs.labels.len(),
+            first_goto: fs.gotos.len(),
+            nactvar: fs.nactvar,
+            upval: false,
+            is_loop,
+        });
+    }
+
+    fn leave_block(&mut self) -> CodegenResult<()> {
+        let fs = self.fs();
+        let block = fs.blocks.last().unwrap();
+        let (nactvar, upval, is_loop) = (block.nactvar, block.upval, block.is_loop);
+        if fs.blocks.len() > 1 && upval {
+            let j = fs.jump();
+            fs.patch_close(j, nactvar);
+            fs.patch_to_here(j);
+        }
+        if is_loop {
+            self.break_label()?;
+        }
+        let fs = self.fs();
+        let block = fs.blocks.pop().unwrap();
+        fs.remove_vars(block.nactvar);
+        fs.free_reg = fs.nactvar;
+        fs.labels.truncate(block.first_label);
+        if !fs.blocks.is_empty() {
+            self.move_gotos_out(&block)?;
+        } else if block.first_goto < fs.gotos.len() {
+            let goto = &fs.gotos[block.first_goto];
+            let message = match goto.name.as_str() {
+                "break" => format!("<break> at line {} not inside a loop", goto.line),
+                name => format!(
+                    "no visible label '{}' for <goto> at line {}",
+                    name, goto.line
+                ),
+            };
+            return Err(self.error(message));
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, block: &Block) -> CodegenResult<()> {
+        self.enter_block(false);
+        self.set_line(block.span.line);
+        self.stat_list(&block.stats, false)?;
+        self.set_line(block_end_line(block));
+        self.leave_block()
+    }
+
+    fn new_label_entry(&mut self, is_label: bool, name: &str, line: u64, pc: i32) -> usize {
+        let fs = self.fs();
+        let entry = LabelDesc {
+            name: name.to_owned(),
+            pc,
+            line,
+            nactvar: fs.nactvar,
+        };
+        let list = match is_label {
+            true => &mut fs.labels,
+            false => &mut fs.gotos,
+        };
+        list.push(entry);
+        list.len() - 1
+    }
+
+    fn close_goto(&mut self, g: usize, label: usize) -> CodegenResult<()> {
+        let fs = self.fs();
+        let (goto, label) = (&fs.gotos[g], &fs.labels[label]);
+        if goto.nactvar < label.nactvar {
+            let message = format!(
+                "<goto {}> at line {} jumps into the scope of local '{}'",
+                goto.name, goto.line, fs.f.locals[fs.active_vars[goto.nactvar]].varname
+            );
+            return Err(self.error(message));
+        }
+        let (list, target) = (goto.pc, label.pc as usize);
+        fs.patch_list(list, target);
+        fs.gotos.remove(g);
+        Ok(())
+    }
+
+    /// Tries to close the goto `g` with a label of the current block.
+    fn find_label(&mut self, g: usize) -> CodegenResult<bool> {
+        let fs = self.fs();
+        let block = fs.blocks.last().unwrap();
+        let goto = &fs.gotos[g];
+        let found = (block.first_label..fs.labels.len()).find(|&i| fs.labels[i].name == goto.name);
+        let i = match found {
+            Some(i) => i,
+            None => return Ok(false),
+        };
+        let label = &fs.labels[i];
+        if goto.nactvar > label.nactvar && (block.upval || fs.labels.len() > block.first_label) {
+            let (list, level) = (goto.pc, label.nactvar);
+            fs.patch_close(list, level);
+        }
+        self.close_goto(g, i)?;
+        Ok(true)
+    }
+
+    /// Closes the pending gotos of the current block that jump to `label`.
+    fn find_gotos(&mut self, label: usize) -> CodegenResult<()> {
+        let mut i = self.fs().blocks.last().unwrap().first_goto;
+        while i < self.fs().gotos.len() {
+            let fs = self.fs();
+            if fs.gotos[i].name == fs.labels[label].name {
+                self.close_goto(i, label)?;
+            } else {
+                i += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the pending gotos of a block that was left to the enclosing
+    /// one, closing them with its labels where possible.
+    fn move_gotos_out(&mut self, block: &BlockCnt) -> CodegenResult<()> {
+        let mut i = block.first_goto;
+        while i < self.fs().gotos.len() {
+            let fs = self.fs();
+            if fs.gotos[i].nactvar > block.nactvar {
+                if block.upval {
+                    let list = fs.gotos[i].pc;
+                    fs.patch_close(list, block.nactvar);
+                }
+                fs.gotos[i].nactvar = block.nactvar;
+            }
+            if !self.find_label(i)? {
+                i += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn break_label(&mut self) -> CodegenResult<()> {
+        let pc = self.fs().pc() as i32;
+        let l = self.new_label_entry(true, "break", 0, pc);
+        self.find_gotos(l)
+    }
+
+    fn stat_list(&mut self, stats: &[Stat], in_repeat: bool) -> CodegenResult<()> {
+        for (i, stat) in stats.iter().enumerate() {
+            let only_labels_follow = stats[i + 1..]
+                .iter()
+                .all(|s| matches!(s.kind, StatKind::Label(_)));
+            self.statement(stat, only_labels_follow && !in_repeat)?;
+        }
+        Ok(())
+    }
+
+    /// Compiles a statement. `is_last` tells labels that nothing but labels
+    /// follow them in their block.
+    fn statement(&mut self, stat: &Stat, is_last: bool) -> CodegenResult<()> {
+        let line = stat.span.line;
+        let end_line = stat.span.end_line;
+        match &stat.kind {
+            StatKind::Local { names, exprs } => self.local_stat(names, exprs),
+            StatKind::Assign { targets, exprs } => self.assignment(targets, exprs),
+            StatKind::Call(call) => self.call_stat(call),
+            StatKind::Do(body) => {
+                self.set_line(line);
+                self.block(body)
+            }
+            StatKind::While { cond, body } => self.while_stat(cond, body, line, end_line),
+            StatKind::Repeat { body, cond } => self.repeat_stat(body, cond),
+            StatKind::If {
+                clauses,
+                else_block,
+            } => self.if_stat(clauses, else_block.as_ref(), line, end_line),
+            StatKind::NumericFor {
+                var,
+                start,
+                limit,
+                step,
+                body,
+            } => self.numeric_for(var, start, limit, step.as_deref(), body, line, end_line),
+            StatKind::GenericFor { names, exprs, body } => {
+                self.generic_for(names, exprs, body, end_line)
+            }
+            StatKind::Function { name, body } => self.function_stat(name, body, line),
+            StatKind::LocalFunction { name, body } => self.local_function(name, body),
+            StatKind::Return(exprs) => {
+                self.set_line(line);
+                self.return_stat(exprs)
+            }
+            StatKind::Break => self.break_stat(line, end_line),
+            StatKind::Goto(label) => {
+                let pc = self.fs().jump();
+                self.set_line(end_line);
+                self.goto_stat(&label.name, line, pc)
+            }
+            StatKind::Label(label) => self.label_stat(label, line, end_line, is_last),
+        }?;
+        self.check_error()?;
+        let fs = self.fs();
+        fs.free_reg = fs.nactvar;
+        Ok(())
+    }
+
+    fn call_stat(&mut self, call: &Expr) -> CodegenResult<()> {
+        let e = self.expr(call)?;
+        let pc = e.info();
+        self.fs().f.code[pc].set_c(1);
+        Ok(())
+    }
+
+    fn while_stat(
+        &mut self,
+        cond: &Expr,
+        body: &Block,
+        line: u64,
+        end_line: u64,
+    ) -> CodegenResult<()> {
+        self.set_line(line);
+        let while_init = self.fs().get_label();
+        let cond_exit = self.cond(cond)?;
+        self.enter_block(true);
+        self.block(body)?;
+        self.fs().jump_to(while_init);
+        self.set_line(end_line);
+        self.leave_block()?;
+        self.fs().patch_to_here(cond_exit);
+        Ok(())
+    }
+
+    fn repeat_stat(&mut self, body: &Block, cond: &Expr) -> CodegenResult<()> {
+        let repeat_init = self.fs().get_label();
+        self.enter_block(true);
+        self.enter_block(false);
+        self.set_line(body.span.line);
+        self.stat_list(&body.stats, true)?;
+        self.set_line(body.span.end_line);
+        let cond_exit = self.cond(cond)?;
+        let block = self.fs().blocks.last().unwrap();
+        if block.upval {
+            let nactvar = block.nactvar;
+            self.fs().patch_close(cond_exit, nactvar);
+        }
+        self.leave_block()?;
+        self.fs().patch_list(cond_exit, repeat_init);
+        self.leave_block()
+    }
+
+    fn if_stat(
+        &mut self,
+        clauses: &[(Expr, Block)],
+        else_block: Option<&Block>,
+        line: u64,
+        end_line: u64,
+    ) -> CodegenResult<()> {
+        self.set_line(line);
+        let mut escape_list = NO_JUMP;
+        for (i, (cond, block)) in clauses.iter().enumerate() {
+            let followed = i + 1 < clauses.len() || else_block.is_some();
+            self.test_then_block(cond, block, followed, &mut escape_list)?;
+        }
+        if let Some(block) = else_block {
+            self.block(block)?;
+        }
+        self.set_line(end_line);
+        self.fs().patch_to_here(escape_list);
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn numeric_for(
+        &mut self,
+        var: &Name,
+        start: &Expr,
+        limit: &Expr,
+        step: Option<&Expr>,
+        body: &Block,
+        line: u64,
+        end_line: u64,
+    ) -> CodegenResult<()> {
+        self.enter_block(true);
+        self.set_line(var.span.end_line);
+        let base = self.fs().free_reg;
+        self.new_local_var("(for index)")?;
+        self.new_local_var("(for limit)")?;
+        self.new_local_var("(for step)")?;
+        self.new_local_var(&var.name)?;
+        self.exp1(start)?;
+        self.exp1(limit)?;
+        match step {
+            Some(step) => self.exp1(step)?,
+            None => {
+                let fs = self.fs();
+                let k = fs.integer_constant(1);
+                fs.code_k(fs.free_reg, k);
+                fs.reserve_regs(1);
+            }
+        }
+        self.for_body(base, line, 1, true, body)?;
+        self.set_line(end_line);
+        self.leave_block()
+    }
+
+    fn generic_for(
+        &mut self,
+        names: &[Name],
+        exprs: &[Expr],
+        body: &Block,
+        end_line: u64,
+    ) -> CodegenResult<()> {
+        self.enter_block(true);
+        let base = self.fs().free_reg;
+        self.new_local_var("(for generator)")?;
+        self.new_local_var("(for state)")?;
+        self.new_local_var("(for control)")?;
+        for name in names {
+            self.new_local_var(&name.name)?;
+        }
+        self.set_line(names.last().unwrap().span.end_line);
+        let call_line = exprs[0].span.line;
+        let e = self.expr_list(exprs)?;
+        self.adjust_assign(3, exprs.len(), e);
+        self.fs().check_stack(3);
+        self.for_body(base, call_line, names.len(), false, body)?;
+        self.set_line(end_line);
+        self.leave_block()
+    }
+
+    fn function_stat(
+        &mut self,
+        name: &FunctionName,
+        body: &FunctionBody,
+        line: u64,
+    ) -> CodegenResult<()> {
+        self.set_line(line);
+        let mut v = self.single_var(&name.path[0].name)?;
+        for field in name.path[1..].iter().chain(&name.method) {
+            self.field_sel(&mut v, field);
+        }
+        let mut b = self.body(body, name.method.is_some(), line)?;
+        let fs = self.fs();
+        fs.store_var(&v, &mut b);
+        fs.fix_line(line);
+        Ok(())
+    }
+
+    fn local_function(&mut self, name: &Name, body: &FunctionBody) -> CodegenResult<()> {
+        self.new_local_var(&name.name)?;
+        self.fs().adjust_local_vars(1);
+        let b = self.body(body, false, body.span.line)?;
+        let fs = self.fs();
+        let pc = fs.pc() as u64;
+        fs.local_var(b.info()).startpc = pc;
+        Ok(())
+    }
+
+    fn break_stat(&mut self, line: u64, end_line: u64) -> CodegenResult<()> {
+        let pc = self.fs().jump();
+        self.set_line(end_line);
+        self.goto_stat("break", line, pc)
+    }
+
+    fn label_stat(
+        &mut self,
+        label: &Name,
+        line: u64,
+        end_line: u64,
+        is_last: bool,
+    ) -> CodegenResult<()> {
+        self.set_line(end_line);
+        let fs = self.fs();
+        let first_label = fs.blocks.last().unwrap().first_label;
+        if let Some(previous) = fs.labels[first_label..]
+            .iter()
+            .find(|l| l.name == label.name)
+        {
+            let message = format!(
+                "label '{}' already defined on line {}",
+                label.name, previous.line
+            );
+            return Err(self.error(message));
+        }
+        let pc = self.fs().get_label() as i32;
+        let l = self.new_label_entry(true, &label.name, line, pc);
+        if is_last {
+            let fs = self.fs();
+            fs.labels[l].nactvar = fs.blocks.last().unwrap().nactvar;
+        }
+        self.find_gotos(l)
+    }
+
+    fn goto_stat(&mut self, name: &str, line: u64, pc: i32) -> CodegenResult<()> {
+        let g = self.new_label_entry(false, name, line, pc);
+        self.find_label(g)?;
+        Ok(())
+    }
+
+    /// Compiles a condition, returning the jumps taken when it is false.
+    fn cond(&mut self, cond: &Expr) -> CodegenResult<i32> {
+        let mut v = self.expr(cond)?;
+        if v.kind == ExpKind::Nil {
+            v.kind = ExpKind::False;
+        }
+        self.fs().go_if_true(&mut v);
+        Ok(v.f)
+    }
+
+    fn test_then_block(
+        &mut self,
+        cond: &Expr,
+        block: &Block,
+        followed: bool,
+        escape_list: &mut i32,
+    ) -> CodegenResult<()> {
+        let mut v = self.expr(cond)?;
+        self.set_line(block.span.line);
+        let (jf, rest) = match block.stats.first().map(|s| (&s.kind, s)) {
+            Some((StatKind::Break | StatKind::Goto(_), first)) => {
+                self.fs().go_if_false(&mut v);
+                self.enter_block(false);
+                let name = match &first.kind {
+                    StatKind::Goto(label) => label.name.as_str(),
+                    _ => "break",
+                };
+                self.set_line(first.span.end_line);
+                self.goto_stat(name, first.span.line, v.t)?;
+                if block.stats.len() == 1 {
+                    return self.leave_block();
+                }
+                (self.fs().jump(), &block.stats[1..])
+            }
+            _ => {
+                self.fs().go_if_true(&mut v);
+                self.enter_block(false);
+                (v.f, &block.stats[..])
+            }
+        };
+        self.stat_list(rest, false)?;
+        self.set_line(block_end_line(block));
+        self.leave_block()?;
+        if followed {
+            let j = self.fs().jump();
+            let mut list = *escape_list;
+            self.fs().concat(&mut list, j);
+            *escape_list = list;
+        }
+        self.fs().patch_to_here(jf);
+        Ok(())
+    }
+
+    fn exp1(&mut self, e: &Expr) -> CodegenResult<()> {
+        let mut e = self.expr(e)?;
+        self.fs().exp_to_next_reg(&mut e);
+        Ok(())
+    }
+
+    fn for_body(
+        &mut self,
+        base: usize,
+        line: u64,
+        nvars: usize,
+        is_numeric: bool,
+        body: &Block,
+    ) -> CodegenResult<()> {
+        self.fs().adjust_local_vars(3);
+        self.set_line(body.span.line);
+        let prep = match is_numeric {
+            true => self.fs().code_asbx(Op::ForPrep, base, NO_JUMP) as i32,
+            false => self.fs().jump(),
+        };
+        self.enter_block(false);
+        let fs = self.fs();
+        fs.adjust_local_vars(nvars);
+        fs.reserve_regs(nvars);
+        self.block(body)?;
+        self.leave_block()?;
+        let fs = self.fs();
+        fs.patch_to_here(prep);
+        let end_for = if is_numeric {
+            fs.code_asbx(Op::ForLoop, base, NO_JUMP)
+        } else {
+            fs.code_abc(Op::TForCall, base, 0, nvars);
+            fs.fix_line(line);
+            fs.code_asbx(Op::TForLoop, base + 2, NO_JUMP)
+        };
+        fs.patch_list(end_for as i32, prep as usize + 1);
+        fs.fix_line(line);
+        Ok(())
+    }
+
+    fn local_stat(&mut self, names: &[Name], exprs: &[Expr]) -> CodegenResult<()> {
+        for name in names {
+            self.set_line(name.span.end_line);
+            self.new_local_var(&name.name)?;
+        }
+        let e = match exprs.is_empty() {
+            true => ExpDesc::new(ExpKind::Void),
+            false => self.expr_list(exprs)?,
+        };
+        self.adjust_assign(names.len(), exprs.len(), e);
+        self.fs().adjust_local_vars(names.len());
+        Ok(())
+    }
+
+    fn adjust_assign(&mut self, nvars: usize, nexps: usize, mut e: ExpDesc) {
+        let fs = self.fs();
+        let mut extra = nvars as i32 - nexps as i32;
+        if e.has_mult_ret() {
+            extra = (extra + 1).max(0);
+            fs.set_returns(&e, extra);
+            if extra > 1 {
+                fs.reserve_regs(extra as usize - 1);
+            }
+        } else {
+            if e.kind != ExpKind::Void {
+                fs.exp_to_next_reg(&mut e);
+            }
+            if extra > 0 {
+                let reg = fs.free_reg;
+                fs.reserve_regs(extra as usize);
+                fs.nil(reg, extra as usize);
+            }
+        }
+        if nexps > nvars {
+            fs.free_reg -= nexps - nvars;
+        }
+    }
+
+    /// Makes a previous target of a multiple assignment use a copy of the
+    /// local or upvalue `v` if it indexes through it, since `v` is assigned
+    /// first.
+    fn check_conflict(&mut self, targets: &mut [ExpDesc], v: &ExpDesc) {
+        let fs = self.fs();
+        let extra = fs.free_reg;
+        let mut conflict = false;
+        for target in targets.iter_mut() {
+            if let ExpKind::Indexed {
+                table,
+                key,
+                table_is_upvalue,
+            } = &mut target.kind
+            {
+                let same_table = match v.kind {
+                    ExpKind::Local(reg) => !*table_is_upvalue && *table == reg,
+                    ExpKind::Upval(index) => *table_is_upvalue && *table == index,
+                    _ => false,
+                };
+                if same_table {
+                    conflict = true;
+                    *table_is_upvalue = false;
+                    *table = extra;
+                }
+                if v.kind == ExpKind::Local(*key) {
+                    conflict = true;
+                    *key = extra;
+                }
+            }
+        }
+        if conflict {
+            match v.kind {
+                ExpKind::Local(reg) => fs.code_abc(Op::Move, extra, reg, 0),
+                _ => fs.code_abc(Op::GetUpval, extra, v.info(), 0),
+            };
+            fs.reserve_regs(1);
+        }
+    }
+
+    fn assignment(&mut self, targets: &[Expr], exprs: &[Expr]) -> CodegenResult<()> {
+        let mut vars: Vec<ExpDesc> = vec![];
+        for target in targets {
+            let v = self.expr(target)?;
+            if !vars.is_empty() && !matches!(v.kind, ExpKind::Indexed { .. }) {
+                self.check_conflict(&mut vars, &v);
+            }
+            vars.push(v);
+        }
+        let mut e = self.expr_list(exprs)?;
+        let mut stores = vars.iter().rev();
+        if exprs.len() == vars.len() {
+            let fs = self.fs();
+            fs.set_one_ret(&mut e);
+            fs.store_var(stores.next().unwrap(), &mut e);
+        } else {
+            self.adjust_assign(vars.len(), exprs.len(), e);
+        }
+        for var in stores {
+            let fs = self.fs();
+            let mut e = ExpDesc::new(ExpKind::NonReloc(fs.free_reg - 1));
+            fs.store_var(var, &mut e);
+        }
+        Ok(())
+    }
+
+    fn return_stat(&mut self, exprs: &[Expr]) -> CodegenResult<()> {
+        let (first, nret) = if exprs.is_empty() {
+            (0, 0)
+        } else {
+            let mut e = self.expr_list(exprs)?;
+            let fs = self.fs();
+            if e.has_mult_ret() {
+                fs.set_mult_ret(&e);
+                if let (ExpKind::Call(pc), 1) = (e.kind, exprs.len()) {
+                    fs.f.code[pc].set_op(Op::Tailcall);
+                }
+                (fs.nactvar, MULT_RET)
+            } else if exprs.len() == 1 {
+                (fs.exp_to_any_reg(&mut e), 1)
+            } else {
+                fs.exp_to_next_reg(&mut e);
+                (fs.nactvar, exprs.len() as i32)
+            }
+        };
+        self.fs().ret(first, nret);
+        Ok(())
+    }
+
+    /// Compiles a function body into a new prototype, returning the closure
+    /// in the next register.
+    fn body(&mut self, body: &FunctionBody, is_method: bool, line: u64) -> CodegenResult<ExpDesc> {
+        let parent_line = self.fs().line;
+        self.open_function(line, parent_line);
+        let params = &body.params;
+        if is_method {
+            self.new_local_var("self")?;
+            self.fs().adjust_local_vars(1);
+        }
+        let named = &params[is_method as usize..];
+        for param in named {
+            self.new_local_var(&param.name)?;
+        }
+        let fs = self.fs();
+        fs.adjust_local_vars(named.len());
+        fs.f.num_params = fs.nactvar as u8;
+        if body.is_vararg {
+            fs.f.vararg_info = Some(VarArgInfo {});
+        }
+        fs.reserve_regs(fs.nactvar);
+        self.set_line(body.body.span.line);
+        self.stat_list(&body.body.stats, false)?;
+        let fs = self.fs();
+        fs.f.last_line_defined = body.span.end_line;
+        fs.line = body.span.end_line;
+        let chunk = self.close_function()?;
+        let fs = self.fs();
+        fs.f.prototypes.push(chunk);
+        let index = fs.f.prototypes.len() - 1;
+        let mut e = ExpDesc::new(ExpKind::Relocable(fs.code_abx(Op::Closure, 0, index)));
+        fs.exp_to_next_reg(&mut e);
+        Ok(e)
+    }
+
+    fn expr_list(&mut self, exprs: &[Expr]) -> CodegenResult<ExpDesc> {
+        let mut e = self.expr(&exprs[0])?;
+        for expr in &exprs[1..] {
+            self.fs().exp_to_next_reg(&mut e);
+            e = self.expr(expr)?;
+        }
+        Ok(e)
+    }
+
+    /// `v.name` or `v:name`
+    fn field_sel(&mut self, v: &mut ExpDesc, name: &Name) {
+        let fs = self.fs();
+        fs.exp_to_any_reg_up(v);
+        fs.line = name.span.end_line;
+        let mut key = ExpDesc::new(ExpKind::K(fs.string_constant(name.name.as_bytes())));
+        fs.indexed(v, &mut key);
+    }
+
+    fn constructor(&mut self, fields: &[Field], expr: &Expr) -> CodegenResult<ExpDesc> {
+        let fs = self.fs();
+        let pc = fs.code_abc(Op::NewTable, 0, 0, 0);
+        let mut t = ExpDesc::new(ExpKind::Relocable(pc));
+        fs.exp_to_next_reg(&mut t);
+        fs.line = expr.span.line;
+        let table = t.info();
+        let (mut na, mut nh, mut to_store) = (0, 0, 0);
+        let mut v = ExpDesc::new(ExpKind::Void);
+        for field in fields {
+            if v.kind != ExpKind::Void {
+                let fs = self.fs();
+                fs.exp_to_next_reg(&mut v);
+                v = ExpDesc::new(ExpKind::Void);
+                if to_store == FIELDS_PER_FLUSH {
+                    fs.set_list(table, na, to_store as i32);
+                    to_store = 0;
+                }
+            }
+            match field {
+                Field::Positional(value) => {
+                    v = self.expr(value)?;
+                    na += 1;
+                    to_store += 1;
+                }
+                Field::Named { name, value } => {
+                    let fs = self.fs();
+                    let reg = fs.free_reg;
+                    fs.line = name.span.end_line;
+                    let mut key =
+                        ExpDesc::new(ExpKind::K(fs.string_constant(name.name.as_bytes())));
+                    nh += 1;
+                    let rk_key = fs.exp_to_rk(&mut key);
+                    self.rec_field_value(table, rk_key, value, reg)?;
+                }
+                Field::Keyed { key, value } => {
+                    let reg = self.fs().free_reg;
+                    let mut key = self.expr(key)?;
+                    let fs = self.fs();
+                    fs.exp_to_val(&mut key);
+                    nh += 1;
+                    let rk_key = fs.exp_to_rk(&mut key);
+                    self.rec_field_value(table, rk_key, value, reg)?;
+                }
+            }
+        }
+        let fs = self.fs();
+        fs.line = expr.span.end_line;
+        if to_store > 0 {
+            if v.has_mult_ret() {
+                fs.set_mult_ret(&v);
+                fs.set_list(table, na, MULT_RET);
+                na -= 1;
+            } else {
+                if v.kind != ExpKind::Void {
+                    fs.exp_to_next_reg(&mut v);
+                }
+                fs.set_list(table, na, to_store as i32);
+            }
+        }
+        fs.f.code[pc].set_b(int_to_fb(na) as u16);
+        fs.f.code[pc].set_c(int_to_fb(nh) as u16);
+        Ok(t)
+    }
+
+    fn rec_field_value(
+        &mut self,
+        table: usize,
+        rk_key: usize,
+        value: &Expr,
+        reg: usize,
+    ) -> CodegenResult<()> {
+        let mut val = self.expr(value)?;
+        let fs = self.fs();
+        let rk_value = fs.exp_to_rk(&mut val);
+        fs.code_abc(Op::SetTable, table, rk_key, rk_value);
+        fs.free_reg = reg;
+        Ok(())
+    }
+
+    /// Compiles the arguments of a call to the function in `f` and the
+    /// call itself.
+    fn call_args(
+        &mut self,
+        f: &mut ExpDesc,
+        args: &[Expr],
+        style: CallStyle,
+        call: &Expr,
+    ) -> CodegenResult<()> {
+        let args = match style {
+            CallStyle::Parens if args.is_empty() => ExpDesc::new(ExpKind::Void),
+            CallStyle::Parens => {
+                let e = self.expr_list(args)?;
+                self.fs().set_mult_ret(&e);
+                e
+            }
+            CallStyle::Table | CallStyle::String => self.expr(&args[0])?,
+        };
+        let mut args = args;
+        let fs = self.fs();
+        fs.line = call.span.end_line;
+        let base = f.info();
+        let nparams = if args.has_mult_ret() {
+            MULT_RET
+        } else {
+            if args.kind != ExpKind::Void {
+                fs.exp_to_next_reg(&mut args);
+            }
+            (fs.free_reg - (base + 1)) as i32
+        };
+        f.kind = ExpKind::Call(fs.code_abc(Op::Call, base, (nparams + 1) as usize, 2));
+        fs.fix_line(call.span.line);
+        fs.free_reg = base + 1;
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: &Expr) -> CodegenResult<ExpDesc> {
+        match &expr.kind {
+            ExprKind::Function(body) => self.body(body, false, body.span.line),
+            ExprKind::Table(fields) => self.constructor(fields, expr),
+            ExprKind::Name(name) => {
+                self.set_line(expr.span.end_line);
+                self.single_var(name)
+            }
+            ExprKind::Field { .. }
+            | ExprKind::Index { .. }
+            | ExprKind::Call { .. }
+            | ExprKind::Method { .. } => self.suffixed_expr(expr),
+            ExprKind::Paren(inner) => self.paren_expr(inner, expr.span.end_line),
+            ExprKind::Binary { .. } => self.binary(expr),
+            ExprKind::Unary {
+                op,
+                op_line,
+                operand,
+            } => self.unary(*op, *op_line, operand),
+            _ => self.simple_expr(expr),
+        }
+    }
+
+    fn simple_expr(&mut self, expr: &Expr) -> CodegenResult<ExpDesc> {
+        let kind = match &expr.kind {
+            ExprKind::Nil => ExpKind::Nil,
+            ExprKind::True => ExpKind::True,
+            ExprKind::False => ExpKind::False,
+            ExprKind::Integer(i) => ExpKind::KInt(*i),
+            ExprKind::Float(f) => ExpKind::KFlt(*f),
+            ExprKind::String(s) => {
+                self.set_line(expr.span.end_line);
+                ExpKind::K(self.fs().string_constant(s))
+            }
+            ExprKind::VarArg => ExpKind::VarArg(self.fs().code_abc(Op::VarArg, 0, 1, 0)),
+            kind => unreachable!("{:?} is not a simple expression", kind),
+        };
+        self.set_line(expr.span.end_line);
+        Ok(ExpDesc::new(kind))
+    }
+
+    fn paren_expr(&mut self, inner: &Expr, end_line: u64) -> CodegenResult<ExpDesc> {
+        let mut v = self.expr(inner)?;
+        self.set_line(end_line);
+        self.fs().discharge_vars(&mut v);
+        Ok(v)
+    }
+
+    fn unary(&mut self, op: UnOp, op_line: u64, operand: &Expr) -> CodegenResult<ExpDesc> {
+        let mut v = self.expr(operand)?;
+        self.fs().prefix(op, &mut v, op_line);
+        Ok(v)
+    }
+
+    /// Compiles fields, indices and calls iteratively from the primary
+    /// expression they are applied to, as chains of them can be long.
+    fn suffixed_expr(&mut self, expr: &Expr) -> CodegenResult<ExpDesc> {
+        let mut suffixes = vec![];
+        let mut primary = expr;
+        loop {
+            let inner = match &primary.kind {
+                ExprKind::Field { table, .. } | ExprKind::Index { table, .. } => table,
+                ExprKind::Call { func, .. } => func,
+                ExprKind::Method { object, .. } => object,
+                _ => break,
+            };
+            suffixes.push(primary);
+            primary = inner;
+        }
+        let mut v = self.expr(primary)?;
+        for suffix in suffixes.into_iter().rev() {
+            self.suffix(&mut v, suffix)?;
+        }
+        Ok(v)
+    }
+
+    fn suffix(&mut self, v: &mut ExpDesc, suffix: &Expr) -> CodegenResult<()> {
+        match &suffix.kind {
+            ExprKind::Field { name, .. } => self.field_sel(v, name),
+            ExprKind::Index { key, .. } => {
+                self.fs().exp_to_any_reg_up(v);
+                let mut k = self.expr(key)?;
+                self.fs().exp_to_val(&mut k);
+                self.set_line(suffix.span.end_line);
+                self.fs().indexed(v, &mut k);
+            }
+            ExprKind::Call { args, style, .. } => {
+                self.fs().exp_to_next_reg(v);
+                self.call_args(v, args, *style, suffix)?;
+            }
+            ExprKind::Method {
+                name, args, style, ..
+            } => {
+                let fs = self.fs();
+                fs.line = name.span.end_line;
+                let mut key = ExpDesc::new(ExpKind::K(fs.string_constant(name.name.as_bytes())));
+                fs.self_(v, &mut key);
+                self.call_args(v, args, *style, suffix)?;
+            }
+            kind => unreachable!("{:?} is not a suffix", kind),
+        }
+        Ok(())
+    }
+
+    /// Compiles a chain of binary operators iteratively along the left
+    /// operands, which the parser nests without limit.
+    fn binary(&mut self, expr: &Expr) -> CodegenResult<ExpDesc> {
+        let mut spine = vec![];
+        let mut lhs = expr;
+        while let ExprKind::Binary {
+            op,
+            op_line,
+            lhs: left,
+            rhs,
+        } = &lhs.kind
+        {
+            spine.push((*op, *op_line, rhs));
+            lhs = left;
+        }
+        let mut v = self.expr(lhs)?;
+        for (op, op_line, rhs) in spine.into_iter().rev() {
+            self.set_line(op_line);
+            self.fs().infix(op, &mut v);
+            let mut v2 = self.expr(rhs)?;
+            self.fs().posfix(op, &mut v, &mut v2, op_line);
+        }
+        Ok(v)
+    }
+}
+
+/// The line of the last token of a block, or of the token opening it if it
+/// is empty.
+fn block_end_line(block: &Block) -> u64 {
+    block
+        .stats
+        .last()
+        .map_or(block.span.line, |s| s.span.end_line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_int_to_fb() {
+        assert_eq!(0, int_to_fb(0));
+        assert_eq!(7, int_to_fb(7));
+        assert_eq!(8, int_to_fb(8));
+        assert_eq!(17, int_to_fb(18));
+        assert_eq!(29, int_to_fb(50));
+    }
+}
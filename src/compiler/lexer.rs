@@ -0,0 +1,689 @@
+use crate::compiler::ast::Span;
+use crate::compiler::CompileError;
+use crate::number::{is_space, Number};
+use crate::stdlib::utf8;
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    And,
+    Break,
+    Do,
+    Else,
+    Elseif,
+    End,
+    False,
+    For,
+    Function,
+    Goto,
+    If,
+    In,
+    Local,
+    Nil,
+    Not,
+    Or,
+    Repeat,
+    Return,
+    Then,
+    True,
+    Until,
+    While,
+    IDiv,
+    Concat,
+    Dots,
+    Eq,
+    Ge,
+    Le,
+    Ne,
+    Shl,
+    Shr,
+    DbColon,
+    Eof,
+    Float(f64),
+    Integer(i64),
+    Name(String),
+    String(Vec<u8>),
+    /// Single-byte symbols like `+` or `(`.
+    Char(u8),
+}
+
+impl Token {
+    fn reserved(name: &str) -> Option<Token> {
+        Some(match name {
+            "and" => Token::And,
+            "break" => Token::Break,
+            "do" => Token::Do,
+            "else" => Token::Else,
+            "elseif" => Token::Elseif,
+            "end" => Token::End,
+            "false" => Token::False,
+            "for" => Token::For,
+            "function" => Token::Function,
+            "goto" => Token::Goto,
+            "if" => Token::If,
+            "in" => Token::In,
+            "local" => Token::Local,
+            "nil" => Token::Nil,
+            "not" => Token::Not,
+            "or" => Token::Or,
+            "repeat" => Token::Repeat,
+            "return" => Token::Return,
+            "then" => Token::Then,
+            "true" => Token::True,
+            "until" => Token::Until,
+            "while" => Token::While,
+            _ => return None,
+        })
+    }
+}
+
+/// Formats tokens like `luaX_token2str`: symbols and reserved words quoted,
+/// token classes like `<name>` without quotes.
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            Token::And => "and",
+            Token::Break => "break",
+            Token::Do => "do",
+            Token::Else => "else",
+            Token::Elseif => "elseif",
+            Token::End => "end",
+            Token::False => "false",
+            Token::For => "for",
+            Token::Function => "function",
+            Token::Goto => "goto",
+            Token::If => "if",
+            Token::In => "in",
+            Token::Local => "local",
+            Token::Nil => "nil",
+            Token::Not => "not",
+            Token::Or => "or",
+            Token::Repeat => "repeat",
+            Token::Return => "return",
+            Token::Then => "then",
+            Token::True => "true",
+            Token::Until => "until",
+            Token::While => "while",
+            Token::IDiv => "//",
+            Token::Concat => "..",
+            Token::Dots => "...",
+            Token::Eq => "==",
+            Token::Ge => ">=",
+            Token::Le => "<=",
+            Token::Ne => "~=",
+            Token::Shl => "<<",
+            Token::Shr => ">>",
+            Token::DbColon => "::",
+            Token::Eof => return write!(f, "<eof>"),
+            Token::Float(_) => return write!(f, "<number>"),
+            Token::Integer(_) => return write!(f, "<integer>"),
+            Token::Name(_) => return write!(f, "<name>"),
+            Token::String(_) => return write!(f, "<string>"),
+            Token::Char(c) if c.is_ascii_graphic() || *c == b' ' => {
+                return write!(f, "'{}'", *c as char)
+            }
+            Token::Char(c) => return write!(f, "'<\\{}>'", c),
+        };
+        write!(f, "'{}'", symbol)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Lexeme {
+    pub token: Token,
+    pub span: Span,
+    /// How the token is shown after `near` in syntax errors.
+    pub near: String,
+}
+
+/// Splits Lua source into tokens, following `llex.c` down to its error
+/// messages.
+pub struct Lexer<'a> {
+    source: &'a [u8],
+    chunk_name: &'a str,
+    pos: usize,
+    line: u64,
+    line_start: usize,
+    /// The text of the token being read, as `near` shows it in errors.
+    buffer: Vec<u8>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a [u8], chunk_name: &'a str) -> Lexer<'a> {
+        Lexer {
+            source,
+            chunk_name,
+            pos: 0,
+            line: 1,
+            line_start: 0,
+            buffer: vec![],
+        }
+    }
+
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    /// The offset of the next byte to read.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn next_token(&mut self) -> Result<Lexeme, CompileError> {
+        let (start, line, column) = self.skip_whitespace()?;
+        self.buffer.clear();
+        let token = self
+            .read_token()
+            .inspect_err(|_| self.skip_bad_string(start))?;
+        let near = match token {
+            Token::Name(_) | Token::String(_) | Token::Float(_) | Token::Integer(_) => {
+                format!("'{}'", String::from_utf8_lossy(&self.buffer))
+            }
+            _ => token.to_string(),
+        };
+        Ok(Lexeme {
+            token,
+            span: Span {
+                start,
+                end: self.pos,
+                line,
+                column,
+                end_line: self.line,
+                end_column: self.column(),
+            },
+            near,
+        })
+    }
+
+    /// Moves past the rest of a string with a bad escape sequence, so that
+    /// lexing resumes after the string instead of inside it.
+    fn skip_bad_string(&mut self, start: usize) {
+        let delimiter = match self.source.get(start) {
+            Some(&c @ (b'"' | b'\'')) => c,
+            _ => return,
+        };
+        // the bad character may have been the closing delimiter
+        if self.pos > start + 1 && self.source[self.pos - 1] == delimiter {
+            return;
+        }
+        while let Some(c) = self.current() {
+            if self.current_is_newline() {
+                return;
+            }
+            self.advance();
+            if c == delimiter {
+                return;
+            }
+            if c == b'\\' && !self.current_is_newline() {
+                self.advance();
+            }
+        }
+    }
+
+    fn current(&self) -> Option<u8> {
+        self.source.get(self.pos).copied()
+    }
+
+    fn column(&self) -> u64 {
+        (self.pos - self.line_start) as u64 + 1
+    }
+
+    fn advance(&mut self) {
+        self.pos += 1;
+    }
+
+    fn save(&mut self, c: u8) {
+        self.buffer.push(c);
+    }
+
+    fn save_and_next(&mut self) {
+        if let Some(c) = self.current() {
+            self.save(c);
+        }
+        self.advance();
+    }
+
+    fn current_is_newline(&self) -> bool {
+        matches!(self.current(), Some(b'\n' | b'\r'))
+    }
+
+    /// Skips `\n`, `\r`, `\n\r` or `\r\n`.
+    fn increment_line(&mut self) {
+        let old = self.current();
+        self.advance();
+        if self.current_is_newline() && self.current() != old {
+            self.advance();
+        }
+        self.line += 1;
+        self.line_start = self.pos;
+    }
+
+    fn check_next(&mut self, c: u8) -> bool {
+        if self.current() == Some(c) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn check_next_saved(&mut self, set: &[u8; 2]) -> bool {
+        match self.current() {
+            Some(c) if set.contains(&c) => {
+                self.save_and_next();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn error(&self, message: &str, near: Option<&str>) -> CompileError {
+        let message = match near {
+            Some(near) => format!("{} near {}", message, near),
+            None => message.to_owned(),
+        };
+        CompileError::new(self.chunk_name, self.line, message)
+    }
+
+    fn error_near_buffer(&self, message: &str) -> CompileError {
+        let near = format!("'{}'", String::from_utf8_lossy(&self.buffer));
+        self.error(message, Some(&near))
+    }
+
+    /// Skips whitespace and comments, returning where the next token starts.
+    fn skip_whitespace(&mut self) -> Result<(usize, u64, u64), CompileError> {
+        loop {
+            match self.current() {
+                Some(b'\n' | b'\r') => self.increment_line(),
+                Some(b' ' | b'\x0c' | b'\t' | b'\x0b') => self.advance(),
+                Some(b'-') if self.source.get(self.pos + 1) == Some(&b'-') => {
+                    self.pos += 2;
+                    if self.current() == Some(b'[') {
+                        let sep = self.skip_separator();
+                        self.buffer.clear();
+                        if sep >= 2 {
+                            self.read_long_string(sep, false)?;
+                            self.buffer.clear();
+                            continue;
+                        }
+                    }
+                    while !self.current_is_newline() && self.current().is_some() {
+                        self.advance();
+                    }
+                }
+                _ => return Ok((self.pos, self.line, self.column())),
+            }
+        }
+    }
+
+    fn read_token(&mut self) -> Result<Token, CompileError> {
+        let c = match self.current() {
+            Some(c) => c,
+            None => return Ok(Token::Eof),
+        };
+        Ok(match c {
+            b'[' => {
+                let sep = self.skip_separator();
+                if sep >= 2 {
+                    return Ok(Token::String(self.read_long_string(sep, true)?));
+                } else if sep == 0 {
+                    return Err(self.error_near_buffer("invalid long string delimiter"));
+                }
+                Token::Char(b'[')
+            }
+            b'=' => {
+                self.advance();
+                match self.check_next(b'=') {
+                    true => Token::Eq,
+                    false => Token::Char(b'='),
+                }
+            }
+            b'<' => {
+                self.advance();
+                if self.check_next(b'=') {
+                    Token::Le
+                } else if self.check_next(b'<') {
+                    Token::Shl
+                } else {
+                    Token::Char(b'<')
+                }
+            }
+            b'>' => {
+                self.advance();
+                if self.check_next(b'=') {
+                    Token::Ge
+                } else if self.check_next(b'>') {
+                    Token::Shr
+                } else {
+                    Token::Char(b'>')
+                }
+            }
+            b'/' => {
+                self.advance();
+                match self.check_next(b'/') {
+                    true => Token::IDiv,
+                    false => Token::Char(b'/'),
+                }
+            }
+            b'~' => {
+                self.advance();
+                match self.check_next(b'=') {
+                    true => Token::Ne,
+                    false => Token::Char(b'~'),
+                }
+            }
+            b':' => {
+                self.advance();
+                match self.check_next(b':') {
+                    true => Token::DbColon,
+                    false => Token::Char(b':'),
+                }
+            }
+            b'"' | b'\'' => Token::String(self.read_string(c)?),
+            b'.' => {
+                self.save_and_next();
+                if self.check_next(b'.') {
+                    match self.check_next(b'.') {
+                        true => Token::Dots,
+                        false => Token::Concat,
+                    }
+                } else if !matches!(self.current(), Some(b'0'..=b'9')) {
+                    Token::Char(b'.')
+                } else {
+                    self.read_numeral()?
+                }
+            }
+            b'0'..=b'9' => self.read_numeral()?,
+            c if c.is_ascii_alphabetic() || c == b'_' => {
+                while let Some(c) = self.current() {
+                    if !(c.is_ascii_alphanumeric() || c == b'_') {
+                        break;
+                    }
+                    self.save_and_next();
+                }
+                let name = String::from_utf8(self.buffer.clone()).unwrap();
+                Token::reserved(&name).unwrap_or(Token::Name(name))
+            }
+            c => {
+                self.advance();
+                Token::Char(c)
+            }
+        })
+    }
+
+    fn read_numeral(&mut self) -> Result<Token, CompileError> {
+        let first = self.current();
+        let mut exponent = b"Ee";
+        self.save_and_next();
+        if first == Some(b'0') && self.check_next_saved(b"xX") {
+            exponent = b"Pp";
+        }
+        loop {
+            if self.check_next_saved(exponent) {
+                self.check_next_saved(b"-+");
+            }
+            match self.current() {
+                Some(c) if c.is_ascii_hexdigit() || c == b'.' => self.save_and_next(),
+                _ => break,
+            }
+        }
+        match Number::parse(&self.buffer) {
+            Some(Number::Integer(i)) => Ok(Token::Integer(i)),
+            Some(Number::Float(f)) => Ok(Token::Float(f)),
+            None => Err(self.error_near_buffer("malformed number")),
+        }
+    }
+
+    /// Reads `[=*[` or `]=*]`, leaving the last bracket. Returns the number
+    /// of `=` plus 2 if well formed, 1 if there are no `=` and 0 otherwise.
+    fn skip_separator(&mut self) -> usize {
+        let mut count = 0;
+        let s = self.current();
+        self.save_and_next();
+        while self.current() == Some(b'=') {
+            self.save_and_next();
+            count += 1;
+        }
+        if self.current() == s {
+            count + 2
+        } else if count == 0 {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn read_long_string(&mut self, sep: usize, is_string: bool) -> Result<Vec<u8>, CompileError> {
+        let line = self.line;
+        self.save_and_next();
+        if self.current_is_newline() {
+            self.increment_line();
+        }
+        loop {
+            match self.current() {
+                None => {
+                    let what = if is_string { "string" } else { "comment" };
+                    let message = format!("unfinished long {} (starting at line {})", what, line);
+                    return Err(self.error(&message, Some("<eof>")));
+                }
+                Some(b']') => {
+                    if self.skip_separator() == sep {
+                        self.save_and_next();
+                        break;
+                    }
+                }
+                Some(b'\n' | b'\r') => {
+                    self.save(b'\n');
+                    self.increment_line();
+                    if !is_string {
+                        self.buffer.clear();
+                    }
+                }
+                Some(_) => {
+                    if is_string {
+                        self.save_and_next();
+                    } else {
+                        self.advance();
+                    }
+                }
+            }
+        }
+        Ok(match is_string {
+            true => self.buffer[sep..self.buffer.len() - sep].to_vec(),
+            false => vec![],
+        })
+    }
+
+    fn escape_check(&mut self, ok: bool, message: &str) -> Result<(), CompileError> {
+        if ok {
+            return Ok(());
+        }
+        if self.current().is_some() {
+            self.save_and_next();
+        }
+        Err(self.error_near_buffer(message))
+    }
+
+    fn hex_digit(&mut self) -> Result<u32, CompileError> {
+        self.save_and_next();
+        let digit = self.current().and_then(|c| (c as char).to_digit(16));
+        self.escape_check(digit.is_some(), "hexadecimal digit expected")?;
+        Ok(digit.unwrap())
+    }
+
+    fn read_hex_escape(&mut self) -> Result<u8, CompileError> {
+        let r = (self.hex_digit()? << 4) + self.hex_digit()?;
+        self.buffer.truncate(self.buffer.len() - 2);
+        Ok(r as u8)
+    }
+
+    fn read_utf8_escape(&mut self) -> Result<u32, CompileError> {
+        let mut removed = 4;
+        self.save_and_next();
+        self.escape_check(self.current() == Some(b'{'), "missing '{'")?;
+        let mut r = self.hex_digit()?;
+        loop {
+            self.save_and_next();
+            match self.current().and_then(|c| (c as char).to_digit(16)) {
+                Some(digit) => {
+                    removed += 1;
+                    r = (r << 4) + digit;
+                    self.escape_check(r <= 0x10ffff, "UTF-8 value too large")?;
+                }
+                None => break,
+            }
+        }
+        self.escape_check(self.current() == Some(b'}'), "missing '}'")?;
+        self.advance();
+        self.buffer.truncate(self.buffer.len() - removed);
+        Ok(r)
+    }
+
+    fn read_decimal_escape(&mut self) -> Result<u8, CompileError> {
+        let mut r = 0;
+        let mut digits = 0;
+        while digits < 3 {
+            match self.current() {
+                Some(c @ b'0'..=b'9') => {
+                    r = 10 * r + (c - b'0') as u32;
+                    self.save_and_next();
+                    digits += 1;
+                }
+                _ => break,
+            }
+        }
+        self.escape_check(r <= u8::MAX as u32, "decimal escape too large")?;
+        self.buffer.truncate(self.buffer.len() - digits);
+        Ok(r as u8)
+    }
+
+    fn read_string(&mut self, delimiter: u8) -> Result<Vec<u8>, CompileError> {
+        self.save_and_next();
+        while self.current() != Some(delimiter) {
+            match self.current() {
+                None => return Err(self.error("unfinished string", Some("<eof>"))),
+                Some(b'\n' | b'\r') => return Err(self.error_near_buffer("unfinished string")),
+                Some(b'\\') => {
+                    self.save_and_next();
+                    let c = match self.current() {
+                        Some(b'a') => b'\x07',
+                        Some(b'b') => b'\x08',
+                        Some(b'f') => b'\x0c',
+                        Some(b'n') => b'\n',
+                        Some(b'r') => b'\r',
+                        Some(b't') => b'\t',
+                        Some(b'v') => b'\x0b',
+                        Some(b'x') => self.read_hex_escape()?,
+                        Some(b'u') => {
+                            let code = self.read_utf8_escape()?;
+                            utf8::encode(code, &mut self.buffer);
+                            continue;
+                        }
+                        Some(b'\n' | b'\r') => {
+                            self.increment_line();
+                            self.buffer.pop();
+                            self.save(b'\n');
+                            continue;
+                        }
+                        Some(c @ (b'\\' | b'"' | b'\'')) => c,
+                        None => continue,
+                        Some(b'z') => {
+                            self.buffer.pop();
+                            self.advance();
+                            while let Some(c) = self.current() {
+                                if !is_space(c) {
+                                    break;
+                                }
+                                if self.current_is_newline() {
+                                    self.increment_line();
+                                } else {
+                                    self.advance();
+                                }
+                            }
+                            continue;
+                        }
+                        Some(c) => {
+                            self.escape_check(c.is_ascii_digit(), "invalid escape sequence")?;
+                            let c = self.read_decimal_escape()?;
+                            self.buffer.pop();
+                            self.save(c);
+                            continue;
+                        }
+                    };
+                    self.advance();
+                    self.buffer.pop();
+                    self.save(c);
+                }
+                Some(_) => self.save_and_next(),
+            }
+        }
+        self.save_and_next();
+        Ok(self.buffer[1..self.buffer.len() - 1].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Result<Vec<Token>, String> {
+        let mut lexer = Lexer::new(source.as_bytes(), "=test");
+        let mut tokens = vec![];
+        loop {
+            match lexer.next_token() {
+                Ok(lexeme) if lexeme.token == Token::Eof => return Ok(tokens),
+                Ok(lexeme) => tokens.push(lexeme.token),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(
+            Ok(vec![
+                Token::Local,
+                Token::Name("x".to_owned()),
+                Token::Char(b'='),
+                Token::Integer(0x10),
+                Token::IDiv,
+                Token::Float(2.5),
+                Token::Concat,
+                Token::String(b"a\nb".to_vec()),
+                Token::Dots,
+                Token::Float(0.5),
+            ]),
+            tokens("local x = 0x10 // 2.5 -- comment\n .. [[\na\nb]] ... .5 --[==[ long\n]==]")
+        );
+        assert_eq!(
+            Ok(vec![Token::String(
+                b"\x41\xe2\x82\xac\n\x07\x0c77".to_vec()
+            )]),
+            tokens("'\\x41\\u{20AC}\\\n\\a\\z  \n \\f\\0557'")
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            Err("test:1: invalid escape sequence near '\"abc\\q'".to_owned()),
+            tokens("\"abc\\q\"")
+        );
+        assert_eq!(
+            Err("test:1: malformed number near '3..2'".to_owned()),
+            tokens("3..2")
+        );
+        assert_eq!(
+            Err("test:2: unfinished string near '\"ab'".to_owned()),
+            tokens("\n\"ab\n")
+        );
+        assert_eq!(
+            Err("test:3: unfinished long comment (starting at line 1) near <eof>".to_owned()),
+            tokens("--[[\n\n")
+        );
+        assert_eq!(
+            Err("test:1: decimal escape too large near '\"\\256\"'".to_owned()),
+            tokens("\"\\256\"")
+        );
+    }
+}
@@ -0,0 +1,48 @@
+use std::fmt::{Display, Formatter};
+
+use crate::compiler::ast::Block;
+use crate::file::chunk::Chunk;
+use crate::file::debug::short_source;
+
+pub mod ast;
+mod codegen;
+mod lexer;
+mod parser;
+pub mod printer;
+
+/// A syntax or limit error, reported like the reference compiler does.
+#[derive(Debug, Eq, PartialEq)]
+pub struct CompileError {
+    pub short_src: String,
+    pub line: u64,
+    pub message: String,
+}
+
+impl CompileError {
+    fn new(chunk_name: &str, line: u64, message: String) -> CompileError {
+        CompileError {
+            short_src: short_source(chunk_name),
+            line,
+            message,
+        }
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.short_src, self.line, self.message)
+    }
+}
+
+/// Parses Lua 5.3 source into the block of its main function. On failure,
+/// all syntax errors are returned in source order.
+pub fn parse(source: &[u8], chunk_name: &str) -> Result<Block, Vec<CompileError>> {
+    parser::Parser::new(source, chunk_name).parse_chunk()
+}
+
+/// Compiles Lua 5.3 source into the main function of a chunk, producing
+/// the same code as `luac`.
+pub fn compile(source: &[u8], chunk_name: &str) -> Result<Chunk, CompileError> {
+    let block = parse(source, chunk_name).map_err(|mut errors| errors.remove(0))?;
+    codegen::generate(&block, chunk_name)
+}
@@ -0,0 +1,1027 @@
+use crate::compiler::ast::{
+    BinOp, Block, CallStyle, Expr, ExprKind, Field, FunctionBody, FunctionName, Name, Span, Stat,
+    StatKind, UnOp,
+};
+use crate::compiler::lexer::{Lexeme, Lexer, Token};
+use crate::compiler::CompileError;
+
+/// Nesting limit for statements and expressions, `LUAI_MAXCCALLS`.
+const MAX_C_LEVELS: usize = 200;
+
+type ParseResult<T> = Result<T, CompileError>;
+
+struct FunctionInfo {
+    line_defined: u64,
+    is_vararg: bool,
+}
+
+/// A recursive descent parser following `lparser.c`, building an AST
+/// instead of emitting code as it goes.
+pub struct Parser<'a> {
+    lexer: Lexer<'a>,
+    chunk_name: &'a str,
+    current: Lexeme,
+    lookahead: Option<Lexeme>,
+    /// The span of the last consumed token.
+    previous: Span,
+    depth: usize,
+    /// Blocks whose `end` has not been reached yet.
+    unclosed: usize,
+    functions: Vec<FunctionInfo>,
+    errors: Vec<CompileError>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(source: &'a [u8], chunk_name: &'a str) -> Parser<'a> {
+        let start = Span {
+            line: 1,
+            column: 1,
+            end_line: 1,
+            end_column: 1,
+            ..Span::default()
+        };
+        Parser {
+            lexer: Lexer::new(source, chunk_name),
+            chunk_name,
+            current: Lexeme {
+                token: Token::Eof,
+                span: start,
+                near: String::new(),
+            },
+            lookahead: None,
+            previous: start,
+            depth: 0,
+            unclosed: 0,
+            functions: vec![],
+            errors: vec![],
+        }
+    }
+
+    /// Parses a whole chunk, the body of the main function. Parsing goes
+    /// on after a syntax error, so that all of them are reported in
+    /// source order; the first one is what `luac` reports.
+    pub fn parse_chunk(mut self) -> Result<Block, Vec<CompileError>> {
+        self.skip();
+        self.previous = self.current.span;
+        self.functions.push(FunctionInfo {
+            line_defined: 0,
+            is_vararg: true,
+        });
+        let mut block = self.block();
+        while !self.is(&Token::Eof) {
+            self.errors.push(self.error_expected(&Token::Eof));
+            self.skip();
+            block.stats.extend(self.block().stats);
+        }
+        match self.errors.is_empty() {
+            true => Ok(block),
+            false => Err(self.errors),
+        }
+    }
+
+    /// Moves to the next token, recording lexical errors until one is read.
+    fn skip(&mut self) {
+        while let Err(e) = self.next() {
+            self.errors.push(e);
+        }
+    }
+
+    /// Skips tokens up to one that starts or ends a statement. A name at
+    /// the start of a line is taken to start one too.
+    fn synchronize(&mut self) {
+        while !self.block_follow(true) && !self.starts_statement() {
+            if matches!(self.current.token, Token::Name(_))
+                && self.current.span.line > self.previous.end_line
+            {
+                break;
+            }
+            self.skip();
+        }
+    }
+
+    /// Skips tokens up to and including the `end` closing the outermost of
+    /// `n` unclosed blocks.
+    fn skip_to_end(&mut self, mut n: usize) {
+        while n > 0 && !self.is(&Token::Eof) {
+            match self.current.token {
+                Token::If | Token::Do | Token::Function => n += 1,
+                Token::End => n -= 1,
+                _ => {}
+            }
+            self.skip();
+        }
+    }
+
+    fn starts_statement(&self) -> bool {
+        matches!(
+            self.current.token,
+            Token::If
+                | Token::While
+                | Token::Do
+                | Token::For
+                | Token::Repeat
+                | Token::Function
+                | Token::Local
+                | Token::DbColon
+                | Token::Return
+                | Token::Break
+                | Token::Goto
+                | Token::Char(b';')
+        )
+    }
+
+    fn next(&mut self) -> ParseResult<()> {
+        self.previous = self.current.span;
+        self.current = match self.lookahead.take() {
+            Some(lexeme) => lexeme,
+            None => self.lexer.next_token()?,
+        };
+        Ok(())
+    }
+
+    fn peek(&mut self) -> ParseResult<&Token> {
+        if self.lookahead.is_none() {
+            self.lookahead = Some(self.lexer.next_token()?);
+        }
+        Ok(&self.lookahead.as_ref().unwrap().token)
+    }
+
+    fn is(&self, token: &Token) -> bool {
+        self.current.token == *token
+    }
+
+    fn is_char(&self, c: u8) -> bool {
+        self.current.token == Token::Char(c)
+    }
+
+    fn error(&self, message: &str) -> CompileError {
+        CompileError::new(
+            self.chunk_name,
+            self.lexer.line(),
+            format!("{} near {}", message, self.current.near),
+        )
+    }
+
+    fn error_expected(&self, token: &Token) -> CompileError {
+        self.error(&format!("{} expected", token))
+    }
+
+    fn error_limit(&self, limit: usize, what: &str) -> CompileError {
+        let line = self.functions.last().map_or(0, |f| f.line_defined);
+        let location = match line {
+            0 => "main function".to_owned(),
+            line => format!("function at line {}", line),
+        };
+        self.error(&format!(
+            "too many {} (limit is {}) in {}",
+            what, limit, location
+        ))
+    }
+
+    fn test_next(&mut self, token: &Token) -> ParseResult<bool> {
+        if self.is(token) {
+            self.next()?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn check(&self, token: &Token) -> ParseResult<()> {
+        match self.is(token) {
+            true => Ok(()),
+            false => Err(self.error_expected(token)),
+        }
+    }
+
+    fn check_next(&mut self, token: &Token) -> ParseResult<()> {
+        self.check(token)?;
+        self.next()
+    }
+
+    /// Checks for the token closing `who`, which was opened on `line`.
+    fn check_match(&mut self, what: &Token, who: &Token, line: u64) -> ParseResult<()> {
+        if self.test_next(what)? {
+            return Ok(());
+        }
+        if line == self.lexer.line() {
+            return Err(self.error_expected(what));
+        }
+        Err(self.error(&format!(
+            "{} expected (to close {} at line {})",
+            what, who, line
+        )))
+    }
+
+    /// Checks for the `end` closing `who`, a block opened on `line`.
+    fn check_end(&mut self, who: &Token, line: u64) -> ParseResult<()> {
+        self.check_match(&Token::End, who, line)?;
+        self.unclosed -= 1;
+        Ok(())
+    }
+
+    fn check_name(&mut self) -> ParseResult<Name> {
+        match &self.current.token {
+            Token::Name(name) => {
+                let name = Name {
+                    name: name.clone(),
+                    span: self.current.span,
+                };
+                self.next()?;
+                Ok(name)
+            }
+            _ => Err(self.error_expected(&Token::Name(String::new()))),
+        }
+    }
+
+    fn enter_level(&mut self) -> ParseResult<()> {
+        self.depth += 1;
+        if self.depth > MAX_C_LEVELS {
+            return Err(self.error_limit(MAX_C_LEVELS, "C levels"));
+        }
+        Ok(())
+    }
+
+    fn leave_level(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// A span starting at `start` and ending with the last consumed token.
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous)
+    }
+
+    fn block_follow(&self, with_until: bool) -> bool {
+        match self.current.token {
+            Token::Else | Token::Elseif | Token::End | Token::Eof => true,
+            Token::Until => with_until,
+            _ => false,
+        }
+    }
+
+    /// Parses statements up to the token closing the block, which is left
+    /// unconsumed. A statement with a syntax error is recorded and
+    /// skipped, up to the `end` of any block it opened.
+    fn block(&mut self) -> Block {
+        let mut stats = vec![];
+        let start = self.previous;
+        while !self.block_follow(true) {
+            let is_return = self.is(&Token::Return);
+            let (depth, functions) = (self.depth, self.functions.len());
+            let unclosed = self.unclosed;
+            let position = self.lexer.position();
+            match self.statement() {
+                Ok(stat) => stats.extend(stat),
+                Err(e) => {
+                    self.errors.push(e);
+                    self.depth = depth;
+                    self.functions.truncate(functions);
+                    if self.unclosed > unclosed {
+                        self.skip_to_end(self.unclosed - unclosed);
+                        self.unclosed = unclosed;
+                        continue;
+                    }
+                    if self.lexer.position() == position {
+                        self.skip();
+                    }
+                    self.synchronize();
+                    continue;
+                }
+            }
+            if is_return {
+                break;
+            }
+        }
+        Block {
+            stats,
+            span: Span {
+                start: start.end,
+                end: self.current.span.start,
+                line: start.end_line,
+                column: start.end_column,
+                end_line: self.current.span.line,
+                end_column: self.current.span.column,
+            },
+        }
+    }
+
+    fn statement(&mut self) -> ParseResult<Option<Stat>> {
+        if self.test_next(&Token::Char(b';'))? {
+            return Ok(None);
+        }
+        let start = self.current.span;
+        let line = self.lexer.line();
+        self.enter_level()?;
+        let kind = match self.current.token {
+            Token::If => self.if_stat(line),
+            Token::While => self.while_stat(line),
+            Token::Do => self.do_stat(line),
+            Token::For => self.for_stat(line),
+            Token::Repeat => self.repeat_stat(line),
+            Token::Function => self.function_stat(line),
+            Token::Local => self.local_stat(),
+            Token::DbColon => self.label_stat(),
+            Token::Return => self.return_stat(),
+            Token::Break | Token::Goto => self.goto_stat(),
+            _ => self.expr_stat(),
+        }?;
+        self.leave_level();
+        Ok(Some(Stat {
+            kind,
+            span: self.span_from(start),
+        }))
+    }
+
+    fn while_stat(&mut self, line: u64) -> ParseResult<StatKind> {
+        self.next()?;
+        let cond = self.expr()?;
+        self.check_next(&Token::Do)?;
+        self.unclosed += 1;
+        let body = self.block();
+        self.check_end(&Token::While, line)?;
+        Ok(StatKind::While { cond, body })
+    }
+
+    fn do_stat(&mut self, line: u64) -> ParseResult<StatKind> {
+        self.next()?;
+        self.unclosed += 1;
+        let body = self.block();
+        self.check_end(&Token::Do, line)?;
+        Ok(StatKind::Do(body))
+    }
+
+    fn repeat_stat(&mut self, line: u64) -> ParseResult<StatKind> {
+        self.next()?;
+        let body = self.block();
+        self.check_match(&Token::Until, &Token::Repeat, line)?;
+        let cond = self.expr()?;
+        Ok(StatKind::Repeat { body, cond })
+    }
+
+    fn function_stat(&mut self, line: u64) -> ParseResult<StatKind> {
+        self.next()?;
+        self.unclosed += 1;
+        let name = self.function_name()?;
+        let body = self.function_body(name.method.is_some(), line)?;
+        Ok(StatKind::Function { name, body })
+    }
+
+    fn label_stat(&mut self) -> ParseResult<StatKind> {
+        self.next()?;
+        let name = self.check_name()?;
+        self.check_next(&Token::DbColon)?;
+        Ok(StatKind::Label(name))
+    }
+
+    fn return_stat(&mut self) -> ParseResult<StatKind> {
+        self.next()?;
+        let exprs = match self.block_follow(true) || self.is_char(b';') {
+            true => vec![],
+            false => self.expr_list()?,
+        };
+        self.test_next(&Token::Char(b';'))?;
+        Ok(StatKind::Return(exprs))
+    }
+
+    fn goto_stat(&mut self) -> ParseResult<StatKind> {
+        if self.test_next(&Token::Break)? {
+            return Ok(StatKind::Break);
+        }
+        self.next()?;
+        Ok(StatKind::Goto(self.check_name()?))
+    }
+
+    fn if_stat(&mut self, line: u64) -> ParseResult<StatKind> {
+        self.unclosed += 1;
+        let mut clauses = vec![self.test_then_block()?];
+        while self.is(&Token::Elseif) {
+            clauses.push(self.test_then_block()?);
+        }
+        let else_block = match self.test_next(&Token::Else)? {
+            true => Some(self.block()),
+            false => None,
+        };
+        self.check_end(&Token::If, line)?;
+        Ok(StatKind::If {
+            clauses,
+            else_block,
+        })
+    }
+
+    /// `if cond then block` or `elseif cond then block`
+    fn test_then_block(&mut self) -> ParseResult<(Expr, Block)> {
+        self.next()?;
+        let cond = self.expr()?;
+        self.check_next(&Token::Then)?;
+        let block = self.block();
+        Ok((cond, block))
+    }
+
+    fn for_stat(&mut self, line: u64) -> ParseResult<StatKind> {
+        self.next()?;
+        let var = self.check_name()?;
+        let kind = match self.current.token {
+            Token::Char(b'=') => self.numeric_for(var),
+            Token::Char(b',') | Token::In => self.generic_for(var),
+            _ => Err(self.error("'=' or 'in' expected")),
+        }?;
+        self.check_end(&Token::For, line)?;
+        Ok(kind)
+    }
+
+    fn numeric_for(&mut self, var: Name) -> ParseResult<StatKind> {
+        self.next()?;
+        let start = Box::new(self.expr()?);
+        self.check_next(&Token::Char(b','))?;
+        let limit = Box::new(self.expr()?);
+        let step = match self.test_next(&Token::Char(b','))? {
+            true => Some(Box::new(self.expr()?)),
+            false => None,
+        };
+        self.check_next(&Token::Do)?;
+        self.unclosed += 1;
+        let body = self.block();
+        Ok(StatKind::NumericFor {
+            var,
+            start,
+            limit,
+            step,
+            body,
+        })
+    }
+
+    fn generic_for(&mut self, var: Name) -> ParseResult<StatKind> {
+        let mut names = vec![var];
+        while self.test_next(&Token::Char(b','))? {
+            names.push(self.check_name()?);
+        }
+        self.check_next(&Token::In)?;
+        let exprs = self.expr_list()?;
+        self.check_next(&Token::Do)?;
+        self.unclosed += 1;
+        let body = self.block();
+        Ok(StatKind::GenericFor { names, exprs, body })
+    }
+
+    fn function_name(&mut self) -> ParseResult<FunctionName> {
+        let mut path = vec![self.check_name()?];
+        while self.test_next(&Token::Char(b'.'))? {
+            path.push(self.check_name()?);
+        }
+        let method = match self.test_next(&Token::Char(b':'))? {
+            true => Some(self.check_name()?),
+            false => None,
+        };
+        Ok(FunctionName { path, method })
+    }
+
+    fn local_stat(&mut self) -> ParseResult<StatKind> {
+        self.next()?;
+        if self.test_next(&Token::Function)? {
+            self.unclosed += 1;
+            let name = self.check_name()?;
+            let line = self.lexer.line();
+            let body = self.function_body(false, line)?;
+            return Ok(StatKind::LocalFunction { name, body });
+        }
+        let mut names = vec![self.check_name()?];
+        while self.test_next(&Token::Char(b','))? {
+            names.push(self.check_name()?);
+        }
+        let exprs = match self.test_next(&Token::Char(b'='))? {
+            true => self.expr_list()?,
+            false => vec![],
+        };
+        Ok(StatKind::Local { names, exprs })
+    }
+
+    fn expr_stat(&mut self) -> ParseResult<StatKind> {
+        let expr = self.suffixed_expr()?;
+        if self.is_char(b'=') || self.is_char(b',') {
+            let mut targets = vec![expr];
+            loop {
+                if !is_assignable(targets.last().unwrap()) {
+                    return Err(self.error("syntax error"));
+                }
+                if !self.test_next(&Token::Char(b','))? {
+                    break;
+                }
+                if targets.len() + self.depth > MAX_C_LEVELS {
+                    return Err(self.error_limit(MAX_C_LEVELS, "C levels"));
+                }
+                targets.push(self.suffixed_expr()?);
+            }
+            self.check_next(&Token::Char(b'='))?;
+            let exprs = self.expr_list()?;
+            return Ok(StatKind::Assign { targets, exprs });
+        }
+        match expr.kind {
+            ExprKind::Call { .. } | ExprKind::Method { .. } => Ok(StatKind::Call(expr)),
+            _ => Err(self.error("syntax error")),
+        }
+    }
+
+    fn function_body(&mut self, is_method: bool, line: u64) -> ParseResult<FunctionBody> {
+        let start = self.current.span;
+        self.check_next(&Token::Char(b'('))?;
+        let mut params = vec![];
+        if is_method {
+            params.push(Name {
+                name: "self".to_owned(),
+                span: start,
+            });
+        }
+        let mut is_vararg = false;
+        if !self.is_char(b')') {
+            loop {
+                match self.current.token {
+                    Token::Name(_) => params.push(self.check_name()?),
+                    Token::Dots => {
+                        self.next()?;
+                        is_vararg = true;
+                    }
+                    _ => return Err(self.error("<name> or '...' expected")),
+                }
+                if is_vararg || !self.test_next(&Token::Char(b','))? {
+                    break;
+                }
+            }
+        }
+        self.check_next(&Token::Char(b')'))?;
+        self.functions.push(FunctionInfo {
+            line_defined: line,
+            is_vararg,
+        });
+        let body = self.block();
+        self.functions.pop();
+        self.check_end(&Token::Function, line)?;
+        Ok(FunctionBody {
+            params,
+            is_vararg,
+            body,
+            span: self.span_from(start),
+        })
+    }
+
+    fn expr_list(&mut self) -> ParseResult<Vec<Expr>> {
+        let mut exprs = vec![self.expr()?];
+        while self.test_next(&Token::Char(b','))? {
+            exprs.push(self.expr()?);
+        }
+        Ok(exprs)
+    }
+
+    fn primary_expr(&mut self) -> ParseResult<Expr> {
+        match &self.current.token {
+            Token::Name(_) => {
+                let name = self.check_name()?;
+                Ok(Expr {
+                    kind: ExprKind::Name(name.name),
+                    span: name.span,
+                })
+            }
+            Token::Char(b'(') => self.paren_expr(),
+            _ => Err(self.error("unexpected symbol")),
+        }
+    }
+
+    fn paren_expr(&mut self) -> ParseResult<Expr> {
+        let start = self.current.span;
+        let line = self.lexer.line();
+        self.next()?;
+        let expr = self.expr()?;
+        self.check_match(&Token::Char(b')'), &Token::Char(b'('), line)?;
+        Ok(Expr {
+            kind: ExprKind::Paren(Box::new(expr)),
+            span: self.span_from(start),
+        })
+    }
+
+    fn suffixed_expr(&mut self) -> ParseResult<Expr> {
+        let start = self.current.span;
+        let line = self.lexer.line();
+        let expr = self.primary_expr()?;
+        self.suffixes(expr, start, line)
+    }
+
+    /// Parses the fields, indices and calls following a primary
+    /// expression.
+    fn suffixes(&mut self, mut expr: Expr, start: Span, line: u64) -> ParseResult<Expr> {
+        loop {
+            let table = Box::new(expr);
+            let kind = match self.current.token {
+                Token::Char(b'.') => self.field_suffix(table),
+                Token::Char(b'[') => self.index_suffix(table),
+                Token::Char(b':') => self.method_suffix(table, line),
+                Token::Char(b'(' | b'{') | Token::String(_) => self.call_suffix(table, line),
+                _ => return Ok(*table),
+            }?;
+            expr = Expr {
+                kind,
+                span: self.span_from(start),
+            };
+        }
+    }
+
+    fn field_suffix(&mut self, table: Box<Expr>) -> ParseResult<ExprKind> {
+        self.next()?;
+        let name = self.check_name()?;
+        Ok(ExprKind::Field { table, name })
+    }
+
+    fn index_suffix(&mut self, table: Box<Expr>) -> ParseResult<ExprKind> {
+        let key = Box::new(self.index()?);
+        Ok(ExprKind::Index { table, key })
+    }
+
+    fn method_suffix(&mut self, object: Box<Expr>, line: u64) -> ParseResult<ExprKind> {
+        self.next()?;
+        let name = self.check_name()?;
+        let (args, style) = self.call_args(line)?;
+        Ok(ExprKind::Method {
+            object,
+            name,
+            args,
+            style,
+        })
+    }
+
+    fn call_suffix(&mut self, func: Box<Expr>, line: u64) -> ParseResult<ExprKind> {
+        let (args, style) = self.call_args(line)?;
+        Ok(ExprKind::Call { func, args, style })
+    }
+
+    /// `[expr]`
+    fn index(&mut self) -> ParseResult<Expr> {
+        self.next()?;
+        let expr = self.expr()?;
+        self.check_next(&Token::Char(b']'))?;
+        Ok(expr)
+    }
+
+    fn call_args(&mut self, line: u64) -> ParseResult<(Vec<Expr>, CallStyle)> {
+        match &self.current.token {
+            Token::Char(b'(') => {
+                self.next()?;
+                let args = match self.is_char(b')') {
+                    true => vec![],
+                    false => self.expr_list()?,
+                };
+                self.check_match(&Token::Char(b')'), &Token::Char(b'('), line)?;
+                Ok((args, CallStyle::Parens))
+            }
+            Token::Char(b'{') => Ok((vec![self.constructor()?], CallStyle::Table)),
+            Token::String(s) => {
+                let arg = Expr {
+                    kind: ExprKind::String(s.clone()),
+                    span: self.current.span,
+                };
+                self.next()?;
+                Ok((vec![arg], CallStyle::String))
+            }
+            _ => Err(self.error("function arguments expected")),
+        }
+    }
+
+    fn constructor(&mut self) -> ParseResult<Expr> {
+        let start = self.current.span;
+        let line = self.lexer.line();
+        self.check_next(&Token::Char(b'{'))?;
+        let mut fields = vec![];
+        loop {
+            if self.is_char(b'}') {
+                break;
+            }
+            fields.push(self.field()?);
+            if !self.test_next(&Token::Char(b','))? && !self.test_next(&Token::Char(b';'))? {
+                break;
+            }
+        }
+        self.check_match(&Token::Char(b'}'), &Token::Char(b'{'), line)?;
+        Ok(Expr {
+            kind: ExprKind::Table(fields),
+            span: self.span_from(start),
+        })
+    }
+
+    fn field(&mut self) -> ParseResult<Field> {
+        let is_named =
+            matches!(self.current.token, Token::Name(_)) && *self.peek()? == Token::Char(b'=');
+        match self.current.token {
+            Token::Name(_) if is_named => self.named_field(),
+            Token::Char(b'[') => self.keyed_field(),
+            _ => self.expr().map(Field::Positional),
+        }
+    }
+
+    fn named_field(&mut self) -> ParseResult<Field> {
+        let name = self.check_name()?;
+        self.check_next(&Token::Char(b'='))?;
+        let value = self.expr()?;
+        Ok(Field::Named { name, value })
+    }
+
+    fn keyed_field(&mut self) -> ParseResult<Field> {
+        let key = self.index()?;
+        self.check_next(&Token::Char(b'='))?;
+        let value = self.expr()?;
+        Ok(Field::Keyed { key, value })
+    }
+
+    fn simple_expr(&mut self) -> ParseResult<Expr> {
+        match self.current.token {
+            Token::Char(b'{') => self.constructor(),
+            Token::Function => self.function_expr(),
+            Token::Float(_)
+            | Token::Integer(_)
+            | Token::String(_)
+            | Token::Nil
+            | Token::True
+            | Token::False
+            | Token::Dots => self.literal(),
+            _ => self.suffixed_expr(),
+        }
+    }
+
+    fn function_expr(&mut self) -> ParseResult<Expr> {
+        let start = self.current.span;
+        self.next()?;
+        self.unclosed += 1;
+        let line = self.lexer.line();
+        let body = self.function_body(false, line)?;
+        Ok(Expr {
+            kind: ExprKind::Function(Box::new(body)),
+            span: self.span_from(start),
+        })
+    }
+
+    fn literal(&mut self) -> ParseResult<Expr> {
+        let kind = match &self.current.token {
+            Token::Float(f) => ExprKind::Float(*f),
+            Token::Integer(i) => ExprKind::Integer(*i),
+            Token::String(s) => ExprKind::String(s.clone()),
+            Token::Nil => ExprKind::Nil,
+            Token::True => ExprKind::True,
+            Token::False => ExprKind::False,
+            Token::Dots => {
+                if !self.functions.last().unwrap().is_vararg {
+                    return Err(self.error("cannot use '...' outside a vararg function"));
+                }
+                ExprKind::VarArg
+            }
+            _ => unreachable!("{} is not a literal", self.current.token),
+        };
+        let span = self.current.span;
+        self.next()?;
+        Ok(Expr { kind, span })
+    }
+
+    fn unary_op(&self) -> Option<UnOp> {
+        Some(match self.current.token {
+            Token::Not => UnOp::Not,
+            Token::Char(b'-') => UnOp::Minus,
+            Token::Char(b'~') => UnOp::BNot,
+            Token::Char(b'#') => UnOp::Len,
+            _ => return None,
+        })
+    }
+
+    fn binary_op(&self) -> Option<BinOp> {
+        Some(match self.current.token {
+            Token::Char(b'+') => BinOp::Add,
+            Token::Char(b'-') => BinOp::Sub,
+            Token::Char(b'*') => BinOp::Mul,
+            Token::Char(b'%') => BinOp::Mod,
+            Token::Char(b'^') => BinOp::Pow,
+            Token::Char(b'/') => BinOp::Div,
+            Token::IDiv => BinOp::IDiv,
+            Token::Char(b'&') => BinOp::BAnd,
+            Token::Char(b'|') => BinOp::BOr,
+            Token::Char(b'~') => BinOp::BXor,
+            Token::Shl => BinOp::Shl,
+            Token::Shr => BinOp::Shr,
+            Token::Concat => BinOp::Concat,
+            Token::Ne => BinOp::Ne,
+            Token::Eq => BinOp::Eq,
+            Token::Char(b'<') => BinOp::Lt,
+            Token::Le => BinOp::Le,
+            Token::Char(b'>') => BinOp::Gt,
+            Token::Ge => BinOp::Ge,
+            Token::And => BinOp::And,
+            Token::Or => BinOp::Or,
+            _ => return None,
+        })
+    }
+
+    fn expr(&mut self) -> ParseResult<Expr> {
+        Ok(self.sub_expr(0)?.0)
+    }
+
+    /// Parses an expression whose binary operators bind tighter than
+    /// `limit`, returning it with the first operator that does not.
+    fn sub_expr(&mut self, limit: u8) -> ParseResult<(Expr, Option<BinOp>)> {
+        self.enter_level()?;
+        let start = self.current.span;
+        let expr = match self.unary_op() {
+            Some(op) => self.unary_expr(op)?,
+            None => self.simple_expr()?,
+        };
+        let result = self.binary_exprs(expr, start, limit);
+        self.leave_level();
+        result
+    }
+
+    fn unary_expr(&mut self, op: UnOp) -> ParseResult<Expr> {
+        let start = self.current.span;
+        let op_line = self.lexer.line();
+        self.next()?;
+        let (operand, _) = self.sub_expr(UnOp::PRIORITY)?;
+        Ok(Expr {
+            kind: ExprKind::Unary {
+                op,
+                op_line,
+                operand: Box::new(operand),
+            },
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parses the binary operators following the operand `expr`.
+    fn binary_exprs(
+        &mut self,
+        mut expr: Expr,
+        start: Span,
+        limit: u8,
+    ) -> ParseResult<(Expr, Option<BinOp>)> {
+        let mut op = self.binary_op();
+        while let Some(binary) = op {
+            let (left, right) = binary.priority();
+            if left <= limit {
+                break;
+            }
+            let op_line = self.lexer.line();
+            self.next()?;
+            let (rhs, next) = self.sub_expr(right)?;
+            expr = Expr {
+                kind: ExprKind::Binary {
+                    op: binary,
+                    op_line,
+                    lhs: Box::new(expr),
+                    rhs: Box::new(rhs),
+                },
+                span: self.span_from(start),
+            };
+            op = next;
+        }
+        Ok((expr, op))
+    }
+}
+
+fn is_assignable(expr: &Expr) -> bool {
+    matches!(
+        expr.kind,
+        ExprKind::Name(_) | ExprKind::Field { .. } | ExprKind::Index { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<Block, String> {
+        Parser::new(source.as_bytes(), "=test")
+            .parse_chunk()
+            .map_err(|errors| errors[0].to_string())
+    }
+
+    #[test]
+    fn test_precedence() {
+        let block = parse("x = 1 + 2 * 3 ^ -4 .. 'a'").unwrap();
+        let StatKind::Assign { exprs, .. } = &block.stats[0].kind else {
+            panic!("expected an assignment")
+        };
+        let ExprKind::Binary { op, lhs, .. } = &exprs[0].kind else {
+            panic!("expected a binary expression")
+        };
+        assert_eq!(BinOp::Concat, *op);
+        let ExprKind::Binary { op, rhs, .. } = &lhs.kind else {
+            panic!("expected a binary expression")
+        };
+        assert_eq!(BinOp::Add, *op);
+        let ExprKind::Binary { op, rhs, .. } = &rhs.kind else {
+            panic!("expected a binary expression")
+        };
+        assert_eq!(BinOp::Mul, *op);
+        assert!(matches!(rhs.kind, ExprKind::Binary { op: BinOp::Pow, .. }));
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        assert_eq!(
+            Err("test:1: <eof> expected near 'end'".to_owned()),
+            parse("x = 1 end")
+        );
+        assert_eq!(
+            Err("test:3: 'end' expected (to close 'function' at line 1) near <eof>".to_owned()),
+            parse("function f()\n\n")
+        );
+        assert_eq!(
+            Err("test:1: syntax error near <eof>".to_owned()),
+            parse("f")
+        );
+        assert_eq!(
+            Err("test:1: unexpected symbol near '<\\1>'".to_owned()),
+            parse("x = \x01")
+        );
+        assert_eq!(
+            Err("test:1: cannot use '...' outside a vararg function near '...'".to_owned()),
+            parse("function f() return ... end")
+        );
+        assert_eq!(
+            Err("test:1: '=' or 'in' expected near 'do'".to_owned()),
+            parse("for i do end")
+        );
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let errors = |source: &str| {
+            Parser::new(source.as_bytes(), "=test")
+                .parse_chunk()
+                .unwrap_err()
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![
+                "test:1: unexpected symbol near '='",
+                "test:2: <name> expected near '2'",
+                "test:4: unexpected symbol near ')'",
+                "test:6: ')' expected near 'end'",
+                "test:6: <eof> expected near 'end'",
+            ],
+            errors("x = = 1\nlocal 2\nfunction f()\n  y = )\nend\nprint('ok' end")
+        );
+        // a failed compound statement is skipped up to its `end`
+        assert_eq!(
+            vec!["test:1: 'end' expected near 'elseif'"],
+            errors("if x then else elseif y then end")
+        );
+        assert_eq!(
+            vec![
+                "test:1: <name> or '...' expected near ')'",
+                "test:2: unexpected symbol near '='",
+            ],
+            errors("x = function(a,) if a then end end\ny = = 1")
+        );
+        assert_eq!(
+            vec!["test:1: <name> expected near 'end'"],
+            errors("local function end")
+        );
+        // lexing resumes after a bad token
+        assert_eq!(
+            vec!["test:1: hexadecimal digit expected near '\"\\xZ'"],
+            errors("a = \"\\xZZ\"")
+        );
+        assert_eq!(
+            vec![
+                "test:1: invalid escape sequence near ''\\q'",
+                "test:2: unexpected symbol near '='",
+            ],
+            errors("a = '\\q' .. 'b'\nc = = 1")
+        );
+        // a name starting a line starts a statement
+        assert_eq!(
+            vec![
+                "test:2: ')' expected (to close '(' at line 1) near '='",
+                "test:3: unexpected symbol near '='",
+            ],
+            errors("f(\nx = 1\ny = = 2")
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let block = parse("local t = {}\nt.x = f(1,\n  2)").unwrap();
+        let StatKind::Assign { targets, exprs } = &block.stats[1].kind else {
+            panic!("expected an assignment")
+        };
+        assert_eq!(
+            Span {
+                start: 13,
+                end: 16,
+                line: 2,
+                column: 1,
+                end_line: 2,
+                end_column: 4,
+            },
+            targets[0].span
+        );
+        assert_eq!((19, 28), (exprs[0].span.start, exprs[0].span.end));
+        assert_eq!((2, 7, 3, 5), {
+            let span = exprs[0].span;
+            (span.line, span.column, span.end_line, span.end_column)
+        });
+        assert_eq!(block.stats[1].span, targets[0].span.to(exprs[0].span));
+    }
+}
@@ -0,0 +1,539 @@
+use crate::compiler::ast::{
+    BinOp, Block, CallStyle, Expr, ExprKind, Field, FunctionBody, Name, Stat, StatKind, UnOp,
+};
+
+/// Prints `block` as the source of a chunk that parses back into the same
+/// tree, apart from spans. Parentheses are added where the tree would not
+/// survive operator precedence otherwise.
+pub fn print(block: &Block) -> String {
+    let mut printer = Printer {
+        out: String::new(),
+        indent: 0,
+    };
+    printer.stats(&block.stats);
+    printer.out
+}
+
+struct Printer {
+    out: String,
+    indent: usize,
+}
+
+impl Printer {
+    fn stats(&mut self, stats: &[Stat]) {
+        for stat in stats {
+            self.stat(stat);
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.indent += 1;
+        self.stats(&block.stats);
+        self.indent -= 1;
+    }
+
+    fn write_indent(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push_str("  ");
+        }
+    }
+
+    fn end(&mut self, keyword: &str) {
+        self.write_indent();
+        self.out.push_str(keyword);
+    }
+
+    fn stat(&mut self, stat: &Stat) {
+        self.write_indent();
+        let start = self.out.len();
+        match &stat.kind {
+            StatKind::Local { names, exprs } => {
+                self.out.push_str("local ");
+                self.names(names);
+                if !exprs.is_empty() {
+                    self.out.push_str(" = ");
+                    self.exprs(exprs);
+                }
+            }
+            StatKind::Assign { targets, exprs } => {
+                self.exprs(targets);
+                self.out.push_str(" = ");
+                self.exprs(exprs);
+            }
+            StatKind::Call(call) => self.expr(call),
+            StatKind::Do(body) => {
+                self.out.push_str("do\n");
+                self.block(body);
+                self.end("end");
+            }
+            StatKind::While { cond, body } => {
+                self.out.push_str("while ");
+                self.expr(cond);
+                self.out.push_str(" do\n");
+                self.block(body);
+                self.end("end");
+            }
+            StatKind::Repeat { body, cond } => {
+                self.out.push_str("repeat\n");
+                self.block(body);
+                self.end("until ");
+                self.expr(cond);
+            }
+            StatKind::If {
+                clauses,
+                else_block,
+            } => {
+                for (i, (cond, body)) in clauses.iter().enumerate() {
+                    match i {
+                        0 => self.out.push_str("if "),
+                        _ => self.end("elseif "),
+                    }
+                    self.expr(cond);
+                    self.out.push_str(" then\n");
+                    self.block(body);
+                }
+                if let Some(body) = else_block {
+                    self.end("else\n");
+                    self.block(body);
+                }
+                self.end("end");
+            }
+            StatKind::NumericFor {
+                var,
+                start,
+                limit,
+                step,
+                body,
+            } => {
+                self.out.push_str("for ");
+                self.out.push_str(&var.name);
+                self.out.push_str(" = ");
+                self.expr(start);
+                self.out.push_str(", ");
+                self.expr(limit);
+                if let Some(step) = step {
+                    self.out.push_str(", ");
+                    self.expr(step);
+                }
+                self.out.push_str(" do\n");
+                self.block(body);
+                self.end("end");
+            }
+            StatKind::GenericFor { names, exprs, body } => {
+                self.out.push_str("for ");
+                self.names(names);
+                self.out.push_str(" in ");
+                self.exprs(exprs);
+                self.out.push_str(" do\n");
+                self.block(body);
+                self.end("end");
+            }
+            StatKind::Function { name, body } => {
+                self.out.push_str("function ");
+                let path = name.path.iter().map(|name| name.name.as_str());
+                self.out.push_str(&path.collect::<Vec<_>>().join("."));
+                if let Some(method) = &name.method {
+                    self.out.push(':');
+                    self.out.push_str(&method.name);
+                }
+                self.function_body(body, name.method.is_some());
+            }
+            StatKind::LocalFunction { name, body } => {
+                self.out.push_str("local function ");
+                self.out.push_str(&name.name);
+                self.function_body(body, false);
+            }
+            StatKind::Return(exprs) => {
+                self.out.push_str("return");
+                if !exprs.is_empty() {
+                    self.out.push(' ');
+                    self.exprs(exprs);
+                }
+            }
+            StatKind::Break => self.out.push_str("break"),
+            StatKind::Goto(label) => {
+                self.out.push_str("goto ");
+                self.out.push_str(&label.name);
+            }
+            StatKind::Label(label) => {
+                self.out.push_str("::");
+                self.out.push_str(&label.name);
+                self.out.push_str("::");
+            }
+        }
+        // a statement starting with a parenthesis would otherwise continue
+        // the previous one as a call
+        if self.out[start..].starts_with('(') {
+            self.out.insert(start, ';');
+        }
+        self.out.push('\n');
+    }
+
+    /// Prints parameters and body, leaving out the implicit `self` of
+    /// methods.
+    fn function_body(&mut self, body: &FunctionBody, is_method: bool) {
+        let params = &body.params[(is_method && !body.params.is_empty()) as usize..];
+        let mut params = params
+            .iter()
+            .map(|name| name.name.as_str())
+            .collect::<Vec<_>>();
+        if body.is_vararg {
+            params.push("...");
+        }
+        self.out.push('(');
+        self.out.push_str(&params.join(", "));
+        self.out.push_str(")\n");
+        self.block(&body.body);
+        self.end("end");
+    }
+
+    fn names(&mut self, names: &[Name]) {
+        let names = names.iter().map(|name| name.name.as_str());
+        self.out.push_str(&names.collect::<Vec<_>>().join(", "));
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) {
+        for (i, expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.expr(expr);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Nil => self.out.push_str("nil"),
+            ExprKind::True => self.out.push_str("true"),
+            ExprKind::False => self.out.push_str("false"),
+            ExprKind::VarArg => self.out.push_str("..."),
+            ExprKind::Integer(i) => self.integer(*i),
+            ExprKind::Float(f) => self.float(*f),
+            ExprKind::String(s) => self.string(s),
+            ExprKind::Function(body) => {
+                self.out.push_str("function");
+                self.function_body(body, false);
+            }
+            ExprKind::Table(fields) => self.table(fields),
+            ExprKind::Name(name) => self.out.push_str(name),
+            ExprKind::Field { table, name } => {
+                self.prefix(table);
+                self.out.push('.');
+                self.out.push_str(&name.name);
+            }
+            ExprKind::Index { table, key } => {
+                self.prefix(table);
+                self.out.push('[');
+                self.expr(key);
+                self.out.push(']');
+            }
+            ExprKind::Call { func, args, style } => {
+                self.prefix(func);
+                self.args(args, *style);
+            }
+            ExprKind::Method {
+                object,
+                name,
+                args,
+                style,
+            } => {
+                self.prefix(object);
+                self.out.push(':');
+                self.out.push_str(&name.name);
+                self.args(args, *style);
+            }
+            ExprKind::Paren(inner) => self.parenthesized(inner),
+            ExprKind::Binary { op, lhs, rhs, .. } => self.binary(*op, lhs, rhs),
+            ExprKind::Unary { op, operand, .. } => self.unary(*op, operand),
+        }
+    }
+
+    fn parenthesized(&mut self, expr: &Expr) {
+        self.out.push('(');
+        self.expr(expr);
+        self.out.push(')');
+    }
+
+    fn operand(&mut self, expr: &Expr, needs_parens: bool) {
+        match needs_parens {
+            true => self.parenthesized(expr),
+            false => self.expr(expr),
+        }
+    }
+
+    /// Prints the expression a field, index or call applies to, which must
+    /// be a variable, a call or parenthesized.
+    fn prefix(&mut self, expr: &Expr) {
+        let is_prefix = matches!(
+            expr.kind,
+            ExprKind::Name(_)
+                | ExprKind::Field { .. }
+                | ExprKind::Index { .. }
+                | ExprKind::Call { .. }
+                | ExprKind::Method { .. }
+                | ExprKind::Paren(_)
+        );
+        self.operand(expr, !is_prefix);
+    }
+
+    fn args(&mut self, args: &[Expr], style: CallStyle) {
+        match (style, args) {
+            (
+                CallStyle::Table,
+                [arg @ Expr {
+                    kind: ExprKind::Table(_),
+                    ..
+                }],
+            )
+            | (
+                CallStyle::String,
+                [arg @ Expr {
+                    kind: ExprKind::String(_),
+                    ..
+                }],
+            ) => self.expr(arg),
+            _ => {
+                self.out.push('(');
+                self.exprs(args);
+                self.out.push(')');
+            }
+        }
+    }
+
+    /// An operand binds to the operator next to it unless parenthesized,
+    /// following the priorities `lparser.c` parses with.
+    fn binary(&mut self, op: BinOp, lhs: &Expr, rhs: &Expr) {
+        let (left, right) = op.priority();
+        let lhs_parens = match &lhs.kind {
+            ExprKind::Binary { op: lhs_op, .. } => left > lhs_op.priority().1,
+            ExprKind::Unary { .. } => left > UnOp::PRIORITY,
+            _ => false,
+        };
+        let rhs_parens = match &rhs.kind {
+            ExprKind::Binary { op: rhs_op, .. } => rhs_op.priority().0 <= right,
+            _ => false,
+        };
+        self.operand(lhs, lhs_parens);
+        self.out.push(' ');
+        self.out.push_str(op.symbol());
+        self.out.push(' ');
+        self.operand(rhs, rhs_parens);
+    }
+
+    fn unary(&mut self, op: UnOp, operand: &Expr) {
+        self.out.push_str(op.symbol());
+        if op == UnOp::Not {
+            self.out.push(' ');
+        }
+        let start = self.out.len();
+        let needs_parens = match &operand.kind {
+            ExprKind::Binary { op, .. } => op.priority().0 <= UnOp::PRIORITY,
+            _ => false,
+        };
+        self.operand(operand, needs_parens);
+        // `--` would start a comment
+        if self.out[start..].starts_with('-') {
+            self.out.insert(start, ' ');
+        }
+    }
+
+    fn table(&mut self, fields: &[Field]) {
+        self.out.push('{');
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            match field {
+                Field::Positional(value) => self.expr(value),
+                Field::Named { name, value } => {
+                    self.out.push_str(&name.name);
+                    self.out.push_str(" = ");
+                    self.expr(value);
+                }
+                Field::Keyed { key, value } => {
+                    self.out.push('[');
+                    self.expr(key);
+                    self.out.push_str("] = ");
+                    self.expr(value);
+                }
+            }
+        }
+        self.out.push('}');
+    }
+
+    /// Negative integers are negated literals, which constant folding turns
+    /// back into the same integer. Only `math.mininteger` has no positive
+    /// counterpart and is written in hexadecimal, which wraps around.
+    fn integer(&mut self, i: i64) {
+        match i {
+            i64::MIN => self.out.push_str(&format!("0x{:x}", i)),
+            i if i < 0 => self.out.push_str(&format!("(-{})", -i)),
+            i => self.out.push_str(&i.to_string()),
+        }
+    }
+
+    fn float(&mut self, f: f64) {
+        if f.is_nan() {
+            self.out.push_str("(0 / 0)");
+        } else if f.is_sign_negative() {
+            self.out.push_str("(-");
+            self.float(-f);
+            self.out.push(')');
+        } else if f.is_infinite() {
+            self.out.push_str("1e999");
+        } else {
+            // the shortest representation reading back as the same float,
+            // always with a `.` or an exponent
+            self.out.push_str(&format!("{:?}", f));
+        }
+    }
+
+    fn string(&mut self, s: &[u8]) {
+        self.out.push('"');
+        match std::str::from_utf8(s) {
+            Ok(text) => text.chars().for_each(|c| self.char(c)),
+            Err(_) => {
+                for &b in s {
+                    match b {
+                        0x80.. => self.out.push_str(&format!("\\{:03}", b)),
+                        _ => self.char(b as char),
+                    }
+                }
+            }
+        }
+        self.out.push('"');
+    }
+
+    fn char(&mut self, c: char) {
+        match c {
+            '"' => self.out.push_str("\\\""),
+            '\\' => self.out.push_str("\\\\"),
+            '\n' => self.out.push_str("\\n"),
+            '\r' => self.out.push_str("\\r"),
+            '\t' => self.out.push_str("\\t"),
+            c if c.is_ascii_control() => self.out.push_str(&format!("\\{:03}", c as u8)),
+            c => self.out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::ast::Span;
+    use crate::compiler::parse;
+
+    fn reprint(source: &str) -> String {
+        print(&parse(source.as_bytes(), "=test").unwrap())
+    }
+
+    fn expr(kind: ExprKind) -> Expr {
+        Expr {
+            kind,
+            span: Span::default(),
+        }
+    }
+
+    fn binary(op: BinOp, lhs: ExprKind, rhs: ExprKind) -> ExprKind {
+        ExprKind::Binary {
+            op,
+            op_line: 0,
+            lhs: Box::new(expr(lhs)),
+            rhs: Box::new(expr(rhs)),
+        }
+    }
+
+    fn name(name: &str) -> ExprKind {
+        ExprKind::Name(name.to_owned())
+    }
+
+    fn print_expr(kind: ExprKind) -> String {
+        let block = Block {
+            stats: vec![Stat {
+                kind: StatKind::Return(vec![expr(kind)]),
+                span: Span::default(),
+            }],
+            span: Span::default(),
+        };
+        print(&block)
+    }
+
+    #[test]
+    fn test_statements() {
+        let source = "local a, b = 1, 2.5\n\
+                      function t.m:f(x, ...)\n  \
+                        if x then\n    \
+                          return self\n  \
+                        elseif not x then\n    \
+                          goto done\n  \
+                        else\n    \
+                          x = {1, k = \"v\", [2] = f\"s\"}\n  \
+                        end\n  \
+                        ::done::\n\
+                      end\n\
+                      for i = 1, 10, -1 do\n  \
+                        repeat\n    \
+                          break\n  \
+                        until (a)\n\
+                      end\n\
+                      ;(f)()\n";
+        assert_eq!(source, reprint(source));
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(
+            "return (a + b) * c\n",
+            print_expr(binary(
+                BinOp::Mul,
+                binary(BinOp::Add, name("a"), name("b")),
+                name("c")
+            ))
+        );
+        assert_eq!(
+            "return a - (b - c)\n",
+            print_expr(binary(
+                BinOp::Sub,
+                name("a"),
+                binary(BinOp::Sub, name("b"), name("c"))
+            ))
+        );
+        assert_eq!(
+            "return a .. b .. c\n",
+            print_expr(binary(
+                BinOp::Concat,
+                name("a"),
+                binary(BinOp::Concat, name("b"), name("c"))
+            ))
+        );
+        assert_eq!(
+            "return (a ^ b) ^ c\n",
+            print_expr(binary(
+                BinOp::Pow,
+                binary(BinOp::Pow, name("a"), name("b")),
+                name("c")
+            ))
+        );
+        assert_eq!("return (-a) ^ - -b\n", reprint("return (-a) ^ - -b"));
+        assert_eq!(
+            "return (-1) + (-2.0)\n",
+            print_expr(binary(
+                BinOp::Add,
+                ExprKind::Integer(-1),
+                ExprKind::Float(-2.0)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(
+            "return \"a\\\"\\\\\\n\\000ü\", \"\\255\", 1e999, 0.1, 1e300, 3.0\n",
+            reprint("return 'a\"\\\\\\n\\0ü', '\\xff', 1e999, 0.1, 1e300, 3.0")
+        );
+        assert_eq!(
+            "return (-5), 0x8000000000000000\n",
+            reprint("return 0xfffffffffffffffb, 0x8000000000000000")
+        );
+    }
+}
@@ -0,0 +1,645 @@
+use std::collections::BTreeMap;
+
+use crate::compiler::ast::{Expr, ExprKind, FunctionName, Name, Stat, StatKind};
+use crate::decompiler::conds::{build, Item, Kind, Part};
+use crate::decompiler::exprs::{close_list, Slot, State, Step, Store};
+use crate::decompiler::{block, expr, label_name, name, stat, DecompileError, Function};
+use crate::opcode::{Op, Opcode};
+
+impl Function<'_> {
+    /// Structures the code in `start..end` into statements. `end_label`
+    /// tells whether a label at `end` belongs to this block rather than to
+    /// the statement following it.
+    pub(super) fn block(
+        &mut self,
+        start: usize,
+        end: usize,
+        end_label: bool,
+    ) -> Result<Vec<Stat>, DecompileError> {
+        let mut stats = Vec::new();
+        let mut st = State::default();
+        let mut pc = start;
+        // made-up scopes ending before the enclosing block does, with where
+        // their statements start, and the start of the last statement
+        let mut scopes: Vec<(usize, usize)> = Vec::new();
+        let mut last = (start, 0);
+        loop {
+            if st.is_empty() {
+                end_scopes(&mut scopes, &mut stats, pc, last);
+                last = (pc, stats.len());
+            }
+            if let Some(next) = self.declare(&mut st, pc, end, &mut stats, &mut scopes)? {
+                pc = next;
+                continue;
+            }
+            if pc >= end {
+                break;
+            }
+            if st.is_empty() {
+                if self.labels.contains(&pc) && self.placed.insert(pc) {
+                    stats.push(stat(StatKind::Label(name(&label_name(pc)))));
+                }
+                if let Some(next) = self.loop_at(pc, end, &mut stats)? {
+                    pc = next;
+                    continue;
+                }
+            }
+            pc = match self.chunk.code[pc].get_op() {
+                Op::Jmp => self.jump(&mut st, pc, &mut stats)?,
+                Op::ForPrep => self.numeric_for(&mut st, pc, &mut stats)?,
+                op if op.is_test() => self.test(&mut st, pc, end, &mut stats)?,
+                _ => {
+                    let (step, next) = self.exec(&mut st, pc)?;
+                    self.step(&mut st, step, next, end, &mut stats)?
+                }
+            };
+        }
+        if pc > end {
+            return self.error(end, "statement crosses the end of its block");
+        }
+        if !st.is_empty() {
+            return self.error(end, "values computed and never used");
+        }
+        end_scopes(&mut scopes, &mut stats, pc, last);
+        if end_label && self.labels.contains(&end) && self.placed.insert(end) {
+            stats.push(stat(StatKind::Label(name(&label_name(end)))));
+        }
+        Ok(stats)
+    }
+
+    /// Declares the locals whose scope starts at `pc`, wrapping the scope
+    /// in a `do` block when it ends before the enclosing block does. Made-up
+    /// scopes can end in the middle of a statement, so they are added to
+    /// `scopes` and wrapped once that statement is found.
+    fn declare(
+        &mut self,
+        st: &mut State,
+        pc: usize,
+        end: usize,
+        stats: &mut Vec<Stat>,
+        scopes: &mut Vec<(usize, usize)>,
+    ) -> Result<Option<usize>, DecompileError> {
+        if pc >= end && st.is_empty() {
+            return Ok(None);
+        }
+        let mut new = (0..self.locals.len())
+            .filter(|&i| {
+                let local = &self.locals[i];
+                local.start == pc && !self.declared[i] && !local.is_hidden()
+            })
+            .collect::<Vec<_>>();
+        if new.is_empty() {
+            return Ok(None);
+        }
+        new.sort_by_key(|&i| self.locals[i].reg);
+        for &i in &new {
+            self.declared[i] = true;
+        }
+        let first = self.locals[new[0]].reg;
+        let count = new.len();
+        let scope_end = self.locals[new[0]].end;
+        let names = new
+            .iter()
+            .map(|&i| name(&self.locals[i].name))
+            .collect::<Vec<_>>();
+
+        let kind = match st.take(first) {
+            Some(Slot::Expr(Expr {
+                kind: ExprKind::Function(body),
+                ..
+            })) if count == 1 && self.is_local_function(pc, first) => StatKind::LocalFunction {
+                name: names.into_iter().next().unwrap(),
+                body: *body,
+            },
+            slot => {
+                if let Some(slot) = slot {
+                    st.set(first, slot);
+                }
+                let mut exprs = match self.regs(pc, first, count)?.any(|reg| st.contains(reg)) {
+                    true => self.list(st, pc, first, count, Some(count))?,
+                    false => Vec::new(),
+                };
+                if exprs.iter().all(|value| value.kind == ExprKind::Nil) {
+                    exprs.clear();
+                }
+                StatKind::Local { names, exprs }
+            }
+        };
+        if !st.is_empty() {
+            return self.error(pc, "values left over after a local declaration");
+        }
+
+        if scope_end < end && self.inferred {
+            scopes.push((stats.len(), scope_end));
+        } else if scope_end < end {
+            let mut inner = vec![stat(kind)];
+            inner.extend(self.block(pc, scope_end, false)?);
+            stats.push(stat(StatKind::Do(block(inner))));
+            return Ok(Some(scope_end));
+        }
+        stats.push(stat(kind));
+        Ok(Some(pc))
+    }
+
+    /// Whether the local starting at `pc` in `reg` was declared with
+    /// `local function`, which is only visible when the function refers to
+    /// itself.
+    fn is_local_function(&self, pc: usize, reg: u8) -> bool {
+        let Some(closure) = pc.checked_sub(1).map(|at| self.chunk.code[at]) else {
+            return false;
+        };
+        closure.get_op() == Op::Closure
+            && closure.get_a() == reg
+            && (self.chunk.prototypes.get(closure.get_bx() as usize)).is_some_and(|proto| {
+                (proto.upvalues.iter()).any(|upvalue| upvalue.in_stack && upvalue.index == reg)
+            })
+    }
+
+    fn step(
+        &mut self,
+        st: &mut State,
+        step: Step,
+        next: usize,
+        end: usize,
+        stats: &mut Vec<Stat>,
+    ) -> Result<usize, DecompileError> {
+        match step {
+            Step::Pure => {}
+            Step::Store(store) => return self.assign(st, store, next, end, stats),
+            Step::Call(_, call) if st.is_empty() => stats.push(stat(StatKind::Call(call))),
+            Step::Call(base, call) => st.set(base, Slot::Void(call)),
+            Step::Return(values) => {
+                let ret = stat(StatKind::Return(values));
+                // 'return' can only be the last statement of a block
+                stats.push(match next < end {
+                    true => stat(StatKind::Do(block(vec![ret]))),
+                    false => ret,
+                });
+            }
+            Step::Nil(names) => {
+                for target in names {
+                    stats.push(stat(StatKind::Assign {
+                        targets: vec![expr(ExprKind::Name(target.name))],
+                        exprs: vec![expr(ExprKind::Nil)],
+                    }));
+                }
+            }
+        }
+        Ok(next)
+    }
+
+    /// Turns a store into an assignment, along with the stores of the other
+    /// values of a multiple assignment, which are made last to first.
+    fn assign(
+        &mut self,
+        st: &mut State,
+        first: Store,
+        mut pc: usize,
+        end: usize,
+        stats: &mut Vec<Stat>,
+    ) -> Result<usize, DecompileError> {
+        let mut stores = vec![first];
+        while !st.is_empty() && pc < end {
+            let mut trial = st.clone();
+            match self.exec(&mut trial, pc) {
+                Ok((Step::Store(store), next)) if store.reg.is_some() => {
+                    *st = trial;
+                    stores.push(store);
+                    pc = next;
+                }
+                _ => break,
+            }
+        }
+        let count = stores.len();
+        let targets = stores
+            .iter()
+            .rev()
+            .map(|store| store.target.clone())
+            .collect::<Vec<_>>();
+        let mut values = stores
+            .into_iter()
+            .map(|store| (store.reg, store.value))
+            .collect::<Vec<_>>();
+        values.sort_by_key(|(reg, _)| reg.map_or(u16::MAX, u16::from));
+        let open = values.iter().any(|(_, value)| value.is_none());
+        let mut exprs = values
+            .into_iter()
+            .filter_map(|(_, value)| value)
+            .collect::<Vec<_>>();
+        // values beyond the targets are only evaluated
+        let extras = match st.lowest() {
+            Some(reg) => self.items(st, pc, reg)?,
+            None => Vec::new(),
+        };
+        if extras.is_empty() {
+            close_list(&mut exprs, open, Some(count));
+        }
+        exprs.extend(extras);
+
+        let kind = match (&targets[..], &exprs[..]) {
+            (
+                [target],
+                [Expr {
+                    kind: ExprKind::Function(body),
+                    ..
+                }],
+            ) => match function_name(target, body.params.first()) {
+                Some(name) => StatKind::Function {
+                    name,
+                    body: (**body).clone(),
+                },
+                None => StatKind::Assign { targets, exprs },
+            },
+            _ => StatKind::Assign { targets, exprs },
+        };
+        stats.push(stat(kind));
+        Ok(pc)
+    }
+
+    /// Structures the loop starting at `pc`, if a jump back to it closes one.
+    fn loop_at(
+        &mut self,
+        pc: usize,
+        end: usize,
+        stats: &mut Vec<Stat>,
+    ) -> Result<Option<usize>, DecompileError> {
+        let chunk = self.chunk;
+        let Some(back) = (pc..end).rev().find(|&at| {
+            chunk.code[at].get_op() == Op::Jmp
+                && self.jump_target(at) == pc
+                && !self.closing.contains(&at)
+        }) else {
+            return Ok(None);
+        };
+        let exit = back + 1;
+        self.closing.insert(back);
+        self.loops.push(exit);
+        let result = match back > pc && self.is_test(back - 1) {
+            // the jump back is the condition of 'repeat ... until'
+            true => self.repeat(pc, exit),
+            false => self.while_loop(pc, back),
+        };
+        self.loops.pop();
+        self.closing.remove(&back);
+        stats.push(result?);
+        Ok(Some(exit))
+    }
+
+    fn repeat(&mut self, pc: usize, exit: usize) -> Result<Stat, DecompileError> {
+        let until = self.until.replace((pc, exit));
+        let until_cond = self.until_cond.take();
+        let body = self.block(pc, exit, false);
+        let cond = self.until_cond.take();
+        self.until = until;
+        self.until_cond = until_cond;
+        match (body, cond) {
+            (Ok(body), Some(cond)) => Ok(stat(StatKind::Repeat {
+                body: block(body),
+                cond,
+            })),
+            (Err(err), _) => Err(err),
+            (_, None) => self.error(exit - 1, "loop condition not found"),
+        }
+    }
+
+    fn while_loop(&mut self, pc: usize, back: usize) -> Result<Stat, DecompileError> {
+        let items = self.chain(&State::default(), pc, back);
+        let found = (0..items.len()).rev().find_map(|i| {
+            let (last, after) = &items[i];
+            if last.target != back + 1 || !after.is_empty() {
+                return None;
+            }
+            let exits = BTreeMap::from([
+                (last.next, Kind::Branch(true)),
+                (last.target, Kind::Branch(false)),
+            ]);
+            let cond = build(&parts(&items[..=i]), Kind::Branch(true), &exits)?;
+            Some((cond, last.next))
+        });
+        let (cond, start) = found.unwrap_or((expr(ExprKind::True), pc));
+        let body = self.block(start, back, true)?;
+        Ok(stat(StatKind::While {
+            cond,
+            body: block(body),
+        }))
+    }
+
+    fn numeric_for(
+        &mut self,
+        st: &mut State,
+        pc: usize,
+        stats: &mut Vec<Stat>,
+    ) -> Result<usize, DecompileError> {
+        let base = self.chunk.code[pc].get_a();
+        let end = self.jump_target(pc);
+        if self.chunk.code.get(end).map(|instr| instr.get_op()) != Some(Op::ForLoop) {
+            return self.error(pc, "FORPREP without FORLOOP");
+        }
+        let step = self.operand(st, pc, base + 2)?;
+        let limit = self.operand(st, pc, base + 1)?;
+        let start = self.operand(st, pc, base)?;
+        if !st.is_empty() {
+            return self.error(pc, "values left over before a loop");
+        }
+        let var = self.declare_vars(pc + 1, base + 3, 1)?.remove(0);
+        let step = match step.kind {
+            ExprKind::Integer(1) => None,
+            _ => Some(Box::new(step)),
+        };
+        self.loops.push(end + 1);
+        let body = self.block(pc + 1, end, true);
+        self.loops.pop();
+        stats.push(stat(StatKind::NumericFor {
+            var,
+            start: Box::new(start),
+            limit: Box::new(limit),
+            step,
+            body: block(body?),
+        }));
+        Ok(end + 1)
+    }
+
+    /// A generic `for` starts with a jump to its `TFORCALL`.
+    fn generic_for(
+        &mut self,
+        st: &mut State,
+        pc: usize,
+        stats: &mut Vec<Stat>,
+    ) -> Result<Option<usize>, DecompileError> {
+        let code = &self.chunk.code;
+        let call = self.jump_target(pc);
+        match (code.get(call), code.get(call + 1)) {
+            (Some(tfor), Some(tloop))
+                if tfor.get_op() == Op::TForCall
+                    && tloop.get_op() == Op::TForLoop
+                    && self.jump_target(call + 1) == pc + 1 => {}
+            _ => return Ok(None),
+        }
+        let base = code[call].get_a();
+        let count = code[call].get_c().raw() as usize;
+        let exprs = self.list(st, pc, base, 3, Some(3))?;
+        if !st.is_empty() {
+            return self.error(pc, "values left over before a loop");
+        }
+        let names = self.declare_vars(pc + 1, base + 3, count)?;
+        self.loops.push(call + 2);
+        let body = self.block(pc + 1, call, true);
+        self.loops.pop();
+        stats.push(stat(StatKind::GenericFor {
+            names,
+            exprs,
+            body: block(body?),
+        }));
+        Ok(Some(call + 2))
+    }
+
+    /// The variables of a `for` loop, which its statement declares.
+    fn declare_vars(
+        &mut self,
+        pc: usize,
+        first: u8,
+        count: usize,
+    ) -> Result<Vec<Name>, DecompileError> {
+        let mut names = Vec::new();
+        for reg in self.regs(pc, first, count)? {
+            let Some(i) = (0..self.locals.len())
+                .find(|&i| self.locals[i].start == pc && self.locals[i].reg == reg)
+            else {
+                return self.error(pc, "missing loop variable names");
+            };
+            self.declared[i] = true;
+            names.push(name(&self.locals[i].name));
+        }
+        Ok(names)
+    }
+
+    fn jump(
+        &mut self,
+        st: &mut State,
+        pc: usize,
+        stats: &mut Vec<Stat>,
+    ) -> Result<usize, DecompileError> {
+        if self.is_close(pc) {
+            return Ok(pc + 1);
+        }
+        if let Some(next) = self.generic_for(st, pc, stats)? {
+            return Ok(next);
+        }
+        if !st.is_empty() {
+            return self.error(pc, "jump in the middle of an expression");
+        }
+        let target = self.jump_target(pc);
+        stats.push(match self.loops.last() == Some(&target) {
+            true => stat(StatKind::Break),
+            false => self.label(target),
+        });
+        Ok(pc + 1)
+    }
+
+    /// Whether the jump at `pc` only closes upvalues at the end of a block,
+    /// where the scope of the locals of the block ends. It goes where the
+    /// code after it goes, which is the target of the jump following it, if
+    /// any, and closes from at most the first local of the block: a close
+    /// jump right after it can lower that.
+    fn is_close(&self, pc: usize) -> bool {
+        let code = &self.chunk.code;
+        let instr = code[pc];
+        let target = self.jump_target(pc);
+        instr.get_a() > 0
+            && self
+                .locals
+                .iter()
+                .any(|local| local.end == pc + 1 && local.reg + 1 >= instr.get_a())
+            && (target == pc + 1
+                || code[pc + 1].get_op() == Op::Jmp && self.jump_target(pc + 1) == target)
+    }
+
+    /// Structures the tests starting at `pc` into an expression, the
+    /// condition of an `if` or the one ending a `repeat`.
+    fn test(
+        &mut self,
+        st: &mut State,
+        pc: usize,
+        end: usize,
+        stats: &mut Vec<Stat>,
+    ) -> Result<usize, DecompileError> {
+        if let Some((step, inner, next)) = self.value_region(st, pc, end)? {
+            *st = inner;
+            return self.step(st, step, next, end, stats);
+        }
+        let items = self.chain(st, pc, end);
+        for i in (0..items.len()).rev() {
+            let (last, after) = &items[i];
+            if !after.is_empty() {
+                continue;
+            }
+            let parts = parts(&items[..=i]);
+            let (start, target) = (last.next, last.target);
+
+            if let Some((top, exit)) = self.until {
+                if start == exit && target == top {
+                    let exits =
+                        BTreeMap::from([(top, Kind::Branch(false)), (exit, Kind::Branch(true))]);
+                    if let Some(cond) = build(&parts, Kind::Branch(true), &exits) {
+                        self.until_cond = Some(cond);
+                        *st = State::default();
+                        return Ok(start);
+                    }
+                }
+            }
+
+            let breaks = self.loops.last() == Some(&target);
+            if breaks || target < start || target > end {
+                let exits =
+                    BTreeMap::from([(start, Kind::Branch(false)), (target, Kind::Branch(true))]);
+                let Some(cond) = build(&parts, Kind::Branch(false), &exits) else {
+                    continue;
+                };
+                let jump = match breaks {
+                    true => stat(StatKind::Break),
+                    false => self.label(target),
+                };
+                stats.push(stat(StatKind::If {
+                    clauses: vec![(cond, block(vec![jump]))],
+                    else_block: None,
+                }));
+                *st = State::default();
+                return Ok(start);
+            }
+
+            let exits =
+                BTreeMap::from([(start, Kind::Branch(true)), (target, Kind::Branch(false))]);
+            let Some(cond) = build(&parts, Kind::Branch(true), &exits) else {
+                continue;
+            };
+            *st = State::default();
+            let else_end = self.else_end(start, target, end);
+            let then_end = match else_end {
+                Some(_) => target - 1,
+                None => target,
+            };
+            let then_block = block(self.block(start, then_end, else_end.is_some())?);
+            let mut clauses = vec![(cond, then_block)];
+            let mut else_block = None;
+            if let Some(else_end) = else_end {
+                let mut stats = self.block(target, else_end, false)?;
+                match &mut stats[..] {
+                    [Stat {
+                        kind:
+                            StatKind::If {
+                                clauses: inner,
+                                else_block: inner_else,
+                            },
+                        ..
+                    }] => {
+                        clauses.append(inner);
+                        else_block = inner_else.take();
+                    }
+                    _ => else_block = Some(block(stats)),
+                }
+            }
+            stats.push(stat(StatKind::If {
+                clauses,
+                else_block,
+            }));
+            return Ok(else_end.unwrap_or(target));
+        }
+        self.error(pc, "condition without a structure")
+    }
+
+    /// Where the `else` part of an `if` whose `then` part is `start..target`
+    /// ends, when it has one. The `then` part jumps over it.
+    fn else_end(&self, start: usize, target: usize, end: usize) -> Option<usize> {
+        let jump = target.checked_sub(1).filter(|&at| at >= start)?;
+        let instr = self.chunk.code[jump];
+        if instr.get_op() != Op::Jmp || instr.get_a() != 0 || instr.get_sbx() < 0 {
+            return None;
+        }
+        if jump > start && self.is_test(jump - 1) {
+            return None;
+        }
+        let else_end = self.jump_target(jump);
+        (else_end <= end && self.loops.last() != Some(&else_end)).then_some(else_end)
+    }
+}
+
+/// Wraps the statements of the scopes in `scopes` ending by `pc` in `do`
+/// blocks. A scope ending inside the last statement, which reuses its
+/// registers, ends before that statement.
+fn end_scopes(
+    scopes: &mut Vec<(usize, usize)>,
+    stats: &mut Vec<Stat>,
+    pc: usize,
+    (last, mut last_index): (usize, usize),
+) {
+    while let Some(&(index, scope_end)) = scopes.last() {
+        if scope_end > pc {
+            break;
+        }
+        scopes.pop();
+        // scopes ending together share a block
+        let mut index = index;
+        while let Some(&(outer, _)) = scopes.last().filter(|&&(_, end)| end == scope_end) {
+            scopes.pop();
+            index = outer;
+        }
+        let at = match scope_end > last && scope_end < pc {
+            true => last_index.max(index + 1),
+            false => stats.len(),
+        };
+        // malformed code can leave a scope without statements
+        last_index = (last_index + index + 1).saturating_sub(at);
+        let after = stats.split_off(at);
+        let mut inner = stats.split_off(index);
+        // a 'return' ending the scope needs no block of its own
+        if let Some(Stat {
+            kind: StatKind::Do(ret),
+            ..
+        }) = inner.last_mut()
+        {
+            if let [Stat {
+                kind: StatKind::Return(_),
+                ..
+            }] = &ret.stats[..]
+            {
+                let ret = ret.stats.pop().unwrap();
+                *inner.last_mut().unwrap() = ret;
+            }
+        }
+        stats.push(stat(StatKind::Do(block(inner))));
+        stats.extend(after);
+    }
+}
+
+fn parts(items: &[(Item, State)]) -> Vec<Part> {
+    items
+        .iter()
+        .map(|(item, _)| Part::Item(item.clone()))
+        .collect()
+}
+
+/// The name of a function statement assigning to `target`, which is a
+/// method when the function takes `self` first.
+fn function_name(target: &Expr, first_param: Option<&Name>) -> Option<FunctionName> {
+    let mut path = Vec::new();
+    let mut current = target;
+    loop {
+        match &current.kind {
+            ExprKind::Name(root) => {
+                path.push(name(root));
+                break;
+            }
+            ExprKind::Field { table, name } => {
+                path.push(name.clone());
+                current = table;
+            }
+            _ => return None,
+        }
+    }
+    path.reverse();
+    let method = match first_param {
+        Some(param) if param.name == "self" && path.len() > 1 => path.pop(),
+        _ => None,
+    };
+    Some(FunctionName { path, method })
+}
@@ -0,0 +1,419 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::compiler::ast::{BinOp, Expr, ExprKind, UnOp};
+use crate::decompiler::exprs::{binary, unary, State, Step, Store};
+use crate::decompiler::{DecompileError, Function};
+use crate::instruction::ArgK;
+use crate::opcode::{Op, Opcode};
+
+/// A test and the jump it controls.
+#[derive(Clone, Debug)]
+pub(super) struct Item {
+    /// Where the code evaluating the operands of the test starts.
+    pub start: usize,
+    pub test: Expr,
+    /// Whether the test is a comparison, which yields a boolean.
+    pub compare: bool,
+    /// The register a `TEST` or `TESTSET` leaves the tested value in.
+    pub reg: Option<u8>,
+    /// Whether the jump is taken when the test is true.
+    pub jump_if: bool,
+    pub target: usize,
+    /// The instruction after the jump, reached when it is not taken.
+    pub next: usize,
+}
+
+/// What reaching a jump target means for the expression jumping there.
+#[derive(Clone, Copy, Debug)]
+pub(super) enum Kind {
+    /// The condition is true or false.
+    Branch(bool),
+    /// The value is `true` or `false`, loaded by a `LOADBOOL`.
+    Const(bool),
+    /// The value is the one tested, left in the register.
+    Value(u8),
+}
+
+impl Kind {
+    /// Whether jumping here means the expression is truthy.
+    fn polarity(self, item: &Item) -> Option<bool> {
+        match self {
+            Kind::Branch(truthy) | Kind::Const(truthy) => Some(truthy),
+            Kind::Value(reg) => (item.reg == Some(reg)).then_some(item.jump_if),
+        }
+    }
+}
+
+/// The assignment of the value of an `and`/`or` expression or a
+/// comparison, the pending values after it and where it ends.
+pub(super) type Region = (Step, State, usize);
+
+/// A piece of a condition: a test, or the value the last operand of an
+/// `and`/`or` evaluates to.
+#[derive(Clone, Debug)]
+pub(super) enum Part {
+    Item(Item),
+    Leaf(usize, Expr),
+}
+
+impl Part {
+    fn start(&self) -> usize {
+        match self {
+            Part::Item(item) => item.start,
+            Part::Leaf(start, _) => *start,
+        }
+    }
+}
+
+impl Function<'_> {
+    fn read_test(&self, st: &mut State, pc: usize, start: usize) -> Result<Item, DecompileError> {
+        let instr = self.chunk.code[pc];
+        let a = instr.get_a();
+        let b = instr.get_b();
+        let c = instr.get_c();
+        let (test, reg, jump_if) = match instr.get_op() {
+            Op::Test => (self.operand(st, pc, a)?, Some(a), c.raw() != 0),
+            Op::TestSet => (self.operand(st, pc, b.value())?, Some(a), c.raw() != 0),
+            op => {
+                // '>' and '>=' are compiled with their operands swapped, which
+                // shows when the operands were evaluated in the other order
+                let temp = |arg: &ArgK| !arg.is_constant() && st.contains(arg.value());
+                let swapped = match (temp(&b), temp(&c)) {
+                    (true, true) => b.value() > c.value(),
+                    _ => b.is_constant() && !c.is_constant(),
+                };
+                let rhs = self.rk(st, pc, &c)?;
+                let lhs = self.rk(st, pc, &b)?;
+                let test = match (op, swapped) {
+                    (Op::Eq, _) => binary(BinOp::Eq, lhs, rhs),
+                    (Op::Lt, false) => binary(BinOp::Lt, lhs, rhs),
+                    (Op::Lt, true) => binary(BinOp::Gt, rhs, lhs),
+                    (_, false) => binary(BinOp::Le, lhs, rhs),
+                    (_, true) => binary(BinOp::Ge, rhs, lhs),
+                };
+                (test, None, a != 0)
+            }
+        };
+        Ok(Item {
+            start,
+            test,
+            compare: reg.is_none(),
+            reg,
+            jump_if,
+            target: self.jump_target(pc + 1),
+            next: pc + 2,
+        })
+    }
+
+    pub(super) fn is_test(&self, pc: usize) -> bool {
+        let code = &self.chunk.code;
+        code[pc].get_op().is_test() && code.get(pc + 1).map(|jmp| jmp.get_op()) == Some(Op::Jmp)
+    }
+
+    fn starts_local(&self, pc: usize) -> bool {
+        self.locals.iter().any(|local| local.start == pc)
+    }
+
+    /// Reads the tests from `pc` on, along with the pending values after
+    /// each of them, until reaching code that is not an expression.
+    pub(super) fn chain(&mut self, st: &State, pc: usize, stop: usize) -> Vec<(Item, State)> {
+        let mut st = st.clone();
+        let mut items: Vec<(Item, State)> = Vec::new();
+        let mut start = pc;
+        let mut cur = pc;
+        while cur < stop {
+            if cur > pc && (self.is_loop_header(cur) || self.starts_local(cur)) {
+                break;
+            }
+            if self.is_test(cur) {
+                if let Ok(Some((Step::Pure, inner, next))) = self.value_region(&st, cur, stop) {
+                    if !items
+                        .iter()
+                        .any(|(item, _)| cur < item.target && item.target < next)
+                    {
+                        st = inner;
+                        cur = next;
+                        continue;
+                    }
+                }
+                let Ok(item) = self.read_test(&mut st, cur, start) else {
+                    break;
+                };
+                items.push((item, st.clone()));
+                cur += 2;
+                start = cur;
+                continue;
+            }
+            match self.exec(&mut st, cur) {
+                Ok((Step::Pure, next)) => cur = next,
+                _ => break,
+            }
+        }
+        items
+    }
+
+    /// Tries to read an `and`/`or` expression or a comparison evaluated to a
+    /// value, starting with the test at `pc`. Returns the assignment of the
+    /// value, the pending values after it and where it ends.
+    pub(super) fn value_region(
+        &mut self,
+        st: &State,
+        pc: usize,
+        stop: usize,
+    ) -> Result<Option<Region>, DecompileError> {
+        // nested tests are each tried as the start of a region, and so are
+        // the tests nested in those, which takes exponential time unless
+        // the regions are remembered
+        let key = (pc, stop, st.shape());
+        match self.value_regions.get(&key) {
+            Some(None) => return Ok(None),
+            Some(Some((found_for, region))) if found_for == st => {
+                return Ok(Some(region.clone()));
+            }
+            _ => {}
+        }
+        let region = self.find_value_region(st, pc, stop)?;
+        let found = region.clone().map(|region| (st.clone(), region));
+        self.value_regions.insert(key, found);
+        Ok(region)
+    }
+
+    fn find_value_region(
+        &mut self,
+        st: &State,
+        pc: usize,
+        stop: usize,
+    ) -> Result<Option<Region>, DecompileError> {
+        let chunk = self.chunk;
+        let code = &chunk.code;
+        let mut st = st.clone();
+        let mut items: Vec<Item> = Vec::new();
+        let mut before_leaf = BTreeSet::new();
+        let mut leaf_store = None;
+        let mut cur = pc;
+        let (leaf_end, false_at, end) = loop {
+            if let Some(max) = items.iter().map(|item| item.target).max() {
+                let targeted = |at: usize| {
+                    items
+                        .iter()
+                        .any(|item| item.target == at || item.target == at + 1)
+                };
+                if self.is_bool_pair(cur) && targeted(cur) {
+                    break (cur, Some(cur), cur + 2);
+                }
+                if code[cur].get_op() == Op::Jmp
+                    && self.jump_target(cur) == cur + 3
+                    && self.is_bool_pair(cur + 1)
+                    && targeted(cur + 1)
+                {
+                    break (cur, Some(cur + 1), cur + 3);
+                }
+                if cur == max {
+                    break (cur, None, cur);
+                }
+            }
+            if leaf_store.is_some()
+                || cur >= stop
+                || cur > pc && (self.is_loop_header(cur) || self.starts_local(cur))
+            {
+                return Ok(None);
+            }
+            if self.is_test(cur) {
+                if cur > pc {
+                    if let Some((Step::Pure, inner, next)) = self.value_region(&st, cur, stop)? {
+                        if !items
+                            .iter()
+                            .any(|item| cur < item.target && item.target < next)
+                        {
+                            st = inner;
+                            cur = next;
+                            continue;
+                        }
+                    }
+                }
+                let start = items.last().map_or(pc, |item| item.next);
+                let Ok(item) = self.read_test(&mut st, cur, start) else {
+                    return Ok(None);
+                };
+                if item.target <= item.next || item.target > stop {
+                    return Ok(None);
+                }
+                items.push(item);
+                before_leaf = st.regs();
+                cur += 2;
+                continue;
+            }
+            match self.exec(&mut st, cur) {
+                Ok((Step::Pure, next)) => cur = next,
+                Ok((
+                    Step::Store(Store {
+                        value: Some(value),
+                        local: Some(reg),
+                        ..
+                    }),
+                    next,
+                )) => {
+                    leaf_store = Some((reg, value));
+                    cur = next;
+                }
+                _ => return Ok(None),
+            }
+        };
+
+        // the register the value ends up in
+        let mut regs = BTreeSet::new();
+        for item in &items {
+            if code[item.next - 2].get_op() == Op::TestSet {
+                regs.extend(item.reg);
+            }
+        }
+        if let Some(at) = false_at {
+            regs.insert(code[at].get_a());
+        }
+        let last = items.last().unwrap();
+        let leaf = match leaf_store {
+            Some((reg, value)) => {
+                regs.insert(reg);
+                Some(value)
+            }
+            None if leaf_end > last.next => {
+                let written = st
+                    .regs()
+                    .difference(&before_leaf)
+                    .copied()
+                    .collect::<Vec<_>>();
+                let [reg] = written[..] else {
+                    return Ok(None);
+                };
+                regs.insert(reg);
+                match self.value(&mut st, pc, reg) {
+                    Ok((Some(value), _)) => Some(value),
+                    _ => return Ok(None),
+                }
+            }
+            None => None,
+        };
+        let reg = match regs.into_iter().collect::<Vec<_>>()[..] {
+            [reg] => reg,
+            _ => return Ok(None),
+        };
+        if leaf.is_none() && false_at != Some(last.next) {
+            return Ok(None);
+        }
+
+        let mut parts = items.iter().cloned().map(Part::Item).collect::<Vec<_>>();
+        let leaf_start = last.next;
+        parts.extend(leaf.map(|leaf| Part::Leaf(leaf_start, leaf)));
+        let mut exits = BTreeMap::new();
+        exits.insert(end, Kind::Value(reg));
+        if let Some(at) = false_at {
+            exits.insert(at, Kind::Const(false));
+            exits.insert(at + 1, Kind::Const(true));
+        }
+        let Some(value) = build(&parts, Kind::Const(false), &exits) else {
+            return Ok(None);
+        };
+        let step = self.write(&mut st, pc, reg, value, None);
+        Ok(Some((step, st, end)))
+    }
+
+    /// `LOADBOOL R 0 1; LOADBOOL R 1 0`, which turns jumps into a boolean.
+    fn is_bool_pair(&self, pc: usize) -> bool {
+        let code = &self.chunk.code;
+        let (Some(first), Some(second)) = (code.get(pc), code.get(pc + 1)) else {
+            return false;
+        };
+        first.get_op() == Op::LoadBool
+            && second.get_op() == Op::LoadBool
+            && first.get_a() == second.get_a()
+            && (first.get_b().raw(), first.get_c().raw()) == (0, 1)
+            && (second.get_b().raw(), second.get_c().raw()) == (1, 0)
+    }
+}
+
+/// Builds the expression of `parts`, given what falling through after the
+/// last of them and jumping to each of `exits` mean.
+pub(super) fn build(parts: &[Part], next: Kind, exits: &BTreeMap<usize, Kind>) -> Option<Expr> {
+    match parts {
+        [Part::Leaf(_, value)] => return Some(value.clone()),
+        [Part::Item(item)] => return test_expr(item, next, exits),
+        _ => {}
+    }
+    // 'a and b' leaves when 'a' is false and continues with 'b' otherwise,
+    // 'a or b' the other way round; the split is at the start of 'b'
+    for k in (1..parts.len()).rev() {
+        let split = parts[k].start();
+        let mut polarity = None;
+        let valid = parts[..k].iter().enumerate().all(|(j, part)| {
+            let Part::Item(item) = part else {
+                return false;
+            };
+            if item.target == split || parts[j + 1..k].iter().any(|p| p.start() == item.target) {
+                return true;
+            }
+            match exits.get(&item.target).and_then(|kind| kind.polarity(item)) {
+                Some(truthy) if polarity.is_none_or(|p| p == truthy) => {
+                    polarity = Some(truthy);
+                    true
+                }
+                _ => false,
+            }
+        });
+        let (true, Some(truthy)) = (valid, polarity) else {
+            continue;
+        };
+        let mut inner = exits.clone();
+        inner.insert(split, Kind::Branch(!truthy));
+        let lhs = build(&parts[..k], Kind::Branch(!truthy), &inner);
+        let rhs = build(&parts[k..], next, exits);
+        if let (Some(lhs), Some(rhs)) = (lhs, rhs) {
+            let op = match truthy {
+                true => BinOp::Or,
+                false => BinOp::And,
+            };
+            return Some(binary(op, lhs, rhs));
+        }
+    }
+    None
+}
+
+fn test_expr(item: &Item, next: Kind, exits: &BTreeMap<usize, Kind>) -> Option<Expr> {
+    let kind = *exits.get(&item.target)?;
+    let truthy = kind.polarity(item)?;
+    if next.polarity(item) != Some(!truthy) {
+        return None;
+    }
+    let test = item.test.clone();
+    Some(match kind {
+        Kind::Value(_) => test,
+        // the value must be a boolean
+        Kind::Const(_) if !item.compare => match item.jump_if == truthy {
+            true => unary(UnOp::Not, unary(UnOp::Not, test)),
+            false => unary(UnOp::Not, test),
+        },
+        _ => match item.jump_if == truthy {
+            true => test,
+            false => negate(test),
+        },
+    })
+}
+
+pub(super) fn negate(value: Expr) -> Expr {
+    match value.kind {
+        ExprKind::Binary {
+            op: BinOp::Eq,
+            op_line,
+            lhs,
+            rhs,
+        } => Expr {
+            kind: ExprKind::Binary {
+                op: BinOp::Ne,
+                op_line,
+                lhs,
+                rhs,
+            },
+            span: value.span,
+        },
+        _ => unary(UnOp::Not, value),
+    }
+}
@@ -0,0 +1,726 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem::Discriminant;
+use std::ops::Range;
+
+use crate::compiler::ast::{BinOp, CallStyle, Expr, ExprKind, Field, FunctionBody, Name, UnOp};
+use crate::decompiler::{expr, name, DecompileError, Function};
+use crate::file::Constant;
+use crate::instruction::ArgK;
+use crate::opcode::{Op, Opcode};
+
+/// What a register holds between being written and being consumed.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Slot {
+    Expr(Expr),
+    /// A call or `...` whose results run up to the top of the stack.
+    Open(Expr),
+    /// The first of several results of a call or `...`.
+    Fixed(Expr),
+    /// One of the results after a `Fixed` one.
+    Cont,
+    /// A call without results evaluated as part of a list.
+    Void(Expr),
+    /// A table constructor still being filled in.
+    Table(Vec<Field>),
+    /// The function of a method call, set up by `SELF`.
+    Method(Expr, Name),
+    /// The object a method is called on.
+    SelfArg,
+}
+
+/// The registers of a `State` and the kinds of values they hold.
+pub(super) type Shape = Vec<(u8, Discriminant<Slot>)>;
+
+/// The temporaries written and not yet consumed, by register.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(super) struct State {
+    slots: BTreeMap<u8, Slot>,
+}
+
+impl State {
+    pub(super) fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub(super) fn contains(&self, reg: u8) -> bool {
+        self.slots.contains_key(&reg)
+    }
+
+    pub(super) fn set(&mut self, reg: u8, slot: Slot) {
+        self.slots.insert(reg, slot);
+    }
+
+    pub(super) fn take(&mut self, reg: u8) -> Option<Slot> {
+        self.slots.remove(&reg)
+    }
+
+    pub(super) fn lowest(&self) -> Option<u8> {
+        self.slots.keys().next().copied()
+    }
+
+    fn highest(&self) -> Option<u8> {
+        self.slots.keys().next_back().copied()
+    }
+
+    pub(super) fn regs(&self) -> BTreeSet<u8> {
+        self.slots.keys().copied().collect()
+    }
+
+    pub(super) fn shape(&self) -> Shape {
+        (self.slots.iter())
+            .map(|(&reg, slot)| (reg, std::mem::discriminant(slot)))
+            .collect()
+    }
+}
+
+/// The effect of an instruction that is not only an expression.
+#[derive(Clone)]
+pub(super) enum Step {
+    Pure,
+    Store(Store),
+    /// A call whose results are discarded, with its base register.
+    Call(u8, Expr),
+    Return(Vec<Expr>),
+    /// `nil` assigned to locals.
+    Nil(Vec<Name>),
+}
+
+/// An assignment of one value.
+#[derive(Clone)]
+pub(super) struct Store {
+    pub target: Expr,
+    /// `None` for one of the later results of a call or `...`.
+    pub value: Option<Expr>,
+    /// The temporary the value was taken from.
+    pub reg: Option<u8>,
+    /// The register of a local target.
+    pub local: Option<u8>,
+}
+
+impl Function<'_> {
+    /// Runs the expression instruction at `pc`, returning what it does
+    /// besides writing temporaries and the pc of the next instruction.
+    pub(super) fn exec(
+        &mut self,
+        st: &mut State,
+        pc: usize,
+    ) -> Result<(Step, usize), DecompileError> {
+        let instr = self.chunk.code[pc];
+        let a = instr.get_a();
+        let b = instr.get_b();
+        let c = instr.get_c();
+        let step = match instr.get_op() {
+            Op::Move => {
+                let from = st.contains(b.value()).then(|| b.value());
+                let value = self.operand(st, pc, b.value())?;
+                self.write(st, pc, a, value, from)
+            }
+            Op::LoadK => {
+                let value = self.constant(pc, instr.get_bx() as usize)?;
+                self.write(st, pc, a, value, None)
+            }
+            Op::LoadKx => {
+                let extra = match self.chunk.code.get(pc + 1) {
+                    Some(extra) if extra.get_op() == Op::ExtraArg => extra.get_ax(),
+                    _ => return self.error(pc, "LOADKX without EXTRAARG"),
+                };
+                let value = self.constant(pc, extra as usize)?;
+                return Ok((self.write(st, pc, a, value, None), pc + 2));
+            }
+            Op::LoadBool if c.raw() == 0 => {
+                let value = expr(match b.raw() {
+                    0 => ExprKind::False,
+                    _ => ExprKind::True,
+                });
+                self.write(st, pc, a, value, None)
+            }
+            Op::LoadNil => {
+                let mut names = Vec::new();
+                for reg in self.regs(pc, a, b.raw() as usize + 1)? {
+                    match self.local_at(pc, reg) {
+                        Some(local) => names.push(name(&local.name)),
+                        None => st.set(reg, Slot::Expr(expr(ExprKind::Nil))),
+                    }
+                }
+                match names.is_empty() {
+                    true => Step::Pure,
+                    false => Step::Nil(names),
+                }
+            }
+            Op::GetUpval => {
+                let value = expr(ExprKind::Name(self.upvalue_name(pc, b.value())?));
+                self.write(st, pc, a, value, None)
+            }
+            Op::GetTabup => {
+                let key = self.rk(st, pc, &c)?;
+                let value = self.upvalue_index(pc, b.value(), key)?;
+                self.write(st, pc, a, value, None)
+            }
+            Op::GetTable => {
+                let key = self.rk(st, pc, &c)?;
+                let table = self.operand(st, pc, b.value())?;
+                self.write(st, pc, a, index(table, key), None)
+            }
+            Op::SetTabup => {
+                let (value, reg) = self.rk_value(st, pc, &c)?;
+                let key = self.rk(st, pc, &b)?;
+                let target = self.upvalue_index(pc, a, key)?;
+                Step::Store(Store {
+                    target,
+                    value,
+                    reg,
+                    local: None,
+                })
+            }
+            Op::SetUpval => {
+                let (value, reg) = self.value(st, pc, a)?;
+                let target = expr(ExprKind::Name(self.upvalue_name(pc, b.value())?));
+                Step::Store(Store {
+                    target,
+                    value,
+                    reg,
+                    local: None,
+                })
+            }
+            Op::SetTable if matches!(st.slots.get(&a), Some(Slot::Table(_))) => {
+                let value = self.rk(st, pc, &c)?;
+                let key = self.rk(st, pc, &b)?;
+                // list items written so far come before this field
+                let items = self.items(st, pc, a + 1)?;
+                let Some(Slot::Table(fields)) = st.slots.get_mut(&a) else {
+                    unreachable!()
+                };
+                fields.extend(items.into_iter().map(Field::Positional));
+                fields.push(match key.kind {
+                    ExprKind::String(ref name) if is_name(name) => Field::Named {
+                        name: Name {
+                            name: String::from_utf8_lossy(name).into_owned(),
+                            span: key.span,
+                        },
+                        value,
+                    },
+                    _ => Field::Keyed { key, value },
+                });
+                Step::Pure
+            }
+            Op::SetTable => {
+                let (value, reg) = self.rk_value(st, pc, &c)?;
+                let key = self.rk(st, pc, &b)?;
+                let table = self.operand(st, pc, a)?;
+                Step::Store(Store {
+                    target: index(table, key),
+                    value,
+                    reg,
+                    local: None,
+                })
+            }
+            Op::NewTable => {
+                st.set(a, Slot::Table(Vec::new()));
+                Step::Pure
+            }
+            Op::LuaSelf => {
+                let key = self.rk(st, pc, &c)?;
+                let object = self.operand(st, pc, b.value())?;
+                let ExprKind::String(method) = key.kind else {
+                    return self.error(pc, "SELF with a key that is not a string");
+                };
+                let method = name(&String::from_utf8_lossy(&method));
+                let Some(arg) = a.checked_add(1) else {
+                    return self.error(pc, "register out of range");
+                };
+                st.set(a, Slot::Method(object, method));
+                st.set(arg, Slot::SelfArg);
+                Step::Pure
+            }
+            op @ (Op::Add
+            | Op::Sub
+            | Op::Mul
+            | Op::Mod
+            | Op::Pow
+            | Op::Div
+            | Op::IDiv
+            | Op::BAnd
+            | Op::BOr
+            | Op::BXor
+            | Op::Shl
+            | Op::Shr) => {
+                let lhs = self.rk(st, pc, &b)?;
+                let rhs = self.rk(st, pc, &c)?;
+                let value = binary(binary_op(op), lhs, rhs);
+                self.write(st, pc, a, value, None)
+            }
+            op @ (Op::Unm | Op::BNot | Op::Not | Op::Len) => {
+                let operand = self.operand(st, pc, b.value())?;
+                let op = match op {
+                    Op::Unm => UnOp::Minus,
+                    Op::BNot => UnOp::BNot,
+                    Op::Not => UnOp::Not,
+                    _ => UnOp::Len,
+                };
+                self.write(st, pc, a, unary(op, operand), None)
+            }
+            Op::Concat => {
+                let mut operands = (b.value()..=c.value())
+                    .map(|reg| self.operand(st, pc, reg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let Some(mut value) = operands.pop() else {
+                    return self.error(pc, "CONCAT without operands");
+                };
+                while let Some(lhs) = operands.pop() {
+                    value = binary(BinOp::Concat, lhs, value);
+                }
+                self.write(st, pc, a, value, None)
+            }
+            Op::Call => self.call(st, pc)?,
+            Op::Tailcall => {
+                self.call(st, pc)?;
+                Step::Pure
+            }
+            Op::Return => {
+                let values = match b.raw() {
+                    0 => self.open_list(st, pc, a)?,
+                    n => self.list(st, pc, a, n as usize - 1, None)?,
+                };
+                Step::Return(values)
+            }
+            Op::SetList => {
+                let Some(Slot::Table(_)) = st.slots.get(&a) else {
+                    return self.error(pc, "SETLIST without a table constructor");
+                };
+                let items = match b.raw() {
+                    // items before a keyed field were already taken
+                    0 => {
+                        let first = st
+                            .slots
+                            .range(a + 1..)
+                            .next()
+                            .map_or(a + 1, |(reg, _)| *reg);
+                        self.open_list(st, pc, first)?
+                    }
+                    _ => {
+                        let mut items = self.items(st, pc, a + 1)?;
+                        close_list(&mut items, false, None);
+                        items
+                    }
+                };
+                let Some(Slot::Table(fields)) = st.slots.get_mut(&a) else {
+                    unreachable!()
+                };
+                fields.extend(items.into_iter().map(Field::Positional));
+                let next = match c.raw() {
+                    0 => pc + 2,
+                    _ => pc + 1,
+                };
+                return Ok((Step::Pure, next));
+            }
+            Op::Closure => {
+                let body = self.closure(pc, instr.get_bx() as usize)?;
+                let value = expr(ExprKind::Function(Box::new(body)));
+                self.write(st, pc, a, value, None)
+            }
+            Op::VarArg => {
+                let value = expr(ExprKind::VarArg);
+                match b.raw() {
+                    0 => {
+                        st.set(a, Slot::Open(value));
+                        Step::Pure
+                    }
+                    1 => Step::Pure,
+                    2 => self.write(st, pc, a, value, None),
+                    n => {
+                        st.set(a, Slot::Fixed(value));
+                        for reg in self.regs(pc, a, n as usize - 1)?.skip(1) {
+                            st.set(reg, Slot::Cont);
+                        }
+                        Step::Pure
+                    }
+                }
+            }
+            op => return self.error(pc, format!("unexpected {}", op.name())),
+        };
+        Ok((step, pc + 1))
+    }
+
+    /// `value` written to `reg`: an assignment if it holds a local, a
+    /// temporary otherwise.
+    pub(super) fn write(
+        &self,
+        st: &mut State,
+        pc: usize,
+        reg: u8,
+        value: Expr,
+        from: Option<u8>,
+    ) -> Step {
+        match self.local_at(pc, reg) {
+            Some(local) => Step::Store(Store {
+                target: expr(ExprKind::Name(local.name.clone())),
+                value: Some(value),
+                reg: from,
+                local: Some(reg),
+            }),
+            None => {
+                st.set(reg, Slot::Expr(value));
+                Step::Pure
+            }
+        }
+    }
+
+    fn call(&mut self, st: &mut State, pc: usize) -> Result<Step, DecompileError> {
+        let instr = self.chunk.code[pc];
+        let base = instr.get_a();
+        let (func, first) = match st.take(base) {
+            Some(Slot::Method(object, method)) => match st.take(base + 1) {
+                Some(Slot::SelfArg) => (Err((object, method)), base + 2),
+                _ => return self.error(pc, "method call without its object"),
+            },
+            Some(Slot::Expr(func)) => (Ok(func), base + 1),
+            None => (Ok(self.operand(st, pc, base)?), base + 1),
+            Some(_) => return self.error(pc, "call of an unfinished value"),
+        };
+        let args = match instr.get_b().raw() {
+            0 => self.open_list(st, pc, first)?,
+            n => {
+                let count = (base as usize + n as usize).saturating_sub(first as usize);
+                self.list(st, pc, first, count, None)?
+            }
+        };
+        let call = expr(match func {
+            Ok(func) => ExprKind::Call {
+                func: Box::new(func),
+                args,
+                style: CallStyle::Parens,
+            },
+            Err((object, name)) => ExprKind::Method {
+                object: Box::new(object),
+                name,
+                args,
+                style: CallStyle::Parens,
+            },
+        });
+        Ok(match instr.get_c().raw() {
+            _ if instr.get_op() == Op::Tailcall => {
+                st.set(base, Slot::Open(call));
+                Step::Pure
+            }
+            0 => {
+                st.set(base, Slot::Open(call));
+                Step::Pure
+            }
+            1 => Step::Call(base, call),
+            2 => {
+                st.set(base, Slot::Expr(call));
+                Step::Pure
+            }
+            n => {
+                st.set(base, Slot::Fixed(call));
+                for reg in self.regs(pc, base, n as usize - 1)?.skip(1) {
+                    st.set(reg, Slot::Cont);
+                }
+                Step::Pure
+            }
+        })
+    }
+
+    fn closure(&mut self, pc: usize, index: usize) -> Result<FunctionBody, DecompileError> {
+        let Some(proto) = self.chunk.prototypes.get(index) else {
+            return self.error(pc, "missing prototype");
+        };
+        if let Some(body) = &self.closures[index] {
+            return Ok(body.clone());
+        }
+        let mut names = Vec::new();
+        for (i, upvalue) in proto.upvalues.iter().enumerate() {
+            let name = match proto.upvalue_names.get(i) {
+                Some(name) => name.clone(),
+                // a function can capture the local it is assigned to
+                None if upvalue.in_stack => match self
+                    .local_at(pc, upvalue.index)
+                    .or_else(|| self.local_at(pc + 1, upvalue.index))
+                {
+                    Some(local) => local.name.clone(),
+                    None => return self.error(pc, "capture of a register without a local"),
+                },
+                None => self.upvalue_name(pc, upvalue.index)?,
+            };
+            names.push(name);
+        }
+        let body = Function::new(proto, names, self.depth + 1).body()?;
+        self.closures[index] = Some(body.clone());
+        Ok(body)
+    }
+
+    /// The value of `reg`, and the temporary it was taken from.
+    pub(super) fn value(
+        &self,
+        st: &mut State,
+        pc: usize,
+        reg: u8,
+    ) -> Result<(Option<Expr>, Option<u8>), DecompileError> {
+        let value = match st.take(reg) {
+            Some(Slot::Expr(value) | Slot::Open(value) | Slot::Fixed(value)) => Some(value),
+            Some(Slot::Table(fields)) => Some(expr(ExprKind::Table(fields))),
+            Some(Slot::Cont) => None,
+            Some(_) => return self.error(pc, format!("register {} is not a value", reg)),
+            None => match self.local_at(pc, reg) {
+                Some(local) => return Ok((Some(expr(ExprKind::Name(local.name.clone()))), None)),
+                None => return self.error(pc, format!("register {} has no value", reg)),
+            },
+        };
+        Ok((value, Some(reg)))
+    }
+
+    pub(super) fn operand(
+        &self,
+        st: &mut State,
+        pc: usize,
+        reg: u8,
+    ) -> Result<Expr, DecompileError> {
+        match self.value(st, pc, reg)? {
+            (Some(value), _) => Ok(value),
+            (None, _) => self.error(pc, format!("register {} is part of a list", reg)),
+        }
+    }
+
+    fn rk_value(
+        &self,
+        st: &mut State,
+        pc: usize,
+        arg: &ArgK,
+    ) -> Result<(Option<Expr>, Option<u8>), DecompileError> {
+        match arg.is_constant() {
+            true => Ok((Some(self.constant(pc, arg.index_k() as usize)?), None)),
+            false => self.value(st, pc, arg.value()),
+        }
+    }
+
+    pub(super) fn rk(&self, st: &mut State, pc: usize, arg: &ArgK) -> Result<Expr, DecompileError> {
+        match arg.is_constant() {
+            true => self.constant(pc, arg.index_k() as usize),
+            false => self.operand(st, pc, arg.value()),
+        }
+    }
+
+    fn constant(&self, pc: usize, index: usize) -> Result<Expr, DecompileError> {
+        let kind = match self.chunk.constants.get(index) {
+            Some(Constant::Nil) => ExprKind::Nil,
+            Some(Constant::Boolean(true)) => ExprKind::True,
+            Some(Constant::Boolean(false)) => ExprKind::False,
+            Some(Constant::IntegralNumber(value)) => ExprKind::Integer(*value as i64),
+            Some(Constant::FloatingNumber(value)) => ExprKind::Float(*value),
+            Some(Constant::String(value)) => ExprKind::String(value.clone()),
+            None => return self.error(pc, format!("missing constant {}", index)),
+        };
+        Ok(expr(kind))
+    }
+
+    pub(super) fn upvalue_name(&self, pc: usize, index: u8) -> Result<String, DecompileError> {
+        match self.upvalue_names.get(index as usize) {
+            Some(name) => Ok(name.clone()),
+            None => self.error(pc, format!("missing upvalue {}", index)),
+        }
+    }
+
+    /// `upvalue[key]`, which is a global when the upvalue is `_ENV` and no
+    /// variable hides the global's name.
+    fn upvalue_index(&self, pc: usize, upvalue: u8, key: Expr) -> Result<Expr, DecompileError> {
+        let table = self.upvalue_name(pc, upvalue)?;
+        if let ExprKind::String(global) = &key.kind {
+            let global = String::from_utf8_lossy(global);
+            let hidden = self.upvalue_names.iter().any(|name| *name == global)
+                || self
+                    .locals
+                    .iter()
+                    .any(|local| local.name == global && local.start <= pc && pc < local.end);
+            if Some(upvalue) == self.env && is_name(global.as_bytes()) && !hidden {
+                return Ok(expr(ExprKind::Name(global.into_owned())));
+            }
+        }
+        Ok(index(expr(ExprKind::Name(table)), key))
+    }
+
+    /// The `count` registers from `first`, which malformed code can place
+    /// past the last register.
+    pub(super) fn regs(
+        &self,
+        pc: usize,
+        first: u8,
+        count: usize,
+    ) -> Result<Range<u8>, DecompileError> {
+        match u8::try_from(first as usize + count) {
+            Ok(end) => Ok(first..end),
+            Err(_) => self.error(pc, "register out of range"),
+        }
+    }
+
+    /// The values of the `count` registers from `first`, and any values
+    /// evaluated after them only for their side effects. `targets` is how
+    /// many variables the values are assigned to, when they are.
+    pub(super) fn list(
+        &self,
+        st: &mut State,
+        pc: usize,
+        first: u8,
+        count: usize,
+        targets: Option<usize>,
+    ) -> Result<Vec<Expr>, DecompileError> {
+        let mut values = Vec::new();
+        let mut open = false;
+        let regs = self.regs(pc, first, count)?;
+        for reg in regs.clone() {
+            open = matches!(st.slots.get(&reg), Some(Slot::Fixed(_) | Slot::Cont));
+            if let Some(Slot::Void(value)) = st.slots.get(&reg) {
+                values.push(value.clone());
+                st.take(reg);
+                continue;
+            }
+            if let (Some(value), _) = self.value(st, pc, reg)? {
+                values.push(value);
+            }
+        }
+        let extras = match targets {
+            Some(_) => self.items(st, pc, regs.end)?,
+            None => Vec::new(),
+        };
+        if extras.is_empty() {
+            close_list(&mut values, open, targets);
+        }
+        values.extend(extras);
+        Ok(values)
+    }
+
+    /// The values from `first` up to a call or `...` whose results are all
+    /// used.
+    fn open_list(&self, st: &mut State, pc: usize, first: u8) -> Result<Vec<Expr>, DecompileError> {
+        match st.highest() {
+            Some(top) if top >= first && matches!(st.slots.get(&top), Some(Slot::Open(_))) => {
+                let mut values = Vec::new();
+                for reg in first..=top {
+                    values.push(self.operand(st, pc, reg)?);
+                }
+                Ok(values)
+            }
+            _ => self.error(pc, "no call or '...' to take values up to"),
+        }
+    }
+
+    /// The pending values from `first` upwards, which are list items of a
+    /// table constructor or values assigned to nothing.
+    pub(super) fn items(
+        &self,
+        st: &mut State,
+        pc: usize,
+        first: u8,
+    ) -> Result<Vec<Expr>, DecompileError> {
+        let regs = st
+            .slots
+            .range(first..)
+            .map(|(reg, _)| *reg)
+            .collect::<Vec<_>>();
+        regs.into_iter()
+            .map(|reg| match st.take(reg) {
+                Some(Slot::Void(value)) => Ok(value),
+                Some(slot) => {
+                    st.set(reg, slot);
+                    self.operand(st, pc, reg)
+                }
+                None => unreachable!(),
+            })
+            .collect()
+    }
+}
+
+/// Ends a list of values the way it was compiled: `nil` padding is left to
+/// the assignment and a single result of a call or `...` that would
+/// otherwise be expanded is parenthesized.
+pub(super) fn close_list(values: &mut Vec<Expr>, open: bool, targets: Option<usize>) {
+    let targets_left = |values: &Vec<Expr>| targets.is_none_or(|n| values.len() <= n);
+    let mut open = open;
+    while values.len() > 1
+        && targets.is_some()
+        && targets_left(values)
+        && matches!(
+            values.last(),
+            Some(Expr {
+                kind: ExprKind::Nil,
+                ..
+            })
+        )
+    {
+        values.pop();
+        open = false;
+    }
+    let short = targets.is_none_or(|n| values.len() < n);
+    if let Some(last) = values.last_mut() {
+        let multi = matches!(
+            last.kind,
+            ExprKind::Call { .. } | ExprKind::Method { .. } | ExprKind::VarArg
+        );
+        if multi && !open && short {
+            let inner = std::mem::replace(last, expr(ExprKind::Nil));
+            *last = expr(ExprKind::Paren(Box::new(inner)));
+        }
+    }
+}
+
+pub(super) fn binary(op: BinOp, lhs: Expr, rhs: Expr) -> Expr {
+    expr(ExprKind::Binary {
+        op,
+        op_line: 0,
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+    })
+}
+
+pub(super) fn unary(op: UnOp, operand: Expr) -> Expr {
+    expr(ExprKind::Unary {
+        op,
+        op_line: 0,
+        operand: Box::new(operand),
+    })
+}
+
+fn binary_op(op: Op) -> BinOp {
+    match op {
+        Op::Add => BinOp::Add,
+        Op::Sub => BinOp::Sub,
+        Op::Mul => BinOp::Mul,
+        Op::Mod => BinOp::Mod,
+        Op::Pow => BinOp::Pow,
+        Op::Div => BinOp::Div,
+        Op::IDiv => BinOp::IDiv,
+        Op::BAnd => BinOp::BAnd,
+        Op::BOr => BinOp::BOr,
+        Op::BXor => BinOp::BXor,
+        Op::Shl => BinOp::Shl,
+        _ => BinOp::Shr,
+    }
+}
+
+/// `table.key` when the key is a name, `table[key]` otherwise.
+fn index(table: Expr, key: Expr) -> Expr {
+    expr(match key.kind {
+        ExprKind::String(ref field) if is_name(field) => ExprKind::Field {
+            table: Box::new(table),
+            name: name(&String::from_utf8_lossy(field)),
+        },
+        _ => ExprKind::Index {
+            table: Box::new(table),
+            key: Box::new(key),
+        },
+    })
+}
+
+const KEYWORDS: [&str; 22] = [
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+fn is_name(name: &[u8]) -> bool {
+    match name.first() {
+        Some(first) if first.is_ascii_alphabetic() || *first == b'_' => {
+            name.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_')
+                && !KEYWORDS.iter().any(|keyword| keyword.as_bytes() == name)
+        }
+        _ => false,
+    }
+}
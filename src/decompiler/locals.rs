@@ -0,0 +1,948 @@
+//! Locals of functions stripped of their debug information.
+//!
+//! The compiler keeps locals in the lowest registers and evaluates
+//! expressions in the free registers above them, where every value is
+//! consumed once, by the next instruction reading it, before the registers
+//! above it are freed. A value read several times or never, kept over a
+//! loop header or a statement, captured by a closure or sitting below a
+//! local was therefore held by a local. The scope of a local ends where a
+//! temporary reuses its register or one below it, where a jump closes its
+//! upvalues at the end of a block, with the body of the loop it is in,
+//! after a `return` ending its block, or at the first code its declaration
+//! does not dominate.
+//!
+//! The same code can come from different sources, so the locals found are
+//! only ones that compile to the same code in most cases. A local never
+//! read after an `and` or `or` computing its value can be taken for one
+//! declared at the end of a branch.
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::decompiler::LocalVar;
+use crate::file::chunk::Chunk;
+use crate::instruction::ArgK;
+use crate::opcode::{Op, Opcode};
+
+/// The registers an instruction reads and writes.
+#[derive(Default)]
+struct Access {
+    reads: Vec<u8>,
+    writes: Vec<u8>,
+}
+
+/// The writes of a register reaching the same reads.
+struct Web {
+    reg: u8,
+    defs: Vec<usize>,
+    uses: Vec<usize>,
+    /// The last store filling in the table constructor written.
+    filled: Option<usize>,
+    local: bool,
+}
+
+/// A local being built, open while `end` is None.
+struct Scope {
+    reg: u8,
+    start: usize,
+    end: Option<usize>,
+    /// Where the scope ends regardless of the code in it.
+    fixed_end: Option<usize>,
+    hidden: Option<&'static str>,
+}
+
+/// Makes up the locals of `chunk`, which must have none. Their names are
+/// unique among the functions `depth` levels deep, so a function nested in
+/// another one does not hide the locals it captures.
+pub(super) fn infer(chunk: &Chunk, depth: usize) -> Vec<LocalVar> {
+    let params = (0..chunk.num_params).map(|reg| Scope {
+        reg,
+        start: 0,
+        end: Some(chunk.code.len()),
+        fixed_end: None,
+        hidden: None,
+    });
+    let scopes = match Flow::build(chunk) {
+        Some(flow) => Analysis::new(chunk, flow).scopes(),
+        None => params.collect(),
+    };
+    let mut count = 0;
+    let mut locals = scopes
+        .into_iter()
+        .map(|scope| LocalVar {
+            name: match scope.hidden {
+                Some(name) => name.to_owned(),
+                None => {
+                    count += 1;
+                    format!("l{}_{}", depth, count - 1)
+                }
+            },
+            reg: scope.reg,
+            start: scope.start,
+            end: scope.end.unwrap_or(chunk.code.len()),
+        })
+        .collect::<Vec<_>>();
+    locals.sort_by_key(|local| (local.start, local.reg));
+    locals
+}
+
+struct Analysis<'a> {
+    chunk: &'a Chunk,
+    flow: Flow,
+    access: Vec<Access>,
+    webs: Vec<Web>,
+    /// The web of every register written, by the pc writing it.
+    web_of: HashMap<(usize, u8), usize>,
+    /// Targets of jumps back.
+    loop_headers: BTreeSet<usize>,
+    /// Stores filling in table constructors.
+    fills: HashSet<usize>,
+}
+
+impl<'a> Analysis<'a> {
+    fn new(chunk: &'a Chunk, flow: Flow) -> Analysis<'a> {
+        let code = &chunk.code;
+        let loop_headers = (0..code.len())
+            .filter(|&pc| matches!(code[pc].get_op(), Op::Jmp | Op::ForLoop | Op::TForLoop))
+            .map(|pc| (pc, jump_target(chunk, pc)))
+            .filter(|&(pc, target)| target <= pc)
+            .map(|(_, target)| target)
+            .collect();
+        let mut analysis = Analysis {
+            chunk,
+            flow,
+            access: (0..code.len()).map(|pc| access(chunk, pc)).collect(),
+            webs: Vec::new(),
+            web_of: HashMap::new(),
+            loop_headers,
+            fills: HashSet::new(),
+        };
+        analysis.webs();
+        analysis.classify();
+        analysis
+    }
+
+    /// The reads of `reg` that the write of it at `pc` reaches.
+    fn uses(&self, pc: usize, reg: u8) -> Vec<usize> {
+        let mut uses = Vec::new();
+        let mut seen = vec![false; self.access.len()];
+        let mut work = self.flow.successors[pc].clone();
+        while let Some(at) = work.pop() {
+            if std::mem::replace(&mut seen[at], true) {
+                continue;
+            }
+            if self.access[at].reads.contains(&reg) {
+                uses.push(at);
+            }
+            if !self.access[at].writes.contains(&reg) {
+                work.extend(&self.flow.successors[at]);
+            }
+        }
+        uses.sort_unstable();
+        uses
+    }
+
+    /// The writes of `reg` reaching `pc`.
+    fn reaching(&self, pc: usize, reg: u8) -> Vec<usize> {
+        let mut defs = Vec::new();
+        let mut seen = vec![false; self.access.len()];
+        let mut work = self.flow.predecessors[pc].clone();
+        while let Some(at) = work.pop() {
+            if std::mem::replace(&mut seen[at], true) {
+                continue;
+            }
+            match self.access[at].writes.contains(&reg) {
+                true => defs.push(at),
+                false => work.extend(&self.flow.predecessors[at]),
+            }
+        }
+        defs
+    }
+
+    /// Groups the writes reaching the same reads into webs.
+    fn webs(&mut self) {
+        let defs = (0..self.access.len())
+            .flat_map(|pc| self.access[pc].writes.iter().map(move |&reg| (pc, reg)))
+            .collect::<Vec<_>>();
+        let mut parent = (0..defs.len()).collect::<Vec<_>>();
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        let mut uses = Vec::new();
+        let mut readers = HashMap::new();
+        for (i, &(pc, reg)) in defs.iter().enumerate() {
+            let found = self.uses(pc, reg);
+            for &at in &found {
+                match readers.entry((at, reg)) {
+                    Entry::Occupied(entry) => {
+                        let (a, b) = (root(&mut parent, i), root(&mut parent, *entry.get()));
+                        parent[a] = b;
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert(i);
+                    }
+                }
+            }
+            uses.push(found);
+        }
+        // the writes making up one value computed across jumps reach where
+        // it is complete, even when nothing reads it
+        let ids = defs
+            .iter()
+            .enumerate()
+            .map(|(i, &def)| (def, i))
+            .collect::<HashMap<_, _>>();
+        for (i, &(pc, reg)) in defs.iter().enumerate() {
+            let done = completed(self.chunk, pc);
+            if done == pc + 1 || done >= self.access.len() {
+                continue;
+            }
+            for at in self.reaching(done, reg) {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, ids[&(at, reg)]));
+                parent[a] = b;
+            }
+        }
+        let mut index = HashMap::new();
+        for (i, &(pc, reg)) in defs.iter().enumerate() {
+            let web = *index.entry(root(&mut parent, i)).or_insert_with(|| {
+                self.webs.push(Web {
+                    reg,
+                    defs: Vec::new(),
+                    uses: Vec::new(),
+                    filled: None,
+                    local: false,
+                });
+                self.webs.len() - 1
+            });
+            self.webs[web].defs.push(pc);
+            self.webs[web].uses.extend(&uses[i]);
+            self.web_of.insert((pc, reg), web);
+        }
+        let code = &self.chunk.code;
+        for web in &mut self.webs {
+            web.uses.sort_unstable();
+            web.uses.dedup();
+            // filling in a table constructor does not consume it, and the
+            // size of the constructor tells how many fields it has
+            if let [def] = web.defs[..] {
+                if code[def].get_op() == Op::NewTable {
+                    let mut fields = fb2int(code[def].get_c().raw());
+                    let mut filled = None;
+                    web.uses.retain(|&at| match code[at].get_op() {
+                        Op::SetList => {
+                            filled = Some(at);
+                            false
+                        }
+                        Op::SetTable if code[at].get_a() == web.reg && fields > 0 => {
+                            fields -= 1;
+                            self.fills.insert(at);
+                            filled = Some(at);
+                            false
+                        }
+                        _ => true,
+                    });
+                    web.filled = filled;
+                }
+            }
+        }
+    }
+
+    /// Tells the webs held by locals from the temporaries.
+    fn classify(&mut self) {
+        let chunk = self.chunk;
+        let code = &chunk.code;
+        for i in 0..self.webs.len() {
+            let local = self.is_loop_variable(i) || !self.is_temporary(i);
+            self.webs[i].local = local;
+        }
+        // a function declared with 'local function' captures itself
+        for (pc, instr) in code.iter().enumerate() {
+            if instr.get_op() == Op::Closure && self.captures(pc).contains(&instr.get_a()) {
+                let web = self.web_of[&(pc, instr.get_a())];
+                self.webs[web].local = true;
+            }
+        }
+
+        // the registers below a local hold locals
+        let mut checked = vec![false; self.webs.len()];
+        loop {
+            let mut changed = self.group_results();
+            for (i, checked) in checked.iter_mut().enumerate() {
+                if !self.webs[i].local || std::mem::replace(checked, true) {
+                    continue;
+                }
+                let web = &self.webs[i];
+                let points = std::iter::once(self.start(web))
+                    .chain(web.uses.iter().copied())
+                    .filter(|&pc| pc < code.len())
+                    .collect::<Vec<_>>();
+                for reg in 0..web.reg {
+                    for &pc in &points {
+                        changed |= self.make_local(pc, reg);
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    /// Makes the values of `reg` reaching `pc` locals.
+    fn make_local(&mut self, pc: usize, reg: u8) -> bool {
+        let mut changed = false;
+        for def in self.reaching(pc, reg) {
+            let web = self.web_of[&(def, reg)];
+            changed |= !std::mem::replace(&mut self.webs[web].local, true);
+        }
+        changed
+    }
+
+    /// Several results of one instruction are all locals or none.
+    fn group_results(&mut self) -> bool {
+        let mut changed = false;
+        for pc in 0..self.access.len() {
+            let webs = (self.access[pc].writes.iter())
+                .map(|&reg| self.web_of[&(pc, reg)])
+                .collect::<Vec<_>>();
+            if webs.len() > 1 && webs.iter().any(|&web| self.webs[web].local) {
+                for web in webs {
+                    changed |= !std::mem::replace(&mut self.webs[web].local, true);
+                }
+            }
+        }
+        changed
+    }
+
+    /// Whether the web holds a variable of a `for` loop, whose scope the
+    /// loop gives.
+    fn is_loop_variable(&self, i: usize) -> bool {
+        let code = &self.chunk.code;
+        self.webs[i]
+            .defs
+            .iter()
+            .any(|&def| match code[def].get_op() {
+                Op::ForPrep | Op::ForLoop | Op::TForCall | Op::TForLoop => true,
+                Op::Jmp => is_generic_for(self.chunk, def),
+                _ => false,
+            })
+    }
+
+    fn is_temporary(&self, i: usize) -> bool {
+        let code = &self.chunk.code;
+        let web = &self.webs[i];
+        let (reg, first) = (web.reg, web.defs[0]);
+        let [consumer] = web.uses[..] else {
+            return false;
+        };
+        if reg < self.chunk.num_params
+            || web.defs.iter().any(|&def| def >= consumer)
+            || self
+                .loop_headers
+                .range(first + 1..=consumer)
+                .next()
+                .is_some()
+            || code[consumer].get_op() == Op::Closure
+        {
+            return false;
+        }
+        // temporaries are evaluated in the lowest free register, which
+        // those of their operands are freed to first
+        if (self.access[consumer].writes.iter()).all(|&write| write > reg)
+            && !self.access[consumer].writes.is_empty()
+        {
+            return false;
+        }
+        for &def in &web.defs {
+            let reads = &self.access[def].reads;
+            if reads.iter().any(|&read| read > reg) && !reads.contains(&reg) {
+                return false;
+            }
+        }
+        // nothing above a temporary outlives it, and only expressions, or
+        // the stores of the multiple assignment it is a value of, are
+        // evaluated while it is pending
+        let pending = first + 1..consumer;
+        !self.webs.iter().any(|other| {
+            other.reg > reg
+                && other.defs[0] < consumer
+                && other.uses.iter().any(|&at| at > consumer)
+        }) && !pending.clone().any(|pc| ends_statement(self.chunk, pc))
+            && (self.is_store(consumer) || !pending.clone().any(|pc| self.is_store(pc)))
+    }
+
+    fn is_store(&self, pc: usize) -> bool {
+        matches!(
+            self.chunk.code[pc].get_op(),
+            Op::SetTabup | Op::SetUpval | Op::SetTable
+        ) && !self.fills.contains(&pc)
+    }
+
+    /// The registers the closure created at `pc` captures.
+    fn captures(&self, pc: usize) -> Vec<u8> {
+        let proto = self
+            .chunk
+            .prototypes
+            .get(self.chunk.code[pc].get_bx() as usize);
+        (proto.iter())
+            .flat_map(|proto| &proto.upvalues)
+            .filter(|upvalue| upvalue.in_stack)
+            .map(|upvalue| upvalue.index)
+            .collect()
+    }
+
+    /// Where a local holding the value of `web` would be declared: the
+    /// first point where the value is complete, with the writes after it
+    /// being assignments in the scope.
+    fn start(&self, web: &Web) -> usize {
+        let len = self.access.len();
+        let mut points = web
+            .defs
+            .iter()
+            .map(|&pc| completed(self.chunk, pc))
+            .collect::<Vec<_>>();
+        points.sort_unstable();
+        let last = points[points.len() - 1];
+        let start = points
+            .into_iter()
+            .find(|&at| {
+                at >= len
+                    || self.covers(&web.defs, at)
+                        && web.defs.iter().all(|&pc| pc < at || self.dominates(at, pc))
+            })
+            .unwrap_or(last);
+        start.max(web.filled.map_or(0, |pc| pc + 1))
+    }
+
+    /// Whether every path to `pc` goes through one of `defs`.
+    fn covers(&self, defs: &[usize], pc: usize) -> bool {
+        let mut seen = vec![false; self.access.len()];
+        let mut work = vec![pc];
+        while let Some(at) = work.pop() {
+            if std::mem::replace(&mut seen[at], true) || (at != pc && defs.contains(&at)) {
+                continue;
+            }
+            if at == 0 {
+                return false;
+            }
+            work.extend(&self.flow.predecessors[at]);
+        }
+        true
+    }
+
+    /// Whether every path to `pc` goes through `start`, taking code no
+    /// path reaches to be dominated by anything.
+    fn dominates(&self, start: usize, pc: usize) -> bool {
+        self.flow.dominates(start, pc) || !self.flow.is_reachable(pc)
+    }
+
+    /// Follows the code in order, opening a scope where a local web starts
+    /// and closing scopes where the code shows they ended.
+    fn scopes(&self) -> Vec<Scope> {
+        let code = &self.chunk.code;
+        let mut scopes = (0..self.chunk.num_params)
+            .map(|reg| Scope {
+                reg,
+                start: 0,
+                end: None,
+                fixed_end: None,
+                hidden: None,
+            })
+            .collect::<Vec<_>>();
+        let mut open: Vec<usize> = (0..scopes.len()).collect();
+        let loops = (0..code.len())
+            .filter_map(|pc| {
+                let end = match code[pc].get_op() {
+                    // the body of a 'while' ends at its jump back, and the
+                    // body of a 'repeat' after its condition, with leaving
+                    // it closing upvalues
+                    Op::Jmp if pc == 0 || !code[pc - 1].get_op().is_test() => pc,
+                    Op::Jmp if closes_block(self.chunk, pc + 1) => pc + 2,
+                    Op::Jmp => pc + 1,
+                    Op::ForLoop => pc,
+                    Op::TForLoop => pc - 1,
+                    _ => return None,
+                };
+                let header = jump_target(self.chunk, pc);
+                (header <= pc).then_some((header, pc, end))
+            })
+            .collect::<Vec<_>>();
+        let mut closing = HashMap::new();
+        for (header, latch, _) in &loops {
+            let a = code[*latch].get_a();
+            if code[*latch].get_op() == Op::Jmp && a > 0 {
+                let reg = closing.entry(*header).or_insert(a - 1);
+                *reg = (*reg).min(a - 1);
+            }
+        }
+        let mut starts: Vec<Vec<usize>> = vec![Vec::new(); code.len() + 1];
+        for (i, web) in self.webs.iter().enumerate() {
+            starts[web.defs[0]].push(i);
+        }
+
+        for pc in 0..code.len() {
+            // scopes ending here
+            open.retain(|&i| {
+                let scope = &mut scopes[i];
+                let ends = scope.fixed_end.map_or_else(
+                    || pc >= scope.start && !self.dominates(scope.start, pc),
+                    |end| end <= pc,
+                );
+                if ends {
+                    scope.end = Some(pc);
+                }
+                !ends
+            });
+            // going around a loop closing upvalues ends the locals of the
+            // body, so any from before the loop end there
+            if let Some(&reg) = closing.get(&pc) {
+                close(&mut scopes, &mut open, reg, pc);
+            }
+            // a 'return' ends its block, so the code after it that no jump
+            // reaches is outside the block, unless it closes its upvalues
+            let returned = pc > 0 && code[pc - 1].get_op() == Op::Return;
+            let closing = code[pc].get_op() == Op::Jmp && code[pc].get_a() > 0;
+            if returned && !closing && !self.flow.is_reachable(pc) {
+                close(&mut scopes, &mut open, self.chunk.num_params, pc);
+            }
+            let lowest = starts[pc]
+                .iter()
+                .filter(|&&web| !self.webs[web].local)
+                .map(|&web| self.webs[web].reg)
+                .min();
+            if let Some(lowest) = lowest {
+                close(&mut scopes, &mut open, lowest, pc);
+            }
+
+            for &i in &starts[pc] {
+                let web = &self.webs[i];
+                // tables and call results are made in fresh registers, so
+                // writing them to a local declares it
+                let fresh = matches!(code[pc].get_op(), Op::NewTable | Op::Call | Op::VarArg);
+                if !web.local
+                    || self.is_loop_variable(i)
+                    || !fresh && open.iter().any(|&open| scopes[open].reg == web.reg)
+                {
+                    continue;
+                }
+                close(&mut scopes, &mut open, web.reg, pc);
+                let start = self.start(web);
+                // a value computed last in a block starts an empty scope
+                let end = (start < code.len() && !self.covers(&web.defs, start)).then_some(start);
+                // a local in a loop body ends with the body
+                let fixed_end = loops
+                    .iter()
+                    .filter(|&&(header, latch, _)| header < start && start <= latch)
+                    .max_by_key(|&&(header, _, end)| (header, end))
+                    .map(|&(_, _, end)| end);
+                scopes.push(Scope {
+                    reg: web.reg,
+                    start,
+                    end,
+                    fixed_end,
+                    hidden: None,
+                });
+                if end.is_none() {
+                    open.push(scopes.len() - 1);
+                }
+            }
+
+            let instr = code[pc];
+            let a = instr.get_a();
+            match instr.get_op() {
+                Op::ForPrep => {
+                    let end = jump_target(self.chunk, pc);
+                    let names = ["(for index)", "(for limit)", "(for step)"];
+                    for (reg, name) in regs(a, 3).into_iter().zip(names) {
+                        open_fixed(&mut scopes, &mut open, reg, pc, end + 1, Some(name));
+                    }
+                    for reg in regs(a, 4).into_iter().skip(3) {
+                        open_fixed(&mut scopes, &mut open, reg, pc + 1, end, None);
+                    }
+                }
+                Op::Jmp if is_generic_for(self.chunk, pc) => {
+                    let call = jump_target(self.chunk, pc);
+                    let base = code[call].get_a();
+                    let names = ["(for generator)", "(for state)", "(for control)"];
+                    for (reg, name) in regs(base, 3).into_iter().zip(names) {
+                        open_fixed(&mut scopes, &mut open, reg, pc, call + 2, Some(name));
+                    }
+                    let vars = code[call].get_c().raw() as usize;
+                    for reg in regs(base, 3 + vars).into_iter().skip(3) {
+                        open_fixed(&mut scopes, &mut open, reg, pc + 1, call, None);
+                    }
+                }
+                // the end of a block closing upvalues
+                Op::Jmp if closes_block(self.chunk, pc) => {
+                    for &i in &open {
+                        if scopes[i].reg + 1 >= a && scopes[i].fixed_end.is_none() {
+                            scopes[i].fixed_end = Some(pc + 1);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        scopes
+    }
+}
+
+/// The ways control can go between the instructions of a function, with
+/// the dominator tree numbered so that dominance takes two comparisons.
+struct Flow {
+    successors: Vec<Vec<usize>>,
+    predecessors: Vec<Vec<usize>>,
+    /// When the depth-first walk of the dominator tree enters and leaves
+    /// each instruction, None for those no path reaches.
+    order: Vec<Option<(usize, usize)>>,
+}
+
+impl Flow {
+    /// None if the code jumps out of the function or runs off its end.
+    fn build(chunk: &Chunk) -> Option<Flow> {
+        let code = &chunk.code;
+        let len = code.len();
+        if len == 0 {
+            return None;
+        }
+        let mut successors = Vec::with_capacity(len);
+        for (pc, instr) in code.iter().enumerate() {
+            let jump = || usize::try_from(pc as i64 + 1 + instr.get_sbx() as i64).ok();
+            let targets = match instr.get_op() {
+                Op::Jmp | Op::ForPrep => vec![jump()?],
+                op if op.is_test() => vec![pc + 1, pc + 2],
+                Op::LoadBool if instr.get_c().raw() != 0 => vec![pc + 2],
+                Op::ForLoop | Op::TForLoop => vec![pc + 1, jump()?],
+                Op::Return => vec![],
+                _ => vec![pc + 1],
+            };
+            if targets.iter().any(|&target| target >= len) {
+                return None;
+            }
+            successors.push(targets);
+        }
+        let mut predecessors = vec![Vec::new(); len];
+        for (pc, targets) in successors.iter().enumerate() {
+            for &target in targets {
+                predecessors[target].push(pc);
+            }
+        }
+        let mut flow = Flow {
+            successors,
+            predecessors,
+            order: vec![None; len],
+        };
+        flow.number(&flow.dominators());
+        Some(flow)
+    }
+
+    /// Cooper, Harvey and Kennedy's iterative algorithm over the
+    /// instructions in reverse postorder.
+    fn dominators(&self) -> Vec<Option<usize>> {
+        let len = self.successors.len();
+        let mut postorder = Vec::with_capacity(len);
+        let mut visited = vec![false; len];
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((pc, next)) = stack.pop() {
+            match self.successors[pc].get(next) {
+                Some(&succ) => {
+                    stack.push((pc, next + 1));
+                    if !std::mem::replace(&mut visited[succ], true) {
+                        stack.push((succ, 0));
+                    }
+                }
+                None => postorder.push(pc),
+            }
+        }
+        let mut rank = vec![0; len];
+        for (i, &pc) in postorder.iter().enumerate() {
+            rank[pc] = i;
+        }
+        let mut idom = vec![None; len];
+        idom[0] = Some(0);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &pc in postorder.iter().rev().skip(1) {
+                let mut preds = (self.predecessors[pc].iter())
+                    .copied()
+                    .filter(|&pred| idom[pred].is_some());
+                let Some(first) = preds.next() else { continue };
+                let new = preds.fold(first, |mut a, mut b| {
+                    while a != b {
+                        while rank[a] < rank[b] {
+                            a = idom[a].unwrap();
+                        }
+                        while rank[b] < rank[a] {
+                            b = idom[b].unwrap();
+                        }
+                    }
+                    a
+                });
+                if idom[pc] != Some(new) {
+                    idom[pc] = Some(new);
+                    changed = true;
+                }
+            }
+        }
+        idom
+    }
+
+    fn number(&mut self, idom: &[Option<usize>]) {
+        let mut children = vec![Vec::new(); idom.len()];
+        for (pc, &parent) in idom.iter().enumerate().skip(1) {
+            if let Some(parent) = parent {
+                children[parent].push(pc);
+            }
+        }
+        let mut count = 0;
+        let mut stack = vec![(0, 0)];
+        while let Some((pc, next)) = stack.pop() {
+            if next == 0 {
+                self.order[pc] = Some((count, 0));
+                count += 1;
+            }
+            match children[pc].get(next) {
+                Some(&child) => {
+                    stack.push((pc, next + 1));
+                    stack.push((child, 0));
+                }
+                None => {
+                    self.order[pc] = self.order[pc].map(|(enter, _)| (enter, count));
+                }
+            }
+        }
+    }
+
+    fn is_reachable(&self, pc: usize) -> bool {
+        self.order[pc].is_some()
+    }
+
+    /// Whether every path from the entry to `pc` goes through `dominator`.
+    fn dominates(&self, dominator: usize, pc: usize) -> bool {
+        match (self.order[dominator], self.order[pc]) {
+            (Some((enter, leave)), Some((at, _))) => enter <= at && at < leave,
+            _ => false,
+        }
+    }
+}
+
+/// Ends the open scopes from `reg` up at `pc`.
+fn close(scopes: &mut [Scope], open: &mut Vec<usize>, reg: u8, pc: usize) {
+    open.retain(|&i| {
+        let ends = scopes[i].reg >= reg;
+        if ends {
+            scopes[i].end = Some(pc);
+        }
+        !ends
+    });
+}
+
+fn open_fixed(
+    scopes: &mut Vec<Scope>,
+    open: &mut Vec<usize>,
+    reg: u8,
+    start: usize,
+    end: usize,
+    hidden: Option<&'static str>,
+) {
+    close(scopes, open, reg, start);
+    scopes.push(Scope {
+        reg,
+        start,
+        end: None,
+        fixed_end: Some(end),
+        hidden,
+    });
+    open.push(scopes.len() - 1);
+}
+
+/// Where the value written at `pc` is complete: a `TESTSET` or a skipping
+/// `LOADBOOL` only writes part of a value computed across jumps.
+fn completed(chunk: &Chunk, pc: usize) -> usize {
+    let instr = chunk.code[pc];
+    match instr.get_op() {
+        Op::TestSet if pc + 1 < chunk.code.len() => jump_target(chunk, pc + 1),
+        Op::LoadBool if instr.get_c().value() != 0 => pc + 2,
+        _ => pc + 1,
+    }
+}
+
+fn jump_target(chunk: &Chunk, pc: usize) -> usize {
+    (pc as i64 + 1 + chunk.code[pc].get_sbx() as i64).max(0) as usize
+}
+
+/// Whether `pc` is the jump closing upvalues at the end of a block, which
+/// goes on to the next instruction or where the jump there goes.
+fn closes_block(chunk: &Chunk, pc: usize) -> bool {
+    let code = &chunk.code;
+    if code
+        .get(pc)
+        .is_none_or(|instr| instr.get_op() != Op::Jmp || instr.get_a() == 0)
+    {
+        return false;
+    }
+    let target = jump_target(chunk, pc);
+    target == pc + 1
+        || code.get(pc + 1).is_some_and(|next| {
+            next.get_op() == Op::Jmp && next.get_a() == 0 && jump_target(chunk, pc + 1) == target
+        })
+}
+
+/// Whether the jump at `pc` starts a generic `for`, jumping to its
+/// `TFORCALL`.
+fn is_generic_for(chunk: &Chunk, pc: usize) -> bool {
+    let code = &chunk.code;
+    let call = jump_target(chunk, pc);
+    match (code.get(call), code.get(call + 1)) {
+        (Some(tfor), Some(tloop)) => {
+            tfor.get_op() == Op::TForCall
+                && tloop.get_op() == Op::TForLoop
+                && jump_target(chunk, call + 1) == pc + 1
+        }
+        _ => false,
+    }
+}
+
+/// Whether the instruction at `pc` completes a statement, which leaves no
+/// temporaries behind.
+fn ends_statement(chunk: &Chunk, pc: usize) -> bool {
+    let instr = chunk.code[pc];
+    match instr.get_op() {
+        Op::Call => instr.get_c().raw() == 1,
+        Op::Return | Op::ForPrep | Op::ForLoop | Op::TForCall | Op::TForLoop => true,
+        Op::Jmp => instr.get_sbx() < 0 || is_generic_for(chunk, pc),
+        _ => false,
+    }
+}
+
+/// Decodes the "floating point byte" sizes of table constructors are
+/// given in.
+fn fb2int(x: u16) -> usize {
+    match x {
+        0..=7 => x as usize,
+        _ => ((x as usize & 7) + 8) << ((x >> 3) - 1),
+    }
+}
+
+/// The registers `count` registers from `first` on.
+fn regs(first: u8, count: usize) -> Vec<u8> {
+    (first as usize..first as usize + count)
+        .filter_map(|reg| u8::try_from(reg).ok())
+        .collect()
+}
+
+fn access(chunk: &Chunk, pc: usize) -> Access {
+    let code = &chunk.code;
+    let instr = code[pc];
+    let op = instr.get_op();
+    let a = instr.get_a();
+    let (b, c) = (instr.get_b(), instr.get_c());
+    // results left open by the instruction before run up to its register
+    let open = |first: u8| {
+        let top = match pc.checked_sub(1).map(|at| code[at]) {
+            Some(prev) if matches!(prev.get_op(), Op::Call | Op::Tailcall | Op::VarArg) => {
+                prev.get_a()
+            }
+            _ => first,
+        };
+        regs(first, (top as usize + 1).saturating_sub(first as usize))
+    };
+    let rk = |arg: &ArgK| (!arg.is_constant()).then(|| arg.value());
+    let (reads, writes) = match op {
+        Op::Move | Op::Unm | Op::BNot | Op::Not | Op::Len | Op::TestSet => {
+            (vec![b.value()], vec![a])
+        }
+        Op::LoadK | Op::LoadKx | Op::LoadBool | Op::GetUpval | Op::NewTable => (vec![], vec![a]),
+        Op::LoadNil => (vec![], regs(a, b.raw() as usize + 1)),
+        Op::GetTabup => (rk(&c).into_iter().collect(), vec![a]),
+        Op::GetTable => (
+            [Some(b.value()), rk(&c)].into_iter().flatten().collect(),
+            vec![a],
+        ),
+        Op::LuaSelf => (
+            [Some(b.value()), rk(&c)].into_iter().flatten().collect(),
+            regs(a, 2),
+        ),
+        Op::SetTabup | Op::Eq | Op::Lt | Op::Le => {
+            ([rk(&b), rk(&c)].into_iter().flatten().collect(), vec![])
+        }
+        Op::SetTable => (
+            [Some(a), rk(&b), rk(&c)].into_iter().flatten().collect(),
+            vec![],
+        ),
+        Op::SetUpval | Op::Test => (vec![a], vec![]),
+        Op::Add
+        | Op::Sub
+        | Op::Mul
+        | Op::Mod
+        | Op::Pow
+        | Op::Div
+        | Op::IDiv
+        | Op::BAnd
+        | Op::BOr
+        | Op::BXor
+        | Op::Shl
+        | Op::Shr => ([rk(&b), rk(&c)].into_iter().flatten().collect(), vec![a]),
+        Op::Concat => (
+            regs(
+                b.value(),
+                (c.value() as usize + 1).saturating_sub(b.value() as usize),
+            ),
+            vec![a],
+        ),
+        Op::Call | Op::Tailcall => {
+            let reads = match b.raw() {
+                0 => open(a),
+                n => regs(a, n as usize),
+            };
+            let writes = match c.raw() {
+                0 => vec![a],
+                _ if op == Op::Tailcall => vec![a],
+                n => regs(a, n as usize - 1),
+            };
+            (reads, writes)
+        }
+        Op::Return => match b.raw() {
+            0 => (open(a), vec![]),
+            n => (regs(a, n as usize - 1), vec![]),
+        },
+        Op::SetList => match b.raw() {
+            0 => (open(a), vec![]),
+            n => (regs(a, n as usize + 1), vec![]),
+        },
+        Op::VarArg => match b.raw() {
+            0 => (vec![], vec![a]),
+            n => (vec![], regs(a, n as usize - 1)),
+        },
+        // a loop takes over the values it starts with as its internal
+        // variables
+        Op::ForPrep => (regs(a, 3), regs(a, 3)),
+        Op::ForLoop => (regs(a, 3), regs(a, 4).into_iter().step_by(3).collect()),
+        Op::Jmp if is_generic_for(chunk, pc) => {
+            let base = code[jump_target(chunk, pc)].get_a();
+            (regs(base, 3), regs(base, 3))
+        }
+        Op::TForCall => (
+            regs(a, 3),
+            regs(a, 3 + c.raw() as usize).into_iter().skip(3).collect(),
+        ),
+        Op::TForLoop => (regs(a, 2).into_iter().skip(1).collect(), vec![a]),
+        Op::Closure => {
+            let proto = chunk.prototypes.get(instr.get_bx() as usize);
+            let reads = (proto.iter())
+                .flat_map(|proto| &proto.upvalues)
+                .filter(|upvalue| upvalue.in_stack && upvalue.index != a)
+                .map(|upvalue| upvalue.index)
+                .collect();
+            (reads, vec![a])
+        }
+        _ => (vec![], vec![]),
+    };
+    Access { reads, writes }
+}
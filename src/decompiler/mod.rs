@@ -0,0 +1,329 @@
+//! Turns compiled functions back into Lua source.
+//!
+//! Registers holding temporaries are folded back into the expressions that
+//! consume them, jumps are structured into `if`, loops, `and`/`or` chains
+//! and, where nothing else fits, `goto`. Local variables come from the
+//! debug information of the chunk, so its scopes are reproduced exactly,
+//! or are made up from how the code uses the registers when the chunk was
+//! stripped of it.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+
+use crate::compiler::ast::{Block, Expr, ExprKind, FunctionBody, Name, Span, Stat, StatKind};
+use crate::compiler::printer::print;
+use crate::file::chunk::Chunk;
+use crate::opcode::{Op, Opcode};
+
+mod block;
+mod conds;
+mod exprs;
+mod locals;
+
+use conds::Region;
+use exprs::{Shape, State};
+
+/// Bytecode that has no source equivalent the decompiler can find.
+#[derive(Debug, Eq, PartialEq)]
+pub struct DecompileError {
+    /// Line where the function being decompiled is defined, 0 for the main
+    /// function.
+    pub line: u64,
+    pub pc: usize,
+    pub message: String,
+}
+
+impl Display for DecompileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "function at line {}, pc {}: {}",
+            self.line, self.pc, self.message
+        )
+    }
+}
+
+/// Decompiles the main function of a chunk into the block of statements it
+/// was compiled from.
+pub fn decompile_block(chunk: &Chunk) -> Result<Block, DecompileError> {
+    let names = (0..chunk.upvalues.len())
+        .map(|i| match chunk.upvalue_names.get(i) {
+            Some(name) => name.clone(),
+            None if i == 0 => "_ENV".to_owned(),
+            None => format!("upvalue{}", i),
+        })
+        .collect();
+    let body = Function::new(chunk, names, 0).body()?;
+    Ok(body.body)
+}
+
+/// Decompiles the main function of a chunk into source that compiles to
+/// equivalent code.
+pub fn decompile(chunk: &Chunk) -> Result<String, DecompileError> {
+    Ok(print(&decompile_block(chunk)?))
+}
+
+struct LocalVar {
+    name: String,
+    reg: u8,
+    start: usize,
+    end: usize,
+}
+
+impl LocalVar {
+    /// Internal variables of `for` loops, named like `(for index)`.
+    fn is_hidden(&self) -> bool {
+        self.name.starts_with('(')
+    }
+}
+
+struct Function<'a> {
+    chunk: &'a Chunk,
+    upvalue_names: Vec<String>,
+    /// The upvalue holding `_ENV`, through which globals are accessed.
+    env: Option<u8>,
+    locals: Vec<LocalVar>,
+    /// Whether the locals were made up, so their scopes can end in the
+    /// middle of the statement reusing their registers.
+    inferred: bool,
+    declared: Vec<bool>,
+    /// How many functions this one is nested in.
+    depth: usize,
+    /// Targets of backward jumps.
+    loop_headers: BTreeSet<usize>,
+    /// The value regions found, by their start, the end of the code searched
+    /// and the kinds of the pending values, with the pending values they
+    /// were found for. None where there is no region.
+    value_regions: HashMap<(usize, usize, Shape), Option<(State, Region)>>,
+    /// Exits of the loops enclosing the code being structured.
+    loops: Vec<usize>,
+    /// Backward jumps already turned into the loop being structured.
+    closing: BTreeSet<usize>,
+    /// Start of the innermost `repeat` and the end of its condition.
+    until: Option<(usize, usize)>,
+    until_cond: Option<Expr>,
+    /// Targets of `goto`, which need labels.
+    labels: BTreeSet<usize>,
+    placed: BTreeSet<usize>,
+    closures: Vec<Option<FunctionBody>>,
+}
+
+impl<'a> Function<'a> {
+    fn new(chunk: &'a Chunk, upvalue_names: Vec<String>, depth: usize) -> Function<'a> {
+        let env = upvalue_names
+            .iter()
+            .position(|name| name == "_ENV")
+            .map(|i| i as u8);
+
+        let mut locals: Vec<LocalVar> = Vec::new();
+        for local in &chunk.locals {
+            let start = local.startpc as usize;
+            let end = local.endpc as usize;
+            // a local whose scope is empty, like one declared last in its
+            // block, still comes after the locals ending with it
+            let reg = locals
+                .iter()
+                .filter(|outer| {
+                    outer.start <= start && (start < outer.end || start == end && outer.end == end)
+                })
+                .count() as u8;
+            locals.push(LocalVar {
+                name: local.varname.clone(),
+                reg,
+                start,
+                end,
+            });
+        }
+
+        let inferred = locals.is_empty();
+        if inferred {
+            locals = locals::infer(chunk, depth);
+        }
+
+        let loop_headers = (chunk.code.iter().enumerate())
+            .filter(|(_, instr)| instr.get_op() == Op::Jmp)
+            .filter_map(|(pc, instr)| {
+                let target = pc as i64 + 1 + instr.get_sbx() as i64;
+                (target <= pc as i64).then_some(target as usize)
+            })
+            .collect();
+
+        Function {
+            chunk,
+            upvalue_names,
+            env,
+            declared: vec![false; locals.len()],
+            locals,
+            inferred,
+            depth,
+            loop_headers,
+            value_regions: HashMap::new(),
+            loops: Vec::new(),
+            closing: BTreeSet::new(),
+            until: None,
+            until_cond: None,
+            labels: BTreeSet::new(),
+            placed: BTreeSet::new(),
+            closures: vec![None; chunk.prototypes.len()],
+        }
+    }
+
+    fn error<T>(&self, pc: usize, message: impl Into<String>) -> Result<T, DecompileError> {
+        Err(DecompileError {
+            line: self.chunk.line_defined,
+            pc,
+            message: message.into(),
+        })
+    }
+
+    fn body(mut self) -> Result<FunctionBody, DecompileError> {
+        let num_params = self.chunk.num_params as usize;
+        let params = (0..num_params)
+            .map(|reg| match self.local_at(0, reg as u8) {
+                Some(local) => Ok(name(&local.name)),
+                None => self.error(0, "missing parameter names"),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let end = self.chunk.code.len().saturating_sub(1);
+
+        // gotos found while structuring need labels, which can change what
+        // is found the next time round
+        let stats = loop {
+            let known = self.labels.len();
+            for (i, local) in self.locals.iter().enumerate() {
+                self.declared[i] = local.start == 0 && (local.reg as usize) < num_params;
+            }
+            self.placed.clear();
+            let stats = self.block(0, end, true)?;
+            if self.labels.len() == known {
+                break stats;
+            }
+        };
+
+        Ok(FunctionBody {
+            params,
+            is_vararg: self.chunk.vararg_info.is_some(),
+            body: block(stats),
+            span: Span::default(),
+        })
+    }
+
+    /// The local variable held in `reg` at `pc`.
+    fn local_at(&self, pc: usize, reg: u8) -> Option<&LocalVar> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|local| local.reg == reg && local.start <= pc && pc < local.end)
+    }
+
+    /// Whether a jump back from later code lands on `pc`.
+    fn is_loop_header(&self, pc: usize) -> bool {
+        self.loop_headers.contains(&pc)
+    }
+
+    fn jump_target(&self, pc: usize) -> usize {
+        (pc as i64 + 1 + self.chunk.code[pc].get_sbx() as i64) as usize
+    }
+
+    fn label(&mut self, target: usize) -> Stat {
+        self.labels.insert(target);
+        stat(StatKind::Goto(name(&label_name(target))))
+    }
+}
+
+fn label_name(pc: usize) -> String {
+    format!("label_{}", pc)
+}
+
+fn expr(kind: ExprKind) -> Expr {
+    Expr {
+        kind,
+        span: Span::default(),
+    }
+}
+
+fn stat(kind: StatKind) -> Stat {
+    Stat {
+        kind,
+        span: Span::default(),
+    }
+}
+
+fn name(name: &str) -> Name {
+    Name {
+        name: name.to_owned(),
+        span: Span::default(),
+    }
+}
+
+fn block(stats: Vec<Stat>) -> Block {
+    Block {
+        stats,
+        span: Span::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+
+    fn round_trip(source: &str) -> String {
+        decompile(&compile(source.as_bytes(), "=test").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_expressions() {
+        for source in [
+            "local a, b = 1, {2, x = 3, [4] = 5}\nprint(a + b[1] * 2, #b, -a)\n",
+            "local t = {}\nt.x, t[1] = t, f(...)\nreturn t.x.y:z(1, \"s\")\n",
+            "function m.a.b:c(x, ...)\n  return x, ...\nend\n",
+            "local x = a and b or c\nlocal y = not (a < b) and a ~= c\nprint(x, y)\n",
+            "return (-5), 0x8000000000000000, (-0.5)\n",
+        ] {
+            assert_eq!(source, round_trip(source));
+        }
+    }
+
+    #[test]
+    fn test_control_flow() {
+        for source in [
+            "if a then\n  f()\nelseif b then\n  g()\nelse\n  h()\nend\n",
+            "while a < 10 do\n  a = a + 1\nend\nrepeat\n  local x = f()\nuntil x\n",
+            "for i = 10, 1, 0.5 do\n  print(i)\nend\n",
+            "for k, v in pairs(t) do\n  if v then\n    break\n  end\nend\n",
+            "local function f(n)\n  return n > 0 and f(n - 1)\nend\n",
+        ] {
+            assert_eq!(source, round_trip(source));
+        }
+        assert_eq!(
+            "repeat\nuntil not f()\n",
+            round_trip("::top:: if f() then goto top end")
+        );
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let n = 40;
+        let source = format!("{}x = 1{}", "if a then ".repeat(n), " end".repeat(n));
+        let conds = vec!["a"; n].join(" and ");
+        assert_eq!(
+            format!("if {} then\n  x = 1\nend\n", conds),
+            round_trip(&source)
+        );
+        let source = format!("{}x = 1{}", "if a then y = 1 ".repeat(n), " end".repeat(n));
+        assert_eq!(n, round_trip(&source).matches("if a then").count());
+    }
+
+    #[test]
+    fn test_globals_shadowed_by_env() {
+        assert_eq!(
+            "local _ENV = {}\n_ENV.x = 1\n",
+            round_trip("local _ENV = {}\nx = 1\n")
+        );
+        assert_eq!(
+            "do\n  local x = 1\nend\ny = 2\n",
+            round_trip("do local x = 1 end y = 2")
+        );
+    }
+}
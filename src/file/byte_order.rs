@@ -1,5 +1,4 @@
 use crate::file::LuaFileParseError;
-use crate::read_bytes;
 use std::fmt::{Display, Formatter};
 use std::io::Read;
 
@@ -30,6 +29,14 @@ impl Display for ByteOrder {
 }
 
 impl ByteOrder {
+    pub fn native() -> ByteOrder {
+        if cfg!(target_endian = "little") {
+            ByteOrder::LittleEndian
+        } else {
+            ByteOrder::BigEndian
+        }
+    }
+
     pub fn read_u8(&self, source: &mut impl Read) -> Result<u8, LuaFileParseError> {
         Ok(read_bytes!(source, 1)[0])
     }
@@ -68,4 +75,48 @@ impl ByteOrder {
             ByteOrder::LittleEndian => f64::from_le_bytes(read_bytes!(source, 8)),
         })
     }
+
+    /// Reads an unsigned integer stored in all of `bytes`. Only the eight
+    /// least significant bytes are kept.
+    pub fn read_uint(&self, bytes: &[u8]) -> u64 {
+        let little_endian: Vec<u8> = match self {
+            ByteOrder::BigEndian => bytes.iter().rev().copied().collect(),
+            ByteOrder::LittleEndian => bytes.to_vec(),
+        };
+        little_endian
+            .iter()
+            .take(8)
+            .rev()
+            .fold(0, |acc, &b| (acc << 8) | b as u64)
+    }
+
+    /// Writes the low `size` bytes of `value`. Bytes past the eighth are
+    /// filled with the sign extension if `negative` is set.
+    pub fn write_uint(&self, dest: &mut Vec<u8>, value: u64, size: usize, negative: bool) {
+        let mut bytes: Vec<u8> = (0..size)
+            .map(|i| match i {
+                0..=7 => (value >> (8 * i)) as u8,
+                _ if negative => 0xff,
+                _ => 0,
+            })
+            .collect();
+        if *self == ByteOrder::BigEndian {
+            bytes.reverse();
+        }
+        dest.extend_from_slice(&bytes);
+    }
+
+    pub fn write_f32(&self, dest: &mut Vec<u8>, value: f32) {
+        dest.extend_from_slice(&match self {
+            ByteOrder::BigEndian => value.to_be_bytes(),
+            ByteOrder::LittleEndian => value.to_le_bytes(),
+        });
+    }
+
+    pub fn write_f64(&self, dest: &mut Vec<u8>, value: f64) {
+        dest.extend_from_slice(&match self {
+            ByteOrder::BigEndian => value.to_be_bytes(),
+            ByteOrder::LittleEndian => value.to_le_bytes(),
+        });
+    }
 }
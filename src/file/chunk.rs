@@ -1,14 +1,15 @@
 use std::io::Read;
 
-use crate::file::byte_order::ByteOrder::{BigEndian, LittleEndian};
 use crate::file::header::ByteSize;
 use crate::file::header::Header;
 use crate::file::string::LuaString;
-use crate::file::{Constant, Local, LuaFileParseError, VarArgInfo};
+use crate::file::{Constant, Local, LuaFileParseError, Upvalue, VarArgInfo};
 use crate::instruction::Instruction;
-use crate::{
-    read_bytes, read_integral, read_lua_int, read_lua_number_float, read_lua_number_integral,
-};
+use crate::{read_integral, read_lua_int, read_lua_number_float, read_lua_number_integral};
+
+/// Longer strings are long strings (`LUA_TLNGSTR`) in the reference
+/// implementation, which shows in the dumped constant tag.
+const MAX_SHORT_STRING_LEN: usize = 40;
 
 #[derive(Debug)]
 pub struct Chunk {
@@ -24,16 +25,34 @@ pub struct Chunk {
     pub prototypes: Vec<Chunk>,
     pub source_lines: Vec<u64>,
     pub locals: Vec<Local>,
+    pub upvalues: Vec<Upvalue>,
     pub upvalue_names: Vec<String>,
 }
 
 impl Chunk {
     pub fn parse(header: &Header, source: &mut impl Read) -> Result<Chunk, LuaFileParseError> {
-        let num_upvalues = header.byte_order.read_u8(source)?;
-        let name = LuaString::parse(header, source)?;
+        // the main function's upvalue count precedes it; nested functions
+        // only have their upvalue descriptors
+        let _num_upvalues = header.byte_order.read_u8(source)?;
+        Chunk::parse_function(header, source, "")
+    }
 
-        let _ = read_bytes!(source, 10); // FIXME: don't know what that is
+    /// Nested functions are dumped without a source name when it is the
+    /// same as their parent's, so it is passed down.
+    fn parse_function(
+        header: &Header,
+        source: &mut impl Read,
+        parent_name: &str,
+    ) -> Result<Chunk, LuaFileParseError> {
+        let mut name = LuaString::parse_name(header, source)?;
+        if name.is_empty() {
+            name = parent_name.to_owned();
+        }
 
+        let line_defined = read_lua_int!(header, source);
+        let last_line_defined = read_lua_int!(header, source);
+        let num_params = header.byte_order.read_u8(source)?;
+        let is_vararg = header.byte_order.read_u8(source)? != 0;
         let max_stack = header.byte_order.read_u8(source)?;
         let num_instructions = header.byte_order.read_u32(source)?;
         let mut code = Vec::with_capacity(num_instructions as usize);
@@ -42,31 +61,112 @@ impl Chunk {
             code.push(instruction);
         }
 
-        let constants = Chunk::parse_constants(&header, source)?;
-
-        let _ = read_bytes!(source, 10); // FIXME: don't know what that is
+        let constants = Chunk::parse_constants(header, source)?;
+        let upvalues = Chunk::parse_upvalues(header, source)?;
+        let num_prototypes = read_lua_int!(header, source);
+        let mut prototypes = Vec::with_capacity(num_prototypes as usize);
+        for _ in 0..num_prototypes {
+            prototypes.push(Chunk::parse_function(header, source, &name)?);
+        }
 
-        let source_lines = Chunk::parse_source_lines(&header, source)?;
-        let locals = Chunk::parse_locals(&header, source)?;
-        let upvalue_names = Chunk::parse_upvalues(&header, source)?;
+        let source_lines = Chunk::parse_source_lines(header, source)?;
+        let locals = Chunk::parse_locals(header, source)?;
+        let upvalue_names = Chunk::parse_upvalue_names(header, source)?;
 
         Ok(Chunk {
             name,
-            line_defined: source_lines[0],
-            last_line_defined: source_lines[source_lines.len() - 1],
-            num_upvalues,
-            num_params: 0,
-            vararg_info: None,
+            line_defined,
+            last_line_defined,
+            num_upvalues: upvalues.len() as u8,
+            num_params,
+            vararg_info: is_vararg.then_some(VarArgInfo {}),
             max_stack,
             code,
             constants,
-            prototypes: vec![],
+            prototypes,
             source_lines,
             locals,
+            upvalues,
             upvalue_names,
         })
     }
 
+    /// Dumps the chunk in the format `parse` reads, like `luac` without
+    /// stripping debug information.
+    pub fn write(&self, header: &Header, dest: &mut Vec<u8>) {
+        dest.push(self.upvalues.len() as u8);
+        self.write_function(header, dest, None);
+    }
+
+    fn write_function(&self, header: &Header, dest: &mut Vec<u8>, parent_name: Option<&str>) {
+        if parent_name == Some(self.name.as_str()) {
+            dest.push(0);
+        } else {
+            LuaString::write(header, dest, self.name.as_bytes());
+        }
+
+        header.write_lua_int(dest, self.line_defined);
+        header.write_lua_int(dest, self.last_line_defined);
+        dest.push(self.num_params);
+        dest.push(self.vararg_info.is_some() as u8);
+        dest.push(self.max_stack);
+        header.write_lua_int(dest, self.code.len() as u64);
+        for instruction in &self.code {
+            header
+                .byte_order
+                .write_uint(dest, u32::from(instruction) as u64, 4, false);
+        }
+
+        header.write_lua_int(dest, self.constants.len() as u64);
+        for constant in &self.constants {
+            match constant {
+                Constant::Nil => dest.push(0),
+                Constant::Boolean(b) => dest.extend_from_slice(&[1, *b as u8]),
+                Constant::FloatingNumber(n) => {
+                    dest.push(3);
+                    header.write_lua_float(dest, *n);
+                }
+                Constant::IntegralNumber(i) => {
+                    dest.push(3 | 1 << 4);
+                    header.write_lua_integer(dest, *i);
+                }
+                Constant::String(s) => {
+                    dest.push(if s.len() <= MAX_SHORT_STRING_LEN {
+                        4
+                    } else {
+                        4 | 1 << 4
+                    });
+                    LuaString::write(header, dest, s);
+                }
+            }
+        }
+
+        header.write_lua_int(dest, self.upvalues.len() as u64);
+        for upvalue in &self.upvalues {
+            dest.extend_from_slice(&[upvalue.in_stack as u8, upvalue.index]);
+        }
+
+        header.write_lua_int(dest, self.prototypes.len() as u64);
+        for prototype in &self.prototypes {
+            prototype.write_function(header, dest, Some(&self.name));
+        }
+
+        header.write_lua_int(dest, self.source_lines.len() as u64);
+        for line in &self.source_lines {
+            header.write_lua_int(dest, *line);
+        }
+        header.write_lua_int(dest, self.locals.len() as u64);
+        for local in &self.locals {
+            LuaString::write(header, dest, local.varname.as_bytes());
+            header.write_lua_int(dest, local.startpc);
+            header.write_lua_int(dest, local.endpc);
+        }
+        header.write_lua_int(dest, self.upvalue_names.len() as u64);
+        for name in &self.upvalue_names {
+            LuaString::write(header, dest, name.as_bytes());
+        }
+    }
+
     fn parse_constants(
         header: &Header,
         source: &mut impl Read,
@@ -87,7 +187,7 @@ impl Chunk {
                         _ => return Err(LuaFileParseError::InvalidNumericConstantType),
                     }
                 }
-                4 => Constant::String(LuaString::parse(&header, source)?),
+                4 => Constant::String(LuaString::parse(header, source)?),
                 _ => return Err(LuaFileParseError::InvalidConstantType),
             };
 
@@ -100,11 +200,25 @@ impl Chunk {
     fn parse_upvalues(
         header: &Header,
         source: &mut impl Read,
+    ) -> Result<Vec<Upvalue>, LuaFileParseError> {
+        let num_upvalues = read_lua_int!(header, source);
+        let mut upvalues = Vec::with_capacity(num_upvalues as usize);
+        for _ in 0..num_upvalues {
+            let in_stack = header.byte_order.read_u8(source)? != 0;
+            let index = header.byte_order.read_u8(source)?;
+            upvalues.push(Upvalue { in_stack, index });
+        }
+        Ok(upvalues)
+    }
+
+    fn parse_upvalue_names(
+        header: &Header,
+        source: &mut impl Read,
     ) -> Result<Vec<String>, LuaFileParseError> {
         let num_upvalues = read_lua_int!(header, source);
         let mut upvalue_names = Vec::with_capacity(num_upvalues as usize);
         for _ in 0..num_upvalues {
-            upvalue_names.push(LuaString::parse(&header, source)?);
+            upvalue_names.push(LuaString::parse_name(header, source)?);
         }
         Ok(upvalue_names)
     }
@@ -128,7 +242,7 @@ impl Chunk {
         let num_locals = read_lua_int!(header, source);
         let mut locals = Vec::with_capacity(num_locals as usize);
         for _ in 0..num_locals {
-            let varname = LuaString::parse(&header, source)?;
+            let varname = LuaString::parse_name(header, source)?;
             let startpc = read_lua_int!(header, source);
             let endpc = read_lua_int!(header, source);
 
@@ -0,0 +1,285 @@
+use crate::file::chunk::Chunk;
+use crate::file::Constant;
+use crate::instruction::ArgK;
+use crate::opcode::{Op, Opcode};
+use std::fmt::{Display, Formatter};
+
+const ID_SIZE: usize = 60;
+const RETS: &str = "...";
+const PRE: &str = "[string \"";
+const POS: &str = "\"]";
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum VariableKind {
+    Local,
+    Global,
+    Field,
+    Upvalue,
+    Constant,
+    Method,
+}
+
+impl VariableKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VariableKind::Local => "local",
+            VariableKind::Global => "global",
+            VariableKind::Field => "field",
+            VariableKind::Upvalue => "upvalue",
+            VariableKind::Constant => "constant",
+            VariableKind::Method => "method",
+        }
+    }
+}
+
+impl Display for VariableKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct VariableInfo {
+    pub kind: VariableKind,
+    pub name: String,
+}
+
+impl Display for VariableInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} '{}'", self.kind, self.name)
+    }
+}
+
+/// Formats a chunk name the way `luaO_chunkid` does for error messages.
+pub fn short_source(name: &str) -> String {
+    let source = if name.is_empty() { "=?" } else { name };
+
+    if let Some(literal) = source.strip_prefix('=') {
+        truncate(literal, ID_SIZE - 1).to_owned()
+    } else if let Some(file_name) = source.strip_prefix('@') {
+        let keep = ID_SIZE - 1 - RETS.len();
+        if file_name.len() < ID_SIZE {
+            file_name.to_owned()
+        } else {
+            let mut start = file_name.len() - keep;
+            while !file_name.is_char_boundary(start) {
+                start += 1;
+            }
+            format!("{}{}", RETS, &file_name[start..])
+        }
+    } else {
+        let available = ID_SIZE - (PRE.len() + RETS.len() + POS.len()) - 1;
+        let first_line = source.split('\n').next().unwrap_or_default();
+        if source.len() < available && first_line.len() == source.len() {
+            format!("{}{}{}", PRE, source, POS)
+        } else {
+            format!("{}{}{}{}", PRE, truncate(first_line, available), RETS, POS)
+        }
+    }
+}
+
+impl Chunk {
+    /// The chunk name as Lua prints it in error messages, e.g. `simple.lua`
+    /// for `@simple.lua` or `[string "x = 1"]` for a loaded string.
+    pub fn short_source(&self) -> String {
+        short_source(&self.name)
+    }
+
+    pub fn line_at(&self, pc: usize) -> Option<u64> {
+        self.source_lines.get(pc).copied()
+    }
+
+    /// The `short_source:line:` prefix Lua puts in front of runtime errors
+    /// raised at `pc`.
+    pub fn error_location(&self, pc: usize) -> String {
+        match self.line_at(pc) {
+            Some(line) => format!("{}:{}:", self.short_source(), line),
+            None => format!("{}:?:", self.short_source()),
+        }
+    }
+
+    /// Name of the `n`-th (1-based) local variable active at `pc`.
+    pub fn local_name(&self, n: usize, pc: usize) -> Option<&str> {
+        self.locals
+            .iter()
+            .take_while(|local| local.startpc as usize <= pc)
+            .filter(|local| pc < local.endpc as usize)
+            .nth(n.checked_sub(1)?)
+            .map(|local| local.varname.as_str())
+    }
+
+    /// Describes where the value in register `reg` came from at `pc`, like
+    /// `global 'x'` or `field 'y'`, by running the same symbolic execution
+    /// over the bytecode that the reference implementation uses for its
+    /// error messages.
+    pub fn variable_info(&self, pc: usize, reg: u8) -> Option<VariableInfo> {
+        if let Some(name) = self.local_name(reg as usize + 1, pc) {
+            return Some(VariableInfo {
+                kind: VariableKind::Local,
+                name: name.to_owned(),
+            });
+        }
+
+        let set_pc = self.find_set_register(pc, reg)?;
+        let instr = &self.code[set_pc];
+        match instr.get_op() {
+            Op::Move => {
+                let b = instr.get_b().value();
+                if b < instr.get_a() {
+                    return self.variable_info(set_pc, b);
+                }
+                None
+            }
+            op @ (Op::GetTabup | Op::GetTable) => {
+                let t = instr.get_b().value();
+                let table_name = if op == Op::GetTable {
+                    self.local_name(t as usize + 1, set_pc)
+                } else {
+                    Some(self.upvalue_name(t as usize))
+                };
+                let kind = match table_name {
+                    Some("_ENV") => VariableKind::Global,
+                    _ => VariableKind::Field,
+                };
+                Some(VariableInfo {
+                    kind,
+                    name: self.key_name(set_pc, instr.get_c()),
+                })
+            }
+            Op::GetUpval => Some(VariableInfo {
+                kind: VariableKind::Upvalue,
+                name: self.upvalue_name(instr.get_b().value() as usize).to_owned(),
+            }),
+            op @ (Op::LoadK | Op::LoadKx) => {
+                let index = match op {
+                    Op::LoadK => instr.get_bx(),
+                    _ => self.code.get(set_pc + 1)?.get_ax(),
+                };
+                match self.constants.get(index as usize) {
+                    Some(Constant::String(name)) => Some(VariableInfo {
+                        kind: VariableKind::Constant,
+                        name: String::from_utf8_lossy(name).into_owned(),
+                    }),
+                    _ => None,
+                }
+            }
+            Op::LuaSelf => Some(VariableInfo {
+                kind: VariableKind::Method,
+                name: self.key_name(set_pc, instr.get_c()),
+            }),
+            _ => None,
+        }
+    }
+
+    /// How the function called by the instruction at `pc` is named, as a
+    /// `(namewhat, name)` pair like `("local", "f")`. Calls made through
+    /// metamethods are named after the event.
+    pub fn function_name(&self, pc: usize) -> Option<(&'static str, String)> {
+        let instr = self.code.get(pc)?;
+        let event = match instr.get_op() {
+            Op::Call | Op::Tailcall => {
+                let info = self.variable_info(pc, instr.get_a())?;
+                return Some((info.kind.as_str(), info.name));
+            }
+            Op::TForCall => return Some(("for iterator", "for iterator".to_owned())),
+            Op::LuaSelf | Op::GetTabup | Op::GetTable => "index",
+            Op::SetTabup | Op::SetTable => "newindex",
+            Op::Add => "add",
+            Op::Sub => "sub",
+            Op::Mul => "mul",
+            Op::Mod => "mod",
+            Op::Pow => "pow",
+            Op::Div => "div",
+            Op::IDiv => "idiv",
+            Op::BAnd => "band",
+            Op::BOr => "bor",
+            Op::BXor => "bxor",
+            Op::Shl => "shl",
+            Op::Shr => "shr",
+            Op::Unm => "unm",
+            Op::BNot => "bnot",
+            Op::Len => "len",
+            Op::Concat => "concat",
+            Op::Eq => "eq",
+            Op::Lt => "lt",
+            Op::Le => "le",
+            _ => return None,
+        };
+        Some(("metamethod", format!("__{}", event)))
+    }
+
+    fn upvalue_name(&self, index: usize) -> &str {
+        self.upvalue_names
+            .get(index)
+            .map(String::as_str)
+            .unwrap_or("?")
+    }
+
+    fn key_name(&self, pc: usize, key: ArgK) -> String {
+        if key.is_constant() {
+            if let Some(Constant::String(name)) = self.constants.get(key.index_k() as usize) {
+                return String::from_utf8_lossy(name).into_owned();
+            }
+        } else if let Some(VariableInfo {
+            kind: VariableKind::Constant,
+            name,
+        }) = self.variable_info(pc, key.value())
+        {
+            return name;
+        }
+        "?".to_owned()
+    }
+
+    /// Finds the last instruction before `last_pc` that unconditionally
+    /// changed register `reg`.
+    fn find_set_register(&self, last_pc: usize, reg: u8) -> Option<usize> {
+        let reg = reg as u32;
+        let mut set_pc = None;
+        let mut jump_target = 0;
+        for (pc, instr) in self.code.iter().enumerate().take(last_pc) {
+            let a = instr.get_a() as u32;
+            let filter = |pc: usize| if pc < jump_target { None } else { Some(pc) };
+            match instr.get_op() {
+                Op::LoadNil => {
+                    let b = instr.get_b().value() as u32;
+                    if a <= reg && reg <= a + b {
+                        set_pc = filter(pc);
+                    }
+                }
+                Op::TForCall => {
+                    if reg >= a + 2 {
+                        set_pc = filter(pc);
+                    }
+                }
+                Op::Call | Op::Tailcall => {
+                    if reg >= a {
+                        set_pc = filter(pc);
+                    }
+                }
+                Op::Jmp => {
+                    let dest = pc as i64 + 1 + instr.get_sbx() as i64;
+                    if (pc as i64) < dest && dest <= last_pc as i64 && dest as usize > jump_target {
+                        jump_target = dest as usize;
+                    }
+                }
+                op => {
+                    if op.sets_a() && reg == a {
+                        set_pc = filter(pc);
+                    }
+                }
+            }
+        }
+        set_pc
+    }
+}
+
+fn truncate(s: &str, max_len: usize) -> &str {
+    if s.len() <= max_len {
+        return s;
+    }
+    let mut end = max_len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
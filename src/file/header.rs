@@ -1,6 +1,6 @@
 use crate::file::byte_order::ByteOrder;
 use crate::file::LuaFileParseError;
-use crate::{debugln, read_bytes};
+use crate::read_bytes;
 use num_enum::TryFromPrimitive;
 use std::io::Read;
 
@@ -14,7 +14,7 @@ pub struct Header {
     pub size_number_float: ByteSize,
 }
 
-#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, TryFromPrimitive)]
+#[derive(Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, TryFromPrimitive)]
 #[repr(u8)]
 pub enum ByteSize {
     _8 = 8,
@@ -114,6 +114,66 @@ impl Header {
     }
 }
 
+const LUAC_DATA: &[u8] = b"\x19\x93\r\n\x1a\n";
+const LUAC_INT: u64 = 0x5678;
+const LUAC_NUM: f64 = 370.5;
+
+impl Header {
+    /// The header `luac` writes on this platform.
+    pub fn native() -> Header {
+        Header {
+            version: 0x53,
+            byte_order: ByteOrder::native(),
+            int_size: ByteSize::_4,
+            ptr_size: (std::mem::size_of::<usize>() as u8).try_into().unwrap(),
+            instruction_size: ByteSize::_4,
+            size_number_integral: ByteSize::_8,
+            size_number_float: ByteSize::_8,
+        }
+    }
+
+    pub fn write(&self, dest: &mut Vec<u8>) {
+        dest.extend_from_slice(b"\x1bLua");
+        dest.push(self.version);
+        dest.push(0); // official format
+        dest.extend_from_slice(LUAC_DATA);
+        for size in [
+            self.int_size,
+            self.ptr_size,
+            self.instruction_size,
+            self.size_number_integral,
+            self.size_number_float,
+        ] {
+            dest.push(size as u8);
+        }
+        self.write_lua_integer(dest, LUAC_INT);
+        self.write_lua_float(dest, LUAC_NUM);
+    }
+
+    pub fn write_lua_int(&self, dest: &mut Vec<u8>, value: u64) {
+        self.byte_order
+            .write_uint(dest, value, self.int_size as usize, false);
+    }
+
+    pub fn write_lua_size_t(&self, dest: &mut Vec<u8>, value: u64) {
+        self.byte_order
+            .write_uint(dest, value, self.ptr_size as usize, false);
+    }
+
+    pub fn write_lua_integer(&self, dest: &mut Vec<u8>, value: u64) {
+        let negative = (value as i64) < 0;
+        self.byte_order
+            .write_uint(dest, value, self.size_number_integral as usize, negative);
+    }
+
+    pub fn write_lua_float(&self, dest: &mut Vec<u8>, value: f64) {
+        match self.size_number_float {
+            ByteSize::_4 => self.byte_order.write_f32(dest, value as f32),
+            _ => self.byte_order.write_f64(dest, value),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -133,4 +193,26 @@ mod tests {
         let result = Header::parse(&mut rd);
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_header_write_native() {
+        let mut dest = Vec::new();
+        Header::native().write(&mut dest);
+        if cfg!(all(target_endian = "little", target_pointer_width = "64")) {
+            assert_eq!(
+                hex!(
+                    "
+                    1b 4c 75 61 53 00 19 93 0d 0a 1a 0a 04 08 04 08
+                    08 78 56 00 00 00 00 00 00 00 00 00 00 00 28 77
+                    40
+                    "
+                )
+                .to_vec(),
+                dest
+            );
+        }
+
+        let header = Header::parse(&mut Cursor::new(dest)).unwrap();
+        assert_eq!(ByteSize::_8, header.size_number_float);
+    }
 }
@@ -1,13 +1,14 @@
+use std::fmt::{Display, Formatter};
 use std::io::Read;
 
-use crate::file::string::LuaString;
 use chunk::Chunk;
 use header::Header;
 
 pub mod byte_order;
 pub mod chunk;
+pub mod debug;
 pub mod header;
-mod string;
+pub mod string;
 
 #[derive(Debug)]
 pub enum LuaFileParseError {
@@ -21,6 +22,29 @@ pub enum LuaFileParseError {
     InvalidFloatingPointByteSize,
 }
 
+impl Display for LuaFileParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LuaFileParseError::UnexpectedEOF => write!(f, "truncated precompiled chunk"),
+            LuaFileParseError::InvalidMagicValue => write!(f, "not a precompiled chunk"),
+            LuaFileParseError::VersionMismatch => {
+                write!(f, "version mismatch in precompiled chunk")
+            }
+            LuaFileParseError::InvalidBytesInString => write!(f, "bad binary format (string)"),
+            LuaFileParseError::InvalidInstruction => {
+                write!(f, "bad binary format (instruction)")
+            }
+            LuaFileParseError::InvalidConstantType
+            | LuaFileParseError::InvalidNumericConstantType => {
+                write!(f, "bad binary format (constant)")
+            }
+            LuaFileParseError::InvalidFloatingPointByteSize => {
+                write!(f, "bad binary format (lua_Number size mismatch)")
+            }
+        }
+    }
+}
+
 pub struct LuaFile {
     pub header: Header,
     pub main_chunk: Chunk,
@@ -33,6 +57,13 @@ impl LuaFile {
 
         Ok(LuaFile { header, main_chunk })
     }
+
+    pub fn write(&self) -> Vec<u8> {
+        let mut dest = Vec::new();
+        self.header.write(&mut dest);
+        self.main_chunk.write(&self.header, &mut dest);
+        dest
+    }
 }
 
 #[derive(Debug)]
@@ -44,12 +75,20 @@ pub enum Constant {
     Boolean(bool),
     IntegralNumber(u64),
     FloatingNumber(f64),
-    String(String),
+    String(Vec<u8>),
 }
 
 #[derive(Debug)]
 pub struct Local {
-    varname: String,
-    startpc: u64,
-    endpc: u64,
+    pub varname: String,
+    pub startpc: u64,
+    pub endpc: u64,
+}
+
+/// Where a closure finds an upvalue when it is created: a register of the
+/// enclosing function if `in_stack`, otherwise one of its upvalues.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Upvalue {
+    pub in_stack: bool,
+    pub index: u8,
 }
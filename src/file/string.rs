@@ -1,7 +1,11 @@
 use std::io::Read;
 
-use crate::file::header::Header;
+use crate::file::header::{ByteSize, Header};
 use crate::file::LuaFileParseError;
+use crate::{read_integral, read_lua_size_t};
+
+/// Strings of this length or longer are dumped with a `size_t` length.
+const LONG_STRING_MARK: u8 = 0xff;
 
 #[derive(Debug)]
 pub struct LuaString {
@@ -9,20 +13,39 @@ pub struct LuaString {
 }
 
 impl LuaString {
-    pub fn parse(header: &Header, source: &mut impl Read) -> Result<String, LuaFileParseError> {
-        let size = header.byte_order.read_u8(source)?;
+    pub fn parse(header: &Header, source: &mut impl Read) -> Result<Vec<u8>, LuaFileParseError> {
+        let mut size = header.byte_order.read_u8(source)? as u64;
+        if size == LONG_STRING_MARK as u64 {
+            size = read_lua_size_t!(header, source);
+        }
         if size == 0 {
-            return Ok("".to_owned());
+            return Ok(vec![]);
         }
 
         let mut data = vec![0_u8; (size - 1) as usize];
         source
             .read_exact(&mut data)
             .or(Err(LuaFileParseError::UnexpectedEOF))?;
+        Ok(data)
+    }
 
-        let rust_string =
-            String::from_utf8(data).or(Err(LuaFileParseError::InvalidBytesInString))?;
+    /// Parses a source, local or upvalue name, which must be UTF-8.
+    pub fn parse_name(
+        header: &Header,
+        source: &mut impl Read,
+    ) -> Result<String, LuaFileParseError> {
+        String::from_utf8(LuaString::parse(header, source)?)
+            .or(Err(LuaFileParseError::InvalidBytesInString))
+    }
 
-        Ok(rust_string)
+    pub fn write(header: &Header, dest: &mut Vec<u8>, s: &[u8]) {
+        let size = s.len() as u64 + 1;
+        if size < LONG_STRING_MARK as u64 {
+            dest.push(size as u8);
+        } else {
+            dest.push(LONG_STRING_MARK);
+            header.write_lua_size_t(dest, size);
+        }
+        dest.extend_from_slice(s);
     }
 }
@@ -17,12 +17,6 @@ macro_rules! mask_hi {
     };
 }
 
-macro_rules! mask_lo {
-    ($num_lo:expr, $pos:expr) => {
-        !mask_hi!($num_lo, $pos)
-    };
-}
-
 const SIZE_OP: u8 = 6;
 const SIZE_A: u8 = 8;
 const SIZE_B: u8 = 9;
@@ -37,7 +31,13 @@ const POS_B: u8 = POS_C + SIZE_C;
 const POS_BX: u8 = POS_C;
 const POS_AX: u8 = POS_A;
 
-const BIT_RK: u16 = 1 << (SIZE_B - 1);
+pub const MAXARG_BX: u32 = (1 << SIZE_BX) - 1;
+pub const MAXARG_SBX: i32 = ((1 << SIZE_BX) - 1) >> 1;
+
+pub const MAXARG_C: u32 = (1 << SIZE_C) - 1;
+pub const MAXARG_AX: u32 = (1 << SIZE_AX) - 1;
+
+pub const BIT_RK: u16 = 1 << (SIZE_B - 1);
 
 pub struct ArgK(u16);
 
@@ -53,10 +53,80 @@ impl ArgK {
     pub fn value(&self) -> u8 {
         self.0 as u8
     }
+
+    /// The raw 9-bit argument: a register, or a constant index with
+    /// [`BIT_RK`] set.
+    pub fn raw(&self) -> u16 {
+        self.0
+    }
 }
 
+#[derive(Clone, Copy, Eq, PartialEq)]
 pub struct Instruction(u32);
 
+macro_rules! set_arg {
+    ($instr:expr, $value:expr, $pos:expr, $size:expr) => {
+        $instr =
+            ($instr & !mask_hi!($size, $pos)) | ((($value as u32) << $pos) & mask_hi!($size, $pos))
+    };
+}
+
+impl Instruction {
+    pub fn abc(op: Op, a: u8, b: u16, c: u16) -> Instruction {
+        let mut instr = Instruction(op as u32);
+        instr.set_a(a);
+        instr.set_b(b);
+        instr.set_c(c);
+        instr
+    }
+
+    pub fn abx(op: Op, a: u8, bx: u32) -> Instruction {
+        let mut instr = Instruction(op as u32);
+        instr.set_a(a);
+        set_arg!(instr.0, bx, POS_BX, SIZE_BX);
+        instr
+    }
+
+    pub fn asbx(op: Op, a: u8, sbx: i32) -> Instruction {
+        let mut instr = Instruction(op as u32);
+        instr.set_a(a);
+        instr.set_sbx(sbx);
+        instr
+    }
+
+    pub fn ax(op: Op, ax: u32) -> Instruction {
+        let mut instr = Instruction(op as u32);
+        set_arg!(instr.0, ax, POS_AX, SIZE_AX);
+        instr
+    }
+
+    pub fn set_op(&mut self, op: Op) {
+        set_arg!(self.0, op as u32, POS_OP, SIZE_OP);
+    }
+
+    pub fn set_a(&mut self, a: u8) {
+        set_arg!(self.0, a, POS_A, SIZE_A);
+    }
+
+    pub fn set_b(&mut self, b: u16) {
+        set_arg!(self.0, b, POS_B, SIZE_B);
+    }
+
+    pub fn set_c(&mut self, c: u16) {
+        set_arg!(self.0, c, POS_C, SIZE_C);
+    }
+
+    pub fn set_sbx(&mut self, sbx: i32) {
+        set_arg!(self.0, sbx + MAXARG_SBX, POS_BX, SIZE_BX);
+    }
+}
+
+impl From<&Instruction> for u32 {
+    fn from(instr: &Instruction) -> u32 {
+        instr.0
+    }
+}
+
 impl TryFrom<u32> for Instruction {
     type Error = LuaFileParseError;
 
@@ -127,15 +197,15 @@ impl Opcode for Instruction {
     }
 
     fn get_ax(&self) -> u32 {
-        get_arg!(self.0, SIZE_AX, POS_AX)
+        get_arg!(self.0, POS_AX, SIZE_AX)
     }
 
     fn get_bx(&self) -> u32 {
-        get_arg!(self.0, SIZE_BX, POS_BX)
+        get_arg!(self.0, POS_BX, SIZE_BX)
     }
 
     fn get_sbx(&self) -> i32 {
-        self.get_bx() as i32
+        self.get_bx() as i32 - MAXARG_SBX
     }
 }
 
@@ -155,4 +225,50 @@ mod tests {
         let failure = Instruction::try_from(47);
         assert!(failure.is_err());
     }
+
+    #[test]
+    fn test_arguments() {
+        // JMP 0 4
+        let jmp = Instruction::try_from(0x8000_c01e).unwrap();
+        assert_eq!(Op::Jmp, jmp.get_op());
+        assert_eq!(4, jmp.get_sbx());
+
+        // LOADK 1 5
+        let loadk = Instruction::try_from(0x0001_4041).unwrap();
+        assert_eq!(Op::LoadK, loadk.get_op());
+        assert_eq!(1, loadk.get_a());
+        assert_eq!(5, loadk.get_bx());
+
+        // EXTRAARG 300
+        let extra = Instruction::try_from(300 << 6 | Op::ExtraArg as u32).unwrap();
+        assert_eq!(300, extra.get_ax());
+    }
+
+    #[test]
+    fn test_constructors() {
+        assert_eq!(
+            Instruction::try_from(0x8000_c01e).unwrap(),
+            Instruction::asbx(Op::Jmp, 0, 4)
+        );
+        assert_eq!(
+            Instruction::try_from(0x0001_4041).unwrap(),
+            Instruction::abx(Op::LoadK, 1, 5)
+        );
+
+        let mut add = Instruction::abc(Op::Add, 2, 1, BIT_RK | 3);
+        assert_eq!(
+            (2, 1, 3),
+            (add.get_a(), add.get_b().value(), add.get_c().index_k())
+        );
+        assert!(add.get_c().is_constant());
+        add.set_a(7);
+        add.set_op(Op::Sub);
+        assert_eq!((Op::Sub, 7), (add.get_op(), add.get_a()));
+
+        let mut jmp = Instruction::asbx(Op::Jmp, 0, -1);
+        assert_eq!(-1, jmp.get_sbx());
+        jmp.set_sbx(-MAXARG_SBX);
+        assert_eq!(-MAXARG_SBX, jmp.get_sbx());
+        assert_eq!(MAXARG_AX, Instruction::ax(Op::ExtraArg, MAXARG_AX).get_ax());
+    }
 }
@@ -1,4 +1,9 @@
+pub mod cfg;
+pub mod compiler;
+pub mod decompiler;
 pub mod file;
 pub mod instruction;
 pub mod log;
+pub mod number;
 pub mod opcode;
+pub mod stdlib;
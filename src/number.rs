@@ -0,0 +1,593 @@
+use std::fmt::{Display, Formatter};
+
+const MAX_SIGNIFICANT_DIGITS: i32 = 30;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Number {
+    Integer(i64),
+    Float(f64),
+}
+
+/// The operators of `luaO_arith`, in the order of `LUA_OPADD` and
+/// following.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Mod,
+    Pow,
+    Div,
+    IDiv,
+    BAnd,
+    BOr,
+    BXor,
+    Shl,
+    Shr,
+    Unm,
+    BNot,
+}
+
+impl Number {
+    /// Converts a numeral the way Lua's `tonumber` and the lexer do: integers
+    /// first (decimal or hexadecimal, hex wrapping around), falling back to a
+    /// float for decimal integers that overflow, for fractions and exponents.
+    pub fn parse(s: &[u8]) -> Option<Number> {
+        if let Some(i) = parse_integer(s) {
+            return Some(Number::Integer(i));
+        }
+        parse_float(s).map(Number::Float)
+    }
+
+    pub fn to_integer(self) -> Option<i64> {
+        match self {
+            Number::Integer(i) => Some(i),
+            Number::Float(f) => float_to_integer(f),
+        }
+    }
+
+    pub fn to_float(self) -> f64 {
+        match self {
+            Number::Integer(i) => i as f64,
+            Number::Float(f) => f,
+        }
+    }
+
+    /// `self < other`, comparing integers and floats by their mathematical
+    /// values instead of converting one to the other's subtype.
+    pub fn less_than(self, other: Number) -> bool {
+        const TWO_POW_63: f64 = 9_223_372_036_854_775_808.0;
+        match (self, other) {
+            (Number::Integer(a), Number::Integer(b)) => a < b,
+            (Number::Float(a), Number::Float(b)) => a < b,
+            (Number::Integer(i), Number::Float(f)) => {
+                if f.is_nan() || f <= -TWO_POW_63 {
+                    false
+                } else {
+                    f >= TWO_POW_63 || i < f.ceil() as i64
+                }
+            }
+            (Number::Float(f), Number::Integer(i)) => {
+                if f.is_nan() || f >= TWO_POW_63 {
+                    false
+                } else {
+                    f < -TWO_POW_63 || (f.floor() as i64) < i
+                }
+            }
+        }
+    }
+}
+
+impl Number {
+    /// The raw operation `luaO_arith` performs on two numbers, `None` where
+    /// it would fall back to a metamethod: bitwise operations on floats
+    /// without an integer value. Unary operators ignore `other`. Integer
+    /// division and modulo by zero are errors the caller has to rule out.
+    pub fn arith(self, op: ArithOp, other: Number) -> Option<Number> {
+        match op {
+            ArithOp::BAnd | ArithOp::BOr | ArithOp::BXor | ArithOp::Shl | ArithOp::Shr => {
+                let (a, b) = (self.to_integer()?, other.to_integer()?);
+                Some(Number::Integer(int_arith(op, a, b)))
+            }
+            ArithOp::BNot => Some(Number::Integer(!self.to_integer()?)),
+            ArithOp::Div | ArithOp::Pow => Some(Number::Float(float_arith(
+                op,
+                self.to_float(),
+                other.to_float(),
+            ))),
+            _ => Some(match (self, other) {
+                (Number::Integer(a), Number::Integer(b)) => Number::Integer(int_arith(op, a, b)),
+                (a, b) => Number::Float(float_arith(op, a.to_float(), b.to_float())),
+            }),
+        }
+    }
+}
+
+fn int_arith(op: ArithOp, a: i64, b: i64) -> i64 {
+    match op {
+        ArithOp::Add => a.wrapping_add(b),
+        ArithOp::Sub => a.wrapping_sub(b),
+        ArithOp::Mul => a.wrapping_mul(b),
+        ArithOp::Mod => int_mod(a, b),
+        ArithOp::IDiv => int_floor_div(a, b),
+        ArithOp::BAnd => a & b,
+        ArithOp::BOr => a | b,
+        ArithOp::BXor => a ^ b,
+        ArithOp::Shl => shift_left(a, b),
+        ArithOp::Shr => shift_left(a, b.wrapping_neg()),
+        ArithOp::Unm => a.wrapping_neg(),
+        ArithOp::BNot => !a,
+        ArithOp::Pow | ArithOp::Div => unreachable!("{:?} is a float operation", op),
+    }
+}
+
+fn float_arith(op: ArithOp, a: f64, b: f64) -> f64 {
+    match op {
+        ArithOp::Add => a + b,
+        ArithOp::Sub => a - b,
+        ArithOp::Mul => a * b,
+        ArithOp::Div => a / b,
+        ArithOp::Pow => a.powf(b),
+        ArithOp::IDiv => (a / b).floor(),
+        ArithOp::Unm => -a,
+        ArithOp::Mod => float_mod(a, b),
+        _ => unreachable!("{:?} is an integer operation", op),
+    }
+}
+
+/// `a // b` for integers, rounding towards minus infinity. `b` must not be
+/// zero.
+pub fn int_floor_div(a: i64, b: i64) -> i64 {
+    if b == -1 {
+        return a.wrapping_neg();
+    }
+    let q = a / b;
+    if (a ^ b) < 0 && a % b != 0 {
+        q - 1
+    } else {
+        q
+    }
+}
+
+/// `a % b` for integers, with the sign of `b`. `b` must not be zero.
+pub fn int_mod(a: i64, b: i64) -> i64 {
+    if b == -1 {
+        return 0;
+    }
+    let r = a % b;
+    if r != 0 && (a ^ b) < 0 {
+        r + b
+    } else {
+        r
+    }
+}
+
+/// `a % b` for floats, with the sign of `b`.
+pub fn float_mod(a: f64, b: f64) -> f64 {
+    let m = a % b;
+    if m * b < 0.0 {
+        m + b
+    } else {
+        m
+    }
+}
+
+/// `a << b`, shifting right for negative `b` and filling with zeros.
+pub fn shift_left(a: i64, b: i64) -> i64 {
+    match b {
+        b if b <= -64 || b >= 64 => 0,
+        b if b < 0 => ((a as u64) >> -b) as i64,
+        b => ((a as u64) << b) as i64,
+    }
+}
+
+impl Display for Number {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Number::Integer(i) => write!(f, "{}", i),
+            Number::Float(x) => {
+                let s = format_float(*x, b'g', 14, false);
+                if s.bytes().all(|b| b == b'-' || b.is_ascii_digit()) {
+                    write!(f, "{}.0", s)
+                } else {
+                    write!(f, "{}", s)
+                }
+            }
+        }
+    }
+}
+
+/// The integer with the same value as `f`, if there is one.
+pub fn float_to_integer(f: f64) -> Option<i64> {
+    if f.floor() == f && f >= -(2_f64.powi(63)) && f < 2_f64.powi(63) {
+        Some(f as i64)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn is_space(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r')
+}
+
+fn trim(s: &[u8]) -> &[u8] {
+    let start = s.iter().position(|&b| !is_space(b)).unwrap_or(s.len());
+    let end = s
+        .iter()
+        .rposition(|&b| !is_space(b))
+        .map_or(start, |e| e + 1);
+    &s[start..end]
+}
+
+fn split_sign(s: &[u8]) -> (bool, &[u8]) {
+    match s.first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    }
+}
+
+fn strip_hex_prefix(s: &[u8]) -> Option<&[u8]> {
+    match s {
+        [b'0', b'x' | b'X', rest @ ..] => Some(rest),
+        _ => None,
+    }
+}
+
+fn hex_value(b: u8) -> u64 {
+    (b as char).to_digit(16).unwrap() as u64
+}
+
+fn parse_integer(s: &[u8]) -> Option<i64> {
+    let (negative, s) = split_sign(trim(s));
+    let mut a: u64 = 0;
+    if let Some(digits) = strip_hex_prefix(s) {
+        if digits.is_empty() || !digits.iter().all(u8::is_ascii_hexdigit) {
+            return None;
+        }
+        for &d in digits {
+            a = a.wrapping_mul(16).wrapping_add(hex_value(d));
+        }
+    } else {
+        if s.is_empty() || !s.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        let max_by_10 = i64::MAX as u64 / 10;
+        let max_last_digit = i64::MAX as u64 % 10;
+        for &d in s {
+            let d = (d - b'0') as u64;
+            if a >= max_by_10 && (a > max_by_10 || d > max_last_digit + negative as u64) {
+                return None;
+            }
+            a = a * 10 + d;
+        }
+    }
+    Some(if negative { 0u64.wrapping_sub(a) } else { a } as i64)
+}
+
+fn parse_float(s: &[u8]) -> Option<f64> {
+    let s = trim(s);
+    if s.iter().any(|&b| b == b'n' || b == b'N') {
+        return None;
+    }
+    let (negative, unsigned) = split_sign(s);
+    if let Some(digits) = strip_hex_prefix(unsigned) {
+        let value = parse_hex_float(digits)?;
+        return Some(if negative { -value } else { value });
+    }
+
+    let mut i = 0;
+    let mut mantissa_digits = 0;
+    while i < unsigned.len() && unsigned[i].is_ascii_digit() {
+        i += 1;
+        mantissa_digits += 1;
+    }
+    if i < unsigned.len() && unsigned[i] == b'.' {
+        i += 1;
+        while i < unsigned.len() && unsigned[i].is_ascii_digit() {
+            i += 1;
+            mantissa_digits += 1;
+        }
+    }
+    if mantissa_digits == 0 {
+        return None;
+    }
+    if i < unsigned.len() && (unsigned[i] == b'e' || unsigned[i] == b'E') {
+        i += 1;
+        if i < unsigned.len() && (unsigned[i] == b'+' || unsigned[i] == b'-') {
+            i += 1;
+        }
+        let exponent_start = i;
+        while i < unsigned.len() && unsigned[i].is_ascii_digit() {
+            i += 1;
+        }
+        if i == exponent_start {
+            return None;
+        }
+    }
+    if i != unsigned.len() {
+        return None;
+    }
+    std::str::from_utf8(s).ok()?.parse().ok()
+}
+
+fn parse_hex_float(s: &[u8]) -> Option<f64> {
+    let mut mantissa = 0.0_f64;
+    let mut exponent: i64 = 0;
+    let mut significant_digits = 0;
+    let mut non_significant_digits = 0;
+    let mut has_dot = false;
+    let mut has_digits = false;
+    let mut i = 0;
+    while i < s.len() {
+        let b = s[i];
+        if b == b'.' {
+            if has_dot {
+                break;
+            }
+            has_dot = true;
+        } else if b.is_ascii_hexdigit() {
+            if significant_digits == 0 && b == b'0' {
+                non_significant_digits += 1;
+            } else {
+                significant_digits += 1;
+                if significant_digits <= MAX_SIGNIFICANT_DIGITS {
+                    mantissa = mantissa * 16.0 + hex_value(b) as f64;
+                } else {
+                    exponent += 1;
+                }
+            }
+            if has_dot {
+                exponent -= 1;
+            }
+            has_digits = true;
+        } else {
+            break;
+        }
+        i += 1;
+    }
+    if !has_digits || non_significant_digits + significant_digits == 0 {
+        return None;
+    }
+    exponent *= 4;
+    if i < s.len() && (s[i] == b'p' || s[i] == b'P') {
+        i += 1;
+        let (negative, rest) = split_sign(&s[i..]);
+        let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+        if digits == 0 {
+            return None;
+        }
+        let mut e: i64 = 0;
+        for &d in &rest[..digits] {
+            e = e.saturating_mul(10).saturating_add((d - b'0') as i64);
+        }
+        exponent += if negative { -e } else { e };
+        i = s.len() - rest.len() + digits;
+    }
+    if i != s.len() {
+        return None;
+    }
+    Some(ldexp(mantissa, exponent))
+}
+
+fn ldexp(x: f64, exponent: i64) -> f64 {
+    let mut x = x;
+    let mut exponent = exponent.clamp(-4000, 4000) as i32;
+    while exponent > 1000 {
+        x *= 2_f64.powi(1000);
+        exponent -= 1000;
+    }
+    while exponent < -1000 {
+        x *= 2_f64.powi(-1000);
+        exponent += 1000;
+    }
+    x * 2_f64.powi(exponent)
+}
+
+/// Formats `x` like C's `printf` with conversion `conv` (one of `eEfFgG`)
+/// and the given precision. `alternate` corresponds to the `#` flag.
+pub fn format_float(x: f64, conv: u8, precision: usize, alternate: bool) -> String {
+    let upper = conv.is_ascii_uppercase();
+    let sign = if x.is_sign_negative() { "-" } else { "" };
+    let body = if x.is_nan() {
+        "nan".to_owned()
+    } else if x.is_infinite() {
+        "inf".to_owned()
+    } else {
+        match conv.to_ascii_lowercase() {
+            b'e' => format_exponent(x.abs(), precision, alternate),
+            b'f' => format_fixed(x.abs(), precision, alternate),
+            _ => format_general(x.abs(), precision, alternate),
+        }
+    };
+    let s = format!("{}{}", sign, body);
+    if upper {
+        s.to_ascii_uppercase()
+    } else {
+        s
+    }
+}
+
+fn format_fixed(x: f64, precision: usize, alternate: bool) -> String {
+    let mut s = format!("{:.*}", precision, x);
+    if alternate && precision == 0 {
+        s.push('.');
+    }
+    s
+}
+
+fn format_exponent(x: f64, precision: usize, alternate: bool) -> String {
+    let s = format!("{:.*e}", precision, x);
+    let (mantissa, exponent) = s.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let dot = if alternate && precision == 0 { "." } else { "" };
+    format!(
+        "{}{}e{}{:02}",
+        mantissa,
+        dot,
+        if exponent < 0 { '-' } else { '+' },
+        exponent.abs()
+    )
+}
+
+fn format_general(x: f64, precision: usize, alternate: bool) -> String {
+    let p = precision.max(1);
+    let exponent = if x == 0.0 {
+        0
+    } else {
+        let s = format!("{:.*e}", p - 1, x);
+        s.split_once('e').unwrap().1.parse::<i32>().unwrap()
+    };
+    let s = if (p as i32) > exponent && exponent >= -4 {
+        format_fixed(x, (p as i32 - 1 - exponent) as usize, alternate)
+    } else {
+        format_exponent(x, p - 1, alternate)
+    };
+    if alternate {
+        return s;
+    }
+    match s.find('e') {
+        Some(e) => format!("{}{}", strip_fraction_zeros(&s[..e]), &s[e..]),
+        None => strip_fraction_zeros(&s).to_owned(),
+    }
+}
+
+fn strip_fraction_zeros(s: &str) -> &str {
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        s
+    }
+}
+
+/// Formats `x` like C's `%a`, as hexadecimal mantissa and binary exponent.
+pub fn format_hex_float(x: f64) -> String {
+    if x.is_nan() || x.is_infinite() {
+        return format_float(x, b'g', 6, false);
+    }
+    let sign = if x.is_sign_negative() { "-" } else { "" };
+    if x == 0.0 {
+        return format!("{}0x0p+0", sign);
+    }
+    let bits = x.abs().to_bits();
+    let biased_exponent = (bits >> 52) as i32;
+    let mut mantissa = bits & ((1 << 52) - 1);
+    let exponent = if biased_exponent == 0 {
+        // normalize subnormals so the leading digit is always 1
+        let shift = mantissa.leading_zeros() - 11;
+        mantissa = (mantissa << shift) & ((1 << 52) - 1);
+        -1022 - shift as i32
+    } else {
+        biased_exponent - 1023
+    };
+    let mut digits = format!("{:013x}", mantissa);
+    while digits.ends_with('0') {
+        digits.pop();
+    }
+    let dot = if digits.is_empty() { "" } else { "." };
+    format!("{}0x1{}{}p{:+}", sign, dot, digits, exponent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Some(Number::Integer(10)), Number::parse(b" 10 "));
+        assert_eq!(Some(Number::Integer(-255)), Number::parse(b"-0xff"));
+        assert_eq!(
+            Some(Number::Integer(-1)),
+            Number::parse(b"0xffffffffffffffff")
+        );
+        assert_eq!(
+            Some(Number::Float(9223372036854775808.0)),
+            Number::parse(b"9223372036854775808")
+        );
+        assert_eq!(
+            Some(Number::Integer(i64::MIN)),
+            Number::parse(b"-9223372036854775808")
+        );
+        assert_eq!(Some(Number::Float(0.5)), Number::parse(b".5"));
+        assert_eq!(Some(Number::Float(5.0)), Number::parse(b"5."));
+        assert_eq!(Some(Number::Float(1e10)), Number::parse(b"1e10"));
+        assert_eq!(Some(Number::Float(1.0)), Number::parse(b"0x.1p4"));
+        assert_eq!(Some(Number::Float(3.0)), Number::parse(b"0x1.8p1"));
+        assert_eq!(None, Number::parse(b"1e"));
+        assert_eq!(None, Number::parse(b"inf"));
+        assert_eq!(None, Number::parse(b"0x"));
+        assert_eq!(None, Number::parse(b"1 2"));
+        assert_eq!(None, Number::parse(b""));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!("1", Number::Integer(1).to_string());
+        assert_eq!("1.0", Number::Float(1.0).to_string());
+        assert_eq!("-0.0", Number::Float(-0.0).to_string());
+        assert_eq!("0.1", Number::Float(0.1).to_string());
+        assert_eq!("1e+100", Number::Float(1e100).to_string());
+        assert_eq!(
+            "3.1415926535898",
+            Number::Float(std::f64::consts::PI).to_string()
+        );
+        assert_eq!("1e+15", Number::Float(1e15).to_string());
+        assert_eq!("inf", Number::Float(f64::INFINITY).to_string());
+        assert_eq!("-inf", Number::Float(f64::NEG_INFINITY).to_string());
+    }
+
+    #[test]
+    fn test_arith() {
+        use ArithOp::*;
+        use Number::{Float, Integer};
+        assert_eq!(Some(Integer(-4)), Integer(-7).arith(IDiv, Integer(2)));
+        assert_eq!(Some(Integer(1)), Integer(-7).arith(Mod, Integer(2)));
+        assert_eq!(
+            Some(Integer(i64::MIN)),
+            Integer(i64::MIN).arith(IDiv, Integer(-1))
+        );
+        assert_eq!(Some(Float(-0.5)), Float(5.5).arith(Mod, Integer(-3)));
+        assert_eq!(Some(Float(1.5)), Integer(3).arith(Div, Integer(2)));
+        assert_eq!(Some(Float(8.0)), Integer(2).arith(Pow, Integer(3)));
+        assert_eq!(
+            Some(Integer(i64::MIN)),
+            Integer(i64::MAX).arith(Add, Integer(1))
+        );
+        assert_eq!(
+            Some(Integer(0x7fff_ffff_ffff_ffff)),
+            Integer(-1).arith(Shr, Integer(1))
+        );
+        assert_eq!(Some(Integer(0)), Integer(1).arith(Shl, Integer(64)));
+        assert_eq!(Some(Integer(3)), Float(1.0).arith(BOr, Integer(2)));
+        assert_eq!(None, Float(1.5).arith(BOr, Integer(2)));
+        assert_eq!(Some(Integer(-1)), Integer(0).arith(BNot, Integer(0)));
+    }
+
+    #[test]
+    fn test_less_than() {
+        use Number::{Float, Integer};
+        assert!(Integer(1).less_than(Float(1.5)));
+        assert!(!Float(1.0).less_than(Integer(1)));
+        assert!(Float(0.5).less_than(Integer(1)));
+        assert!(Integer(i64::MAX).less_than(Float(9223372036854775808.0)));
+        assert!(!Float(9223372036854775808.0).less_than(Integer(i64::MAX)));
+        assert!(Float(-1e300).less_than(Integer(i64::MIN)));
+        assert!(!Integer(1).less_than(Float(f64::NAN)));
+        assert!(!Float(f64::NAN).less_than(Integer(1)));
+    }
+
+    #[test]
+    fn test_format_float() {
+        assert_eq!("1.500000e+00", format_float(1.5, b'e', 6, false));
+        assert_eq!("1.5E-07", format_float(1.5e-7, b'E', 1, false));
+        assert_eq!("0.000100", format_float(1e-4, b'f', 6, false));
+        assert_eq!("0.0001", format_float(1e-4, b'g', 6, false));
+        assert_eq!("1e-05", format_float(1e-5, b'g', 6, false));
+        assert_eq!("100000", format_float(1e5, b'g', 6, false));
+        assert_eq!("1e+06", format_float(1e6, b'g', 6, false));
+        assert_eq!("1.00000", format_float(1.0, b'g', 6, true));
+        assert_eq!("0x1.5555555555555p-2", format_hex_float(1.0 / 3.0));
+        assert_eq!("-0x1p+0", format_hex_float(-1.0));
+        assert_eq!("0x1p-1074", format_hex_float(f64::from_bits(1)));
+    }
+}
@@ -1,8 +1,6 @@
-use crate::file::LuaFileParseError;
 use crate::instruction::ArgK;
-use num_enum::{IntoPrimitive, TryFromPrimitive};
-use std::fmt::{Debug, Display, Formatter};
-use std::ptr::write;
+use num_enum::TryFromPrimitive;
+use std::fmt::Debug;
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum Mode {
@@ -12,7 +10,7 @@ pub enum Mode {
     Ax,
 }
 
-#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, TryFromPrimitive)]
+#[derive(Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, TryFromPrimitive)]
 #[repr(u8)]
 pub enum Op {
     Move,
@@ -119,6 +117,30 @@ impl Op {
         }
     }
 
+    pub fn sets_a(&self) -> bool {
+        !matches!(
+            self,
+            Op::SetTabup
+                | Op::SetUpval
+                | Op::SetTable
+                | Op::Jmp
+                | Op::Eq
+                | Op::Lt
+                | Op::Le
+                | Op::Test
+                | Op::Return
+                | Op::TForCall
+                | Op::SetList
+                | Op::ExtraArg
+        )
+    }
+
+    /// Comparisons and tests, which skip the next instruction (a `Jmp`)
+    /// depending on their outcome.
+    pub fn is_test(&self) -> bool {
+        matches!(self, Op::Eq | Op::Lt | Op::Le | Op::Test | Op::TestSet)
+    }
+
     pub fn name(&self) -> &'static str {
         match self {
             Op::Move => "Move",
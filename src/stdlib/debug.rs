@@ -0,0 +1,375 @@
+use crate::file::chunk::Chunk;
+use crate::stdlib::ArgumentError;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Levels shown before and after the `...` in long tracebacks.
+const LEVELS1: usize = 10;
+const LEVELS2: usize = 11;
+
+/// A function activation on the call stack.
+#[derive(Copy, Clone, Debug)]
+pub enum Frame<'a> {
+    Lua {
+        chunk: &'a Chunk,
+        /// The instruction being executed.
+        pc: usize,
+        tail_call: bool,
+    },
+    /// A function provided by the host.
+    Host,
+}
+
+/// The fields of the table returned by `debug.getinfo`.
+#[derive(Debug, Eq, PartialEq)]
+pub struct DebugInfo {
+    pub source: String,
+    pub short_src: String,
+    /// `"Lua"`, `"main"` or `"C"`.
+    pub what: &'static str,
+    /// -1 when no line information is available.
+    pub current_line: i64,
+    pub line_defined: i64,
+    pub last_line_defined: i64,
+    pub num_upvalues: u8,
+    pub num_params: u8,
+    pub is_vararg: bool,
+    pub name: Option<String>,
+    /// How `name` was found, e.g. `"global"` or `"method"`; empty without
+    /// a name.
+    pub namewhat: &'static str,
+    pub is_tail_call: bool,
+}
+
+impl DebugInfo {
+    /// `debug.getinfo(f)` for a function that is not running.
+    pub fn for_function(chunk: &Chunk) -> DebugInfo {
+        DebugInfo {
+            source: if chunk.name.is_empty() {
+                "=?".to_owned()
+            } else {
+                chunk.name.clone()
+            },
+            short_src: chunk.short_source(),
+            what: if chunk.line_defined == 0 {
+                "main"
+            } else {
+                "Lua"
+            },
+            current_line: -1,
+            line_defined: chunk.line_defined as i64,
+            last_line_defined: chunk.last_line_defined as i64,
+            num_upvalues: chunk.num_upvalues,
+            num_params: chunk.num_params,
+            is_vararg: chunk.vararg_info.is_some(),
+            name: None,
+            namewhat: "",
+            is_tail_call: false,
+        }
+    }
+
+    fn for_host() -> DebugInfo {
+        DebugInfo {
+            source: "=[C]".to_owned(),
+            short_src: "[C]".to_owned(),
+            what: "C",
+            current_line: -1,
+            line_defined: -1,
+            last_line_defined: -1,
+            num_upvalues: 0,
+            num_params: 0,
+            is_vararg: true,
+            name: None,
+            namewhat: "",
+            is_tail_call: false,
+        }
+    }
+}
+
+/// `debug.getinfo(level)`. `frames[0]` is the running function,
+/// `frames[1]` its caller and so on.
+pub fn getinfo(frames: &[Frame], level: usize) -> Option<DebugInfo> {
+    let mut info = match *frames.get(level)? {
+        Frame::Lua {
+            chunk,
+            pc,
+            tail_call,
+        } => DebugInfo {
+            current_line: chunk.line_at(pc).map_or(-1, |line| line as i64),
+            is_tail_call: tail_call,
+            ..DebugInfo::for_function(chunk)
+        },
+        Frame::Host => DebugInfo::for_host(),
+    };
+    // the name comes from the instruction that made the call
+    if !info.is_tail_call {
+        if let Some(Frame::Lua { chunk, pc, .. }) = frames.get(level + 1) {
+            if let Some((namewhat, name)) = chunk.function_name(*pc) {
+                info.name = Some(name);
+                info.namewhat = namewhat;
+            }
+        }
+    }
+    Some(info)
+}
+
+/// `debug.traceback(message, level)` over `frames`, ordered as for
+/// [`getinfo`]. Functions called through a global are assumed to be found
+/// in `_G` under that name.
+pub fn traceback(frames: &[Frame], message: Option<&str>, level: usize) -> String {
+    let mut result = String::new();
+    if let Some(message) = message {
+        result.push_str(message);
+        result.push('\n');
+    }
+    result.push_str("stack traceback:");
+
+    let last = frames.len().saturating_sub(1);
+    let mut first_levels = (last.saturating_sub(level) > LEVELS1 + LEVELS2).then_some(LEVELS1);
+    let mut level = level;
+    while let Some(info) = getinfo(frames, level) {
+        level += 1;
+        if first_levels == Some(0) {
+            result.push_str("\n\t...");
+            first_levels = None;
+            level = last - LEVELS2 + 1;
+            continue;
+        }
+        first_levels = first_levels.map(|n| n - 1);
+
+        result.push_str(&format!("\n\t{}:", info.short_src));
+        if info.current_line > 0 {
+            result.push_str(&format!("{}:", info.current_line));
+        }
+        result.push_str(" in ");
+        match &info.name {
+            Some(name) if info.namewhat == "global" => {
+                result.push_str(&format!("function '{}'", name))
+            }
+            Some(name) => result.push_str(&format!("{} '{}'", info.namewhat, name)),
+            None if info.what == "main" => result.push_str("main chunk"),
+            None if info.what != "C" => result.push_str(&format!(
+                "function <{}:{}>",
+                info.short_src, info.line_defined
+            )),
+            None => result.push('?'),
+        }
+        if info.is_tail_call {
+            result.push_str("\n\t(...tail calls...)");
+        }
+    }
+    result
+}
+
+enum Slot {
+    Register(usize),
+    VarArg(usize),
+}
+
+/// Finds the `n`-th local of `frame`: active locals first, then unnamed
+/// registers up to `num_registers`. Negative `n` counts the varargs.
+fn find_local<'a>(
+    frame: &Frame<'a>,
+    num_registers: usize,
+    num_varargs: usize,
+    n: i64,
+) -> Option<(&'a str, Slot)> {
+    let name = match *frame {
+        Frame::Lua { .. } if n < 0 => {
+            let index = n.unsigned_abs() as usize - 1;
+            return (index < num_varargs).then_some(("(*vararg)", Slot::VarArg(index)));
+        }
+        Frame::Lua { chunk, pc, .. } if n > 0 => chunk.local_name(n as usize, pc),
+        _ => None,
+    };
+    if n <= 0 || n as usize > num_registers {
+        return None;
+    }
+    Some((
+        name.unwrap_or("(*temporary)"),
+        Slot::Register(n as usize - 1),
+    ))
+}
+
+/// `debug.getlocal(level, n)`. `registers` are the frame's stack slots
+/// from its base to its top and `varargs` the extra arguments it got.
+pub fn getlocal<'a, 'v, V>(
+    frame: &Frame<'a>,
+    registers: &'v [V],
+    varargs: &'v [V],
+    n: i64,
+) -> Option<(&'a str, &'v V)> {
+    let (name, slot) = find_local(frame, registers.len(), varargs.len(), n)?;
+    match slot {
+        Slot::Register(i) => Some((name, &registers[i])),
+        Slot::VarArg(i) => Some((name, &varargs[i])),
+    }
+}
+
+/// `debug.setlocal(level, n, value)`: the name of the local that was
+/// assigned, or `None` if there is no such local.
+pub fn setlocal<'a, V>(
+    frame: &Frame<'a>,
+    registers: &mut [V],
+    varargs: &mut [V],
+    n: i64,
+    value: V,
+) -> Option<&'a str> {
+    let (name, slot) = find_local(frame, registers.len(), varargs.len(), n)?;
+    match slot {
+        Slot::Register(i) => registers[i] = value,
+        Slot::VarArg(i) => varargs[i] = value,
+    }
+    Some(name)
+}
+
+/// An upvalue shared between closures.
+pub type UpvalueCell<V> = Rc<RefCell<V>>;
+
+fn upvalue_name(chunk: &Chunk, n: usize) -> &str {
+    chunk
+        .upvalue_names
+        .get(n - 1)
+        .map_or("(*no name)", String::as_str)
+}
+
+fn upvalue_index<V>(upvalues: &[UpvalueCell<V>], n: i64) -> Option<usize> {
+    (1..=upvalues.len() as i64)
+        .contains(&n)
+        .then_some(n as usize)
+}
+
+/// `debug.getupvalue(f, n)` for a closure of `chunk`.
+pub fn getupvalue<'a, V: Clone>(
+    chunk: &'a Chunk,
+    upvalues: &[UpvalueCell<V>],
+    n: i64,
+) -> Option<(&'a str, V)> {
+    let n = upvalue_index(upvalues, n)?;
+    Some((upvalue_name(chunk, n), upvalues[n - 1].borrow().clone()))
+}
+
+/// `debug.setupvalue(f, n, value)`: the name of the upvalue that was
+/// assigned, or `None` if there is no such upvalue.
+pub fn setupvalue<'a, V>(
+    chunk: &'a Chunk,
+    upvalues: &[UpvalueCell<V>],
+    n: i64,
+    value: V,
+) -> Option<&'a str> {
+    let n = upvalue_index(upvalues, n)?;
+    *upvalues[n - 1].borrow_mut() = value;
+    Some(upvalue_name(chunk, n))
+}
+
+fn check_upvalue<V>(
+    upvalues: &[UpvalueCell<V>],
+    n: i64,
+    arg: usize,
+    function: &'static str,
+) -> Result<usize, ArgumentError> {
+    upvalue_index(upvalues, n)
+        .ok_or_else(|| ArgumentError::new(arg, function, "invalid upvalue index"))
+}
+
+/// `debug.upvalueid(f, n)`: equal for closures sharing the upvalue.
+pub fn upvalueid<V>(upvalues: &[UpvalueCell<V>], n: i64) -> Result<usize, ArgumentError> {
+    let n = check_upvalue(upvalues, n, 2, "upvalueid")?;
+    Ok(Rc::as_ptr(&upvalues[n - 1]) as usize)
+}
+
+/// `debug.upvaluejoin(f1, n1, f2, n2)`: makes upvalue `n1` of the first
+/// closure refer to upvalue `n2` of the second. To join upvalues of the
+/// same closure, pass a copy of its upvalues as `upvalues2`.
+pub fn upvaluejoin<V>(
+    upvalues1: &mut [UpvalueCell<V>],
+    n1: i64,
+    upvalues2: &[UpvalueCell<V>],
+    n2: i64,
+) -> Result<(), ArgumentError> {
+    let n1 = check_upvalue(upvalues1, n1, 2, "upvaluejoin")?;
+    let n2 = check_upvalue(upvalues2, n2, 4, "upvaluejoin")?;
+    upvalues1[n1 - 1] = upvalues2[n2 - 1].clone();
+    Ok(())
+}
+
+/// The events a hook set with `debug.sethook` is called for.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct HookMask {
+    pub call: bool,
+    pub ret: bool,
+    pub line: bool,
+    /// Call the hook every `count` instructions if positive.
+    pub count: i32,
+}
+
+impl HookMask {
+    /// Parses the `mask` and `count` arguments of `debug.sethook`. Unknown
+    /// characters in `mask` are ignored.
+    pub fn parse(mask: &[u8], count: Option<i64>) -> HookMask {
+        HookMask {
+            call: mask.contains(&b'c'),
+            ret: mask.contains(&b'r'),
+            line: mask.contains(&b'l'),
+            count: count.unwrap_or(0) as i32,
+        }
+    }
+
+    /// A hook with an empty mask is never called and is removed.
+    pub fn is_empty(&self) -> bool {
+        !(self.call || self.ret || self.line || self.count > 0)
+    }
+
+    /// The mask string returned by `debug.gethook`.
+    pub fn to_mask_string(&self) -> String {
+        [(self.call, 'c'), (self.ret, 'r'), (self.line, 'l')]
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, c)| c)
+            .collect()
+    }
+}
+
+/// Decides when the line and count hooks fire, the way the interpreter
+/// does before executing each instruction.
+#[derive(Clone, Debug)]
+pub struct Hooks {
+    mask: HookMask,
+    remaining: i32,
+}
+
+impl Hooks {
+    pub fn new(mask: HookMask) -> Hooks {
+        Hooks {
+            mask,
+            remaining: mask.count,
+        }
+    }
+
+    pub fn mask(&self) -> HookMask {
+        self.mask
+    }
+
+    /// Counts one instruction; true when the count hook is due.
+    pub fn count_event(&mut self) -> bool {
+        if self.mask.count <= 0 {
+            return false;
+        }
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            return false;
+        }
+        self.remaining = self.mask.count;
+        true
+    }
+
+    /// The line to report before executing `pc` after `old_pc`: on
+    /// entering the function, on jumping backwards and on a new line.
+    pub fn line_event(&self, chunk: &Chunk, old_pc: usize, pc: usize) -> Option<u64> {
+        if !self.mask.line {
+            return None;
+        }
+        let line = chunk.line_at(pc)?;
+        (pc == 0 || pc <= old_pc || chunk.line_at(old_pc) != Some(line)).then_some(line)
+    }
+}
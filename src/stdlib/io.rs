@@ -0,0 +1,677 @@
+use crate::number::{format_float, is_space, Number};
+use crate::stdlib::sandbox::{Capabilities, OpenMode, Stream};
+use crate::stdlib::string::format::FormatArg;
+use crate::stdlib::ArgumentError;
+use std::borrow::BorrowMut;
+use std::fmt::{Display, Formatter};
+use std::io::{self, ErrorKind, Read, SeekFrom};
+
+const MAX_LENGTH_NUMERAL: usize = 200;
+const MAX_ARG_LINE: usize = 250;
+const BUFFER_SIZE: usize = 8192;
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum IoError {
+    BadArgument(ArgumentError),
+    ClosedFile,
+    FileAlreadyClosed,
+    /// A failed operation on the host side. Lua reports these as
+    /// `nil, message, code` instead of raising them.
+    Os {
+        message: String,
+        code: i32,
+    },
+}
+
+impl IoError {
+    pub(crate) fn from_io(error: io::Error, path: Option<&[u8]>) -> IoError {
+        let code = error.raw_os_error().unwrap_or(0);
+        let mut message = error.to_string();
+        if let Some(index) = message.find(" (os error") {
+            message.truncate(index);
+        }
+        if let Some(path) = path {
+            message = format!("{}: {}", String::from_utf8_lossy(path), message);
+        }
+        IoError::Os { message, code }
+    }
+}
+
+impl Display for IoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IoError::BadArgument(error) => write!(f, "{}", error),
+            IoError::ClosedFile => write!(f, "attempt to use a closed file"),
+            IoError::FileAlreadyClosed => write!(f, "file is already closed"),
+            IoError::Os { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<ArgumentError> for IoError {
+    fn from(error: ArgumentError) -> IoError {
+        IoError::BadArgument(error)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ReadFormat {
+    Number,
+    Line,
+    LineWithEnd,
+    All,
+    Count(usize),
+}
+
+impl ReadFormat {
+    /// Parses a format argument of `read` or `lines`: a byte count or one
+    /// of `n`, `l`, `L` and `a`, optionally prefixed with `*`.
+    pub fn parse(value: FormatArg, arg: usize, function: &'static str) -> Result<Self, IoError> {
+        let bad_argument = |message: &str| ArgumentError::new(arg, function, message).into();
+        if let FormatArg::Number(n) = value {
+            let n = n
+                .to_integer()
+                .ok_or_else(|| bad_argument("number has no integer representation"))?;
+            return Ok(ReadFormat::Count(n as usize));
+        }
+        let format = match value {
+            FormatArg::String(s) => s,
+            other => {
+                let message = format!("string expected, got {}", other.type_name());
+                return Err(bad_argument(&message));
+            }
+        };
+        let format = format.strip_prefix(b"*").unwrap_or(format);
+        match format.first() {
+            Some(b'n') => Ok(ReadFormat::Number),
+            Some(b'l') => Ok(ReadFormat::Line),
+            Some(b'L') => Ok(ReadFormat::LineWithEnd),
+            Some(b'a') => Ok(ReadFormat::All),
+            _ => Err(bad_argument("invalid format")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReadValue {
+    Number(Number),
+    String(Vec<u8>),
+}
+
+/// A file handle from `io.open`. Reads go through a buffer, like a C
+/// `FILE` does, which also gives the `n` format its look-ahead byte.
+pub struct FileHandle {
+    stream: Option<Box<dyn Stream>>,
+    buffer: Vec<u8>,
+    /// Position of the next unread byte in `buffer`.
+    pos: usize,
+}
+
+impl Display for FileHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.stream {
+            None => write!(f, "file (closed)"),
+            Some(stream) => write!(f, "file ({:p})", stream),
+        }
+    }
+}
+
+impl FileHandle {
+    pub fn new(stream: Box<dyn Stream>) -> Self {
+        FileHandle {
+            stream: Some(stream),
+            buffer: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.stream.is_none()
+    }
+
+    fn stream(&mut self) -> Result<&mut Box<dyn Stream>, IoError> {
+        self.stream.as_mut().ok_or(IoError::ClosedFile)
+    }
+
+    pub fn close(&mut self) -> Result<(), IoError> {
+        let mut stream = self.stream.take().ok_or(IoError::ClosedFile)?;
+        self.buffer.clear();
+        self.pos = 0;
+        stream.flush().map_err(|e| IoError::from_io(e, None))
+    }
+
+    pub fn flush(&mut self) -> Result<(), IoError> {
+        self.stream()?
+            .flush()
+            .map_err(|e| IoError::from_io(e, None))
+    }
+
+    /// Moves the stream back over the bytes read into the buffer but not
+    /// consumed yet, so the stream position is where the script thinks it
+    /// is.
+    fn discard_buffer(&mut self) -> io::Result<()> {
+        let unread = self.buffer.len() - self.pos;
+        self.buffer.clear();
+        self.pos = 0;
+        if unread > 0 {
+            if let Some(stream) = self.stream.as_mut() {
+                stream.seek(SeekFrom::Current(-(unread as i64)))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The unread bytes in the buffer, refilling it if they ran out. Empty
+    /// at the end of the file.
+    fn fill_buffer(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buffer.len() {
+            self.buffer.resize(BUFFER_SIZE, 0);
+            self.pos = 0;
+            let n = match self.stream.as_mut() {
+                Some(stream) => loop {
+                    match stream.read(&mut self.buffer) {
+                        Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                        Err(e) => {
+                            self.buffer.clear();
+                            return Err(e);
+                        }
+                        Ok(n) => break n,
+                    }
+                },
+                None => 0,
+            };
+            self.buffer.truncate(n);
+        }
+        Ok(&self.buffer[self.pos..])
+    }
+
+    fn getc(&mut self) -> io::Result<Option<u8>> {
+        let c = self.fill_buffer()?.first().copied();
+        self.pos += c.is_some() as usize;
+        Ok(c)
+    }
+
+    /// Puts back `c`, the byte `getc` just returned, like `ungetc`.
+    fn ungetc(&mut self, c: Option<u8>) {
+        if c.is_some() {
+            self.pos -= 1;
+        }
+    }
+
+    fn read_line(&mut self, keep_end: bool) -> io::Result<Option<Vec<u8>>> {
+        let mut line = vec![];
+        loop {
+            let available = self.fill_buffer()?;
+            if available.is_empty() {
+                return Ok((!line.is_empty()).then_some(line));
+            }
+            match available.iter().position(|&b| b == b'\n') {
+                Some(end) => {
+                    line.extend_from_slice(&available[..end + keep_end as usize]);
+                    self.pos += end + 1;
+                    return Ok(Some(line));
+                }
+                None => {
+                    line.extend_from_slice(available);
+                    self.pos = self.buffer.len();
+                }
+            }
+        }
+    }
+
+    fn read_all(&mut self) -> io::Result<Vec<u8>> {
+        let mut all = self.buffer.split_off(self.pos);
+        self.buffer.clear();
+        self.pos = 0;
+        if let Some(stream) = self.stream.as_mut() {
+            stream.read_to_end(&mut all)?;
+        }
+        Ok(all)
+    }
+
+    fn read_chars(&mut self, n: usize) -> io::Result<Option<Vec<u8>>> {
+        if n == 0 {
+            let at_end = self.fill_buffer()?.is_empty();
+            return Ok((!at_end).then(Vec::new));
+        }
+        let mut chars = Vec::with_capacity(n.min(1 << 16));
+        while chars.len() < n {
+            let available = self.fill_buffer()?;
+            if available.is_empty() {
+                break;
+            }
+            let count = available.len().min(n - chars.len());
+            chars.extend_from_slice(&available[..count]);
+            self.pos += count;
+        }
+        Ok((!chars.is_empty()).then_some(chars))
+    }
+
+    fn read_number(&mut self) -> io::Result<Option<Number>> {
+        let mut c = self.getc()?;
+        while c.is_some_and(is_space) {
+            c = self.getc()?;
+        }
+        let mut reader = NumeralReader {
+            handle: self,
+            c,
+            numeral: vec![],
+            too_long: false,
+        };
+        let mut count = 0;
+        let mut hex = false;
+        reader.accept(b"-+")?;
+        if reader.accept(b"00")? {
+            if reader.accept(b"xX")? {
+                hex = true;
+            } else {
+                count = 1;
+            }
+        }
+        count += reader.read_digits(hex)?;
+        if reader.accept(b"..")? {
+            count += reader.read_digits(hex)?;
+        }
+        if count > 0 && reader.accept(if hex { b"pP" } else { b"eE" })? {
+            reader.accept(b"-+")?;
+            reader.read_digits(false)?;
+        }
+        let NumeralReader {
+            c,
+            numeral,
+            too_long,
+            ..
+        } = reader;
+        self.ungetc(c);
+        if too_long {
+            return Ok(None);
+        }
+        Ok(Number::parse(&numeral))
+    }
+
+    /// `file:read(...)`: reads one value per format, stopping at the first
+    /// that fails, which is returned as `None`. Without formats, reads a
+    /// line.
+    pub fn read(&mut self, formats: &[ReadFormat]) -> Result<Vec<Option<ReadValue>>, IoError> {
+        self.stream()?;
+        let formats = if formats.is_empty() {
+            &[ReadFormat::Line]
+        } else {
+            formats
+        };
+        let mut values = vec![];
+        for format in formats {
+            let value = match format {
+                ReadFormat::Number => self.read_number().map(|n| n.map(ReadValue::Number)),
+                ReadFormat::Line => self.read_line(false).map(|l| l.map(ReadValue::String)),
+                ReadFormat::LineWithEnd => self.read_line(true).map(|l| l.map(ReadValue::String)),
+                ReadFormat::All => self.read_all().map(|a| Some(ReadValue::String(a))),
+                ReadFormat::Count(n) => self.read_chars(*n).map(|s| s.map(ReadValue::String)),
+            }
+            .map_err(|e| IoError::from_io(e, None))?;
+            let failed = value.is_none();
+            values.push(value);
+            if failed {
+                break;
+            }
+        }
+        Ok(values)
+    }
+
+    /// `file:write(...)`: numbers are written like `%.14g` or `%d`, strings
+    /// as they are.
+    pub fn write(&mut self, values: &[FormatArg]) -> Result<(), IoError> {
+        self.stream()?;
+        self.discard_buffer()
+            .map_err(|e| IoError::from_io(e, None))?;
+        for (i, value) in values.iter().enumerate() {
+            let bytes = match *value {
+                FormatArg::Number(Number::Integer(n)) => n.to_string().into_bytes(),
+                FormatArg::Number(Number::Float(x)) => {
+                    format_float(x, b'g', 14, false).into_bytes()
+                }
+                FormatArg::String(s) => s.to_vec(),
+                other => {
+                    let message = format!("string expected, got {}", other.type_name());
+                    return Err(ArgumentError::new(i + 1, "write", &message).into());
+                }
+            };
+            self.stream()?
+                .write_all(&bytes)
+                .map_err(|e| IoError::from_io(e, None))?;
+        }
+        Ok(())
+    }
+
+    /// `file:seek([whence [, offset]])`: returns the new position.
+    pub fn seek(&mut self, whence: Option<&[u8]>, offset: Option<i64>) -> Result<u64, IoError> {
+        self.stream()?;
+        let offset = offset.unwrap_or(0);
+        let position = match whence.unwrap_or(b"cur") {
+            b"set" => SeekFrom::Start(offset as u64),
+            b"cur" => SeekFrom::Current(offset),
+            b"end" => SeekFrom::End(offset),
+            other => {
+                let message = format!("invalid option '{}'", String::from_utf8_lossy(other));
+                return Err(ArgumentError::new(1, "seek", &message).into());
+            }
+        };
+        self.discard_buffer()
+            .and_then(|_| self.stream.as_mut().unwrap().seek(position))
+            .map_err(|e| IoError::from_io(e, None))
+    }
+
+    /// `file:lines(...)`: iterates over `read(formats)` until the first
+    /// value fails. The file stays open.
+    pub fn lines(&mut self, formats: &[ReadFormat]) -> Result<Lines<&mut FileHandle>, IoError> {
+        self.stream()?;
+        Lines::new(self, formats, false)
+    }
+}
+
+struct NumeralReader<'h> {
+    handle: &'h mut FileHandle,
+    c: Option<u8>,
+    numeral: Vec<u8>,
+    too_long: bool,
+}
+
+impl NumeralReader<'_> {
+    fn next(&mut self) -> io::Result<bool> {
+        if self.numeral.len() >= MAX_LENGTH_NUMERAL {
+            self.too_long = true;
+            return Ok(false);
+        }
+        self.numeral.extend(self.c);
+        self.c = self.handle.getc()?;
+        Ok(true)
+    }
+
+    fn accept(&mut self, set: &[u8; 2]) -> io::Result<bool> {
+        match self.c {
+            Some(c) if set.contains(&c) => self.next(),
+            _ => Ok(false),
+        }
+    }
+
+    fn read_digits(&mut self, hex: bool) -> io::Result<usize> {
+        let mut count = 0;
+        while self.c.is_some_and(|c| {
+            if hex {
+                c.is_ascii_hexdigit()
+            } else {
+                c.is_ascii_digit()
+            }
+        }) && self.next()?
+        {
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// Iterator behind `file:lines` and `io.lines`. Each item holds the values
+/// of one `read` call.
+pub struct Lines<H: BorrowMut<FileHandle>> {
+    handle: H,
+    formats: Vec<ReadFormat>,
+    close_at_end: bool,
+    done: bool,
+}
+
+impl<H: BorrowMut<FileHandle>> Lines<H> {
+    fn new(handle: H, formats: &[ReadFormat], close_at_end: bool) -> Result<Self, IoError> {
+        if formats.len() > MAX_ARG_LINE {
+            return Err(ArgumentError::new(MAX_ARG_LINE + 2, "lines", "too many arguments").into());
+        }
+        Ok(Lines {
+            handle,
+            formats: formats.to_vec(),
+            close_at_end,
+            done: false,
+        })
+    }
+}
+
+impl<H: BorrowMut<FileHandle>> Iterator for Lines<H> {
+    type Item = Result<Vec<Option<ReadValue>>, IoError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let handle = self.handle.borrow_mut();
+        if handle.is_closed() {
+            self.done = true;
+            return Some(Err(IoError::FileAlreadyClosed));
+        }
+        match handle.read(&self.formats) {
+            Ok(values) if values.first().is_some_and(Option::is_some) => Some(Ok(values)),
+            Ok(_) => {
+                self.done = true;
+                if self.close_at_end {
+                    let _ = handle.close();
+                }
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// `io.open(filename [, mode])`. Failures are `IoError::Os` with the file
+/// name in the message.
+pub fn open(
+    capabilities: &dyn Capabilities,
+    filename: &[u8],
+    mode: Option<&[u8]>,
+) -> Result<FileHandle, IoError> {
+    let mode = OpenMode::parse(mode.unwrap_or(b"r"))
+        .ok_or_else(|| ArgumentError::new(2, "open", "invalid mode"))?;
+    capabilities
+        .open(filename, mode)
+        .map(FileHandle::new)
+        .map_err(|e| IoError::from_io(e, Some(filename)))
+}
+
+/// `io.lines(filename, ...)`: like `file:lines`, but on a file it opens
+/// itself and closes once the iteration ends. Lua raises the error if the
+/// file cannot be opened.
+pub fn lines(
+    capabilities: &dyn Capabilities,
+    filename: &[u8],
+    formats: &[ReadFormat],
+) -> Result<Lines<FileHandle>, IoError> {
+    Lines::new(open(capabilities, filename, None)?, formats, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::sandbox::{DenyAll, MemoryFileSystem};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn string(s: &[u8]) -> Option<ReadValue> {
+        Some(ReadValue::String(s.to_vec()))
+    }
+
+    fn number(n: Number) -> Option<ReadValue> {
+        Some(ReadValue::Number(n))
+    }
+
+    #[test]
+    fn test_read_formats() {
+        let fs = MemoryFileSystem::new().with_file(b"data", b"line 1\nline 2\n 0x10 -2.5e1 7abc");
+        let mut file = open(&fs, b"data", None).unwrap();
+        assert_eq!(
+            vec![string(b"line 1"), string(b"line 2\n")],
+            file.read(&[ReadFormat::Line, ReadFormat::LineWithEnd])
+                .unwrap()
+        );
+        assert_eq!(
+            vec![
+                number(Number::Integer(16)),
+                number(Number::Float(-25.0)),
+                number(Number::Integer(7)),
+                string(b"ab")
+            ],
+            file.read(&[
+                ReadFormat::Number,
+                ReadFormat::Number,
+                ReadFormat::Number,
+                ReadFormat::Count(2)
+            ])
+            .unwrap()
+        );
+        assert_eq!(
+            vec![string(b"c"), string(b"")],
+            file.read(&[ReadFormat::All, ReadFormat::All]).unwrap()
+        );
+        assert_eq!(
+            vec![None],
+            file.read(&[ReadFormat::Count(0), ReadFormat::Line])
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_read_number_failure_stops_reading() {
+        let fs = MemoryFileSystem::new().with_file(b"data", b"1 x 2");
+        let mut file = open(&fs, b"data", None).unwrap();
+        assert_eq!(
+            vec![number(Number::Integer(1)), None],
+            file.read(&[ReadFormat::Number, ReadFormat::Number, ReadFormat::Number])
+                .unwrap()
+        );
+        assert_eq!(vec![string(b"x 2")], file.read(&[ReadFormat::All]).unwrap());
+    }
+
+    #[test]
+    fn test_write_and_seek() {
+        let fs = MemoryFileSystem::new();
+        let mut file = open(&fs, b"out", Some(b"w+")).unwrap();
+        file.write(&[
+            FormatArg::String(b"x="),
+            FormatArg::Number(Number::Float(1.0)),
+            FormatArg::String(b" "),
+            FormatArg::Number(Number::Integer(2)),
+        ])
+        .unwrap();
+        assert_eq!(Ok(0), file.seek(Some(b"set"), None));
+        assert_eq!(
+            vec![string(b"x")],
+            file.read(&[ReadFormat::Count(1)]).unwrap()
+        );
+        assert_eq!(Ok(1), file.seek(None, None));
+        assert_eq!(Ok(5), file.seek(Some(b"end"), None));
+        file.close().unwrap();
+        assert_eq!(Some(b"x=1 2".to_vec()), fs.contents(b"out"));
+        assert_eq!(Err(IoError::ClosedFile), file.seek(None, None));
+        assert_eq!("file (closed)", file.to_string());
+        assert_eq!(
+            "bad argument #1 to 'write' (string expected, got boolean)",
+            open(&fs, b"out", Some(b"w"))
+                .unwrap()
+                .write(&[FormatArg::Boolean(true)])
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    /// Counts the reads that reach the underlying stream.
+    struct CountingStream {
+        inner: io::Cursor<Vec<u8>>,
+        reads: Rc<Cell<usize>>,
+    }
+
+    impl Read for CountingStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads.set(self.reads.get() + 1);
+            self.inner.read(buf)
+        }
+    }
+
+    impl io::Write for CountingStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl io::Seek for CountingStream {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_reads_are_buffered() {
+        let reads = Rc::new(Cell::new(0));
+        let mut file = FileHandle::new(Box::new(CountingStream {
+            inner: io::Cursor::new(b"12 line\n".repeat(1000)),
+            reads: reads.clone(),
+        }));
+        let formats = [ReadFormat::Number, ReadFormat::Line];
+        assert_eq!(1000, file.lines(&formats).unwrap().count());
+        assert!(reads.get() < 10, "{} reads", reads.get());
+    }
+
+    #[test]
+    fn test_writes_after_buffered_reads() {
+        let fs = MemoryFileSystem::new().with_file(b"data", b"first\nsecond\n");
+        let mut file = open(&fs, b"data", Some(b"r+")).unwrap();
+        assert_eq!(vec![string(b"first")], file.read(&[]).unwrap());
+        assert_eq!(Ok(6), file.seek(None, None));
+        file.write(&[FormatArg::String(b"SE")]).unwrap();
+        assert_eq!(vec![string(b"cond")], file.read(&[]).unwrap());
+        file.close().unwrap();
+        assert_eq!(Some(b"first\nSEcond\n".to_vec()), fs.contents(b"data"));
+    }
+
+    #[test]
+    fn test_lines() {
+        let fs = MemoryFileSystem::new().with_file(b"data", b"a\nb\n\nc");
+        let lines: Vec<_> = lines(&fs, b"data", &[])
+            .unwrap()
+            .map(|values| values.unwrap().remove(0))
+            .collect();
+        assert_eq!(
+            vec![string(b"a"), string(b"b"), string(b""), string(b"c")],
+            lines
+        );
+
+        let mut file = open(&fs, b"data", None).unwrap();
+        assert_eq!(2, file.lines(&[ReadFormat::Count(3)]).unwrap().count());
+        assert!(!file.is_closed());
+    }
+
+    #[test]
+    fn test_open_errors() {
+        let fs = MemoryFileSystem::new();
+        assert_eq!(
+            "missing.txt: No such file or directory",
+            open(&fs, b"missing.txt", None).err().unwrap().to_string()
+        );
+        assert!(matches!(
+            open(&DenyAll, b"secret.txt", None),
+            Err(IoError::Os { code: 13, .. })
+        ));
+        assert_eq!(
+            "bad argument #2 to 'open' (invalid mode)",
+            open(&fs, b"a", Some(b"rw")).err().unwrap().to_string()
+        );
+        assert_eq!(
+            "bad argument #1 to 'read' (invalid format)",
+            ReadFormat::parse(FormatArg::String(b"x"), 1, "read")
+                .unwrap_err()
+                .to_string()
+        );
+    }
+}
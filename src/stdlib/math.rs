@@ -0,0 +1,307 @@
+use crate::number::{float_to_integer, Number};
+use crate::stdlib::ArgumentError;
+
+pub const PI: f64 = std::f64::consts::PI;
+pub const HUGE: f64 = f64::INFINITY;
+pub const MAX_INTEGER: i64 = i64::MAX;
+pub const MIN_INTEGER: i64 = i64::MIN;
+
+/// Floats with an integral value that fits are returned as integers, like
+/// the results of `floor`, `ceil` and `modf`.
+fn number_from_float(f: f64) -> Number {
+    float_to_integer(f).map_or(Number::Float(f), Number::Integer)
+}
+
+/// `math.type`: `"integer"` or `"float"`.
+pub fn number_type(n: Number) -> &'static str {
+    match n {
+        Number::Integer(_) => "integer",
+        Number::Float(_) => "float",
+    }
+}
+
+pub fn tointeger(n: Number) -> Option<i64> {
+    n.to_integer()
+}
+
+pub fn floor(n: Number) -> Number {
+    match n {
+        Number::Integer(_) => n,
+        Number::Float(f) => number_from_float(f.floor()),
+    }
+}
+
+pub fn ceil(n: Number) -> Number {
+    match n {
+        Number::Integer(_) => n,
+        Number::Float(f) => number_from_float(f.ceil()),
+    }
+}
+
+pub fn abs(n: Number) -> Number {
+    match n {
+        Number::Integer(i) => Number::Integer(i.wrapping_abs()),
+        Number::Float(f) => Number::Float(f.abs()),
+    }
+}
+
+/// `math.fmod`: the remainder of the division rounding towards zero.
+/// Integer operands give an integer result.
+pub fn fmod(a: Number, b: Number) -> Result<Number, ArgumentError> {
+    match (a, b) {
+        (Number::Integer(_), Number::Integer(0)) => Err(ArgumentError::new(2, "fmod", "zero")),
+        (Number::Integer(_), Number::Integer(-1)) => Ok(Number::Integer(0)),
+        (Number::Integer(a), Number::Integer(b)) => Ok(Number::Integer(a % b)),
+        (a, b) => Ok(Number::Float(a.to_float() % b.to_float())),
+    }
+}
+
+/// `math.modf`: the integral part (rounded towards zero) and the
+/// fractional part of `n`.
+pub fn modf(n: Number) -> (Number, f64) {
+    match n {
+        Number::Integer(_) => (n, 0.0),
+        Number::Float(f) => {
+            let integral = f.trunc();
+            let fraction = if f == integral { 0.0 } else { f - integral };
+            (number_from_float(integral), fraction)
+        }
+    }
+}
+
+/// `math.ult`: compares two integers as if they were unsigned.
+pub fn ult(a: i64, b: i64) -> bool {
+    (a as u64) < (b as u64)
+}
+
+/// `math.max`: the first of the largest values in `values`.
+pub fn max(values: &[Number]) -> Result<Number, ArgumentError> {
+    select(values, "max", |max, n| max.less_than(n))
+}
+
+/// `math.min`: the first of the smallest values in `values`.
+pub fn min(values: &[Number]) -> Result<Number, ArgumentError> {
+    select(values, "min", |min, n| n.less_than(min))
+}
+
+fn select(
+    values: &[Number],
+    function: &'static str,
+    replaces: impl Fn(Number, Number) -> bool,
+) -> Result<Number, ArgumentError> {
+    let (&first, rest) = values
+        .split_first()
+        .ok_or_else(|| ArgumentError::new(1, function, "value expected"))?;
+    Ok(rest
+        .iter()
+        .fold(first, |best, &n| if replaces(best, n) { n } else { best }))
+}
+
+pub fn sqrt(x: f64) -> f64 {
+    x.sqrt()
+}
+
+pub fn sin(x: f64) -> f64 {
+    x.sin()
+}
+
+pub fn cos(x: f64) -> f64 {
+    x.cos()
+}
+
+pub fn tan(x: f64) -> f64 {
+    x.tan()
+}
+
+pub fn asin(x: f64) -> f64 {
+    x.asin()
+}
+
+pub fn acos(x: f64) -> f64 {
+    x.acos()
+}
+
+/// `math.atan(y [, x])`: the arc tangent of `y / x`, using the signs of
+/// both to find the quadrant. `x` defaults to 1.
+pub fn atan(y: f64, x: Option<f64>) -> f64 {
+    y.atan2(x.unwrap_or(1.0))
+}
+
+pub fn exp(x: f64) -> f64 {
+    x.exp()
+}
+
+/// `math.log(x [, base])`: the natural logarithm unless `base` is given.
+pub fn log(x: f64, base: Option<f64>) -> f64 {
+    match base {
+        None => x.ln(),
+        Some(2.0) => x.log2(),
+        Some(10.0) => x.log10(),
+        Some(base) => x.ln() / base.ln(),
+    }
+}
+
+pub fn deg(x: f64) -> f64 {
+    x * (180.0 / PI)
+}
+
+pub fn rad(x: f64) -> f64 {
+    x * (PI / 180.0)
+}
+
+/// The generator behind `math.random`: xoshiro256**, seeded the same way
+/// as in Lua 5.4 so seeded sequences can be replayed and compared with the
+/// reference implementation.
+#[derive(Clone, Debug)]
+pub struct Random {
+    state: [u64; 4],
+}
+
+impl Random {
+    pub fn new(seed: i64) -> Random {
+        let mut random = Random { state: [0; 4] };
+        random.randomseed(seed);
+        random
+    }
+
+    /// `math.randomseed`: restarts the sequence for `seed`.
+    pub fn randomseed(&mut self, seed: i64) {
+        self.state = [seed as u64, 0xff, 0, 0];
+        for _ in 0..16 {
+            self.next();
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    /// `math.random([m [, n]])`: a float in `[0, 1)` without arguments, an
+    /// integer in `[1, m]` with one and in `[m, n]` with two. As in 5.3,
+    /// intervals with more than `math.maxinteger` values are rejected.
+    pub fn random(&mut self, m: Option<i64>, n: Option<i64>) -> Result<Number, ArgumentError> {
+        let value = self.next();
+        let (low, up) = match (m, n) {
+            (None, _) => return Ok(Number::Float((value >> 11) as f64 * 2_f64.powi(-53))),
+            (Some(up), None) => (1, up),
+            (Some(low), Some(up)) => (low, up),
+        };
+        if low > up {
+            return Err(ArgumentError::new(1, "random", "interval is empty"));
+        }
+        if low < 0 && up > i64::MAX + low {
+            return Err(ArgumentError::new(1, "random", "interval too large"));
+        }
+        let projected = self.project(value, up.wrapping_sub(low) as u64);
+        Ok(Number::Integer(projected.wrapping_add(low as u64) as i64))
+    }
+
+    /// Maps `value` into `[0, n]` without bias, drawing again when it falls
+    /// outside.
+    fn project(&mut self, value: u64, n: u64) -> u64 {
+        if n & n.wrapping_add(1) == 0 {
+            return value & n;
+        }
+        let lim = u64::MAX >> n.leading_zeros();
+        let mut value = value & lim;
+        while value > n {
+            value = self.next() & lim;
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Number::{Float, Integer};
+
+    #[test]
+    fn test_subtypes() {
+        assert_eq!("integer", number_type(Integer(1)));
+        assert_eq!("float", number_type(Float(1.0)));
+        assert_eq!(Some(3), tointeger(Float(3.0)));
+        assert_eq!(None, tointeger(Float(3.5)));
+        assert_eq!(Integer(-4), floor(Float(-3.5)));
+        assert_eq!(Float(1e300), floor(Float(1e300)));
+        assert_eq!(Integer(4), ceil(Float(3.2)));
+        assert_eq!(Integer(i64::MIN), abs(Integer(i64::MIN)));
+        assert_eq!((Integer(3), 0.5), modf(Float(3.5)));
+        assert_eq!((Integer(-3), -0.5), modf(Float(-3.5)));
+        assert_eq!((Float(HUGE), 0.0), modf(Float(HUGE)));
+        assert_eq!((Integer(5), 0.0), modf(Integer(5)));
+        assert!(!ult(-1, 1));
+    }
+
+    #[test]
+    fn test_fmod() {
+        assert_eq!(Ok(Integer(-1)), fmod(Integer(-7), Integer(3)));
+        assert_eq!(Ok(Integer(0)), fmod(Integer(i64::MIN), Integer(-1)));
+        assert_eq!(Ok(Float(-1.5)), fmod(Float(-7.5), Integer(2)));
+        assert_eq!(
+            "bad argument #2 to 'fmod' (zero)",
+            fmod(Integer(1), Integer(0)).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_max_min() {
+        assert_eq!(Ok(Float(2.0)), max(&[Integer(1), Float(2.0), Integer(2)]));
+        assert_eq!(Ok(Float(1.0)), min(&[Integer(3), Float(1.0), Integer(1)]));
+        assert_eq!(
+            "bad argument #1 to 'max' (value expected)",
+            max(&[]).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_log() {
+        assert_eq!(3.0, log(8.0, Some(2.0)));
+        assert_eq!(2.0, log(100.0, Some(10.0)));
+        assert_eq!(1.0, log(std::f64::consts::E, None));
+    }
+
+    #[test]
+    fn test_random_is_reproducible() {
+        let mut random = Random::new(42);
+        assert_eq!(
+            "0.93081217803957",
+            random.random(None, None).unwrap().to_string()
+        );
+        assert_eq!(Ok(Integer(50)), random.random(Some(1), Some(100)));
+        assert_eq!(Ok(Integer(6)), random.random(Some(10), None));
+        assert_eq!(Ok(Integer(3)), random.random(Some(3), Some(3)));
+        assert_eq!(
+            Ok(Integer(870823234106)),
+            random.random(Some(-5), Some(1 << 40))
+        );
+        assert_eq!(
+            "bad argument #1 to 'random' (interval is empty)",
+            random.random(Some(0), None).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_random_interval_too_large() {
+        let mut random = Random::new(0);
+        assert_eq!(
+            "bad argument #1 to 'random' (interval too large)",
+            random
+                .random(Some(MIN_INTEGER), Some(MAX_INTEGER))
+                .unwrap_err()
+                .to_string()
+        );
+        assert!(random.random(Some(-1), Some(MAX_INTEGER)).is_err());
+        assert!(random.random(Some(0), Some(MAX_INTEGER)).is_ok());
+        assert!(random.random(Some(MIN_INTEGER), Some(-1)).is_ok());
+    }
+}
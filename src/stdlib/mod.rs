@@ -0,0 +1,40 @@
+use std::fmt::{Display, Formatter};
+
+pub mod debug;
+pub mod io;
+pub mod math;
+pub mod os;
+pub mod package;
+pub mod sandbox;
+pub mod string;
+pub mod table;
+pub mod utf8;
+
+/// An invalid argument to a library function, reported the way
+/// `luaL_argerror` does.
+#[derive(Debug, Eq, PartialEq)]
+pub struct ArgumentError {
+    pub arg: usize,
+    pub function: &'static str,
+    pub message: String,
+}
+
+impl ArgumentError {
+    pub(crate) fn new(arg: usize, function: &'static str, message: &str) -> ArgumentError {
+        ArgumentError {
+            arg,
+            function,
+            message: message.to_owned(),
+        }
+    }
+}
+
+impl Display for ArgumentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "bad argument #{} to '{}' ({})",
+            self.arg, self.function, self.message
+        )
+    }
+}
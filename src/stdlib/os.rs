@@ -0,0 +1,516 @@
+use crate::stdlib::io::IoError;
+use crate::stdlib::sandbox::{Capabilities, TimeZone};
+use crate::stdlib::ArgumentError;
+use std::fmt::{Display, Formatter};
+
+const MAX_DATE_FIELD: i64 = i32::MAX as i64 / 2;
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// Conversions accepted by `os.date`, as in C99 `strftime`. Options
+/// after each `|` are one character longer.
+const STRFTIME_OPTIONS: &[u8] =
+    b"aAbBcCdDeFgGhHIjmMnprRStTuUVwWxXyYzZ%||EcECExEXEyEYOdOeOHOIOmOMOSOuOUOVOwOWOy";
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum OsError {
+    BadArgument(ArgumentError),
+    FieldMissing(&'static str),
+    FieldOutOfBound(&'static str),
+    TimeNotRepresentable,
+}
+
+impl Display for OsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OsError::BadArgument(error) => write!(f, "{}", error),
+            OsError::FieldMissing(field) => write!(f, "field '{}' missing in date table", field),
+            OsError::FieldOutOfBound(field) => write!(f, "field '{}' is out-of-bound", field),
+            OsError::TimeNotRepresentable => {
+                write!(f, "time result cannot be represented in this installation")
+            }
+        }
+    }
+}
+
+impl From<ArgumentError> for OsError {
+    fn from(error: ArgumentError) -> OsError {
+        OsError::BadArgument(error)
+    }
+}
+
+/// The fields of a date table passed to `os.time`. `os.time` replaces
+/// them with their normalized values, like the reference implementation
+/// does.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DateFields {
+    pub year: Option<i64>,
+    pub month: Option<i64>,
+    pub day: Option<i64>,
+    pub hour: Option<i64>,
+    pub min: Option<i64>,
+    pub sec: Option<i64>,
+    pub isdst: Option<bool>,
+}
+
+/// The table `os.date("*t")` returns.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DateTable {
+    pub year: i64,
+    pub month: i64,
+    pub day: i64,
+    pub hour: i64,
+    pub min: i64,
+    pub sec: i64,
+    /// 1 is Sunday.
+    pub wday: i64,
+    /// 1 is January 1st.
+    pub yday: i64,
+    pub isdst: bool,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Date {
+    Table(DateTable),
+    String(Vec<u8>),
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+impl DateTable {
+    fn from_time(time: i64, zone: &TimeZone) -> Result<DateTable, OsError> {
+        let local = time
+            .checked_add(zone.offset)
+            .ok_or(OsError::TimeNotRepresentable)?;
+        let days = local.div_euclid(SECONDS_PER_DAY);
+        let seconds = local.rem_euclid(SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        if i32::try_from(year - 1900).is_err() {
+            return Err(OsError::TimeNotRepresentable);
+        }
+        Ok(DateTable {
+            year,
+            month,
+            day,
+            hour: seconds / 3600,
+            min: seconds / 60 % 60,
+            sec: seconds % 60,
+            wday: (days + 4).rem_euclid(7) + 1,
+            yday: days - days_from_civil(year, 1, 1) + 1,
+            isdst: zone.is_dst,
+        })
+    }
+
+    /// The ISO 8601 week-based year and week number.
+    fn iso_week(&self) -> (i64, i64) {
+        fn weeks_in_year(year: i64) -> i64 {
+            let p = |y: i64| (y + y.div_euclid(4) - y.div_euclid(100) + y.div_euclid(400)) % 7;
+            if p(year) == 4 || p(year - 1) == 3 {
+                53
+            } else {
+                52
+            }
+        }
+        let weekday = (self.wday + 5) % 7 + 1;
+        let week = (self.yday - weekday + 10) / 7;
+        if week < 1 {
+            (self.year - 1, weeks_in_year(self.year - 1))
+        } else if week > weeks_in_year(self.year) {
+            (self.year + 1, 1)
+        } else {
+            (self.year, week)
+        }
+    }
+
+    /// Formats one `strftime` conversion in the C locale.
+    fn format(&self, conversion: u8, zone: &TimeZone) -> String {
+        let hour12 = (self.hour + 11) % 12 + 1;
+        match conversion {
+            b'a' => WEEKDAYS[self.wday as usize - 1][..3].to_owned(),
+            b'A' => WEEKDAYS[self.wday as usize - 1].to_owned(),
+            b'b' | b'h' => MONTHS[self.month as usize - 1][..3].to_owned(),
+            b'B' => MONTHS[self.month as usize - 1].to_owned(),
+            b'c' => format!(
+                "{} {} {:2} {:02}:{:02}:{:02} {}",
+                self.format(b'a', zone),
+                self.format(b'b', zone),
+                self.day,
+                self.hour,
+                self.min,
+                self.sec,
+                self.year
+            ),
+            b'C' => format!("{:02}", self.year.div_euclid(100)),
+            b'd' => format!("{:02}", self.day),
+            b'D' | b'x' => format!(
+                "{:02}/{:02}/{:02}",
+                self.month,
+                self.day,
+                self.year.rem_euclid(100)
+            ),
+            b'e' => format!("{:2}", self.day),
+            b'F' => format!("{}-{:02}-{:02}", self.year, self.month, self.day),
+            b'g' => format!("{:02}", self.iso_week().0.rem_euclid(100)),
+            b'G' => self.iso_week().0.to_string(),
+            b'H' => format!("{:02}", self.hour),
+            b'I' => format!("{:02}", hour12),
+            b'j' => format!("{:03}", self.yday),
+            b'm' => format!("{:02}", self.month),
+            b'M' => format!("{:02}", self.min),
+            b'n' => "\n".to_owned(),
+            b'p' => if self.hour < 12 { "AM" } else { "PM" }.to_owned(),
+            b'r' => format!(
+                "{:02}:{:02}:{:02} {}",
+                hour12,
+                self.min,
+                self.sec,
+                self.format(b'p', zone)
+            ),
+            b'R' => format!("{:02}:{:02}", self.hour, self.min),
+            b'S' => format!("{:02}", self.sec),
+            b't' => "\t".to_owned(),
+            b'T' | b'X' => format!("{:02}:{:02}:{:02}", self.hour, self.min, self.sec),
+            b'u' => ((self.wday + 5) % 7 + 1).to_string(),
+            b'U' => format!("{:02}", (self.yday + 6 - (self.wday - 1)) / 7),
+            b'V' => format!("{:02}", self.iso_week().1),
+            b'w' => (self.wday - 1).to_string(),
+            b'W' => format!("{:02}", (self.yday + 6 - (self.wday + 5) % 7) / 7),
+            b'y' => format!("{:02}", self.year.rem_euclid(100)),
+            b'Y' => self.year.to_string(),
+            b'z' => {
+                let sign = if zone.offset < 0 { '-' } else { '+' };
+                let minutes = zone.offset.abs() / 60;
+                format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+            }
+            b'Z' => zone.name.clone(),
+            _ => "%".to_owned(),
+        }
+    }
+}
+
+/// Finds the conversion at the start of `conversion`, returning its
+/// length.
+fn check_option(conversion: &[u8]) -> Result<usize, OsError> {
+    let mut length = 1;
+    let mut options = STRFTIME_OPTIONS;
+    while !options.is_empty() && length <= conversion.len() {
+        if options[0] == b'|' {
+            length += 1;
+        } else if options.len() >= length && options[..length] == conversion[..length] {
+            return Ok(length);
+        }
+        options = &options[length.min(options.len())..];
+    }
+    let message = format!(
+        "invalid conversion specifier '%{}'",
+        String::from_utf8_lossy(conversion)
+    );
+    Err(ArgumentError::new(1, "date", &message).into())
+}
+
+/// `os.date([format [, time]])`. A leading `!` in `format` formats the
+/// time in UTC instead of the host's time zone; `*t` returns a table.
+pub fn date(
+    capabilities: &dyn Capabilities,
+    format: Option<&[u8]>,
+    time: Option<i64>,
+) -> Result<Date, OsError> {
+    let mut format = format.unwrap_or(b"%c");
+    let time = time.unwrap_or_else(|| capabilities.time());
+    let zone = match format.strip_prefix(b"!") {
+        Some(rest) => {
+            format = rest;
+            TimeZone {
+                offset: 0,
+                name: "GMT".to_owned(),
+                is_dst: false,
+            }
+        }
+        None => capabilities.time_zone(time),
+    };
+    let table = DateTable::from_time(time, &zone)?;
+    if format == b"*t" {
+        return Ok(Date::Table(table));
+    }
+
+    let mut result = vec![];
+    let mut i = 0;
+    while i < format.len() {
+        if format[i] != b'%' {
+            result.push(format[i]);
+            i += 1;
+            continue;
+        }
+        let length = check_option(&format[i + 1..])?;
+        // 'E' and 'O' modifiers change nothing in the C locale
+        let conversion = format[i + length];
+        result.extend_from_slice(table.format(conversion, &zone).as_bytes());
+        i += 1 + length;
+    }
+    Ok(Date::String(result))
+}
+
+fn get_field(value: Option<i64>, name: &'static str, default: Option<i64>) -> Result<i64, OsError> {
+    match value {
+        None => default.ok_or(OsError::FieldMissing(name)),
+        Some(v) if (-MAX_DATE_FIELD..=MAX_DATE_FIELD).contains(&v) => Ok(v),
+        Some(_) => Err(OsError::FieldOutOfBound(name)),
+    }
+}
+
+/// `os.time([table])`: the current time, or the time described by
+/// `fields` in the host's time zone. Missing `hour` defaults to 12, `min`
+/// and `sec` to 0.
+pub fn time(
+    capabilities: &dyn Capabilities,
+    fields: Option<&mut DateFields>,
+) -> Result<i64, OsError> {
+    let fields = match fields {
+        None => return Ok(capabilities.time()),
+        Some(fields) => fields,
+    };
+    let sec = get_field(fields.sec, "sec", Some(0))?;
+    let min = get_field(fields.min, "min", Some(0))?;
+    let hour = get_field(fields.hour, "hour", Some(12))?;
+    let day = get_field(fields.day, "day", None)?;
+    let month = get_field(fields.month, "month", None)? - 1;
+    let year = get_field(fields.year, "year", None)?;
+
+    let days = days_from_civil(year + month.div_euclid(12), month.rem_euclid(12) + 1, 1) + day - 1;
+    let local = days * SECONDS_PER_DAY + hour * 3600 + min * 60 + sec;
+    let zone = capabilities.time_zone(local);
+    let time = local - zone.offset;
+
+    let normalized = DateTable::from_time(time, &zone)?;
+    *fields = DateFields {
+        year: Some(normalized.year),
+        month: Some(normalized.month),
+        day: Some(normalized.day),
+        hour: Some(normalized.hour),
+        min: Some(normalized.min),
+        sec: Some(normalized.sec),
+        isdst: Some(normalized.isdst),
+    };
+    Ok(time)
+}
+
+pub fn clock(capabilities: &dyn Capabilities) -> f64 {
+    capabilities.clock()
+}
+
+/// `os.difftime`: subtracts as floats, like `loslib` does, so times far
+/// apart cannot overflow.
+pub fn difftime(t2: i64, t1: i64) -> f64 {
+    t2 as f64 - t1 as f64
+}
+
+pub fn getenv(capabilities: &dyn Capabilities, name: &[u8]) -> Option<Vec<u8>> {
+    capabilities.getenv(name)
+}
+
+pub fn remove(capabilities: &dyn Capabilities, filename: &[u8]) -> Result<(), IoError> {
+    capabilities
+        .remove(filename)
+        .map_err(|e| IoError::from_io(e, Some(filename)))
+}
+
+pub fn rename(capabilities: &dyn Capabilities, from: &[u8], to: &[u8]) -> Result<(), IoError> {
+    capabilities
+        .rename(from, to)
+        .map_err(|e| IoError::from_io(e, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::sandbox::{DenyAll, MemoryFileSystem};
+
+    struct FixedClock;
+
+    impl Capabilities for FixedClock {
+        fn time(&self) -> i64 {
+            1_000_000_000
+        }
+
+        fn time_zone(&self, _time: i64) -> TimeZone {
+            TimeZone {
+                offset: 2 * 3600,
+                name: "CEST".to_owned(),
+                is_dst: true,
+            }
+        }
+    }
+
+    fn date_string(format: &str, time: i64) -> String {
+        match date(&DenyAll, Some(format.as_bytes()), Some(time)).unwrap() {
+            Date::String(s) => String::from_utf8(s).unwrap(),
+            Date::Table(_) => panic!("expected a string"),
+        }
+    }
+
+    #[test]
+    fn test_date_formats() {
+        assert_eq!("Thu Jan  1 00:00:00 1970", date_string("!%c", 0));
+        assert_eq!(
+            "2001-09-09 01:46:40 Sun Sunday Sep September",
+            date_string("%F %T %a %A %b %B", 1_000_000_000)
+        );
+        assert_eq!(
+            "09/09/01 01:46:40 AM 252 0 7 36 36 2001 01 36 20",
+            date_string("%x %r %j %w %u %U %W %G %g %V %C", 1_000_000_000)
+        );
+        assert_eq!(
+            "2004-W53 2005-01-01",
+            date_string("%G-W%V %F", 1_104_537_600)
+        );
+        assert_eq!("%\t\n+0000 GMT", date_string("!%%%t%n%z %Z", 0));
+        assert_eq!(
+            "12 PM 20",
+            date_string("%OI %p %EC", 12 * 3600 + 1_000_000_000 - 3600)
+        );
+        assert_eq!(
+            Err(OsError::BadArgument(ArgumentError::new(
+                1,
+                "date",
+                "invalid conversion specifier '%Ez'"
+            ))),
+            date(&DenyAll, Some(b"%Ez"), Some(0))
+        );
+    }
+
+    #[test]
+    fn test_date_table_uses_time_zone() {
+        assert_eq!(
+            Ok(Date::Table(DateTable {
+                year: 2001,
+                month: 9,
+                day: 9,
+                hour: 3,
+                min: 46,
+                sec: 40,
+                wday: 1,
+                yday: 252,
+                isdst: true
+            })),
+            date(&FixedClock, Some(b"*t"), None)
+        );
+        assert_eq!(
+            Ok(Date::String(b"+0200 CEST".to_vec())),
+            date(&FixedClock, Some(b"%z %Z"), None)
+        );
+    }
+
+    #[test]
+    fn test_time() {
+        assert_eq!(Ok(1_000_000_000), time(&FixedClock, None));
+        let mut fields = DateFields {
+            year: Some(2001),
+            month: Some(9),
+            day: Some(9),
+            hour: Some(3),
+            min: Some(46),
+            sec: Some(40),
+            ..DateFields::default()
+        };
+        assert_eq!(Ok(1_000_000_000), time(&FixedClock, Some(&mut fields)));
+
+        let mut fields = DateFields {
+            year: Some(2000),
+            month: Some(14),
+            day: Some(0),
+            ..DateFields::default()
+        };
+        assert_eq!(Ok(980_942_400), time(&DenyAll, Some(&mut fields)));
+        assert_eq!(Some(2001), fields.year);
+        assert_eq!(Some(1), fields.month);
+        assert_eq!(Some(31), fields.day);
+        assert_eq!(Some(12), fields.hour);
+
+        assert_eq!(
+            "field 'day' missing in date table",
+            time(&DenyAll, Some(&mut DateFields::default()))
+                .unwrap_err()
+                .to_string()
+        );
+        let mut fields = DateFields {
+            year: Some(i64::MAX),
+            month: Some(1),
+            day: Some(1),
+            ..DateFields::default()
+        };
+        assert_eq!(
+            Err(OsError::FieldOutOfBound("year")),
+            time(&DenyAll, Some(&mut fields))
+        );
+    }
+
+    #[test]
+    fn test_difftime() {
+        assert_eq!(-60.0, difftime(1_000_000_000, 1_000_000_060));
+        assert_eq!(2f64.powi(63), difftime(i64::MAX, -1));
+        assert_eq!(-(2f64.powi(64)), difftime(i64::MIN, i64::MAX));
+    }
+
+    #[test]
+    fn test_files_and_environment() {
+        let fs = MemoryFileSystem::new()
+            .with_file(b"old", b"data")
+            .with_env(b"HOME", b"/home/lua");
+        assert_eq!(Some(b"/home/lua".to_vec()), getenv(&fs, b"HOME"));
+        assert_eq!(None, getenv(&DenyAll, b"HOME"));
+        rename(&fs, b"old", b"new").unwrap();
+        remove(&fs, b"new").unwrap();
+        assert_eq!(
+            "new: No such file or directory",
+            remove(&fs, b"new").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "Permission denied",
+            rename(&DenyAll, b"a", b"b").unwrap_err().to_string()
+        );
+    }
+}
@@ -0,0 +1,403 @@
+use crate::compiler::compile;
+use crate::file::header::Header;
+use crate::file::{LuaFile, LuaFileParseError};
+use crate::stdlib::sandbox::{Capabilities, OpenMode};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+
+/// The default `package.path`.
+pub const PATH_DEFAULT: &[u8] =
+    b"/usr/local/share/lua/5.3/?.lua;/usr/local/share/lua/5.3/?/init.lua;\
+/usr/local/lib/lua/5.3/?.lua;/usr/local/lib/lua/5.3/?/init.lua;./?.lua;./?/init.lua";
+
+/// The extra value passed to loaders found in `package.preload`.
+pub const PRELOAD_ORIGIN: &[u8] = b":preload:";
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum PackageError {
+    /// No searcher found the module; `message` collects what each one
+    /// tried.
+    NotFound { name: Vec<u8>, message: Vec<u8> },
+    Load {
+        name: Vec<u8>,
+        filename: Vec<u8>,
+        message: String,
+    },
+}
+
+impl Display for PackageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PackageError::NotFound { name, message } => write!(
+                f,
+                "module '{}' not found:{}",
+                String::from_utf8_lossy(name),
+                String::from_utf8_lossy(message)
+            ),
+            PackageError::Load {
+                name,
+                filename,
+                message,
+            } => write!(
+                f,
+                "error loading module '{}' from file '{}':\n\t{}",
+                String::from_utf8_lossy(name),
+                String::from_utf8_lossy(filename),
+                message
+            ),
+        }
+    }
+}
+
+/// Where module files come from. Paths are the expanded `package.path`
+/// templates, so a resolver can serve them from disk, an embedded archive
+/// or anything else.
+pub trait ModuleResolver {
+    fn read(&self, path: &[u8]) -> Option<Vec<u8>>;
+
+    fn exists(&self, path: &[u8]) -> bool {
+        self.read(path).is_some()
+    }
+}
+
+/// Resolves modules from a map of paths to file contents.
+impl ModuleResolver for HashMap<Vec<u8>, Vec<u8>> {
+    fn read(&self, path: &[u8]) -> Option<Vec<u8>> {
+        self.get(path).cloned()
+    }
+
+    fn exists(&self, path: &[u8]) -> bool {
+        self.contains_key(path)
+    }
+}
+
+/// Resolves modules through the files the host's [`Capabilities`] allow
+/// to be opened.
+pub struct CapabilityResolver<C: Capabilities>(pub C);
+
+impl<C: Capabilities> ModuleResolver for CapabilityResolver<C> {
+    fn read(&self, path: &[u8]) -> Option<Vec<u8>> {
+        let mode = OpenMode::parse(b"r")?;
+        let mut contents = vec![];
+        self.0
+            .open(path, mode)
+            .and_then(|mut stream| stream.read_to_end(&mut contents))
+            .ok()?;
+        Some(contents)
+    }
+}
+
+pub enum ModuleChunk {
+    Compiled(LuaFile),
+}
+
+impl ModuleChunk {
+    /// Precompiled chunks are recognized by their first byte, like
+    /// `luaL_loadfile` does; anything else is compiled as source.
+    /// `chunk_name` prefixes load errors.
+    pub fn load(contents: Vec<u8>, chunk_name: &[u8]) -> Result<ModuleChunk, String> {
+        if contents.first() != Some(&0x1b) {
+            let chunk_name = format!("@{}", String::from_utf8_lossy(chunk_name));
+            return compile(&contents, &chunk_name)
+                .map(|main_chunk| {
+                    ModuleChunk::Compiled(LuaFile {
+                        header: Header::native(),
+                        main_chunk,
+                    })
+                })
+                .map_err(|e| e.to_string());
+        }
+        LuaFile::parse(&mut contents.as_slice())
+            .map(ModuleChunk::Compiled)
+            .map_err(|e: LuaFileParseError| {
+                format!("{}: {}", String::from_utf8_lossy(chunk_name), e)
+            })
+    }
+}
+
+/// A module found by a searcher, ready to be run by its loader.
+pub struct Module {
+    pub name: Vec<u8>,
+    /// The second argument to the loader: the file name, or
+    /// [`PRELOAD_ORIGIN`] for preloaded modules.
+    pub origin: Vec<u8>,
+    pub chunk: ModuleChunk,
+}
+
+/// What a searcher gets to look at.
+pub struct SearchContext<'a> {
+    pub path: &'a [u8],
+    pub preload: &'a HashMap<Vec<u8>, Vec<u8>>,
+    pub resolver: &'a dyn ModuleResolver,
+}
+
+pub enum SearchResult {
+    Found(Box<Module>),
+    /// Appended to the "module not found" message.
+    NotFound(Vec<u8>),
+}
+
+/// An entry of `package.searchers`.
+pub trait Searcher {
+    fn search(&self, name: &[u8], context: &SearchContext) -> Result<SearchResult, PackageError>;
+}
+
+/// Looks `name` up in `package.preload`. Preloaded modules are source or
+/// precompiled chunks registered by the host.
+pub struct PreloadSearcher;
+
+impl Searcher for PreloadSearcher {
+    fn search(&self, name: &[u8], context: &SearchContext) -> Result<SearchResult, PackageError> {
+        let Some(contents) = context.preload.get(name) else {
+            let mut message = b"\n\tno field package.preload['".to_vec();
+            message.extend_from_slice(name);
+            message.extend_from_slice(b"']");
+            return Ok(SearchResult::NotFound(message));
+        };
+        let chunk = ModuleChunk::load(contents.clone(), b"preload").map_err(|message| {
+            PackageError::Load {
+                name: name.to_vec(),
+                filename: PRELOAD_ORIGIN.to_vec(),
+                message,
+            }
+        })?;
+        Ok(SearchResult::Found(Box::new(Module {
+            name: name.to_vec(),
+            origin: PRELOAD_ORIGIN.to_vec(),
+            chunk,
+        })))
+    }
+}
+
+/// Looks `name` up along `package.path`.
+pub struct PathSearcher;
+
+impl Searcher for PathSearcher {
+    fn search(&self, name: &[u8], context: &SearchContext) -> Result<SearchResult, PackageError> {
+        let filename = match searchpath(context.resolver, name, context.path, None, None) {
+            Ok(filename) => filename,
+            Err(message) => return Ok(SearchResult::NotFound(message)),
+        };
+        let load_error = |message| PackageError::Load {
+            name: name.to_vec(),
+            filename: filename.clone(),
+            message,
+        };
+        let contents = context.resolver.read(&filename).ok_or_else(|| {
+            load_error(format!(
+                "cannot read {}",
+                String::from_utf8_lossy(&filename)
+            ))
+        })?;
+        let chunk = ModuleChunk::load(contents, &filename).map_err(load_error)?;
+        Ok(SearchResult::Found(Box::new(Module {
+            name: name.to_vec(),
+            origin: filename,
+            chunk,
+        })))
+    }
+}
+
+fn replace(s: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut result = vec![];
+    let mut rest = s;
+    while let Some(i) = rest.windows(from.len()).position(|w| w == from) {
+        result.extend_from_slice(&rest[..i]);
+        result.extend_from_slice(to);
+        rest = &rest[i + from.len()..];
+    }
+    result.extend_from_slice(rest);
+    result
+}
+
+/// `package.searchpath`: the first template in `path` that names an
+/// existing file once every `?` is replaced by `name`. Occurrences of
+/// `sep` (default `.`) in `name` are replaced by `rep` (default `/`)
+/// first. On failure, the error lists every file that was tried.
+pub fn searchpath(
+    resolver: &dyn ModuleResolver,
+    name: &[u8],
+    path: &[u8],
+    sep: Option<&[u8]>,
+    rep: Option<&[u8]>,
+) -> Result<Vec<u8>, Vec<u8>> {
+    let sep = sep.unwrap_or(b".");
+    let name = if sep.is_empty() {
+        name.to_vec()
+    } else {
+        replace(name, sep, rep.unwrap_or(b"/"))
+    };
+    let mut message = vec![];
+    for template in path.split(|&b| b == b';').filter(|t| !t.is_empty()) {
+        let filename = replace(template, b"?", &name);
+        if resolver.exists(&filename) {
+            return Ok(filename);
+        }
+        message.extend_from_slice(b"\n\tno file '");
+        message.extend_from_slice(&filename);
+        message.push(b'\'');
+    }
+    Err(message)
+}
+
+/// The `package` library and `require`. Running a module is left to the
+/// caller, so `V` is whatever value the host keeps for loaded modules.
+pub struct Package<V> {
+    /// `package.loaded`
+    pub loaded: HashMap<Vec<u8>, V>,
+    /// `package.preload`
+    pub preload: HashMap<Vec<u8>, Vec<u8>>,
+    /// `package.path`
+    pub path: Vec<u8>,
+    /// `package.searchers`
+    pub searchers: Vec<Box<dyn Searcher>>,
+    resolver: Box<dyn ModuleResolver>,
+}
+
+impl<V> Package<V> {
+    /// A package library with the default path and the preload and path
+    /// searchers, in that order.
+    pub fn new(resolver: Box<dyn ModuleResolver>) -> Self {
+        Package {
+            loaded: HashMap::new(),
+            preload: HashMap::new(),
+            path: PATH_DEFAULT.to_vec(),
+            searchers: vec![Box::new(PreloadSearcher), Box::new(PathSearcher)],
+            resolver,
+        }
+    }
+
+    pub fn resolver(&self) -> &dyn ModuleResolver {
+        self.resolver.as_ref()
+    }
+
+    /// Asks each searcher in turn for `name`.
+    pub fn find(&self, name: &[u8]) -> Result<Module, PackageError> {
+        let context = SearchContext {
+            path: &self.path,
+            preload: &self.preload,
+            resolver: self.resolver.as_ref(),
+        };
+        let mut message = vec![];
+        for searcher in &self.searchers {
+            match searcher.search(name, &context)? {
+                SearchResult::Found(module) => return Ok(*module),
+                SearchResult::NotFound(reason) => message.extend(reason),
+            }
+        }
+        Err(PackageError::NotFound {
+            name: name.to_vec(),
+            message,
+        })
+    }
+
+    /// `require`: the value in `package.loaded`, running the module with
+    /// `run` to produce it on first use.
+    pub fn require<E: From<PackageError>>(
+        &mut self,
+        name: &[u8],
+        run: impl FnOnce(Module) -> Result<V, E>,
+    ) -> Result<&V, E> {
+        if !self.loaded.contains_key(name) {
+            let value = run(self.find(name)?)?;
+            self.loaded.insert(name.to_vec(), value);
+        }
+        Ok(&self.loaded[name])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(files: &[(&[u8], &[u8])]) -> Box<dyn ModuleResolver> {
+        Box::new(
+            files
+                .iter()
+                .map(|(path, contents)| (path.to_vec(), contents.to_vec()))
+                .collect::<HashMap<_, _>>(),
+        )
+    }
+
+    #[test]
+    fn test_searchpath() {
+        let resolver = archive(&[(b"./a/b/init.lua", b"return 1")]);
+        let path = b"./?.lua;./?/init.lua";
+        assert_eq!(
+            Ok(b"./a/b/init.lua".to_vec()),
+            searchpath(resolver.as_ref(), b"a.b", path, None, None)
+        );
+        assert_eq!(
+            Err(b"\n\tno file './a_c.lua'\n\tno file './a_c/init.lua'".to_vec()),
+            searchpath(resolver.as_ref(), b"a.c", path, Some(b"."), Some(b"_"))
+        );
+    }
+
+    #[test]
+    fn test_require_not_found() {
+        let mut package = Package::<()>::new(archive(&[]));
+        package.path = b"./?.lua;;./?/init.lua".to_vec();
+        let error = package
+            .require(b"zz.q", |_| Ok::<_, PackageError>(()))
+            .unwrap_err();
+        assert_eq!(
+            "module 'zz.q' not found:\n\tno field package.preload['zz.q']\
+             \n\tno file './zz/q.lua'\n\tno file './zz/q/init.lua'",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_require_loads_once() {
+        let luac = include_bytes!("../../tests/resources/simple.luac");
+        let mut package = Package::new(archive(&[(b"./simple.lua", luac)]));
+        package.path = b"./?.lua".to_vec();
+        package
+            .preload
+            .insert(b"config".to_vec(), b"return {}".to_vec());
+
+        let mut runs = 0;
+        let mut run = |module: Module| {
+            runs += 1;
+            let ModuleChunk::Compiled(file) = module.chunk;
+            assert!(!file.main_chunk.code.is_empty());
+            Ok::<_, PackageError>(module.origin)
+        };
+        assert_eq!(
+            b"./simple.lua",
+            package.require(b"simple", &mut run).unwrap().as_slice()
+        );
+        assert_eq!(
+            b"./simple.lua",
+            package.require(b"simple", &mut run).unwrap().as_slice()
+        );
+        assert_eq!(
+            PRELOAD_ORIGIN,
+            package.require(b"config", &mut run).unwrap().as_slice()
+        );
+        assert_eq!(2, runs);
+    }
+
+    #[test]
+    fn test_load_error() {
+        let mut package = Package::<()>::new(archive(&[(b"./broken.lua", b"\x1bLu")]));
+        package.path = b"./?.lua".to_vec();
+        assert_eq!(
+            "error loading module 'broken' from file './broken.lua':\n\t\
+             ./broken.lua: truncated precompiled chunk",
+            package.find(b"broken").err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        let mut package = Package::<()>::new(archive(&[(b"./broken.lua", b"return +")]));
+        package.path = b"./?.lua".to_vec();
+        assert_eq!(
+            "error loading module 'broken' from file './broken.lua':\n\t\
+             ./broken.lua:1: unexpected symbol near '+'",
+            package.find(b"broken").err().unwrap().to_string()
+        );
+    }
+}
@@ -0,0 +1,426 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+const ENOENT: i32 = 2;
+const EBADF: i32 = 9;
+const EACCES: i32 = 13;
+const EINVAL: i32 = 22;
+
+/// An open file as the `io` library sees it.
+pub trait Stream: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> Stream for T {}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct OpenMode {
+    pub read: bool,
+    pub write: bool,
+    pub append: bool,
+    pub truncate: bool,
+    pub create: bool,
+}
+
+impl OpenMode {
+    /// Parses an `io.open` mode: one of `r`, `w` or `a`, an optional `+`
+    /// and any number of `b`s.
+    pub fn parse(mode: &[u8]) -> Option<OpenMode> {
+        let (&kind, rest) = mode.split_first()?;
+        let (update, rest) = match rest.split_first() {
+            Some((b'+', rest)) => (true, rest),
+            _ => (false, rest),
+        };
+        if rest.iter().any(|&b| b != b'b') {
+            return None;
+        }
+        match kind {
+            b'r' => Some(OpenMode {
+                read: true,
+                write: update,
+                ..OpenMode::default()
+            }),
+            b'w' => Some(OpenMode {
+                read: update,
+                write: true,
+                truncate: true,
+                create: true,
+                ..OpenMode::default()
+            }),
+            b'a' => Some(OpenMode {
+                read: update,
+                write: true,
+                append: true,
+                create: true,
+                ..OpenMode::default()
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_options(self) -> OpenOptions {
+        let mut options = OpenOptions::new();
+        options
+            .read(self.read)
+            .write(self.write && !self.append)
+            .append(self.append)
+            .truncate(self.truncate)
+            .create(self.create);
+        options
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TimeZone {
+    /// Seconds to add to UTC to get the local time.
+    pub offset: i64,
+    pub name: String,
+    pub is_dst: bool,
+}
+
+impl TimeZone {
+    pub fn utc() -> TimeZone {
+        TimeZone {
+            offset: 0,
+            name: "UTC".to_owned(),
+            is_dst: false,
+        }
+    }
+}
+
+fn denied() -> io::Error {
+    io::Error::from_raw_os_error(EACCES)
+}
+
+/// Everything the `io` and `os` libraries can do outside of the script
+/// goes through this trait, so the host decides what a script may reach.
+/// The default methods deny filesystem and environment access, read the
+/// system clock and treat local time as UTC.
+pub trait Capabilities {
+    fn open(&self, _path: &[u8], _mode: OpenMode) -> io::Result<Box<dyn Stream>> {
+        Err(denied())
+    }
+
+    fn remove(&self, _path: &[u8]) -> io::Result<()> {
+        Err(denied())
+    }
+
+    fn rename(&self, _from: &[u8], _to: &[u8]) -> io::Result<()> {
+        Err(denied())
+    }
+
+    fn getenv(&self, _name: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Seconds since the epoch, for `os.time()`.
+    fn time(&self) -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64)
+    }
+
+    /// Seconds for `os.clock()`. Without access to the process' CPU time
+    /// this defaults to the time since the first call.
+    fn clock(&self) -> f64 {
+        static START: OnceLock<Instant> = OnceLock::new();
+        START.get_or_init(Instant::now).elapsed().as_secs_f64()
+    }
+
+    fn time_zone(&self, _time: i64) -> TimeZone {
+        TimeZone::utc()
+    }
+}
+
+/// Denies every filesystem and environment operation.
+pub struct DenyAll;
+
+impl Capabilities for DenyAll {}
+
+/// Gives access to the files below one directory only. Absolute paths and
+/// paths containing `..` are rejected. Symbolic links are resolved before
+/// each operation and must not lead out of the directory either.
+pub struct RootDirectory {
+    root: PathBuf,
+}
+
+impl RootDirectory {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        RootDirectory { root: root.into() }
+    }
+
+    /// The entry `path` names below the root, with the links among its
+    /// directories resolved but the entry itself left as it is, which is
+    /// what removing and renaming work on.
+    fn resolve_entry(&self, path: &[u8]) -> io::Result<PathBuf> {
+        let path = std::str::from_utf8(path).map_err(|_| denied())?;
+        let path = Path::new(path);
+        if !path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(denied());
+        }
+        let root = self.root.canonicalize()?;
+        let joined = root.join(path);
+        let (Some(parent), Some(name)) = (joined.parent(), joined.file_name()) else {
+            return Ok(root);
+        };
+        let parent = parent.canonicalize()?;
+        if !parent.starts_with(&root) {
+            return Err(denied());
+        }
+        Ok(parent.join(name))
+    }
+
+    /// Like `resolve_entry`, but also follows the entry if it is a link,
+    /// since opening it does.
+    fn resolve(&self, path: &[u8]) -> io::Result<PathBuf> {
+        let entry = self.resolve_entry(path)?;
+        match entry.canonicalize() {
+            Ok(target) if target.starts_with(self.root.canonicalize()?) => Ok(target),
+            Ok(_) => Err(denied()),
+            // a link to a missing file could still create it elsewhere
+            Err(e) if e.kind() == ErrorKind::NotFound && entry.symlink_metadata().is_err() => {
+                Ok(entry)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(denied()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Capabilities for RootDirectory {
+    fn open(&self, path: &[u8], mode: OpenMode) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(mode.to_options().open(self.resolve(path)?)?))
+    }
+
+    fn remove(&self, path: &[u8]) -> io::Result<()> {
+        std::fs::remove_file(self.resolve_entry(path)?)
+    }
+
+    fn rename(&self, from: &[u8], to: &[u8]) -> io::Result<()> {
+        std::fs::rename(self.resolve_entry(from)?, self.resolve_entry(to)?)
+    }
+}
+
+type MemoryFile = Rc<RefCell<Vec<u8>>>;
+
+/// An in-memory filesystem and environment, mostly for tests.
+#[derive(Default)]
+pub struct MemoryFileSystem {
+    files: RefCell<HashMap<Vec<u8>, MemoryFile>>,
+    environment: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_file(self, path: &[u8], contents: &[u8]) -> Self {
+        self.files
+            .borrow_mut()
+            .insert(path.to_vec(), Rc::new(RefCell::new(contents.to_vec())));
+        self
+    }
+
+    pub fn with_env(mut self, name: &[u8], value: &[u8]) -> Self {
+        self.environment.insert(name.to_vec(), value.to_vec());
+        self
+    }
+
+    pub fn contents(&self, path: &[u8]) -> Option<Vec<u8>> {
+        self.files
+            .borrow()
+            .get(path)
+            .map(|file| file.borrow().clone())
+    }
+}
+
+impl Capabilities for MemoryFileSystem {
+    fn open(&self, path: &[u8], mode: OpenMode) -> io::Result<Box<dyn Stream>> {
+        let mut files = self.files.borrow_mut();
+        let data = match files.get(path) {
+            Some(data) => data.clone(),
+            None if mode.create => files.entry(path.to_vec()).or_default().clone(),
+            None => return Err(io::Error::from_raw_os_error(ENOENT)),
+        };
+        if mode.truncate {
+            data.borrow_mut().clear();
+        }
+        Ok(Box::new(MemoryStream { data, pos: 0, mode }))
+    }
+
+    fn remove(&self, path: &[u8]) -> io::Result<()> {
+        match self.files.borrow_mut().remove(path) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::from_raw_os_error(ENOENT)),
+        }
+    }
+
+    fn rename(&self, from: &[u8], to: &[u8]) -> io::Result<()> {
+        let mut files = self.files.borrow_mut();
+        let data = files
+            .remove(from)
+            .ok_or_else(|| io::Error::from_raw_os_error(ENOENT))?;
+        files.insert(to.to_vec(), data);
+        Ok(())
+    }
+
+    fn getenv(&self, name: &[u8]) -> Option<Vec<u8>> {
+        self.environment.get(name).cloned()
+    }
+}
+
+struct MemoryStream {
+    data: MemoryFile,
+    pos: usize,
+    mode: OpenMode,
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.mode.read {
+            return Err(io::Error::from_raw_os_error(EBADF));
+        }
+        let data = self.data.borrow();
+        let available = data.get(self.pos..).unwrap_or_default();
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.mode.write {
+            return Err(io::Error::from_raw_os_error(EBADF));
+        }
+        let mut data = self.data.borrow_mut();
+        if self.mode.append {
+            self.pos = data.len();
+        }
+        if data.len() < self.pos {
+            data.resize(self.pos, 0);
+        }
+        let overlap = buf.len().min(data.len() - self.pos);
+        data[self.pos..self.pos + overlap].copy_from_slice(&buf[..overlap]);
+        data.extend_from_slice(&buf[overlap..]);
+        self.pos += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => (0, offset as i64),
+            SeekFrom::Current(offset) => (self.pos as i64, offset),
+            SeekFrom::End(offset) => (self.data.borrow().len() as i64, offset),
+        };
+        match base.checked_add(offset) {
+            Some(pos) if pos >= 0 => {
+                self.pos = pos as usize;
+                Ok(self.pos as u64)
+            }
+            _ => Err(io::Error::from_raw_os_error(EINVAL)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_mode() {
+        let read_update = OpenMode::parse(b"r+b").unwrap();
+        assert!(read_update.read && read_update.write && !read_update.create);
+        assert!(OpenMode::parse(b"a").unwrap().append);
+        assert_eq!(None, OpenMode::parse(b""));
+        assert_eq!(None, OpenMode::parse(b"rw"));
+        assert_eq!(None, OpenMode::parse(b"x"));
+    }
+
+    #[test]
+    fn test_memory_file_system() {
+        let fs = MemoryFileSystem::new().with_file(b"a.txt", b"hello");
+        let mut stream = fs.open(b"a.txt", OpenMode::parse(b"a").unwrap()).unwrap();
+        stream.write_all(b" world").unwrap();
+        assert_eq!(Some(b"hello world".to_vec()), fs.contents(b"a.txt"));
+
+        assert_eq!(
+            Some(ENOENT),
+            fs.open(b"b.txt", OpenMode::parse(b"r").unwrap())
+                .err()
+                .and_then(|e| e.raw_os_error())
+        );
+        fs.rename(b"a.txt", b"b.txt").unwrap();
+        fs.remove(b"b.txt").unwrap();
+        assert!(fs.remove(b"b.txt").is_err());
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rlua-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("root/data")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_root_directory_rejects_escapes() {
+        let dir = temp_dir("escapes");
+        let root = RootDirectory::new(dir.join("root"));
+        assert!(root.resolve(b"data/file.txt").is_ok());
+        assert!(root.resolve(b"../file.txt").is_err());
+        assert!(root.resolve(b"/etc/passwd").is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_root_directory_rejects_escaping_links() {
+        use std::os::unix::fs::symlink;
+
+        let dir = temp_dir("links");
+        std::fs::write(dir.join("secret.txt"), b"secret").unwrap();
+        std::fs::write(dir.join("root/data/file.txt"), b"data").unwrap();
+        symlink(&dir, dir.join("root/outside")).unwrap();
+        symlink(dir.join("secret.txt"), dir.join("root/secret.txt")).unwrap();
+        symlink(dir.join("missing.txt"), dir.join("root/missing.txt")).unwrap();
+        symlink("data/file.txt", dir.join("root/inside.txt")).unwrap();
+        let root = RootDirectory::new(dir.join("root"));
+        let read = OpenMode::parse(b"r").unwrap();
+        let write = OpenMode::parse(b"w").unwrap();
+
+        let denied = |result: io::Result<Box<dyn Stream>>| {
+            result.err().and_then(|e| e.raw_os_error()) == Some(EACCES)
+        };
+        assert!(denied(root.open(b"outside/secret.txt", read)));
+        assert!(denied(root.open(b"secret.txt", read)));
+        assert!(denied(root.open(b"missing.txt", write)));
+        assert!(denied(root.open(b"outside/new.txt", write)));
+        assert!(!dir.join("missing.txt").exists());
+        assert!(!dir.join("new.txt").exists());
+
+        let mut contents = String::new();
+        let mut file = root.open(b"inside.txt", read).unwrap();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!("data", contents);
+        root.open(b"data/new.txt", write).unwrap();
+        assert!(root.rename(b"outside/secret.txt", b"stolen.txt").is_err());
+        root.remove(b"secret.txt").unwrap();
+        assert!(dir.join("secret.txt").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,447 @@
+use crate::number::{format_float, format_hex_float, Number};
+use crate::stdlib::string::StringError;
+
+const ESC: u8 = b'%';
+const FLAGS: &[u8] = b"-+ #0";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FormatArg<'a> {
+    Nil,
+    Boolean(bool),
+    Number(Number),
+    String(&'a [u8]),
+}
+
+impl FormatArg<'_> {
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            FormatArg::Nil => "nil",
+            FormatArg::Boolean(_) => "boolean",
+            FormatArg::Number(_) => "number",
+            FormatArg::String(_) => "string",
+        }
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        match self {
+            FormatArg::Nil => b"nil".to_vec(),
+            FormatArg::Boolean(b) => b.to_string().into_bytes(),
+            FormatArg::Number(n) => n.to_string().into_bytes(),
+            FormatArg::String(s) => s.to_vec(),
+        }
+    }
+
+    pub(crate) fn to_number(
+        self,
+        arg: usize,
+        function: &'static str,
+    ) -> Result<Number, StringError> {
+        let number = match self {
+            FormatArg::Number(n) => Some(n),
+            FormatArg::String(s) => Number::parse(s),
+            _ => None,
+        };
+        number.ok_or_else(|| {
+            let message = format!("number expected, got {}", self.type_name());
+            StringError::bad_argument(arg, function, &message)
+        })
+    }
+
+    pub(crate) fn to_integer(self, arg: usize, function: &'static str) -> Result<i64, StringError> {
+        self.to_number(arg, function)?.to_integer().ok_or_else(|| {
+            StringError::bad_argument(arg, function, "number has no integer representation")
+        })
+    }
+
+    /// Accepts strings and numbers, like `luaL_checklstring`.
+    pub(crate) fn to_lua_string(
+        self,
+        arg: usize,
+        function: &'static str,
+    ) -> Result<Vec<u8>, StringError> {
+        match self {
+            FormatArg::String(_) | FormatArg::Number(_) => Ok(self.to_bytes()),
+            _ => {
+                let message = format!("string expected, got {}", self.type_name());
+                Err(StringError::bad_argument(arg, function, &message))
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    has_modifiers: bool,
+}
+
+impl Spec {
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+
+    fn pad(&self, prefix: &str, digits: &str, zero_allowed: bool) -> Vec<u8> {
+        let len = prefix.len() + digits.len();
+        let fill = self.width.saturating_sub(len);
+        let s = if self.left {
+            format!("{}{}{}", prefix, digits, " ".repeat(fill))
+        } else if self.zero && zero_allowed {
+            format!("{}{}{}", prefix, "0".repeat(fill), digits)
+        } else {
+            format!("{}{}{}", " ".repeat(fill), prefix, digits)
+        };
+        s.into_bytes()
+    }
+}
+
+fn scan_spec(fmt: &[u8], start: usize) -> Result<(Spec, usize), StringError> {
+    let at = |i: usize| fmt.get(i).copied().unwrap_or(0);
+    let mut spec = Spec::default();
+    let mut p = start;
+    while FLAGS.contains(&at(p)) && at(p) != 0 {
+        match at(p) {
+            b'-' => spec.left = true,
+            b'+' => spec.plus = true,
+            b' ' => spec.space = true,
+            b'#' => spec.alternate = true,
+            _ => spec.zero = true,
+        }
+        p += 1;
+    }
+    if p - start > FLAGS.len() {
+        return Err(StringError::InvalidFormatRepeatedFlags);
+    }
+    let read_number = |p: &mut usize| {
+        let mut n = 0;
+        for _ in 0..2 {
+            if at(*p).is_ascii_digit() {
+                n = n * 10 + (at(*p) - b'0') as usize;
+                *p += 1;
+            }
+        }
+        n
+    };
+    spec.width = read_number(&mut p);
+    if at(p) == b'.' {
+        p += 1;
+        spec.precision = Some(read_number(&mut p));
+    }
+    if at(p).is_ascii_digit() {
+        return Err(StringError::InvalidFormatTooLong);
+    }
+    spec.has_modifiers = p != start;
+    Ok((spec, p))
+}
+
+fn format_integer(n: i64, conv: u8, spec: &Spec) -> Vec<u8> {
+    let (negative, mut digits, prefix) = match conv {
+        b'd' | b'i' => (n < 0, n.unsigned_abs().to_string(), ""),
+        b'u' => (false, (n as u64).to_string(), ""),
+        b'o' => (false, format!("{:o}", n as u64), ""),
+        b'x' => (false, format!("{:x}", n as u64), "0x"),
+        _ => (false, format!("{:X}", n as u64), "0X"),
+    };
+    if let Some(precision) = spec.precision {
+        if precision == 0 && n == 0 {
+            digits.clear();
+        }
+        if digits.len() < precision {
+            digits = format!("{}{}", "0".repeat(precision - digits.len()), digits);
+        }
+    }
+    let prefix = match conv {
+        b'o' if spec.alternate && !digits.starts_with('0') => {
+            digits.insert(0, '0');
+            ""
+        }
+        b'x' | b'X' if spec.alternate && n != 0 => prefix,
+        b'd' | b'i' => spec.sign(negative),
+        _ => "",
+    };
+    spec.pad(prefix, &digits, spec.precision.is_none())
+}
+
+fn format_number(x: f64, conv: u8, spec: &Spec) -> Vec<u8> {
+    let s = format_float(x, conv, spec.precision.unwrap_or(6), spec.alternate);
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s.as_str()),
+    };
+    spec.pad(spec.sign(negative), digits, x.is_finite())
+}
+
+fn push_padded(result: &mut Vec<u8>, s: &[u8], spec: &Spec) {
+    let fill = spec.width.saturating_sub(s.len());
+    if !spec.left {
+        result.resize(result.len() + fill, b' ');
+    }
+    result.extend_from_slice(s);
+    if spec.left {
+        result.resize(result.len() + fill, b' ');
+    }
+}
+
+fn add_quoted(result: &mut Vec<u8>, s: &[u8]) {
+    result.push(b'"');
+    for (i, &c) in s.iter().enumerate() {
+        if c == b'"' || c == b'\\' || c == b'\n' {
+            result.push(b'\\');
+            result.push(c);
+        } else if c.is_ascii_control() {
+            let next_is_digit = s.get(i + 1).is_some_and(u8::is_ascii_digit);
+            if next_is_digit {
+                result.extend_from_slice(format!("\\{:03}", c).as_bytes());
+            } else {
+                result.extend_from_slice(format!("\\{}", c).as_bytes());
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result.push(b'"');
+}
+
+fn add_literal(result: &mut Vec<u8>, value: FormatArg) {
+    match value {
+        FormatArg::String(s) => add_quoted(result, s),
+        FormatArg::Number(Number::Float(x)) => {
+            result.extend_from_slice(format_hex_float(x).as_bytes())
+        }
+        FormatArg::Number(Number::Integer(i64::MIN)) => {
+            result.extend_from_slice(format!("0x{:x}", i64::MIN).as_bytes())
+        }
+        value => result.extend_from_slice(&value.to_bytes()),
+    }
+}
+
+/// `string.format`. Arguments are numbered like Lua does, so the first
+/// value after the format string is argument #2 in error messages.
+pub fn format(fmt: &[u8], args: &[FormatArg]) -> Result<Vec<u8>, StringError> {
+    let mut result = Vec::with_capacity(fmt.len());
+    let mut args = args.iter().copied();
+    let mut arg = 1;
+    let mut i = 0;
+    while i < fmt.len() {
+        if fmt[i] != ESC {
+            result.push(fmt[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        if fmt.get(i) == Some(&ESC) {
+            result.push(ESC);
+            i += 1;
+            continue;
+        }
+
+        arg += 1;
+        let value = args
+            .next()
+            .ok_or_else(|| StringError::bad_argument(arg, "format", "no value"))?;
+        let (spec, p) = scan_spec(fmt, i)?;
+        let conv = fmt.get(p).copied().unwrap_or(0);
+        i = p + 1;
+        match conv {
+            b'c' => {
+                let c = value.to_integer(arg, "format")? as u8;
+                push_padded(&mut result, &[c], &spec);
+            }
+            b'd' | b'i' | b'o' | b'u' | b'x' | b'X' => {
+                let n = value.to_integer(arg, "format")?;
+                result.extend_from_slice(&format_integer(n, conv, &spec));
+            }
+            b'a' | b'A' => {
+                if spec.has_modifiers {
+                    return Err(StringError::HexFloatModifiersNotImplemented);
+                }
+                let s = format_hex_float(value.to_number(arg, "format")?.to_float());
+                let s = if conv == b'A' {
+                    s.to_ascii_uppercase()
+                } else {
+                    s
+                };
+                result.extend_from_slice(s.as_bytes());
+            }
+            b'e' | b'E' | b'f' | b'g' | b'G' => {
+                let x = value.to_number(arg, "format")?.to_float();
+                result.extend_from_slice(&format_number(x, conv, &spec));
+            }
+            b'q' => add_literal(&mut result, value),
+            b's' => {
+                let s = value.to_bytes();
+                if !spec.has_modifiers {
+                    result.extend_from_slice(&s);
+                } else if s.contains(&0) {
+                    return Err(StringError::bad_argument(
+                        arg,
+                        "format",
+                        "string contains zeros",
+                    ));
+                } else if spec.precision.is_none() && s.len() >= 100 {
+                    result.extend_from_slice(&s);
+                } else {
+                    let s = &s[..spec.precision.map_or(s.len(), |p| p.min(s.len()))];
+                    push_padded(&mut result, s, &spec);
+                }
+            }
+            conv => return Err(StringError::InvalidFormatOption(conv)),
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(f: &str, args: &[FormatArg]) -> String {
+        String::from_utf8(format(f.as_bytes(), args).unwrap()).unwrap()
+    }
+
+    fn int(i: i64) -> FormatArg<'static> {
+        FormatArg::Number(Number::Integer(i))
+    }
+
+    fn float(x: f64) -> FormatArg<'static> {
+        FormatArg::Number(Number::Float(x))
+    }
+
+    #[test]
+    fn test_integers() {
+        assert_eq!(
+            "42|-42|+42| 42",
+            fmt("%d|%i|%+d|% d", &[int(42), int(-42), int(42), int(42)])
+        );
+        assert_eq!(
+            "  -42|-42  |-0042",
+            fmt("%5d|%-5d|%05d", &[int(-42), int(-42), int(-42)])
+        );
+        assert_eq!("00042|   00042", fmt("%.5d|%8.5d", &[int(42), int(42)]));
+        assert_eq!(
+            "ff|FF|0xff|377|0377",
+            fmt(
+                "%x|%X|%#x|%o|%#o",
+                &[int(255), int(255), int(255), int(255), int(255)]
+            )
+        );
+        assert_eq!("ffffffffffffffff", fmt("%x", &[int(-1)]));
+        assert_eq!("3", fmt("%d", &[float(3.0)]));
+        assert_eq!("10", fmt("%d", &[FormatArg::String(b" 10 ")]));
+        assert_eq!(
+            "A|  A|A  ",
+            fmt("%c|%3c|%-3c", &[int(65), int(65), int(65)])
+        );
+    }
+
+    #[test]
+    fn test_floats() {
+        assert_eq!(
+            "2.250000|2.25|2.2|2",
+            fmt(
+                "%f|%.2f|%.1f|%.0f",
+                &[float(2.25), float(2.25), float(2.25), float(2.25)]
+            )
+        );
+        assert_eq!(
+            "1.000000e+03|1E+03",
+            fmt("%e|%.0E", &[float(1000.0), float(1000.0)])
+        );
+        assert_eq!(
+            "0.0001|1e-05|1.5",
+            fmt("%g|%g|%g", &[float(1e-4), float(1e-5), float(1.5)])
+        );
+        assert_eq!(
+            "+001.50|1.50   |   inf|-inf",
+            fmt(
+                "%+07.2f|%-7.2f|%06f|%f",
+                &[
+                    float(1.5),
+                    float(1.5),
+                    float(f64::INFINITY),
+                    float(f64::NEG_INFINITY)
+                ]
+            )
+        );
+        assert_eq!("0x1p+0|-0X1.8P+1", fmt("%a|%A", &[float(1.0), float(-3.0)]));
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!("x=hi!", fmt("x=%s!", &[FormatArg::String(b"hi")]));
+        assert_eq!(
+            "   hi|hi   |h",
+            fmt(
+                "%5s|%-5s|%.1s",
+                &[
+                    FormatArg::String(b"hi"),
+                    FormatArg::String(b"hi"),
+                    FormatArg::String(b"hi")
+                ]
+            )
+        );
+        assert_eq!(
+            "nil true 1.5 2",
+            fmt(
+                "%s %s %s %s",
+                &[FormatArg::Nil, FormatArg::Boolean(true), float(1.5), int(2)]
+            )
+        );
+        assert_eq!("100%", fmt("%d%%", &[int(100)]));
+        assert_eq!(
+            "\"a\\\\\\\"\\\n\\0001\"",
+            fmt("%q", &[FormatArg::String(b"a\\\"\n\x001")])
+        );
+        assert_eq!(
+            "0x8000000000000000|0x1p-1|true",
+            fmt(
+                "%q|%q|%q",
+                &[int(i64::MIN), float(0.5), FormatArg::Boolean(true)]
+            )
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |f: &str, args: &[FormatArg]| format(f.as_bytes(), args).unwrap_err().to_string();
+        assert_eq!("bad argument #2 to 'format' (no value)", err("%d", &[]));
+        assert_eq!(
+            "bad argument #3 to 'format' (number expected, got boolean)",
+            err("%d %d", &[int(1), FormatArg::Boolean(true)])
+        );
+        assert_eq!(
+            "bad argument #2 to 'format' (number has no integer representation)",
+            err("%d", &[float(1.5)])
+        );
+        assert_eq!(
+            "invalid format (repeated flags)",
+            err("%------d", &[int(1)])
+        );
+        assert_eq!(
+            "invalid format (width or precision too long)",
+            err("%100d", &[int(1)])
+        );
+        assert_eq!("invalid option '%y' to 'format'", err("%y", &[int(1)]));
+        assert_eq!(
+            "modifiers for format '%a'/'%A' not implemented",
+            err("%5a", &[float(1.0)])
+        );
+        assert_eq!(
+            "bad argument #2 to 'format' (string contains zeros)",
+            err("%5s", &[FormatArg::String(b"a\0")])
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use crate::stdlib::ArgumentError;
+use std::fmt::{Display, Formatter};
+
+pub mod format;
+pub mod pack;
+pub mod pattern;
+
+const MAX_SIZE: usize = i32::MAX as usize;
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum StringError {
+    ResultingStringTooLarge,
+    StringSliceTooLong,
+    BadArgument(ArgumentError),
+    InvalidCaptureIndex(i32),
+    InvalidPatternCapture,
+    MalformedPatternEndsWithEscape,
+    MalformedPatternMissingBracket,
+    MalformedPatternMissingBalanceArguments,
+    TooManyCaptures,
+    PatternTooComplex,
+    MissingBracketAfterFrontier,
+    UnfinishedCapture,
+    InvalidReplacementEscape,
+    InvalidFormatRepeatedFlags,
+    InvalidFormatTooLong,
+    InvalidFormatOption(u8),
+    HexFloatModifiersNotImplemented,
+    IntegralSizeOutOfLimits(usize),
+    MissingSizeForFormatC,
+    InvalidPackOption(u8),
+    IntegerDoesNotFit(usize),
+}
+
+impl StringError {
+    pub(crate) fn bad_argument(arg: usize, function: &'static str, message: &str) -> StringError {
+        StringError::BadArgument(ArgumentError::new(arg, function, message))
+    }
+}
+
+impl Display for StringError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StringError::ResultingStringTooLarge => write!(f, "resulting string too large"),
+            StringError::StringSliceTooLong => write!(f, "string slice too long"),
+            StringError::BadArgument(error) => write!(f, "{}", error),
+            StringError::InvalidCaptureIndex(index) => {
+                write!(f, "invalid capture index %{}", index)
+            }
+            StringError::InvalidPatternCapture => write!(f, "invalid pattern capture"),
+            StringError::MalformedPatternEndsWithEscape => {
+                write!(f, "malformed pattern (ends with '%')")
+            }
+            StringError::MalformedPatternMissingBracket => {
+                write!(f, "malformed pattern (missing ']')")
+            }
+            StringError::MalformedPatternMissingBalanceArguments => {
+                write!(f, "malformed pattern (missing arguments to '%b')")
+            }
+            StringError::TooManyCaptures => write!(f, "too many captures"),
+            StringError::PatternTooComplex => write!(f, "pattern too complex"),
+            StringError::MissingBracketAfterFrontier => {
+                write!(f, "missing '[' after '%f' in pattern")
+            }
+            StringError::UnfinishedCapture => write!(f, "unfinished capture"),
+            StringError::InvalidReplacementEscape => {
+                write!(f, "invalid use of '%' in replacement string")
+            }
+            StringError::InvalidFormatRepeatedFlags => write!(f, "invalid format (repeated flags)"),
+            StringError::InvalidFormatTooLong => {
+                write!(f, "invalid format (width or precision too long)")
+            }
+            StringError::InvalidFormatOption(option) => {
+                write!(f, "invalid option '%{}' to 'format'", *option as char)
+            }
+            StringError::HexFloatModifiersNotImplemented => {
+                write!(f, "modifiers for format '%a'/'%A' not implemented")
+            }
+            StringError::IntegralSizeOutOfLimits(size) => {
+                write!(f, "integral size ({}) out of limits [1,16]", size)
+            }
+            StringError::MissingSizeForFormatC => {
+                write!(f, "missing size for format option 'c'")
+            }
+            StringError::InvalidPackOption(option) => {
+                write!(f, "invalid format option '{}'", *option as char)
+            }
+            StringError::IntegerDoesNotFit(size) => {
+                write!(f, "{}-byte integer does not fit into Lua Integer", size)
+            }
+        }
+    }
+}
+
+impl From<ArgumentError> for StringError {
+    fn from(error: ArgumentError) -> StringError {
+        StringError::BadArgument(error)
+    }
+}
+
+/// Translates a relative string position: negative means back from the end.
+pub fn posrelat(pos: i64, len: usize) -> i64 {
+    if pos >= 0 {
+        pos
+    } else if pos.unsigned_abs() > len as u64 {
+        0
+    } else {
+        len as i64 + pos + 1
+    }
+}
+
+pub fn len(s: &[u8]) -> usize {
+    s.len()
+}
+
+pub fn sub(s: &[u8], i: i64, j: Option<i64>) -> &[u8] {
+    let start = posrelat(i, s.len()).max(1);
+    let end = posrelat(j.unwrap_or(-1), s.len()).min(s.len() as i64);
+    if start <= end {
+        &s[start as usize - 1..end as usize]
+    } else {
+        &[]
+    }
+}
+
+pub fn reverse(s: &[u8]) -> Vec<u8> {
+    s.iter().rev().copied().collect()
+}
+
+pub fn lower(s: &[u8]) -> Vec<u8> {
+    s.to_ascii_lowercase()
+}
+
+pub fn upper(s: &[u8]) -> Vec<u8> {
+    s.to_ascii_uppercase()
+}
+
+pub fn rep(s: &[u8], n: i64, sep: &[u8]) -> Result<Vec<u8>, StringError> {
+    if n <= 0 {
+        return Ok(vec![]);
+    }
+    let l = s.len();
+    let lsep = sep.len();
+    if l + lsep > MAX_SIZE / n as usize {
+        return Err(StringError::ResultingStringTooLarge);
+    }
+
+    let mut result = Vec::with_capacity(n as usize * l + (n as usize - 1) * lsep);
+    for _ in 1..n {
+        result.extend_from_slice(s);
+        result.extend_from_slice(sep);
+    }
+    result.extend_from_slice(s);
+    Ok(result)
+}
+
+pub fn byte(s: &[u8], i: Option<i64>, j: Option<i64>) -> Result<&[u8], StringError> {
+    let posi = posrelat(i.unwrap_or(1), s.len());
+    let pose = posrelat(j.unwrap_or(posi), s.len()).min(s.len() as i64);
+    let posi = posi.max(1);
+    if posi > pose {
+        return Ok(&[]);
+    }
+    if pose - posi >= i32::MAX as i64 {
+        return Err(StringError::StringSliceTooLong);
+    }
+    Ok(&s[posi as usize - 1..pose as usize])
+}
+
+pub fn char(codes: &[i64]) -> Result<Vec<u8>, StringError> {
+    codes
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| {
+            u8::try_from(c)
+                .map_err(|_| StringError::bad_argument(i + 1, "char", "value out of range"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sub() {
+        assert_eq!(b"ell", sub(b"hello", 2, Some(4)));
+        assert_eq!(b"lo", sub(b"hello", -2, None));
+        assert_eq!(b"hello", sub(b"hello", -100, Some(100)));
+        assert_eq!(b"", sub(b"hello", 4, Some(2)));
+    }
+
+    #[test]
+    fn test_rep() {
+        assert_eq!(b"ab,ab,ab".to_vec(), rep(b"ab", 3, b",").unwrap());
+        assert_eq!(b"".to_vec(), rep(b"ab", 0, b",").unwrap());
+        assert_eq!(
+            Err(StringError::ResultingStringTooLarge),
+            rep(b"ab", i64::MAX, b"")
+        );
+    }
+
+    #[test]
+    fn test_byte_and_char() {
+        assert_eq!(b"e", byte(b"hello", Some(2), None).unwrap());
+        assert_eq!(b"llo", byte(b"hello", Some(-3), Some(-1)).unwrap());
+        assert_eq!(b"", byte(b"hello", Some(10), None).unwrap());
+        assert_eq!(b"\x00\xffA".to_vec(), char(&[0, 255, 65]).unwrap());
+        assert_eq!(
+            "bad argument #2 to 'char' (value out of range)",
+            char(&[1, 256]).unwrap_err().to_string()
+        );
+    }
+}
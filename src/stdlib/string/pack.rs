@@ -0,0 +1,523 @@
+use crate::file::byte_order::ByteOrder;
+use crate::number::Number;
+use crate::stdlib::string::format::FormatArg;
+use crate::stdlib::string::{posrelat, StringError, MAX_SIZE};
+
+const PADDING_BYTE: u8 = 0x00;
+const MAX_INT_SIZE: usize = 16;
+const SIZE_INT: usize = 8;
+const MAX_ALIGN: usize = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Unpacked<'a> {
+    Number(Number),
+    String(&'a [u8]),
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum KOption {
+    Int,
+    Uint,
+    Float,
+    Char,
+    String,
+    ZeroTerminated,
+    Padding,
+    PaddingAlign,
+    Nop,
+}
+
+/// Walks a format string, keeping track of the endianness and maximum
+/// alignment set by the options read so far.
+struct Header<'f> {
+    fmt: &'f [u8],
+    pos: usize,
+    order: ByteOrder,
+    max_align: usize,
+    function: &'static str,
+}
+
+impl<'f> Header<'f> {
+    fn new(fmt: &'f [u8], function: &'static str) -> Self {
+        Header {
+            fmt,
+            pos: 0,
+            order: ByteOrder::native(),
+            max_align: 1,
+            function,
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.fmt.len()
+    }
+
+    fn get_num(&mut self) -> Option<usize> {
+        if !self.fmt.get(self.pos)?.is_ascii_digit() {
+            return None;
+        }
+        let mut a = 0;
+        while let Some(d) = self.fmt.get(self.pos).filter(|d| d.is_ascii_digit()) {
+            a = a * 10 + (d - b'0') as usize;
+            self.pos += 1;
+            if a > (MAX_SIZE - 9) / 10 {
+                break;
+            }
+        }
+        Some(a)
+    }
+
+    fn get_num_limit(&mut self, default: usize) -> Result<usize, StringError> {
+        let size = self.get_num().unwrap_or(default);
+        if size > MAX_INT_SIZE || size == 0 {
+            return Err(StringError::IntegralSizeOutOfLimits(size));
+        }
+        Ok(size)
+    }
+
+    fn get_option(&mut self) -> Result<(KOption, usize), StringError> {
+        let opt = self.fmt[self.pos];
+        self.pos += 1;
+        Ok(match opt {
+            b'b' => (KOption::Int, 1),
+            b'B' => (KOption::Uint, 1),
+            b'h' => (KOption::Int, 2),
+            b'H' => (KOption::Uint, 2),
+            b'l' | b'j' => (KOption::Int, 8),
+            b'L' | b'J' | b'T' => (KOption::Uint, 8),
+            b'f' => (KOption::Float, 4),
+            b'd' | b'n' => (KOption::Float, 8),
+            b'i' => (KOption::Int, self.get_num_limit(4)?),
+            b'I' => (KOption::Uint, self.get_num_limit(4)?),
+            b's' => (KOption::String, self.get_num_limit(8)?),
+            b'c' => (
+                KOption::Char,
+                self.get_num().ok_or(StringError::MissingSizeForFormatC)?,
+            ),
+            b'z' => (KOption::ZeroTerminated, 0),
+            b'x' => (KOption::Padding, 1),
+            b'X' => (KOption::PaddingAlign, 0),
+            b' ' => (KOption::Nop, 0),
+            b'<' => {
+                self.order = ByteOrder::LittleEndian;
+                (KOption::Nop, 0)
+            }
+            b'>' => {
+                self.order = ByteOrder::BigEndian;
+                (KOption::Nop, 0)
+            }
+            b'=' => {
+                self.order = ByteOrder::native();
+                (KOption::Nop, 0)
+            }
+            b'!' => {
+                self.max_align = self.get_num_limit(MAX_ALIGN)?;
+                (KOption::Nop, 0)
+            }
+            _ => return Err(StringError::InvalidPackOption(opt)),
+        })
+    }
+
+    /// Reads the next option and returns it together with its size and the
+    /// number of padding bytes needed to align it at `total_size`.
+    fn get_details(&mut self, total_size: usize) -> Result<(KOption, usize, usize), StringError> {
+        let (opt, size) = self.get_option()?;
+        let mut align = size;
+        if opt == KOption::PaddingAlign {
+            let invalid = self.at_end() || {
+                let (next, next_size) = self.get_option()?;
+                align = next_size;
+                next == KOption::Char || align == 0
+            };
+            if invalid {
+                return Err(self.bad_argument(1, "invalid next option for option 'X'"));
+            }
+        }
+
+        if align <= 1 || opt == KOption::Char {
+            return Ok((opt, size, 0));
+        }
+        let align = align.min(self.max_align);
+        if !align.is_power_of_two() {
+            return Err(self.bad_argument(1, "format asks for alignment not power of 2"));
+        }
+        Ok((
+            opt,
+            size,
+            (align - (total_size & (align - 1))) & (align - 1),
+        ))
+    }
+
+    fn bad_argument(&self, arg: usize, message: &str) -> StringError {
+        StringError::bad_argument(arg, self.function, message)
+    }
+}
+
+/// The value for argument `arg`. One that was not passed at all is
+/// reported as no value rather than nil, as `luaL_checkinteger` does.
+fn argument<'a>(
+    args: &[FormatArg<'a>],
+    arg: usize,
+    expected: &str,
+) -> Result<FormatArg<'a>, StringError> {
+    args.get(arg - 2).copied().ok_or_else(|| {
+        let message = format!("{} expected, got no value", expected);
+        StringError::bad_argument(arg, "pack", &message)
+    })
+}
+
+/// `string.pack`: serializes `args` according to `fmt`. Argument numbers
+/// in errors count the format string as argument 1.
+pub fn pack(fmt: &[u8], args: &[FormatArg]) -> Result<Vec<u8>, StringError> {
+    let mut h = Header::new(fmt, "pack");
+    let mut result = vec![];
+    let mut arg = 1;
+    let mut total_size = 0;
+    while !h.at_end() {
+        let (opt, size, to_align) = h.get_details(total_size)?;
+        total_size += to_align + size;
+        result.resize(result.len() + to_align, PADDING_BYTE);
+        arg += 1;
+        match opt {
+            KOption::Int => {
+                let n = argument(args, arg, "number")?.to_integer(arg, "pack")?;
+                if size < SIZE_INT {
+                    let lim = 1_i64 << (size * 8 - 1);
+                    if !(-lim <= n && n < lim) {
+                        return Err(h.bad_argument(arg, "integer overflow"));
+                    }
+                }
+                h.order.write_uint(&mut result, n as u64, size, n < 0);
+            }
+            KOption::Uint => {
+                let n = argument(args, arg, "number")?.to_integer(arg, "pack")?;
+                if size < SIZE_INT && (n as u64) >= 1 << (size * 8) {
+                    return Err(h.bad_argument(arg, "unsigned overflow"));
+                }
+                h.order.write_uint(&mut result, n as u64, size, false);
+            }
+            KOption::Float => {
+                let n = argument(args, arg, "number")?
+                    .to_number(arg, "pack")?
+                    .to_float();
+                if size == 4 {
+                    h.order.write_f32(&mut result, n as f32);
+                } else {
+                    h.order.write_f64(&mut result, n);
+                }
+            }
+            KOption::Char => {
+                let s = argument(args, arg, "string")?.to_lua_string(arg, "pack")?;
+                if s.len() > size {
+                    return Err(h.bad_argument(arg, "string longer than given size"));
+                }
+                result.extend_from_slice(&s);
+                result.resize(result.len() + size - s.len(), PADDING_BYTE);
+            }
+            KOption::String => {
+                let s = argument(args, arg, "string")?.to_lua_string(arg, "pack")?;
+                if size < SIZE_INT && s.len() as u64 >= 1 << (size * 8) {
+                    return Err(h.bad_argument(arg, "string length does not fit in given size"));
+                }
+                h.order.write_uint(&mut result, s.len() as u64, size, false);
+                result.extend_from_slice(&s);
+                total_size += s.len();
+            }
+            KOption::ZeroTerminated => {
+                let s = argument(args, arg, "string")?.to_lua_string(arg, "pack")?;
+                if s.contains(&0) {
+                    return Err(h.bad_argument(arg, "string contains zeros"));
+                }
+                result.extend_from_slice(&s);
+                result.push(0);
+                total_size += s.len() + 1;
+            }
+            KOption::Padding => {
+                result.push(PADDING_BYTE);
+                arg -= 1;
+            }
+            KOption::PaddingAlign | KOption::Nop => arg -= 1,
+        }
+    }
+    Ok(result)
+}
+
+/// `string.packsize`: the size of a string produced by `pack` with the
+/// fixed-size format `fmt`.
+pub fn packsize(fmt: &[u8]) -> Result<usize, StringError> {
+    let mut h = Header::new(fmt, "packsize");
+    let mut total_size: usize = 0;
+    while !h.at_end() {
+        let (opt, size, to_align) = h.get_details(total_size)?;
+        let size = size + to_align;
+        if size > MAX_SIZE || total_size > MAX_SIZE - size {
+            return Err(h.bad_argument(1, "format result too large"));
+        }
+        total_size += size;
+        if matches!(opt, KOption::String | KOption::ZeroTerminated) {
+            return Err(h.bad_argument(1, "variable-length format"));
+        }
+    }
+    Ok(total_size)
+}
+
+fn unpack_int(bytes: &[u8], order: ByteOrder, is_signed: bool) -> Result<i64, StringError> {
+    let size = bytes.len();
+    let mut res = order.read_uint(bytes);
+    if size < SIZE_INT {
+        if is_signed {
+            let mask = 1_u64 << (size * 8 - 1);
+            res = (res ^ mask).wrapping_sub(mask);
+        }
+    } else if size > SIZE_INT {
+        let extension = if !is_signed || (res as i64) >= 0 {
+            0
+        } else {
+            0xff
+        };
+        let unread = match order {
+            ByteOrder::LittleEndian => &bytes[SIZE_INT..],
+            ByteOrder::BigEndian => &bytes[..size - SIZE_INT],
+        };
+        if unread.iter().any(|&b| b != extension) {
+            return Err(StringError::IntegerDoesNotFit(size));
+        }
+    }
+    Ok(res as i64)
+}
+
+/// `string.unpack`: reads the values packed in `data` according to `fmt`,
+/// starting at the 1-based position `init`. Returns the values and the
+/// position of the first unread byte.
+pub fn unpack<'a>(
+    fmt: &[u8],
+    data: &'a [u8],
+    init: Option<i64>,
+) -> Result<(Vec<Unpacked<'a>>, usize), StringError> {
+    let mut h = Header::new(fmt, "unpack");
+    let ld = data.len();
+    let pos = posrelat(init.unwrap_or(1), ld) - 1;
+    if pos < 0 || pos as usize > ld {
+        return Err(h.bad_argument(3, "initial position out of string"));
+    }
+    let mut pos = pos as usize;
+    let mut values = vec![];
+    while !h.at_end() {
+        let (opt, size, to_align) = h.get_details(pos)?;
+        if pos + to_align + size > ld {
+            return Err(h.bad_argument(2, "data string too short"));
+        }
+        pos += to_align;
+        let item = &data[pos..pos + size];
+        match opt {
+            KOption::Int | KOption::Uint => {
+                let n = unpack_int(item, h.order, opt == KOption::Int)?;
+                values.push(Unpacked::Number(Number::Integer(n)));
+            }
+            KOption::Float => {
+                let n = if size == 4 {
+                    h.order.read_f32(&mut &item[..]).map(f64::from)
+                } else {
+                    h.order.read_f64(&mut &item[..])
+                };
+                let n = n.map_err(|_| h.bad_argument(2, "data string too short"))?;
+                values.push(Unpacked::Number(Number::Float(n)));
+            }
+            KOption::Char => values.push(Unpacked::String(item)),
+            KOption::String => {
+                let len = unpack_int(item, h.order, false)? as u64;
+                if len > (ld - pos - size) as u64 {
+                    return Err(h.bad_argument(2, "data string too short"));
+                }
+                let len = len as usize;
+                values.push(Unpacked::String(&data[pos + size..pos + size + len]));
+                pos += len;
+            }
+            KOption::ZeroTerminated => {
+                let len = data[pos..].iter().position(|&b| b == 0).unwrap_or(ld - pos);
+                values.push(Unpacked::String(&data[pos..pos + len]));
+                pos += len + 1;
+            }
+            KOption::Padding | KOption::PaddingAlign | KOption::Nop => {}
+        }
+        pos += size;
+    }
+    Ok((values, pos + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(i: i64) -> FormatArg<'static> {
+        FormatArg::Number(Number::Integer(i))
+    }
+
+    fn err(result: Result<impl std::fmt::Debug, StringError>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_pack_integers() {
+        assert_eq!(
+            b"\x01\x00\x00\x00\x00\x02".to_vec(),
+            pack(b"<i4>I2", &[int(1), int(2)]).unwrap()
+        );
+        assert_eq!(b"\xff\xfe".to_vec(), pack(b">h", &[int(-2)]).unwrap());
+        assert_eq!(vec![0xff; 12], pack(b"i12", &[int(-1)]).unwrap());
+        assert_eq!(
+            b"\x05\x00\x00\x00\x00\x00\x00\x00\x00".to_vec(),
+            pack(b"<I9", &[int(5)]).unwrap()
+        );
+        assert_eq!(
+            b"\x07".to_vec(),
+            pack(b"B", &[FormatArg::String(b"7")]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_pack_strings_and_floats() {
+        assert_eq!(
+            b"\x00\x03abchi\x00x\x00\x00".to_vec(),
+            pack(
+                b">s2zc3",
+                &[
+                    FormatArg::String(b"abc"),
+                    FormatArg::String(b"hi"),
+                    FormatArg::String(b"x")
+                ]
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            b"\x3f\xc0\x00\x00".to_vec(),
+            pack(b">f", &[FormatArg::Number(Number::Float(1.5))]).unwrap()
+        );
+        assert_eq!(
+            b"\x00\x00\x00\x00\x00\x00\xf0\x3f".to_vec(),
+            pack(b"<n", &[int(1)]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_alignment() {
+        assert_eq!(
+            b"\x01\x00\x00\x00\x02\x00\x00\x00".to_vec(),
+            pack(b"<!4 b i4", &[int(1), int(2)]).unwrap()
+        );
+        assert_eq!(
+            b"\x01\x00\x00\x00\x02".to_vec(),
+            pack(b"<!bXi4b", &[int(1), int(2)]).unwrap()
+        );
+        assert_eq!(8, packsize(b"!8bXd").unwrap());
+        assert_eq!(20, packsize(b"!bdi4").unwrap());
+        assert_eq!(13, packsize(b"bdi4").unwrap());
+    }
+
+    #[test]
+    fn test_unpack() {
+        let (values, next) = unpack(b"<i4>I2", b"\xff\xff\xff\xff\x01\x02", None).unwrap();
+        assert_eq!(
+            vec![
+                Unpacked::Number(Number::Integer(-1)),
+                Unpacked::Number(Number::Integer(258))
+            ],
+            values
+        );
+        assert_eq!(7, next);
+
+        let data = pack(
+            b"s1zd",
+            &[
+                FormatArg::String(b"abc"),
+                FormatArg::String(b"de"),
+                FormatArg::Number(Number::Float(0.25)),
+            ],
+        )
+        .unwrap();
+        let (values, next) = unpack(b"s1zd", &data, None).unwrap();
+        assert_eq!(
+            vec![
+                Unpacked::String(b"abc"),
+                Unpacked::String(b"de"),
+                Unpacked::Number(Number::Float(0.25))
+            ],
+            values
+        );
+        assert_eq!(data.len() + 1, next);
+
+        let (values, next) = unpack(b"B", b"abc", Some(-1)).unwrap();
+        assert_eq!(vec![Unpacked::Number(Number::Integer(99))], values);
+        assert_eq!(4, next);
+        assert_eq!(
+            vec![Unpacked::Number(Number::Integer(-1))],
+            unpack(b"i16", &[0xff; 16], None).unwrap().0
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            "integral size (17) out of limits [1,16]",
+            err(pack(b"i17", &[int(1)]))
+        );
+        assert_eq!("missing size for format option 'c'", err(packsize(b"c")));
+        assert_eq!("invalid format option 'y'", err(packsize(b"y")));
+        assert_eq!(
+            "bad argument #1 to 'packsize' (invalid next option for option 'X')",
+            err(packsize(b"X"))
+        );
+        assert_eq!(
+            "bad argument #1 to 'pack' (format asks for alignment not power of 2)",
+            err(pack(b"!4i3", &[int(1)]))
+        );
+        assert_eq!(
+            "bad argument #2 to 'pack' (integer overflow)",
+            err(pack(b"b", &[int(128)]))
+        );
+        assert_eq!(
+            "bad argument #3 to 'pack' (unsigned overflow)",
+            err(pack(b"BB", &[int(1), int(-1)]))
+        );
+        assert_eq!(
+            "bad argument #2 to 'pack' (string length does not fit in given size)",
+            err(pack(b"s1", &[FormatArg::String(&[b'a'; 256])]))
+        );
+        assert_eq!(
+            "bad argument #2 to 'pack' (string contains zeros)",
+            err(pack(b"z", &[FormatArg::String(b"a\0b")]))
+        );
+        assert_eq!(
+            "bad argument #2 to 'pack' (number expected, got no value)",
+            err(pack(b"i", &[]))
+        );
+        assert_eq!(
+            "bad argument #3 to 'pack' (string expected, got no value)",
+            err(pack(b"bz", &[int(1)]))
+        );
+        assert_eq!(
+            "bad argument #2 to 'pack' (number expected, got nil)",
+            err(pack(b"d", &[FormatArg::Nil]))
+        );
+        assert_eq!(
+            "bad argument #1 to 'packsize' (variable-length format)",
+            err(packsize(b"s"))
+        );
+        assert_eq!(
+            "bad argument #1 to 'packsize' (format result too large)",
+            err(packsize(b"c2000000000c2000000000"))
+        );
+        assert_eq!(
+            "bad argument #2 to 'unpack' (data string too short)",
+            err(unpack(b"i4", b"abc", None))
+        );
+        assert_eq!(
+            "bad argument #3 to 'unpack' (initial position out of string)",
+            err(unpack(b"b", b"abc", Some(5)))
+        );
+        assert_eq!(
+            "9-byte integer does not fit into Lua Integer",
+            err(unpack(b"<i9", b"\0\0\0\0\0\0\0\0\x01", None))
+        );
+    }
+}
@@ -0,0 +1,768 @@
+use crate::stdlib::string::{posrelat, StringError};
+
+pub const MAX_CAPTURES: usize = 32;
+const MAX_CALLS: usize = 200;
+
+const ESC: u8 = b'%';
+const SPECIALS: &[u8] = b"^$*+?.([%-";
+
+const CAP_UNFINISHED: isize = -1;
+const CAP_POSITION: isize = -2;
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Capture<'a> {
+    Bytes(&'a [u8]),
+    Position(usize),
+}
+
+impl Capture<'_> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Capture::Bytes(bytes) => bytes.to_vec(),
+            Capture::Position(position) => position.to_string().into_bytes(),
+        }
+    }
+}
+
+/// Result of `find`, with 1-based inclusive positions like `string.find`.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Match<'a> {
+    pub start: usize,
+    pub end: usize,
+    pub captures: Vec<Capture<'a>>,
+}
+
+pub enum Replacement<'r> {
+    String(&'r [u8]),
+    /// Called with the captures of every match (the whole match if the
+    /// pattern has none). Returning `None` keeps the original text, which
+    /// is what `false`/`nil` from a Lua function or table lookup does.
+    Function(&'r mut dyn FnMut(&[Capture]) -> Option<Vec<u8>>),
+}
+
+struct MatchState<'a, 'p> {
+    src: &'a [u8],
+    pat: &'p [u8],
+    depth: usize,
+    level: usize,
+    capture: [(usize, isize); MAX_CAPTURES],
+}
+
+impl<'a, 'p> MatchState<'a, 'p> {
+    fn new(src: &'a [u8], pat: &'p [u8]) -> MatchState<'a, 'p> {
+        MatchState {
+            src,
+            pat,
+            depth: MAX_CALLS,
+            level: 0,
+            capture: [(0, 0); MAX_CAPTURES],
+        }
+    }
+
+    fn reset(&mut self) {
+        self.level = 0;
+        self.depth = MAX_CALLS;
+    }
+
+    // Reading past the end yields 0, like the terminating '\0' the reference
+    // implementation relies on.
+    fn p(&self, i: usize) -> u8 {
+        self.pat.get(i).copied().unwrap_or(0)
+    }
+
+    fn s(&self, i: usize) -> u8 {
+        self.src.get(i).copied().unwrap_or(0)
+    }
+
+    fn check_capture(&self, l: u8) -> Result<usize, StringError> {
+        let l = l as i32 - b'1' as i32;
+        if l < 0 || l as usize >= self.level || self.capture[l as usize].1 == CAP_UNFINISHED {
+            return Err(StringError::InvalidCaptureIndex(l + 1));
+        }
+        Ok(l as usize)
+    }
+
+    fn capture_to_close(&self) -> Result<usize, StringError> {
+        (0..self.level)
+            .rev()
+            .find(|&level| self.capture[level].1 == CAP_UNFINISHED)
+            .ok_or(StringError::InvalidPatternCapture)
+    }
+
+    fn class_end(&self, p: usize) -> Result<usize, StringError> {
+        let c = self.p(p);
+        let mut p = p + 1;
+        match c {
+            ESC => {
+                if p >= self.pat.len() {
+                    return Err(StringError::MalformedPatternEndsWithEscape);
+                }
+                Ok(p + 1)
+            }
+            b'[' => {
+                if self.p(p) == b'^' {
+                    p += 1;
+                }
+                loop {
+                    if p >= self.pat.len() {
+                        return Err(StringError::MalformedPatternMissingBracket);
+                    }
+                    let c = self.p(p);
+                    p += 1;
+                    if c == ESC && p < self.pat.len() {
+                        p += 1;
+                    }
+                    if self.p(p) == b']' {
+                        break;
+                    }
+                }
+                Ok(p + 1)
+            }
+            _ => Ok(p),
+        }
+    }
+
+    /// `p` points at the opening `[`, `ec` at the closing `]`.
+    fn match_bracket_class(&self, c: u8, p: usize, ec: usize) -> bool {
+        let mut p = p;
+        let mut sig = true;
+        if self.p(p + 1) == b'^' {
+            sig = false;
+            p += 1;
+        }
+        loop {
+            p += 1;
+            if p >= ec {
+                break;
+            }
+            if self.p(p) == ESC {
+                p += 1;
+                if match_class(c, self.p(p)) {
+                    return sig;
+                }
+            } else if self.p(p + 1) == b'-' && p + 2 < ec {
+                p += 2;
+                if self.p(p - 2) <= c && c <= self.p(p) {
+                    return sig;
+                }
+            } else if self.p(p) == c {
+                return sig;
+            }
+        }
+        !sig
+    }
+
+    fn single_match(&self, s: usize, p: usize, ep: usize) -> bool {
+        if s >= self.src.len() {
+            return false;
+        }
+        let c = self.src[s];
+        match self.p(p) {
+            b'.' => true,
+            ESC => match_class(c, self.p(p + 1)),
+            b'[' => self.match_bracket_class(c, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, StringError> {
+        if p + 1 >= self.pat.len() {
+            return Err(StringError::MalformedPatternMissingBalanceArguments);
+        }
+        if self.s(s) != self.p(p) {
+            return Ok(None);
+        }
+        let b = self.p(p);
+        let e = self.p(p + 1);
+        let mut cont = 1;
+        let mut s = s;
+        loop {
+            s += 1;
+            if s >= self.src.len() {
+                return Ok(None);
+            }
+            if self.src[s] == e {
+                cont -= 1;
+                if cont == 0 {
+                    return Ok(Some(s + 1));
+                }
+            } else if self.src[s] == b {
+                cont += 1;
+            }
+        }
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>, StringError> {
+        let mut i = 0;
+        while self.single_match(s + i, p, ep) {
+            i += 1;
+        }
+        loop {
+            if let Some(res) = self.do_match(s + i, ep + 1)? {
+                return Ok(Some(res));
+            }
+            if i == 0 {
+                return Ok(None);
+            }
+            i -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>, StringError> {
+        let mut s = s;
+        loop {
+            if let Some(res) = self.do_match(s, ep + 1)? {
+                return Ok(Some(res));
+            } else if self.single_match(s, p, ep) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(
+        &mut self,
+        s: usize,
+        p: usize,
+        what: isize,
+    ) -> Result<Option<usize>, StringError> {
+        let level = self.level;
+        if level >= MAX_CAPTURES {
+            return Err(StringError::TooManyCaptures);
+        }
+        self.capture[level] = (s, what);
+        self.level = level + 1;
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.level -= 1;
+        }
+        Ok(res)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, StringError> {
+        let l = self.capture_to_close()?;
+        self.capture[l].1 = (s - self.capture[l].0) as isize;
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.capture[l].1 = CAP_UNFINISHED;
+        }
+        Ok(res)
+    }
+
+    fn match_capture(&self, s: usize, l: u8) -> Result<Option<usize>, StringError> {
+        let l = self.check_capture(l)?;
+        let (init, len) = self.capture[l];
+        let len = len as usize;
+        if self.src.len() - s >= len && self.src[init..init + len] == self.src[s..s + len] {
+            Ok(Some(s + len))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn do_match(&mut self, s: usize, p: usize) -> Result<Option<usize>, StringError> {
+        if self.depth == 0 {
+            return Err(StringError::PatternTooComplex);
+        }
+        self.depth -= 1;
+        let res = self.match_here(s, p);
+        self.depth += 1;
+        res
+    }
+
+    fn match_here(&mut self, s: usize, p: usize) -> Result<Option<usize>, StringError> {
+        let mut s = s;
+        let mut p = p;
+        loop {
+            if p == self.pat.len() {
+                return Ok(Some(s));
+            }
+            match (self.p(p), self.p(p + 1)) {
+                (b'(', b')') => return self.start_capture(s, p + 2, CAP_POSITION),
+                (b'(', _) => return self.start_capture(s, p + 1, CAP_UNFINISHED),
+                (b')', _) => return self.end_capture(s, p + 1),
+                (b'$', _) if p + 1 == self.pat.len() => {
+                    return Ok((s == self.src.len()).then_some(s));
+                }
+                (ESC, b'b') => match self.match_balance(s, p + 2)? {
+                    Some(end) => {
+                        s = end;
+                        p += 4;
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+                (ESC, b'f') => {
+                    p += 2;
+                    if self.p(p) != b'[' {
+                        return Err(StringError::MissingBracketAfterFrontier);
+                    }
+                    let ep = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { self.src[s - 1] };
+                    if !self.match_bracket_class(previous, p, ep - 1)
+                        && self.match_bracket_class(self.s(s), p, ep - 1)
+                    {
+                        p = ep;
+                        continue;
+                    }
+                    return Ok(None);
+                }
+                (ESC, l) if l.is_ascii_digit() => match self.match_capture(s, l)? {
+                    Some(end) => {
+                        s = end;
+                        p += 2;
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+                _ => {}
+            }
+
+            let ep = self.class_end(p)?;
+            let suffix = self.p(ep);
+            if !self.single_match(s, p, ep) {
+                if suffix == b'*' || suffix == b'?' || suffix == b'-' {
+                    p = ep + 1;
+                    continue;
+                }
+                return Ok(None);
+            }
+            match suffix {
+                b'?' => {
+                    if let Some(res) = self.do_match(s + 1, ep + 1)? {
+                        return Ok(Some(res));
+                    }
+                    p = ep + 1;
+                }
+                b'+' => return self.max_expand(s + 1, p, ep),
+                b'*' => return self.max_expand(s, p, ep),
+                b'-' => return self.min_expand(s, p, ep),
+                _ => {
+                    s += 1;
+                    p = ep;
+                }
+            }
+        }
+    }
+
+    fn get_capture(&self, i: usize, s: usize, e: usize) -> Result<Capture<'a>, StringError> {
+        if i >= self.level {
+            if i == 0 {
+                return Ok(Capture::Bytes(&self.src[s..e]));
+            }
+            return Err(StringError::InvalidCaptureIndex(i as i32 + 1));
+        }
+        let (init, len) = self.capture[i];
+        match len {
+            CAP_UNFINISHED => Err(StringError::UnfinishedCapture),
+            CAP_POSITION => Ok(Capture::Position(init + 1)),
+            len => Ok(Capture::Bytes(&self.src[init..init + len as usize])),
+        }
+    }
+
+    /// All captures, or the whole match `s..e` if the pattern has none.
+    fn captures(&self, whole: Option<(usize, usize)>) -> Result<Vec<Capture<'a>>, StringError> {
+        let (s, e) = whole.unwrap_or((0, 0));
+        let levels = if self.level == 0 && whole.is_some() {
+            1
+        } else {
+            self.level
+        };
+        (0..levels).map(|i| self.get_capture(i, s, e)).collect()
+    }
+}
+
+fn match_class(c: u8, cl: u8) -> bool {
+    let res = match cl.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => crate::number::is_space(c),
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        b'z' => c == 0,
+        _ => return cl == c,
+    };
+    if cl.is_ascii_lowercase() {
+        res
+    } else {
+        !res
+    }
+}
+
+fn find_plain(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn has_specials(pattern: &[u8]) -> bool {
+    pattern.iter().any(|b| SPECIALS.contains(b))
+}
+
+fn start_position(init: i64, len: usize) -> Option<usize> {
+    let init = posrelat(init, len);
+    if init < 1 {
+        Some(0)
+    } else if init > len as i64 + 1 {
+        None
+    } else {
+        Some(init as usize - 1)
+    }
+}
+
+fn find_aux<'a>(
+    s: &'a [u8],
+    pattern: &[u8],
+    init: i64,
+    plain: bool,
+    find: bool,
+) -> Result<Option<Match<'a>>, StringError> {
+    let init = match start_position(init, s.len()) {
+        Some(init) => init,
+        None => return Ok(None),
+    };
+
+    if find && (plain || !has_specials(pattern)) {
+        return Ok(find_plain(&s[init..], pattern).map(|pos| Match {
+            start: init + pos + 1,
+            end: init + pos + pattern.len(),
+            captures: vec![],
+        }));
+    }
+
+    let anchor = pattern.first() == Some(&b'^');
+    let pattern = if anchor { &pattern[1..] } else { pattern };
+    let mut ms = MatchState::new(s, pattern);
+    let mut s1 = init;
+    loop {
+        ms.reset();
+        if let Some(res) = ms.do_match(s1, 0)? {
+            let captures = if find {
+                ms.captures(None)?
+            } else {
+                ms.captures(Some((s1, res)))?
+            };
+            return Ok(Some(Match {
+                start: s1 + 1,
+                end: res,
+                captures,
+            }));
+        }
+        s1 += 1;
+        if s1 > s.len() || anchor {
+            return Ok(None);
+        }
+    }
+}
+
+/// `string.find`: positions of the first match plus any captures.
+pub fn find<'a>(
+    s: &'a [u8],
+    pattern: &[u8],
+    init: i64,
+    plain: bool,
+) -> Result<Option<Match<'a>>, StringError> {
+    find_aux(s, pattern, init, plain, true)
+}
+
+/// `string.match`: the captures of the first match, or the whole match.
+pub fn match_pattern<'a>(
+    s: &'a [u8],
+    pattern: &[u8],
+    init: i64,
+) -> Result<Option<Vec<Capture<'a>>>, StringError> {
+    Ok(find_aux(s, pattern, init, false, false)?.map(|m| m.captures))
+}
+
+/// `string.gmatch`: iterates over the captures of successive matches.
+pub struct GMatch<'a, 'p> {
+    state: MatchState<'a, 'p>,
+    src: usize,
+    last_match: Option<usize>,
+}
+
+pub fn gmatch<'a, 'p>(s: &'a [u8], pattern: &'p [u8]) -> GMatch<'a, 'p> {
+    GMatch {
+        state: MatchState::new(s, pattern),
+        src: 0,
+        last_match: None,
+    }
+}
+
+impl<'a> Iterator for GMatch<'a, '_> {
+    type Item = Result<Vec<Capture<'a>>, StringError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.src <= self.state.src.len() {
+            self.state.reset();
+            let src = self.src;
+            match self.state.do_match(src, 0) {
+                Ok(Some(e)) if Some(e) != self.last_match => {
+                    self.src = e;
+                    self.last_match = Some(e);
+                    return Some(self.state.captures(Some((src, e))));
+                }
+                Ok(_) => self.src += 1,
+                Err(e) => {
+                    self.src = self.state.src.len() + 1;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// `string.gsub`: returns the substituted string and the number of matches.
+pub fn gsub(
+    src: &[u8],
+    pattern: &[u8],
+    replacement: Replacement,
+    max_n: Option<i64>,
+) -> Result<(Vec<u8>, usize), StringError> {
+    let mut replacement = replacement;
+    let anchor = pattern.first() == Some(&b'^');
+    let pattern = if anchor { &pattern[1..] } else { pattern };
+    let max_n = max_n.unwrap_or(src.len() as i64 + 1);
+    let mut ms = MatchState::new(src, pattern);
+    let mut result = Vec::with_capacity(src.len());
+    let mut s = 0;
+    let mut last_match = None;
+    let mut n = 0;
+    while (n as i64) < max_n {
+        ms.reset();
+        match ms.do_match(s, 0)? {
+            Some(e) if Some(e) != last_match => {
+                n += 1;
+                add_value(&ms, &mut result, s, e, &mut replacement)?;
+                s = e;
+                last_match = Some(e);
+            }
+            _ if s < src.len() => {
+                result.push(src[s]);
+                s += 1;
+            }
+            _ => break,
+        }
+        if anchor {
+            break;
+        }
+    }
+    result.extend_from_slice(&src[s..]);
+    Ok((result, n))
+}
+
+fn add_value(
+    ms: &MatchState,
+    result: &mut Vec<u8>,
+    s: usize,
+    e: usize,
+    replacement: &mut Replacement,
+) -> Result<(), StringError> {
+    match replacement {
+        Replacement::String(news) => {
+            let mut i = 0;
+            while i < news.len() {
+                if news[i] != ESC {
+                    result.push(news[i]);
+                } else {
+                    i += 1;
+                    let c = news.get(i).copied().unwrap_or(0);
+                    if c == b'0' {
+                        result.extend_from_slice(&ms.src[s..e]);
+                    } else if c.is_ascii_digit() {
+                        let capture = ms.get_capture((c - b'1') as usize, s, e)?;
+                        result.extend_from_slice(&capture.to_bytes());
+                    } else if c == ESC {
+                        result.push(c);
+                    } else {
+                        return Err(StringError::InvalidReplacementEscape);
+                    }
+                }
+                i += 1;
+            }
+        }
+        Replacement::Function(f) => {
+            let captures = ms.captures(Some((s, e)))?;
+            match f(&captures) {
+                Some(value) => result.extend_from_slice(&value),
+                None => result.extend_from_slice(&ms.src[s..e]),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(captures: Vec<Capture>) -> Vec<Vec<u8>> {
+        captures.iter().map(Capture::to_bytes).collect()
+    }
+
+    fn matched(s: &str, pattern: &str) -> Option<Vec<Vec<u8>>> {
+        match_pattern(s.as_bytes(), pattern.as_bytes(), 1)
+            .unwrap()
+            .map(bytes)
+    }
+
+    fn error(s: &str, pattern: &str) -> String {
+        match_pattern(s.as_bytes(), pattern.as_bytes(), 1)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn test_find() {
+        let m = find(b"hello world", b"o w", 1, false).unwrap().unwrap();
+        assert_eq!((5, 7), (m.start, m.end));
+
+        let m = find(b"hello world", b"l+", 1, false).unwrap().unwrap();
+        assert_eq!((3, 4), (m.start, m.end));
+
+        let m = find(b"a.b", b".", 1, true).unwrap().unwrap();
+        assert_eq!((2, 2), (m.start, m.end));
+
+        let m = find(b"key = value", b"(%w+) = (%w+)", 1, false)
+            .unwrap()
+            .unwrap();
+        assert_eq!((1, 11), (m.start, m.end));
+        assert_eq!(vec![b"key".to_vec(), b"value".to_vec()], bytes(m.captures));
+
+        let m = find(b"abc", b"", 10, false).unwrap();
+        assert_eq!(None, m);
+        let m = find(b"abc", b"", 4, false).unwrap().unwrap();
+        assert_eq!((4, 3), (m.start, m.end));
+        assert_eq!(None, find(b"abc", b"^b", 1, false).unwrap());
+        assert!(find(b"abc", b"b", -2, false).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_match() {
+        assert_eq!(Some(vec![b"2024".to_vec()]), matched("in 2024!", "%d+"));
+        assert_eq!(Some(vec![b"".to_vec()]), matched("abc", "x*"));
+        assert_eq!(
+            Some(vec![b"(a(b)c)".to_vec()]),
+            matched("x(a(b)c)y", "%b()")
+        );
+        assert_eq!(
+            Some(vec![b"THE".to_vec()]),
+            matched("THE (quick) fox", "%f[%a]%u+%f[%A]")
+        );
+        assert_eq!(
+            Some(vec![b"2".to_vec(), b"3".to_vec()]),
+            match_pattern(b"abc", b"()b()", 1).unwrap().map(bytes)
+        );
+        assert_eq!(
+            Some(vec![b"\"".to_vec(), b"x".to_vec()]),
+            matched("say \"x\"", "([\"'])(.-)%1")
+        );
+        assert_eq!(Some(vec![b"a-b".to_vec()]), matched("a-b", "[%a%-]+"));
+        assert_eq!(Some(vec![b"]".to_vec()]), matched("x]", "[]]"));
+        assert_eq!(Some(vec![b"end".to_vec()]), matched("the end", "%a+$"));
+        assert_eq!(None, matched("the end.", "%a+$"));
+        assert_eq!(
+            Some(vec![b"a\0\xff".to_vec()]),
+            match_pattern(b"a\0\xff", b"[\0-\xff]+$", 1)
+                .unwrap()
+                .map(bytes)
+        );
+        assert_eq!(Some(vec![b"ab".to_vec()]), matched("ab", "a?b"));
+        assert_eq!(Some(vec![b"b".to_vec()]), matched("b", "a?b"));
+        assert_eq!(Some(vec![b"<a><b>".to_vec()]), matched("<a><b>", "<.*>"));
+        assert_eq!(Some(vec![b"<a>".to_vec()]), matched("<a><b>", "<.->"));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!("malformed pattern (ends with '%')", error("a", "a%"));
+        assert_eq!("malformed pattern (missing ']')", error("a", "[a"));
+        assert_eq!(
+            "malformed pattern (missing arguments to '%b')",
+            error("a", "%b(")
+        );
+        assert_eq!("missing '[' after '%f' in pattern", error("a", "%fa"));
+        assert_eq!("invalid capture index %1", error("a", "%1"));
+        assert_eq!("invalid pattern capture", error("a", "a)"));
+        assert_eq!("unfinished capture", error("a", "(a"));
+        assert_eq!("too many captures", error("a", &"()".repeat(33)));
+        assert_eq!(
+            "pattern too complex",
+            error(&"a".repeat(300), &"a?".repeat(300))
+        );
+    }
+
+    #[test]
+    fn test_gmatch() {
+        let words: Vec<Vec<u8>> = gmatch(b"one two  three", b"%a+")
+            .map(|c| c.unwrap().remove(0).to_bytes())
+            .collect();
+        assert_eq!(
+            vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()],
+            words
+        );
+
+        let pairs: Vec<Vec<Vec<u8>>> = gmatch(b"a=1, b=2", b"(%w+)=(%w+)")
+            .map(|c| bytes(c.unwrap()))
+            .collect();
+        assert_eq!(
+            vec![
+                vec![b"a".to_vec(), b"1".to_vec()],
+                vec![b"b".to_vec(), b"2".to_vec()]
+            ],
+            pairs
+        );
+
+        assert_eq!(4, gmatch(b"abc", b"x*").count());
+    }
+
+    #[test]
+    fn test_gsub() {
+        let (s, n) = gsub(b"hello world", b"o", Replacement::String(b"0"), None).unwrap();
+        assert_eq!((b"hell0 w0rld".to_vec(), 2), (s, n));
+
+        let (s, n) = gsub(
+            b"hello world",
+            b"(%w+)",
+            Replacement::String(b"<%1>"),
+            Some(1),
+        )
+        .unwrap();
+        assert_eq!((b"<hello> world".to_vec(), 1), (s, n));
+
+        let (s, _) = gsub(b"abc", b"", Replacement::String(b"-"), None).unwrap();
+        assert_eq!(b"-a-b-c-".to_vec(), s);
+
+        let (s, _) = gsub(b"abc", b"%w", Replacement::String(b"%0%0%%"), None).unwrap();
+        assert_eq!(b"aa%bb%cc%".to_vec(), s);
+
+        let (s, n) = gsub(b"hello", b"^h", Replacement::String(b"H"), None).unwrap();
+        assert_eq!((b"Hello".to_vec(), 1), (s, n));
+
+        let mut upper = |captures: &[Capture]| match &captures[0] {
+            Capture::Bytes(b"keep") => None,
+            capture => Some(capture.to_bytes().to_ascii_uppercase()),
+        };
+        let (s, n) = gsub(
+            b"keep this",
+            b"%a+",
+            Replacement::Function(&mut upper),
+            None,
+        )
+        .unwrap();
+        assert_eq!((b"keep THIS".to_vec(), 2), (s, n));
+
+        let err = gsub(b"abc", b"b", Replacement::String(b"%x"), None).unwrap_err();
+        assert_eq!("invalid use of '%' in replacement string", err.to_string());
+    }
+}
@@ -0,0 +1,614 @@
+use crate::stdlib::string::format::FormatArg;
+use crate::stdlib::ArgumentError;
+use std::fmt::{Display, Formatter};
+
+/// Intervals at least this long get a randomized pivot in `sort` once
+/// partitions turn out unbalanced.
+const RANLIMIT: u64 = 100;
+/// `LUAI_MAXSTACK`, the most values `unpack` can return.
+const MAX_RESULTS: u64 = 1_000_000;
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum TableError<E> {
+    BadArgument(ArgumentError),
+    InvalidOrderFunction,
+    /// A value `concat` cannot turn into a string, at the given index.
+    InvalidValue(i64),
+    TooManyResults,
+    /// An error raised while reading or writing the table or comparing
+    /// its values, like one from a metamethod.
+    Host(E),
+}
+
+impl<E: Display> Display for TableError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TableError::BadArgument(error) => write!(f, "{}", error),
+            TableError::InvalidOrderFunction => write!(f, "invalid order function for sorting"),
+            TableError::InvalidValue(i) => {
+                write!(f, "invalid value (at index {}) in table for 'concat'", i)
+            }
+            TableError::TooManyResults => write!(f, "too many results to unpack"),
+            TableError::Host(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl<E> From<ArgumentError> for TableError<E> {
+    fn from(error: ArgumentError) -> TableError<E> {
+        TableError::BadArgument(error)
+    }
+}
+
+/// A table as the `table` library sees it. Like `lua_geti`, `lua_seti`
+/// and `luaL_len`, the methods go through the `__index`, `__newindex` and
+/// `__len` metamethods when the table has them, so each may run Lua code
+/// and fail.
+pub trait Table {
+    /// A Lua value, whose default is nil.
+    type Value: Clone + Default;
+    type Error;
+
+    /// `#t`. A length that is not an integer is an error.
+    fn length(&mut self) -> Result<i64, Self::Error>;
+
+    fn get(&mut self, i: i64) -> Result<Self::Value, Self::Error>;
+
+    fn set(&mut self, i: i64, value: Self::Value) -> Result<(), Self::Error>;
+
+    /// `value` as a `FormatArg`, or `None` for the types it cannot hold.
+    /// `concat` only accepts strings and numbers.
+    fn as_arg<'v>(&self, value: &'v Self::Value) -> Option<FormatArg<'v>>;
+}
+
+fn len<T: Table>(t: &mut T) -> Result<i64, TableError<T::Error>> {
+    t.length().map_err(TableError::Host)
+}
+
+fn get<T: Table>(t: &mut T, i: i64) -> Result<T::Value, TableError<T::Error>> {
+    t.get(i).map_err(TableError::Host)
+}
+
+fn set<T: Table>(t: &mut T, i: i64, value: T::Value) -> Result<(), TableError<T::Error>> {
+    t.set(i, value).map_err(TableError::Host)
+}
+
+/// `table.insert(t, [pos,] value)`: without `pos`, appends `value`.
+pub fn insert<T: Table>(
+    t: &mut T,
+    pos: Option<i64>,
+    value: T::Value,
+) -> Result<(), TableError<T::Error>> {
+    // first empty element
+    let e = len(t)?.wrapping_add(1);
+    let pos = match pos {
+        None => e,
+        Some(pos) => {
+            if (pos as u64).wrapping_sub(1) >= e as u64 {
+                return Err(ArgumentError::new(2, "insert", "position out of bounds").into());
+            }
+            for i in (pos + 1..=e).rev() {
+                let value = get(t, i - 1)?;
+                set(t, i, value)?;
+            }
+            pos
+        }
+    };
+    set(t, pos, value)
+}
+
+/// `table.remove(t [, pos])`: removes and returns `t[pos]`, the last
+/// element by default, shifting down the elements after it.
+pub fn remove<T: Table>(t: &mut T, pos: Option<i64>) -> Result<T::Value, TableError<T::Error>> {
+    let size = len(t)?;
+    let mut pos = pos.unwrap_or(size);
+    // 5.3 blames the table argument here
+    if pos != size && (pos as u64).wrapping_sub(1) > size as u64 {
+        return Err(ArgumentError::new(1, "remove", "position out of bounds").into());
+    }
+    let result = get(t, pos)?;
+    while pos < size {
+        let value = get(t, pos + 1)?;
+        set(t, pos, value)?;
+        pos += 1;
+    }
+    set(t, pos, T::Value::default())?;
+    Ok(result)
+}
+
+/// `table.move(a1, f, e, t [, a2])`: copies `a1[f..e]` to `a2[t..]`, or
+/// within `a1` when `a2` is `None`, in an order that works for overlapping
+/// ranges.
+pub fn move_elements<T: Table>(
+    a1: &mut T,
+    f: i64,
+    e: i64,
+    t: i64,
+    a2: Option<&mut T>,
+) -> Result<(), TableError<T::Error>> {
+    if e < f {
+        return Ok(());
+    }
+    if f <= 0 && e >= i64::MAX + f {
+        return Err(ArgumentError::new(3, "move", "too many elements to move").into());
+    }
+    let n = e - f + 1;
+    if t > i64::MAX - n + 1 {
+        return Err(ArgumentError::new(4, "move", "destination wrap around").into());
+    }
+    match a2 {
+        Some(a2) => {
+            for i in 0..n {
+                let value = get(a1, f + i)?;
+                set(a2, t + i, value)?;
+            }
+        }
+        None if t > e || t <= f => {
+            for i in 0..n {
+                let value = get(a1, f + i)?;
+                set(a1, t + i, value)?;
+            }
+        }
+        None => {
+            for i in (0..n).rev() {
+                let value = get(a1, f + i)?;
+                set(a1, t + i, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `table.concat(t [, sep [, i [, j]]])`: joins `t[i..j]`, which must
+/// be strings or numbers, with `sep` between them.
+pub fn concat<T: Table>(
+    t: &mut T,
+    sep: &[u8],
+    i: Option<i64>,
+    j: Option<i64>,
+) -> Result<Vec<u8>, TableError<T::Error>> {
+    let last = len(t)?;
+    let last = j.unwrap_or(last);
+    let mut i = i.unwrap_or(1);
+    let mut result = vec![];
+    while i <= last {
+        let value = get(t, i)?;
+        match t.as_arg(&value) {
+            Some(FormatArg::String(s)) => result.extend_from_slice(s),
+            Some(FormatArg::Number(n)) => result.extend_from_slice(n.to_string().as_bytes()),
+            _ => return Err(TableError::InvalidValue(i)),
+        }
+        if i == last {
+            break;
+        }
+        result.extend_from_slice(sep);
+        i += 1;
+    }
+    Ok(result)
+}
+
+/// `table.unpack(t [, i [, j]])`: the values `t[i..j]`.
+pub fn unpack<T: Table>(
+    t: &mut T,
+    i: Option<i64>,
+    j: Option<i64>,
+) -> Result<Vec<T::Value>, TableError<T::Error>> {
+    let i = i.unwrap_or(1);
+    let e = match j {
+        Some(j) => j,
+        None => len(t)?,
+    };
+    if i > e {
+        return Ok(vec![]);
+    }
+    // number of elements minus 1, which cannot overflow
+    let n = (e as u64).wrapping_sub(i as u64);
+    if n >= MAX_RESULTS {
+        return Err(TableError::TooManyResults);
+    }
+    let mut values = Vec::with_capacity(n as usize + 1);
+    for k in 0..=n {
+        values.push(get(t, i.wrapping_add(k as i64))?);
+    }
+    Ok(values)
+}
+
+/// `table.sort(t [, comp])`: sorts `t[1..#t]` in place with `less`, which
+/// is `comp` or the `<` operator. Like the reference quicksort, it detects
+/// comparators that are not consistent orders when they would make it run
+/// past the interval, and stops with an error instead.
+pub fn sort<T: Table>(
+    t: &mut T,
+    mut less: impl FnMut(&T::Value, &T::Value) -> Result<bool, T::Error>,
+) -> Result<(), TableError<T::Error>> {
+    let n = len(t)?;
+    if n > 1 {
+        if n >= i32::MAX as i64 {
+            return Err(ArgumentError::new(1, "sort", "array too big").into());
+        }
+        Sort { t, less: &mut less }.auxsort(1, n as u64, 0)?;
+    }
+    Ok(())
+}
+
+/// The quicksort of `ltablib.c`, keeping its order of reads, writes and
+/// comparisons, which metamethods and comparators can observe.
+struct Sort<'a, T: Table, F> {
+    t: &'a mut T,
+    less: &'a mut F,
+}
+
+impl<T, F> Sort<'_, T, F>
+where
+    T: Table,
+    F: FnMut(&T::Value, &T::Value) -> Result<bool, T::Error>,
+{
+    fn get(&mut self, i: u64) -> Result<T::Value, TableError<T::Error>> {
+        get(self.t, i as i64)
+    }
+
+    fn set(&mut self, i: u64, value: T::Value) -> Result<(), TableError<T::Error>> {
+        set(self.t, i as i64, value)
+    }
+
+    fn less(&mut self, a: &T::Value, b: &T::Value) -> Result<bool, TableError<T::Error>> {
+        (self.less)(a, b).map_err(TableError::Host)
+    }
+
+    fn auxsort(
+        &mut self,
+        mut lo: u64,
+        mut up: u64,
+        mut rnd: u64,
+    ) -> Result<(), TableError<T::Error>> {
+        while lo < up {
+            // sort elements lo, p and up
+            let a_lo = self.get(lo)?;
+            let a_up = self.get(up)?;
+            if self.less(&a_up, &a_lo)? {
+                self.set(lo, a_up)?;
+                self.set(up, a_lo)?;
+            }
+            if up - lo == 1 {
+                break;
+            }
+            let p = if up - lo < RANLIMIT || rnd == 0 {
+                (lo + up) / 2
+            } else {
+                let r4 = (up - lo) / 4;
+                rnd % (r4 * 2) + (lo + r4)
+            };
+            let a_p = self.get(p)?;
+            let a_lo = self.get(lo)?;
+            if self.less(&a_p, &a_lo)? {
+                self.set(p, a_lo)?;
+                self.set(lo, a_p)?;
+            } else {
+                let a_up = self.get(up)?;
+                if self.less(&a_up, &a_p)? {
+                    self.set(p, a_up)?;
+                    self.set(up, a_p)?;
+                }
+            }
+            if up - lo == 2 {
+                break;
+            }
+            // move the pivot to up - 1
+            let pivot = self.get(p)?;
+            let a_up1 = self.get(up - 1)?;
+            self.set(p, a_up1)?;
+            self.set(up - 1, pivot.clone())?;
+            let p = self.partition(lo, up, &pivot)?;
+            // recurse into the smaller half and loop on the larger one
+            let n = if p - lo < up - p {
+                self.auxsort(lo, p - 1, rnd)?;
+                let n = p - lo;
+                lo = p + 1;
+                n
+            } else {
+                self.auxsort(p + 1, up, rnd)?;
+                let n = up - p;
+                up = p - 1;
+                n
+            };
+            if (up.wrapping_sub(lo)) / 128 > n {
+                // the reference mixes in clock() and time(); this keeps
+                // sorts reproducible
+                rnd = (lo ^ up.rotate_left(32)).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32;
+            }
+        }
+        Ok(())
+    }
+
+    /// Partitions `lo..=up` around `pivot`, which is at `up - 1`, and
+    /// returns its final position.
+    fn partition(
+        &mut self,
+        lo: u64,
+        up: u64,
+        pivot: &T::Value,
+    ) -> Result<u64, TableError<T::Error>> {
+        let mut i = lo;
+        let mut j = up - 1;
+        loop {
+            // repeat i += 1 while t[i] < pivot
+            let a_i = loop {
+                i += 1;
+                let a_i = self.get(i)?;
+                if !self.less(&a_i, pivot)? {
+                    break a_i;
+                }
+                if i == up - 1 {
+                    return Err(TableError::InvalidOrderFunction);
+                }
+            };
+            // repeat j -= 1 while pivot < t[j]
+            let a_j = loop {
+                j -= 1;
+                let a_j = self.get(j)?;
+                if !self.less(pivot, &a_j)? {
+                    break a_j;
+                }
+                if j < i {
+                    return Err(TableError::InvalidOrderFunction);
+                }
+            };
+            if j < i {
+                self.set(up - 1, a_i)?;
+                self.set(i, pivot.clone())?;
+                return Ok(i);
+            }
+            self.set(i, a_j)?;
+            self.set(j, a_i)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::number::Number;
+    use std::collections::BTreeMap;
+
+    #[derive(Clone, Debug, Default, PartialEq)]
+    enum Value {
+        #[default]
+        Nil,
+        Int(i64),
+        Float(f64),
+        Str(&'static str),
+        Function,
+    }
+
+    use Value::*;
+
+    /// A table with an optional `__len`, counting the accesses that would
+    /// go through metamethods.
+    #[derive(Default)]
+    struct TestTable {
+        items: BTreeMap<i64, Value>,
+        len: Option<i64>,
+        accesses: usize,
+    }
+
+    impl TestTable {
+        fn new(values: &[Value]) -> TestTable {
+            TestTable {
+                items: (1..).zip(values.iter().cloned()).collect(),
+                ..TestTable::default()
+            }
+        }
+
+        fn values(&self) -> Vec<Value> {
+            (1..).map_while(|i| self.items.get(&i).cloned()).collect()
+        }
+    }
+
+    impl Table for TestTable {
+        type Value = Value;
+        type Error = String;
+
+        fn length(&mut self) -> Result<i64, String> {
+            Ok(self
+                .len
+                .unwrap_or_else(|| (0..).find(|i| !self.items.contains_key(&(i + 1))).unwrap()))
+        }
+
+        fn get(&mut self, i: i64) -> Result<Value, String> {
+            self.accesses += 1;
+            Ok(self.items.get(&i).cloned().unwrap_or_default())
+        }
+
+        fn set(&mut self, i: i64, value: Value) -> Result<(), String> {
+            self.accesses += 1;
+            match value {
+                Nil => self.items.remove(&i),
+                value => self.items.insert(i, value),
+            };
+            Ok(())
+        }
+
+        fn as_arg<'v>(&self, value: &'v Value) -> Option<FormatArg<'v>> {
+            match value {
+                Nil => Some(FormatArg::Nil),
+                Int(i) => Some(FormatArg::Number(Number::Integer(*i))),
+                Float(x) => Some(FormatArg::Number(Number::Float(*x))),
+                Str(s) => Some(FormatArg::String(s.as_bytes())),
+                Function => None,
+            }
+        }
+    }
+
+    fn ints(values: &[i64]) -> Vec<Value> {
+        values.iter().map(|&i| Int(i)).collect()
+    }
+
+    fn compare(a: &Value, b: &Value) -> Result<bool, String> {
+        match (a, b) {
+            (Int(a), Int(b)) => Ok(a < b),
+            _ => Err("attempt to compare".to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_insert_and_remove() {
+        let mut t = TestTable::new(&ints(&[1, 2, 3]));
+        insert(&mut t, None, Int(4)).unwrap();
+        insert(&mut t, Some(1), Int(0)).unwrap();
+        insert(&mut t, Some(6), Int(5)).unwrap();
+        assert_eq!(ints(&[0, 1, 2, 3, 4, 5]), t.values());
+        assert_eq!(Ok(Int(2)), remove(&mut t, Some(3)));
+        assert_eq!(Ok(Int(5)), remove(&mut t, None));
+        assert_eq!(ints(&[0, 1, 3, 4]), t.values());
+        assert_eq!(Ok(Nil), remove(&mut t, Some(5)));
+
+        assert_eq!(
+            "bad argument #2 to 'insert' (position out of bounds)",
+            insert(&mut t, Some(6), Int(0)).unwrap_err().to_string()
+        );
+        assert_eq!(
+            "bad argument #2 to 'insert' (position out of bounds)",
+            insert(&mut t, Some(0), Int(0)).unwrap_err().to_string()
+        );
+        assert_eq!(
+            "bad argument #1 to 'remove' (position out of bounds)",
+            remove(&mut t, Some(6)).unwrap_err().to_string()
+        );
+
+        let mut empty = TestTable::new(&[]);
+        assert_eq!(Ok(Nil), remove(&mut empty, None));
+        assert_eq!(Ok(Nil), remove(&mut empty, Some(0)));
+    }
+
+    #[test]
+    fn test_length_comes_from_len() {
+        let mut t = TestTable::new(&ints(&[1, 2, 3]));
+        t.len = Some(1);
+        insert(&mut t, None, Int(9)).unwrap();
+        assert_eq!(ints(&[1, 9, 3]), t.values());
+        assert_eq!(b"1".to_vec(), concat(&mut t, b",", None, None).unwrap());
+        assert_eq!(Ok(ints(&[1])), unpack(&mut t, None, None));
+    }
+
+    #[test]
+    fn test_move() {
+        let mut t = TestTable::new(&ints(&[1, 2, 3, 4, 5]));
+        move_elements(&mut t, 1, 3, 3, None).unwrap();
+        assert_eq!(ints(&[1, 2, 1, 2, 3]), t.values());
+        move_elements(&mut t, 2, 5, 1, None).unwrap();
+        assert_eq!(ints(&[2, 1, 2, 3, 3]), t.values());
+
+        let mut other = TestTable::new(&[]);
+        move_elements(&mut t, 1, 2, 1, Some(&mut other)).unwrap();
+        assert_eq!(ints(&[2, 1]), other.values());
+        move_elements(&mut t, 3, 1, 1, Some(&mut other)).unwrap();
+        assert_eq!(2, other.accesses);
+
+        assert_eq!(
+            "bad argument #3 to 'move' (too many elements to move)",
+            move_elements(&mut t, 0, i64::MAX, 1, None)
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "bad argument #4 to 'move' (destination wrap around)",
+            move_elements(&mut t, 1, 2, i64::MAX, None)
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_concat_and_unpack() {
+        let mut t = TestTable::new(&[Str("a"), Int(1), Float(2.0), Float(0.5)]);
+        assert_eq!(
+            b"a, 1, 2.0, 0.5".to_vec(),
+            concat(&mut t, b", ", None, None).unwrap()
+        );
+        assert_eq!(
+            b"1".to_vec(),
+            concat(&mut t, b"", Some(2), Some(2)).unwrap()
+        );
+        assert_eq!(b"".to_vec(), concat(&mut t, b"", Some(3), Some(2)).unwrap());
+        assert_eq!(
+            Err(TableError::InvalidValue(5)),
+            concat(&mut t, b"", Some(4), Some(5))
+        );
+        t.items.insert(5, Function);
+        assert_eq!(
+            "invalid value (at index 5) in table for 'concat'",
+            concat(&mut t, b"", None, None).unwrap_err().to_string()
+        );
+
+        assert_eq!(
+            Ok(vec![Int(1), Float(2.0)]),
+            unpack(&mut t, Some(2), Some(3))
+        );
+        assert_eq!(Ok(vec![Nil, Str("a")]), unpack(&mut t, Some(0), Some(1)));
+        assert_eq!(Ok(vec![]), unpack(&mut t, Some(3), Some(2)));
+        assert_eq!(
+            Err(TableError::TooManyResults),
+            unpack(&mut t, Some(i64::MIN), Some(i64::MAX))
+        );
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut state = 12345_u64;
+        let values: Vec<i64> = (0..1000)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (state >> 40) as i64 % 500
+            })
+            .collect();
+        let mut t = TestTable::new(&ints(&values));
+        sort(&mut t, compare).unwrap();
+        let mut expected = values.clone();
+        expected.sort();
+        assert_eq!(ints(&expected), t.values());
+
+        sort(&mut t, |a, b| compare(b, a)).unwrap();
+        expected.reverse();
+        assert_eq!(ints(&expected), t.values());
+
+        // already sorted and constant inputs must not degrade
+        let mut t = TestTable::new(&ints(&[7; 2000]));
+        sort(&mut t, compare).unwrap();
+        assert!(t.accesses < 100_000, "{} accesses", t.accesses);
+
+        for n in 0..4 {
+            let mut t = TestTable::new(&ints(&(0..n).rev().collect::<Vec<_>>()));
+            sort(&mut t, compare).unwrap();
+            assert_eq!(ints(&(0..n).collect::<Vec<_>>()), t.values());
+        }
+    }
+
+    #[test]
+    fn test_sort_errors() {
+        let mut t = TestTable::new(&ints(&(0..100).collect::<Vec<_>>()));
+        assert_eq!(
+            Err(TableError::InvalidOrderFunction),
+            sort(&mut t, |_, _| Ok(true))
+        );
+        let mut t = TestTable::new(&ints(&[1; 100]));
+        assert_eq!(
+            "invalid order function for sorting",
+            sort(&mut t, |a, b| compare(a, b).map(|less| !less))
+                .unwrap_err()
+                .to_string()
+        );
+
+        let mut t = TestTable::new(&[Int(1), Str("x"), Int(2)]);
+        assert_eq!(
+            Err(TableError::Host("attempt to compare".to_owned())),
+            sort(&mut t, compare)
+        );
+
+        let mut t = TestTable::new(&[]);
+        t.len = Some(i32::MAX as i64);
+        assert_eq!(
+            "bad argument #1 to 'sort' (array too big)",
+            sort(&mut t, compare).unwrap_err().to_string()
+        );
+    }
+}
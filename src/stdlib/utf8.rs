@@ -0,0 +1,342 @@
+use crate::stdlib::string::posrelat;
+use crate::stdlib::ArgumentError;
+use std::fmt::{Display, Formatter};
+
+const MAX_UNICODE: i64 = 0x10FFFF;
+
+/// `utf8.charpattern`: matches exactly one UTF-8 byte sequence, assuming
+/// the subject is valid UTF-8.
+pub const CHAR_PATTERN: &[u8] = b"[\0-\x7F\xC2-\xF4][\x80-\xBF]*";
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Utf8Error {
+    BadArgument(ArgumentError),
+    InvalidCode,
+    StringSliceTooLong,
+    InitialPositionIsContinuation,
+}
+
+impl Display for Utf8Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Utf8Error::BadArgument(error) => write!(f, "{}", error),
+            Utf8Error::InvalidCode => write!(f, "invalid UTF-8 code"),
+            Utf8Error::StringSliceTooLong => write!(f, "string slice too long"),
+            Utf8Error::InitialPositionIsContinuation => {
+                write!(f, "initial position is a continuation byte")
+            }
+        }
+    }
+}
+
+impl From<ArgumentError> for Utf8Error {
+    fn from(error: ArgumentError) -> Utf8Error {
+        Utf8Error::BadArgument(error)
+    }
+}
+
+/// Result of `utf8.len`, which does not fail on malformed input.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Utf8Length {
+    Characters(usize),
+    /// The 1-based position of the first byte that does not start a valid
+    /// sequence; Lua returns it after a `nil`.
+    InvalidByteAt(usize),
+}
+
+/// Reads past the end as `\0`, like the terminator after a Lua string.
+fn byte_at(s: &[u8], pos: usize) -> u8 {
+    s.get(pos).copied().unwrap_or(0)
+}
+
+fn is_continuation(s: &[u8], pos: usize) -> bool {
+    byte_at(s, pos) & 0xC0 == 0x80
+}
+
+/// Decodes the sequence starting at `pos`, returning the code point and
+/// the position after it. Overlong encodings and values above
+/// `0x10FFFF` are rejected, surrogates are not.
+fn decode(s: &[u8], pos: usize) -> Option<(u32, usize)> {
+    const LIMITS: [u32; 4] = [0xFF, 0x7F, 0x7FF, 0xFFFF];
+    let mut c = byte_at(s, pos) as u32;
+    if c < 0x80 {
+        return Some((c, pos + 1));
+    }
+    let mut result = 0;
+    let mut count = 0;
+    while c & 0x40 != 0 {
+        count += 1;
+        let cc = byte_at(s, pos + count) as u32;
+        if cc & 0xC0 != 0x80 {
+            return None;
+        }
+        result = (result << 6) | (cc & 0x3F);
+        c <<= 1;
+    }
+    if count > 3 {
+        return None;
+    }
+    result |= (c & 0x7F) << (count * 5);
+    if result > MAX_UNICODE as u32 || result <= LIMITS[count] {
+        return None;
+    }
+    Some((result, pos + count + 1))
+}
+
+pub(crate) fn encode(code: u32, result: &mut Vec<u8>) {
+    if code < 0x80 {
+        result.push(code as u8);
+        return;
+    }
+    let mut buffer = vec![];
+    let mut code = code;
+    let mut first_limit = 0x3f;
+    while code > first_limit {
+        buffer.push(0x80 | (code & 0x3f) as u8);
+        code >>= 6;
+        first_limit >>= 1;
+    }
+    buffer.push(((!first_limit << 1) | code) as u8);
+    result.extend(buffer.iter().rev());
+}
+
+/// `utf8.char`: the concatenated UTF-8 encodings of `codes`.
+pub fn char(codes: &[i64]) -> Result<Vec<u8>, Utf8Error> {
+    let mut result = vec![];
+    for (i, &code) in codes.iter().enumerate() {
+        if !(0..=MAX_UNICODE).contains(&code) {
+            return Err(ArgumentError::new(i + 1, "char", "value out of range").into());
+        }
+        encode(code as u32, &mut result);
+    }
+    Ok(result)
+}
+
+/// `utf8.len`: the number of characters starting between the 1-based
+/// positions `i` and `j`.
+pub fn len(s: &[u8], i: Option<i64>, j: Option<i64>) -> Result<Utf8Length, Utf8Error> {
+    let posi = posrelat(i.unwrap_or(1), s.len());
+    let posj = posrelat(j.unwrap_or(-1), s.len());
+    if !(1 <= posi && posi - 1 <= s.len() as i64) {
+        return Err(ArgumentError::new(2, "len", "initial position out of string").into());
+    }
+    if posj > s.len() as i64 {
+        return Err(ArgumentError::new(3, "len", "final position out of string").into());
+    }
+
+    let mut n = 0;
+    let mut pos = posi - 1;
+    while pos < posj {
+        match decode(s, pos as usize) {
+            Some((_, next)) => pos = next as i64,
+            None => return Ok(Utf8Length::InvalidByteAt(pos as usize + 1)),
+        }
+        n += 1;
+    }
+    Ok(Utf8Length::Characters(n))
+}
+
+/// `utf8.codepoint`: the code points of all characters starting between
+/// the 1-based positions `i` and `j`.
+pub fn codepoint(s: &[u8], i: Option<i64>, j: Option<i64>) -> Result<Vec<u32>, Utf8Error> {
+    let posi = posrelat(i.unwrap_or(1), s.len());
+    let pose = posrelat(j.unwrap_or(posi), s.len());
+    if posi < 1 {
+        return Err(ArgumentError::new(2, "codepoint", "out of range").into());
+    }
+    if pose > s.len() as i64 {
+        return Err(ArgumentError::new(3, "codepoint", "out of range").into());
+    }
+    if posi > pose {
+        return Ok(vec![]);
+    }
+    if pose - posi >= i32::MAX as i64 {
+        return Err(Utf8Error::StringSliceTooLong);
+    }
+
+    let mut codes = vec![];
+    let mut pos = posi as usize - 1;
+    while pos < pose as usize {
+        let (code, next) = decode(s, pos).ok_or(Utf8Error::InvalidCode)?;
+        codes.push(code);
+        pos = next;
+    }
+    Ok(codes)
+}
+
+/// `utf8.offset`: the 1-based position where the `n`-th character counted
+/// from position `i` starts. `n == 0` finds the start of the character
+/// containing `i`.
+pub fn offset(s: &[u8], n: i64, i: Option<i64>) -> Result<Option<usize>, Utf8Error> {
+    let len = s.len() as i64;
+    let default = if n >= 0 { 1 } else { len + 1 };
+    let posi = posrelat(i.unwrap_or(default), s.len());
+    if !(1 <= posi && posi - 1 <= len) {
+        return Err(ArgumentError::new(3, "offset", "position out of range").into());
+    }
+
+    let mut posi = (posi - 1) as usize;
+    let mut n = n;
+    if n == 0 {
+        while posi > 0 && is_continuation(s, posi) {
+            posi -= 1;
+        }
+    } else if is_continuation(s, posi) {
+        return Err(Utf8Error::InitialPositionIsContinuation);
+    } else if n < 0 {
+        while n < 0 && posi > 0 {
+            posi -= 1;
+            while posi > 0 && is_continuation(s, posi) {
+                posi -= 1;
+            }
+            n += 1;
+        }
+    } else {
+        n -= 1;
+        while n > 0 && posi < s.len() {
+            posi += 1;
+            while is_continuation(s, posi) {
+                posi += 1;
+            }
+            n -= 1;
+        }
+    }
+    Ok((n == 0).then_some(posi + 1))
+}
+
+/// `utf8.codes`: iterates over the 1-based positions and code points of
+/// all characters in `s`.
+pub fn codes(s: &[u8]) -> Codes<'_> {
+    Codes { s, pos: None }
+}
+
+pub struct Codes<'a> {
+    s: &'a [u8],
+    pos: Option<usize>,
+}
+
+impl Iterator for Codes<'_> {
+    type Item = Result<(usize, u32), Utf8Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let s = self.s;
+        let pos = match self.pos {
+            None => 0,
+            Some(pos) if pos < s.len() => {
+                let mut pos = pos + 1;
+                while is_continuation(s, pos) {
+                    pos += 1;
+                }
+                pos
+            }
+            Some(pos) => pos,
+        };
+        if pos >= s.len() {
+            return None;
+        }
+        match decode(s, pos) {
+            Some((code, next)) if !is_continuation(s, next) => {
+                self.pos = Some(pos);
+                Some(Ok((pos + 1, code)))
+            }
+            _ => {
+                // Lua raises an error here, ending the loop
+                self.pos = Some(s.len());
+                Some(Err(Utf8Error::InvalidCode))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char() {
+        assert_eq!(
+            b"A\xc3\xa9\xe2\x82\xac\xf0\x9f\x98\x80".to_vec(),
+            char(&[0x41, 0xe9, 0x20ac, 0x1f600]).unwrap()
+        );
+        assert_eq!(b"\xed\xa0\x80".to_vec(), char(&[0xd800]).unwrap());
+        assert_eq!(
+            "bad argument #2 to 'char' (value out of range)",
+            char(&[1, 0x110000]).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_len() {
+        assert_eq!(
+            Ok(Utf8Length::Characters(3)),
+            len(b"a\xc3\xa9b", None, None)
+        );
+        assert_eq!(
+            Ok(Utf8Length::Characters(2)),
+            len(b"a\xc3\xa9b", Some(2), None)
+        );
+        assert_eq!(Ok(Utf8Length::Characters(0)), len(b"", None, None));
+        assert_eq!(Ok(Utf8Length::InvalidByteAt(2)), len(b"a\xffb", None, None));
+        assert_eq!(
+            Ok(Utf8Length::InvalidByteAt(1)),
+            len(b"\xc0\x80", None, None)
+        );
+        assert_eq!(
+            "bad argument #2 to 'len' (initial position out of string)",
+            len(b"abc", Some(5), None).unwrap_err().to_string()
+        );
+        assert_eq!(
+            "bad argument #3 to 'len' (final position out of string)",
+            len(b"abc", None, Some(4)).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_codepoint() {
+        assert_eq!(Ok(vec![0x61]), codepoint(b"a\xc3\xa9b", None, None));
+        assert_eq!(
+            Ok(vec![0x61, 0xe9, 0x62]),
+            codepoint(b"a\xc3\xa9b", Some(1), Some(-1))
+        );
+        assert_eq!(Ok(vec![]), codepoint(b"abc", Some(3), Some(2)));
+        assert_eq!(
+            "invalid UTF-8 code",
+            codepoint(b"\xff", None, None).unwrap_err().to_string()
+        );
+        assert_eq!(
+            "bad argument #3 to 'codepoint' (out of range)",
+            codepoint(b"abc", Some(1), Some(4)).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_offset() {
+        let s = b"a\xc3\xa9b";
+        assert_eq!(Ok(Some(2)), offset(s, 2, None));
+        assert_eq!(Ok(Some(4)), offset(s, 3, None));
+        assert_eq!(Ok(Some(5)), offset(s, 4, None));
+        assert_eq!(Ok(None), offset(s, 5, None));
+        assert_eq!(Ok(Some(4)), offset(s, -1, None));
+        assert_eq!(Ok(Some(2)), offset(s, 0, Some(3)));
+        assert_eq!(
+            "initial position is a continuation byte",
+            offset(s, 1, Some(3)).unwrap_err().to_string()
+        );
+        assert_eq!(
+            "bad argument #3 to 'offset' (position out of range)",
+            offset(s, 1, Some(6)).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_codes() {
+        assert_eq!(
+            vec![Ok((1, 0x61)), Ok((2, 0xe9)), Ok((4, 0x62))],
+            codes(b"a\xc3\xa9b").collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![Ok((1, 0x61)), Err(Utf8Error::InvalidCode)],
+            codes(b"a\xff").collect::<Vec<_>>()
+        );
+    }
+}
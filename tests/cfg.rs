@@ -0,0 +1,57 @@
+use rlua::cfg::Cfg;
+use rlua::compiler::compile;
+use rlua::file::chunk::Chunk;
+use std::fs;
+
+fn check_function(chunk: &Chunk) {
+    let cfg = Cfg::build(&chunk.code).unwrap();
+    let blocks = cfg.blocks();
+    assert_eq!(0, blocks[0].start);
+    assert_eq!(chunk.code.len(), blocks[blocks.len() - 1].end);
+    for (i, block) in blocks.iter().enumerate() {
+        assert!(block.start < block.end);
+        assert!(block
+            .successors
+            .iter()
+            .all(|&s| blocks[s].predecessors.contains(&i)));
+        assert!(block
+            .predecessors
+            .iter()
+            .all(|&p| blocks[p].successors.contains(&i)));
+        assert_eq!(block.successors.is_empty(), cfg.is_exit(i));
+        if cfg.is_reachable(i) {
+            assert!(cfg.dominates(0, i));
+        }
+        if let Some(idom) = cfg.immediate_dominator(i) {
+            assert!(block
+                .predecessors
+                .iter()
+                .all(|&p| !cfg.is_reachable(p) || cfg.dominates(idom, p)));
+        }
+    }
+    for l in cfg.loops() {
+        assert!(l.blocks.iter().all(|&b| cfg.dominates(l.header, b)));
+        assert!(l
+            .latches
+            .iter()
+            .all(|&b| blocks[b].successors.contains(&l.header)));
+    }
+    assert!(cfg.to_dot(&chunk.name).starts_with("digraph"));
+    chunk.prototypes.iter().for_each(check_function);
+}
+
+#[test]
+fn test_resources() {
+    for name in [
+        "simple",
+        "functions",
+        "recursion",
+        "errors",
+        "compiler",
+        "constants",
+        "folding",
+    ] {
+        let source = fs::read(format!("tests/resources/{}.lua", name)).unwrap();
+        check_function(&compile(&source, "=test").unwrap());
+    }
+}
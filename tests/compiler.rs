@@ -0,0 +1,155 @@
+use rlua::compiler::printer::print;
+use rlua::compiler::{compile, parse};
+use rlua::file::chunk::Chunk;
+use rlua::file::header::Header;
+use rlua::file::{Constant, LuaFile};
+use std::fs;
+
+fn compile_resource(name: &str) -> Vec<u8> {
+    let source = fs::read(format!("tests/resources/{}.lua", name)).unwrap();
+    let main_chunk = match compile(&source, &format!("@{}.lua", name)) {
+        Ok(chunk) => chunk,
+        Err(e) => panic!("{}", e),
+    };
+    LuaFile {
+        header: Header::native(),
+        main_chunk,
+    }
+    .write()
+}
+
+fn compile_error(source: &str) -> String {
+    match compile(source.as_bytes(), "=test") {
+        Ok(_) => panic!("compiled: {}", source),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn test_matches_luac() {
+    for name in [
+        "simple",
+        "functions",
+        "recursion",
+        "errors",
+        "compiler",
+        "constants",
+        "folding",
+    ] {
+        let expected = fs::read(format!("tests/resources/{}.luac", name)).unwrap();
+        assert!(expected == compile_resource(name), "{}.lua", name);
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    let bytes = compile_resource("compiler");
+    let file = LuaFile::parse(&mut bytes.as_slice()).unwrap();
+    assert_eq!(bytes, file.write());
+}
+
+fn assert_same_code(expected: &Chunk, actual: &Chunk) {
+    assert_eq!(expected.code, actual.code);
+    assert_eq!(expected.constants, actual.constants);
+    assert_eq!(expected.prototypes.len(), actual.prototypes.len());
+    for (expected, actual) in expected.prototypes.iter().zip(&actual.prototypes) {
+        assert_same_code(expected, actual);
+    }
+}
+
+#[test]
+fn test_printed_source_compiles_the_same() {
+    for name in [
+        "simple",
+        "functions",
+        "recursion",
+        "compiler",
+        "constants",
+        "folding",
+    ] {
+        let source = fs::read(format!("tests/resources/{}.lua", name)).unwrap();
+        let printed = print(&parse(&source, "=test").unwrap());
+        assert_eq!(
+            printed,
+            print(&parse(printed.as_bytes(), "=test").unwrap()),
+            "{}.lua",
+            name
+        );
+        assert_same_code(
+            &compile(&source, "=test").unwrap(),
+            &compile(printed.as_bytes(), "=test").unwrap(),
+        );
+    }
+}
+
+#[test]
+fn test_binary_strings() {
+    let main_chunk = compile(b"x = '\\xff\\0\\200' .. '\xfe'", "=test").unwrap();
+    assert_eq!(
+        vec![
+            Constant::String(b"x".to_vec()),
+            Constant::String(b"\xff\0\xc8".to_vec()),
+            Constant::String(b"\xfe".to_vec()),
+        ],
+        main_chunk.constants
+    );
+    let bytes = LuaFile {
+        header: Header::native(),
+        main_chunk,
+    }
+    .write();
+    let file = LuaFile::parse(&mut bytes.as_slice()).unwrap();
+    assert_eq!(bytes, file.write());
+}
+
+#[test]
+fn test_syntax_errors() {
+    assert_eq!(
+        "test:1: unexpected symbol near <eof>",
+        compile_error("x = ")
+    );
+    assert_eq!(
+        "test:3: 'end' expected (to close 'if' at line 1) near <eof>",
+        compile_error("if x then\n\n")
+    );
+    assert_eq!(
+        "test:1: unfinished string near ''abc'",
+        compile_error("x = 'abc\ny'")
+    );
+    assert_eq!(
+        "test:1: cannot use '...' outside a vararg function near '...'",
+        compile_error("function f() return ... end")
+    );
+    assert_eq!(
+        "test:1: too many C levels (limit is 200) in main function near '1'",
+        compile_error(&format!("x = {}1{}", "(".repeat(199), ")".repeat(199)))
+    );
+}
+
+#[test]
+fn test_semantic_errors() {
+    assert_eq!(
+        "test:1: no visible label 'nowhere' for <goto> at line 1",
+        compile_error("goto nowhere")
+    );
+    assert_eq!(
+        "test:2: <break> at line 2 not inside a loop",
+        compile_error("local x\nbreak")
+    );
+    assert_eq!(
+        "test:1: <goto l> at line 1 jumps into the scope of local 'x'",
+        compile_error("do goto l; local x; ::l:: print(x) end")
+    );
+    assert_eq!(
+        "test:1: label 'a' already defined on line 1",
+        compile_error("::a:: ::a::")
+    );
+    let locals = (0..201)
+        .map(|i| format!("l{}", i))
+        .collect::<Vec<_>>()
+        .join(", ");
+    assert_eq!(
+        "test:1: too many local variables (limit is 200) in main function",
+        compile_error(&format!("local {}", locals))
+    );
+}
@@ -0,0 +1,92 @@
+use rlua::file::chunk::Chunk;
+use rlua::file::debug::{VariableInfo, VariableKind};
+use rlua::file::LuaFile;
+use rlua::opcode::{Op, Opcode};
+use std::fs::File;
+use std::io::BufReader;
+
+fn load(path: &str) -> Chunk {
+    let os_file = File::open(path).unwrap();
+    let mut rd = BufReader::new(os_file);
+    match LuaFile::parse(&mut rd) {
+        Ok(f) => f.main_chunk,
+        Err(e) => panic!("{:?}", e),
+    }
+}
+
+fn variable(kind: VariableKind, name: &str) -> Option<VariableInfo> {
+    Some(VariableInfo {
+        kind,
+        name: name.to_owned(),
+    })
+}
+
+#[test]
+fn test_error_location() {
+    let chunk = load("tests/resources/errors.luac");
+    assert_eq!("errors.lua", chunk.short_source());
+    assert_eq!(Some(2), chunk.line_at(3));
+    assert_eq!("errors.lua:2:", chunk.error_location(3));
+    assert_eq!("errors.lua:5:", chunk.error_location(11));
+}
+
+#[test]
+fn test_local_names() {
+    let chunk = load("tests/resources/errors.luac");
+    assert_eq!(None, chunk.local_name(1, 0));
+    assert_eq!(Some("t"), chunk.local_name(1, 1));
+    assert_eq!(None, chunk.local_name(2, 9));
+    assert_eq!(Some("s"), chunk.local_name(2, 10));
+    assert_eq!(None, chunk.local_name(0, 10));
+}
+
+#[test]
+fn test_variable_info() {
+    let chunk = load("tests/resources/errors.luac");
+
+    // print(t.x.y)
+    assert_eq!(Op::GetTable, chunk.code[2].get_op());
+    assert_eq!(
+        variable(VariableKind::Local, "t"),
+        chunk.variable_info(2, 0)
+    );
+    assert_eq!(Op::GetTable, chunk.code[3].get_op());
+    assert_eq!(
+        variable(VariableKind::Field, "x"),
+        chunk.variable_info(3, 2)
+    );
+
+    // print(undefined_global.field)
+    assert_eq!(
+        variable(VariableKind::Global, "undefined_global"),
+        chunk.variable_info(7, 2)
+    );
+    assert_eq!(
+        "global 'undefined_global'",
+        chunk.variable_info(7, 2).unwrap().to_string()
+    );
+
+    // s:nomethod()
+    assert_eq!(Op::Call, chunk.code[11].get_op());
+    assert_eq!(
+        variable(VariableKind::Method, "nomethod"),
+        chunk.variable_info(11, 2)
+    );
+}
+
+#[test]
+fn test_short_source() {
+    let mut chunk = load("tests/resources/errors.luac");
+
+    chunk.name = "=stdin".to_owned();
+    assert_eq!("stdin", chunk.short_source());
+
+    chunk.name = "x = 1".to_owned();
+    assert_eq!("[string \"x = 1\"]", chunk.short_source());
+
+    chunk.name = "x = 1\nreturn x".to_owned();
+    assert_eq!("[string \"x = 1...\"]", chunk.short_source());
+
+    chunk.name = format!("@{}", "a/".repeat(40));
+    assert_eq!(format!("...{}", "a/".repeat(28)), chunk.short_source());
+}
@@ -0,0 +1,190 @@
+use rlua::file::chunk::Chunk;
+use rlua::file::LuaFile;
+use rlua::stdlib::debug::{
+    getinfo, getlocal, getupvalue, setlocal, setupvalue, traceback, upvalueid, upvaluejoin, Frame,
+    HookMask, Hooks, UpvalueCell,
+};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::BufReader;
+use std::rc::Rc;
+
+fn load(path: &str) -> Chunk {
+    let os_file = File::open(path).unwrap();
+    let mut rd = BufReader::new(os_file);
+    match LuaFile::parse(&mut rd) {
+        Ok(f) => f.main_chunk,
+        Err(e) => panic!("{:?}", e),
+    }
+}
+
+fn lua(chunk: &Chunk, pc: usize) -> Frame<'_> {
+    Frame::Lua {
+        chunk,
+        pc,
+        tail_call: false,
+    }
+}
+
+#[test]
+fn test_nested_prototypes() {
+    let main = load("tests/resources/functions.luac");
+    assert_eq!(2, main.prototypes.len());
+
+    let add = &main.prototypes[0];
+    assert_eq!("@functions.lua", add.name);
+    assert_eq!((2, 6), (add.line_defined, add.last_line_defined));
+    assert_eq!(vec!["count"], add.upvalue_names);
+    assert!(add.upvalues[0].in_stack);
+    assert_eq!(0, add.upvalues[0].index);
+}
+
+#[test]
+fn test_getinfo() {
+    let main = load("tests/resources/functions.luac");
+    let add = &main.prototypes[0];
+    let frames = [lua(add, 2), lua(&main, 8), Frame::Host];
+
+    let info = getinfo(&frames, 0).unwrap();
+    assert_eq!("@functions.lua", info.source);
+    assert_eq!("functions.lua", info.short_src);
+    assert_eq!("Lua", info.what);
+    assert_eq!(4, info.current_line);
+    assert_eq!(2, info.line_defined);
+    assert_eq!(2, info.num_params);
+    assert!(info.is_vararg);
+    assert_eq!(
+        (Some("add".to_owned()), "local"),
+        (info.name, info.namewhat)
+    );
+
+    let info = getinfo(&frames, 1).unwrap();
+    assert_eq!("main", info.what);
+    assert_eq!(10, info.current_line);
+    assert_eq!(None, info.name);
+
+    assert_eq!("C", getinfo(&frames, 2).unwrap().what);
+    assert_eq!(None, getinfo(&frames, 3));
+}
+
+#[test]
+fn test_traceback() {
+    let main = load("tests/resources/functions.luac");
+    let greet = &main.prototypes[1];
+    let frames = [lua(greet, 2), lua(&main, 11), Frame::Host];
+    assert_eq!(
+        "oops\nstack traceback:\n\tfunctions.lua:8: in function 'greet'\
+         \n\tfunctions.lua:10: in main chunk\n\t[C]: in ?",
+        traceback(&frames, Some("oops"), 0)
+    );
+
+    let frames = [
+        Frame::Lua {
+            chunk: greet,
+            pc: 2,
+            tail_call: true,
+        },
+        lua(&main, 11),
+    ];
+    assert_eq!(
+        "stack traceback:\n\tfunctions.lua:8: in function <functions.lua:7>\
+         \n\t(...tail calls...)\n\tfunctions.lua:10: in main chunk",
+        traceback(&frames, None, 0)
+    );
+}
+
+#[test]
+fn test_long_traceback() {
+    let main = load("tests/resources/recursion.luac");
+    let r = &main.prototypes[0];
+    let mut frames = vec![Frame::Host, lua(r, 5)];
+    frames.extend((0..30).map(|_| lua(r, 9)));
+    frames.push(lua(&main, 4));
+
+    let mut expected = "x\nstack traceback:\n\trecursion.lua:2: in upvalue 'r'".to_owned();
+    expected.push_str(&"\n\trecursion.lua:3: in upvalue 'r'".repeat(9));
+    expected.push_str("\n\t...");
+    expected.push_str(&"\n\trecursion.lua:3: in upvalue 'r'".repeat(9));
+    expected.push_str("\n\trecursion.lua:3: in local 'r'");
+    expected.push_str("\n\trecursion.lua:5: in main chunk");
+    assert_eq!(expected, traceback(&frames, Some("x"), 1));
+}
+
+#[test]
+fn test_locals() {
+    let main = load("tests/resources/functions.luac");
+    let frame = lua(&main, 8);
+    let mut registers = [0, 1, 2, 3];
+    assert_eq!(Some(("count", &0)), getlocal(&frame, &registers, &[], 1));
+    assert_eq!(Some(("add", &1)), getlocal(&frame, &registers, &[], 2));
+    assert_eq!(
+        Some(("(*temporary)", &3)),
+        getlocal(&frame, &registers, &[], 4)
+    );
+    assert_eq!(None, getlocal(&frame, &registers, &[], 5));
+    assert_eq!(None, getlocal(&frame, &registers, &[], -1));
+
+    assert_eq!(Some("add"), setlocal(&frame, &mut registers, &mut [], 2, 9));
+    assert_eq!(9, registers[1]);
+
+    let add = lua(&main.prototypes[0], 0);
+    let mut varargs = [7];
+    assert_eq!(
+        Some(("(*vararg)", &7)),
+        getlocal(&add, &registers, &varargs, -1)
+    );
+    assert_eq!(None, getlocal(&add, &registers, &varargs, -2));
+    assert_eq!(
+        Some("(*vararg)"),
+        setlocal(&add, &mut registers, &mut varargs, -1, 8)
+    );
+    assert_eq!([8], varargs);
+    assert_eq!(Some("a"), main.prototypes[0].local_name(1, 0));
+}
+
+#[test]
+fn test_upvalues() {
+    let main = load("tests/resources/functions.luac");
+    let add = &main.prototypes[0];
+    let count: UpvalueCell<i64> = Rc::new(RefCell::new(0));
+    let mut upvalues = vec![count.clone()];
+
+    assert_eq!(Some(("count", 0)), getupvalue(add, &upvalues, 1));
+    assert_eq!(None, getupvalue(add, &upvalues, 2));
+    assert_eq!(Some("count"), setupvalue(add, &upvalues, 1, 5));
+    assert_eq!(5, *count.borrow());
+
+    let other = vec![Rc::new(RefCell::new(1))];
+    assert_ne!(upvalueid(&upvalues, 1), upvalueid(&other, 1));
+    upvaluejoin(&mut upvalues, 1, &other, 1).unwrap();
+    assert_eq!(upvalueid(&upvalues, 1), upvalueid(&other, 1));
+    assert_eq!(
+        "bad argument #2 to 'upvalueid' (invalid upvalue index)",
+        upvalueid(&upvalues, 2).unwrap_err().to_string()
+    );
+    assert_eq!(
+        "bad argument #4 to 'upvaluejoin' (invalid upvalue index)",
+        upvaluejoin(&mut upvalues, 1, &other, 0)
+            .unwrap_err()
+            .to_string()
+    );
+}
+
+#[test]
+fn test_hooks() {
+    let mask = HookMask::parse(b"lrx", Some(5));
+    assert_eq!("rl", mask.to_mask_string());
+    assert!(HookMask::parse(b"", Some(0)).is_empty());
+
+    let main = load("tests/resources/functions.luac");
+    let hooks = Hooks::new(mask);
+    let lines: Vec<_> = (0..main.code.len())
+        .filter_map(|pc| hooks.line_event(&main, pc.saturating_sub(1), pc))
+        .collect();
+    assert_eq!(vec![1, 6, 9, 7, 10], lines);
+    assert_eq!(Some(10), hooks.line_event(&main, 12, 4));
+
+    let mut hooks = Hooks::new(HookMask::parse(b"", Some(2)));
+    let counts: Vec<_> = (0..5).map(|_| hooks.count_event()).collect();
+    assert_eq!(vec![false, true, false, true, false], counts);
+}
@@ -0,0 +1,233 @@
+use rlua::compiler::compile;
+use rlua::decompiler::decompile;
+use rlua::file::chunk::Chunk;
+use rlua::instruction::{ArgK, Instruction};
+use rlua::opcode::{Mode, Op, Opcode};
+use std::fs;
+use std::time::{Duration, Instant};
+
+/// Asserts that two functions and the functions nested in them have the
+/// same code and constants.
+fn assert_same_code(expected: &Chunk, actual: &Chunk) {
+    assert_eq!(expected.code, actual.code);
+    assert_eq!(expected.constants, actual.constants);
+    assert_eq!(expected.prototypes.len(), actual.prototypes.len());
+    for (expected, actual) in expected.prototypes.iter().zip(&actual.prototypes) {
+        assert_same_code(expected, actual);
+    }
+}
+
+/// Like [`assert_same_code`], but the operands naming constants are
+/// compared by the constants' values. The constants may then be numbered
+/// differently, and ones the code does not use may be missing, like those
+/// of operands that constant folding removed.
+fn assert_equivalent_code(expected: &Chunk, actual: &Chunk) {
+    assert_eq!(listing(expected), listing(actual));
+    assert_eq!(expected.prototypes.len(), actual.prototypes.len());
+    for (expected, actual) in expected.prototypes.iter().zip(&actual.prototypes) {
+        assert_equivalent_code(expected, actual);
+    }
+}
+
+/// The instructions of `chunk` with their constants in place of the
+/// operands naming them.
+fn listing(chunk: &Chunk) -> Vec<String> {
+    let constant = |i: u32| format!("{:?}", &chunk.constants[i as usize]);
+    let rk = |arg: ArgK| match arg.is_constant() {
+        true => constant(arg.index_k() as u32),
+        false => arg.value().to_string(),
+    };
+    let mut listing = vec![];
+    for (pc, instr) in chunk.code.iter().enumerate() {
+        let op = instr.get_op();
+        let a = instr.get_a();
+        let operands = match op {
+            Op::LoadK => constant(instr.get_bx()),
+            Op::ExtraArg if pc > 0 && chunk.code[pc - 1].get_op() == Op::LoadKx => {
+                constant(instr.get_ax())
+            }
+            Op::GetTabup | Op::GetTable | Op::LuaSelf => {
+                format!("{} {} {}", a, instr.get_b().value(), rk(instr.get_c()))
+            }
+            Op::SetTabup
+            | Op::SetTable
+            | Op::Add
+            | Op::Sub
+            | Op::Mul
+            | Op::Mod
+            | Op::Pow
+            | Op::Div
+            | Op::IDiv
+            | Op::BAnd
+            | Op::BOr
+            | Op::BXor
+            | Op::Shl
+            | Op::Shr
+            | Op::Eq
+            | Op::Lt
+            | Op::Le => format!("{} {} {}", a, rk(instr.get_b()), rk(instr.get_c())),
+            _ => format!("{:?}", raw_operands(instr)),
+        };
+        listing.push(format!("{} {}", op.name(), operands));
+    }
+    listing
+}
+
+fn raw_operands(instr: &Instruction) -> Vec<i64> {
+    let a = instr.get_a() as i64;
+    match instr.get_op().mode() {
+        Mode::ABC => vec![a, instr.get_b().raw() as i64, instr.get_c().raw() as i64],
+        Mode::ABx => vec![a, instr.get_bx() as i64],
+        Mode::AsBx => vec![a, instr.get_sbx() as i64],
+        Mode::Ax => vec![instr.get_ax() as i64],
+    }
+}
+
+#[test]
+fn test_decompiled_source_compiles_the_same() {
+    for name in [
+        "simple",
+        "functions",
+        "recursion",
+        "errors",
+        "compiler",
+        "constants",
+    ] {
+        let source = fs::read(format!("tests/resources/{}.lua", name)).unwrap();
+        let chunk = compile(&source, "=test").unwrap();
+        let decompiled = match decompile(&chunk) {
+            Ok(decompiled) => decompiled,
+            Err(e) => panic!("{}.lua: {}", name, e),
+        };
+        let recompiled = match compile(decompiled.as_bytes(), "=test") {
+            Ok(recompiled) => recompiled,
+            Err(e) => panic!("{}.lua: {}\n{}", name, e, decompiled),
+        };
+        assert_same_code(&chunk, &recompiled);
+    }
+}
+
+/// Folded expressions decompile to their results, which drops the
+/// constants only their operands used, like the `"s"` of `not "s"`, and
+/// numbers the remaining ones differently.
+#[test]
+fn test_decompiled_folding_compiles_equivalently() {
+    let source = fs::read("tests/resources/folding.lua").unwrap();
+    let chunk = compile(&source, "=test").unwrap();
+    let decompiled = decompile(&chunk).unwrap();
+    let recompiled = compile(decompiled.as_bytes(), "=test").unwrap();
+    assert!(recompiled.constants.len() < chunk.constants.len());
+    assert_equivalent_code(&chunk, &recompiled);
+}
+
+/// Chunks stripped of their debug information, like those `luac -s`
+/// writes, get locals made up from how the code uses the registers. The
+/// unused `and`/`or` locals of `folding.lua` compile like locals declared
+/// at the end of a branch, so it is left out.
+#[test]
+fn test_decompiled_stripped_source_compiles_the_same() {
+    for name in [
+        "simple",
+        "functions",
+        "recursion",
+        "errors",
+        "compiler",
+        "constants",
+    ] {
+        let source = fs::read(format!("tests/resources/{}.lua", name)).unwrap();
+        let mut chunk = compile(&source, "=test").unwrap();
+        strip(&mut chunk);
+        let decompiled = match decompile(&chunk) {
+            Ok(decompiled) => decompiled,
+            Err(e) => panic!("{}.lua: {}", name, e),
+        };
+        let recompiled = match compile(decompiled.as_bytes(), "=test") {
+            Ok(recompiled) => recompiled,
+            Err(e) => panic!("{}.lua: {}\n{}", name, e, decompiled),
+        };
+        assert_equivalent_code(&chunk, &recompiled);
+    }
+}
+
+fn strip(chunk: &mut Chunk) {
+    chunk.locals.clear();
+    chunk.upvalue_names.clear();
+    for proto in &mut chunk.prototypes {
+        strip(proto);
+    }
+}
+
+/// Every test of an `and`/`or` chain starts a nested chain, which took
+/// time exponential in the number of terms when each was read anew.
+#[test]
+fn test_long_value_chain_decompiles_quickly() {
+    let terms = (0..32)
+        .map(|i| format!("t == {} and \"s{}\"", i, i))
+        .collect::<Vec<_>>()
+        .join(" or ");
+    let source = format!(
+        "local t = ...\nlocal s = {} or \"other\"\nreturn s\n",
+        terms
+    );
+    let chunk = compile(source.as_bytes(), "=test").unwrap();
+    let started = Instant::now();
+    let decompiled = decompile(&chunk).unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    let recompiled = compile(decompiled.as_bytes(), "=test").unwrap();
+    assert_same_code(&chunk, &recompiled);
+}
+
+/// Code with operands no compiler emits gives an error, whether or not the
+/// locals come from debug information.
+#[test]
+fn test_malformed_code_gives_errors() {
+    fn modify(chunk: &mut Chunk, op: Op, change: impl Fn(&mut Instruction)) {
+        let pc = chunk.code.iter().position(|instr| instr.get_op() == op);
+        change(&mut chunk.code[pc.unwrap()]);
+    }
+    // the source, how its code is broken and the error it then gives
+    type Case = (&'static str, fn(&mut Chunk), &'static str);
+    let cases: [Case; 4] = [
+        (
+            "local f = function() end",
+            |chunk| {
+                modify(chunk, Op::Closure, |instr| {
+                    *instr = Instruction::abx(Op::Closure, 0, 1)
+                })
+            },
+            "missing prototype",
+        ),
+        (
+            "local a, b = ...\nlocal s = a .. b",
+            |chunk| {
+                modify(chunk, Op::Concat, |instr| {
+                    instr.set_b(instr.get_c().raw() + 1)
+                })
+            },
+            "CONCAT without operands",
+        ),
+        (
+            "return 1, 2",
+            |chunk| modify(chunk, Op::Return, |instr| instr.set_b(300)),
+            "register out of range",
+        ),
+        (
+            "local t = ...\nt:m()",
+            |chunk| modify(chunk, Op::LuaSelf, |instr| instr.set_a(255)),
+            "register out of range",
+        ),
+    ];
+    for (source, change, message) in cases {
+        for stripped in [false, true] {
+            let mut chunk = compile(source.as_bytes(), "=test").unwrap();
+            if stripped {
+                strip(&mut chunk);
+            }
+            change(&mut chunk);
+            match decompile(&chunk) {
+                Ok(decompiled) => panic!("{}: decompiled to {}", source, decompiled),
+                Err(e) => assert_eq!(message, e.message, "{}", source),
+            }
+        }
+    }
+}
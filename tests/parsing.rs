@@ -1,14 +1,11 @@
-use rlua::file::byte_order::ByteOrder;
-use rlua::file::chunk::Chunk;
-use rlua::file::header::{ByteSize, Header};
-use rlua::file::{Constant, LuaFile, LuaFileParseError};
+use rlua::file::{Constant, LuaFile};
 use rlua::opcode::{Op, Opcode};
 use std::fs::File;
-use std::io::{BufReader, Cursor, Read};
+use std::io::BufReader;
 
 #[test]
 fn test_simple() {
-    let mut os_file = File::open("tests/resources/simple.luac").unwrap();
+    let os_file = File::open("tests/resources/simple.luac").unwrap();
     let mut rd = BufReader::new(os_file);
     let file_res = LuaFile::parse(&mut rd);
     let file = match file_res {
@@ -21,9 +18,11 @@ fn test_simple() {
 
     let main_chunk = file.main_chunk;
     assert_eq!("@simple.lua", main_chunk.name);
-    assert_eq!(1, main_chunk.line_defined);
-    assert_eq!(4, main_chunk.last_line_defined);
+    assert_eq!(0, main_chunk.line_defined);
+    assert_eq!(0, main_chunk.last_line_defined);
     assert_eq!(1, main_chunk.num_upvalues);
+    assert_eq!(0, main_chunk.num_params);
+    assert!(main_chunk.vararg_info.is_some());
     assert_eq!(2, main_chunk.max_stack);
 
     // byte code
@@ -36,17 +35,14 @@ fn test_simple() {
 
     // constant pool
     assert_eq!(8, main_chunk.constants.len());
-    assert_eq!(Constant::String("a".to_owned()), main_chunk.constants[0]);
-    assert_eq!(
-        Constant::String("hello".to_owned()),
-        main_chunk.constants[1]
-    );
-    assert_eq!(Constant::String("b".to_owned()), main_chunk.constants[2]);
+    assert_eq!(Constant::String(b"a".to_vec()), main_chunk.constants[0]);
+    assert_eq!(Constant::String(b"hello".to_vec()), main_chunk.constants[1]);
+    assert_eq!(Constant::String(b"b".to_vec()), main_chunk.constants[2]);
     assert_eq!(Constant::IntegralNumber(1), main_chunk.constants[3]);
-    assert_eq!(Constant::String("c".to_owned()), main_chunk.constants[4]);
+    assert_eq!(Constant::String(b"c".to_vec()), main_chunk.constants[4]);
     assert_eq!(Constant::FloatingNumber(1.5), main_chunk.constants[5]);
-    assert_eq!(Constant::String("d".to_owned()), main_chunk.constants[6]);
-    assert_eq!(Constant::String("bye".to_owned()), main_chunk.constants[7]);
+    assert_eq!(Constant::String(b"d".to_vec()), main_chunk.constants[6]);
+    assert_eq!(Constant::String(b"bye".to_vec()), main_chunk.constants[7]);
 
     // source lines
     assert_eq!(vec![1, 2, 3, 4, 4], main_chunk.source_lines);
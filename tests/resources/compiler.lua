@@ -0,0 +1,212 @@
+do
+local a, b, c = 1, 2.5, "str"
+local d
+local e, f = ...
+local g, h = print("x")
+x, y = y, x
+a, b = b, a
+local t = {1, 2, 3, x = 1, ["y"] = 2, [a] = b, f(), ...}
+local t2 = {f()}
+local t3 = {}
+t.x.y.z = t[1][2]
+t[a], t[b] = t[b], t[a]
+a, t[a] = 1, 2
+local u = #t + a * b - c / d % e ^ f // g
+local v = a & b | c ~ d << e >> f
+local w = ~a, - b, #t
+local s = a .. b .. c .. (d .. e)
+local cmp = a < b, a <= b, a > b, a >= b, a == b, a ~= b
+local cmp2 = a < 1, 1 < a, "x" == a, a ~= nil
+local logic = a and b or c
+local l2 = (a or b) and (c or d)
+local l3 = a and b and c
+local l4 = a or b or c
+local l5 = a == b and c or d
+print(a, b, c, ...)
+print((f()))
+obj:method(1, 2)
+obj.field:method{1}
+obj:method"str"
+f{}
+f"x"
+return a, b
+end
+do
+local function fact(n)
+  if n <= 1 then return 1 end
+  return n * fact(n - 1)
+end
+function g.h.i:j(a, ...)
+  local x = {...}
+  return self, a, ...
+end
+function glob(a, b) return a end
+local up = 1
+local function closure()
+  up = up + 1
+  local inner = function() return up end
+  return inner
+end
+for i = 1, 10 do print(i) end
+for i = 10, 1, step do
+  local k = i
+  local function cap() return k end
+end
+for k, v in pairs(t) do print(k, v) end
+for a, b, c, d in next, t do end
+while a < b do a = a + 1 if a == 5 then break end end
+repeat local z = a; a = a + 1 until z > 10
+repeat local q = 1; local function f() return q end until q
+if a then print(1) elseif b then print(2) else print(3) end
+if a == b then elseif c then end
+if a then break_it() end
+do local x = 1; local function y() return x end end
+goto skip
+print("skipped")
+::skip::
+for i = 1, 3 do
+  for j = 1, 3 do
+    if j == 2 then goto continue end
+    print(i, j)
+    ::continue::
+  end
+end
+while x do
+  if y then break end
+  local k = 1
+  if z then goto out end
+  local function fk() return k end
+end
+::out::
+return f(a)
+end
+do
+local a, b, c
+local function f(...)
+  local x, y = ...
+  return ...
+end
+local function g()
+  return f(1), f(2)
+end
+local function h()
+  return (f())
+end
+local function i(...)
+  return select('#', ...), ...
+end
+local function j()
+  local p = 1
+  return function()
+    local q = p
+    return function() return p + q end
+  end
+end
+a = a == nil
+b = a ~= b
+c = a and b
+local d = a or nil
+local e = a and nil
+local z = a < b and c or d
+while a and b do end
+while a or b do end
+if a and b or c then end
+if (a or b) and c then end
+if a == 1 and b == 2 then x = 1 end
+if a ~= 1 or b ~= 2 then x = 2 end
+local r = a and b or c and d or e
+local s = (a and b) == (c or d)
+print(a and b, a or b)
+return a or b
+end
+do
+local x = 1
+do
+  local y = 2
+  goto l1
+  local z = 3
+  ::l1::
+end
+while x do
+  local a = 1
+  local function f() return a end
+  if x then break end
+  goto cont
+  ::cont::
+end
+for i = 1, 2 do
+  local k = i
+  local g = function() return k end
+  if k then goto next end
+  print(k)
+  ::next::
+end
+repeat
+  local u = 1
+  local h = function() return u end
+until u
+do
+  goto fwd
+  do
+    local p = 1
+    local fp = function() return p end
+  end
+  ::fwd::
+end
+::top::
+x = x + 1
+if x < 10 then goto top end
+local function outer()
+  local a, b = 1, 2
+  local function mid()
+    local c = 3
+    return function() return a + b + c + x end
+  end
+  return mid
+end
+local s = ("x"):rep(3)
+local n = #"abc"
+local q = {f(), g()}
+local r = {f(), (g())}
+local v = {..., 1}
+local w = {1, ...}
+end
+do
+local a, b, c, d = 1
+local e, f = g()
+local h, i, j = k(), l()
+local m = n(), o()
+p, q = r()
+s, t, u = 1
+v.w, x[y] = 1, 2, 3
+local aa = {}
+aa.b, aa.c = aa.c, aa.b
+local bb, cc = 1, 2
+bb, cc.dd = cc, bb
+cc, bb[cc] = 1, 2
+local up = 0
+local function setter()
+  up, up2 = up + 1, up
+  up = {up = up}
+  up.x, up = 1, 2
+end
+local str = "a" .. 1 .. 2.5 .. up
+local cmp = 1 < 2, 1 == 1, "a" < "b", nil == false
+return a, b, ...
+end
+do
+local a = {}
+a.b = function(x, y) return x + y, x - y end
+local x = a.b(1, 2)
+local t = {
+  1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20,
+  21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40,
+  41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60,
+  "a", "b", x, y = 1, ...
+}
+local s = [[long
+string]] .. [==[another]==] .. '\x41\65\u{48}\z
+     tail' .. "\n\t\\\""
+local n = {0x10, 0xA.8p1, 1e10, 3.0, .5, 5., 0xffffffffffffffff, 9223372036854775807, 1.0, 1, 2.5}
+return
+end
@@ -0,0 +1,55 @@
+local t = {}
+t.k0 = 'v0' t.k1 = 'v1' t.k2 = 'v2' t.k3 = 'v3' t.k4 = 'v4' t.k5 = 'v5'
+t.k6 = 'v6' t.k7 = 'v7' t.k8 = 'v8' t.k9 = 'v9' t.k10 = 'v10' t.k11 = 'v11'
+t.k12 = 'v12' t.k13 = 'v13' t.k14 = 'v14' t.k15 = 'v15' t.k16 = 'v16' t.k17 = 'v17'
+t.k18 = 'v18' t.k19 = 'v19' t.k20 = 'v20' t.k21 = 'v21' t.k22 = 'v22' t.k23 = 'v23'
+t.k24 = 'v24' t.k25 = 'v25' t.k26 = 'v26' t.k27 = 'v27' t.k28 = 'v28' t.k29 = 'v29'
+t.k30 = 'v30' t.k31 = 'v31' t.k32 = 'v32' t.k33 = 'v33' t.k34 = 'v34' t.k35 = 'v35'
+t.k36 = 'v36' t.k37 = 'v37' t.k38 = 'v38' t.k39 = 'v39' t.k40 = 'v40' t.k41 = 'v41'
+t.k42 = 'v42' t.k43 = 'v43' t.k44 = 'v44' t.k45 = 'v45' t.k46 = 'v46' t.k47 = 'v47'
+t.k48 = 'v48' t.k49 = 'v49' t.k50 = 'v50' t.k51 = 'v51' t.k52 = 'v52' t.k53 = 'v53'
+t.k54 = 'v54' t.k55 = 'v55' t.k56 = 'v56' t.k57 = 'v57' t.k58 = 'v58' t.k59 = 'v59'
+t.k60 = 'v60' t.k61 = 'v61' t.k62 = 'v62' t.k63 = 'v63' t.k64 = 'v64' t.k65 = 'v65'
+t.k66 = 'v66' t.k67 = 'v67' t.k68 = 'v68' t.k69 = 'v69' t.k70 = 'v70' t.k71 = 'v71'
+t.k72 = 'v72' t.k73 = 'v73' t.k74 = 'v74' t.k75 = 'v75' t.k76 = 'v76' t.k77 = 'v77'
+t.k78 = 'v78' t.k79 = 'v79' t.k80 = 'v80' t.k81 = 'v81' t.k82 = 'v82' t.k83 = 'v83'
+t.k84 = 'v84' t.k85 = 'v85' t.k86 = 'v86' t.k87 = 'v87' t.k88 = 'v88' t.k89 = 'v89'
+t.k90 = 'v90' t.k91 = 'v91' t.k92 = 'v92' t.k93 = 'v93' t.k94 = 'v94' t.k95 = 'v95'
+t.k96 = 'v96' t.k97 = 'v97' t.k98 = 'v98' t.k99 = 'v99' t.k100 = 'v100' t.k101 = 'v101'
+t.k102 = 'v102' t.k103 = 'v103' t.k104 = 'v104' t.k105 = 'v105' t.k106 = 'v106' t.k107 = 'v107'
+t.k108 = 'v108' t.k109 = 'v109' t.k110 = 'v110' t.k111 = 'v111' t.k112 = 'v112' t.k113 = 'v113'
+t.k114 = 'v114' t.k115 = 'v115' t.k116 = 'v116' t.k117 = 'v117' t.k118 = 'v118' t.k119 = 'v119'
+t.k120 = 'v120' t.k121 = 'v121' t.k122 = 'v122' t.k123 = 'v123' t.k124 = 'v124' t.k125 = 'v125'
+t.k126 = 'v126' t.k127 = 'v127' t.k128 = 'v128' t.k129 = 'v129' t.k130 = 'v130' t.k131 = 'v131'
+t.k132 = 'v132' t.k133 = 'v133' t.k134 = 'v134' t.k135 = 'v135' t.k136 = 'v136' t.k137 = 'v137'
+t.k138 = 'v138' t.k139 = 'v139' t.k140 = 'v140' t.k141 = 'v141' t.k142 = 'v142' t.k143 = 'v143'
+t.k144 = 'v144' t.k145 = 'v145' t.k146 = 'v146' t.k147 = 'v147' t.k148 = 'v148' t.k149 = 'v149'
+t.k150 = 'v150' t.k151 = 'v151' t.k152 = 'v152' t.k153 = 'v153' t.k154 = 'v154' t.k155 = 'v155'
+t.k156 = 'v156' t.k157 = 'v157' t.k158 = 'v158' t.k159 = 'v159' t.k160 = 'v160' t.k161 = 'v161'
+t.k162 = 'v162' t.k163 = 'v163' t.k164 = 'v164' t.k165 = 'v165' t.k166 = 'v166' t.k167 = 'v167'
+t.k168 = 'v168' t.k169 = 'v169' t.k170 = 'v170' t.k171 = 'v171' t.k172 = 'v172' t.k173 = 'v173'
+t.k174 = 'v174' t.k175 = 'v175' t.k176 = 'v176' t.k177 = 'v177' t.k178 = 'v178' t.k179 = 'v179'
+t.k180 = 'v180' t.k181 = 'v181' t.k182 = 'v182' t.k183 = 'v183' t.k184 = 'v184' t.k185 = 'v185'
+t.k186 = 'v186' t.k187 = 'v187' t.k188 = 'v188' t.k189 = 'v189' t.k190 = 'v190' t.k191 = 'v191'
+t.k192 = 'v192' t.k193 = 'v193' t.k194 = 'v194' t.k195 = 'v195' t.k196 = 'v196' t.k197 = 'v197'
+t.k198 = 'v198' t.k199 = 'v199' t.k200 = 'v200' t.k201 = 'v201' t.k202 = 'v202' t.k203 = 'v203'
+t.k204 = 'v204' t.k205 = 'v205' t.k206 = 'v206' t.k207 = 'v207' t.k208 = 'v208' t.k209 = 'v209'
+t.k210 = 'v210' t.k211 = 'v211' t.k212 = 'v212' t.k213 = 'v213' t.k214 = 'v214' t.k215 = 'v215'
+t.k216 = 'v216' t.k217 = 'v217' t.k218 = 'v218' t.k219 = 'v219' t.k220 = 'v220' t.k221 = 'v221'
+t.k222 = 'v222' t.k223 = 'v223' t.k224 = 'v224' t.k225 = 'v225' t.k226 = 'v226' t.k227 = 'v227'
+t.k228 = 'v228' t.k229 = 'v229' t.k230 = 'v230' t.k231 = 'v231' t.k232 = 'v232' t.k233 = 'v233'
+t.k234 = 'v234' t.k235 = 'v235' t.k236 = 'v236' t.k237 = 'v237' t.k238 = 'v238' t.k239 = 'v239'
+t.k240 = 'v240' t.k241 = 'v241' t.k242 = 'v242' t.k243 = 'v243' t.k244 = 'v244' t.k245 = 'v245'
+t.k246 = 'v246' t.k247 = 'v247' t.k248 = 'v248' t.k249 = 'v249' t.k250 = 'v250' t.k251 = 'v251'
+t.k252 = 'v252' t.k253 = 'v253' t.k254 = 'v254' t.k255 = 'v255' t.k256 = 'v256' t.k257 = 'v257'
+t.k258 = 'v258' t.k259 = 'v259' t.k260 = 'v260' t.k261 = 'v261' t.k262 = 'v262' t.k263 = 'v263'
+t.k264 = 'v264' t.k265 = 'v265' t.k266 = 'v266' t.k267 = 'v267' t.k268 = 'v268' t.k269 = 'v269'
+local a = t.k5 .. t.k269
+t[t] = t.k1 + 'zz' * x
+if a == 'v269' then print(1) end
+local x = {0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, f()}
+local function many()
+  local l0, l1, l2, l3, l4, l5, l6, l7, l8, l9, l10, l11, l12, l13, l14, l15, l16, l17, l18, l19, l20, l21, l22, l23, l24, l25, l26, l27, l28, l29, l30, l31, l32, l33, l34, l35, l36, l37, l38, l39, l40, l41, l42, l43, l44, l45, l46, l47, l48, l49, l50, l51, l52, l53, l54, l55, l56, l57, l58, l59, l60, l61, l62, l63, l64, l65, l66, l67, l68, l69, l70, l71, l72, l73, l74, l75, l76, l77, l78, l79, l80, l81, l82, l83, l84, l85, l86, l87, l88, l89, l90, l91, l92, l93, l94, l95, l96, l97, l98, l99, l100, l101, l102, l103, l104, l105, l106, l107, l108, l109, l110, l111, l112, l113, l114, l115, l116, l117, l118, l119, l120, l121, l122, l123, l124, l125, l126, l127, l128, l129, l130, l131, l132, l133, l134, l135, l136, l137, l138, l139, l140, l141, l142, l143, l144, l145, l146, l147, l148, l149
+  l1 = l2 + l149
+  return function() return l0, l149 end
+end
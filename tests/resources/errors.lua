@@ -0,0 +1,5 @@
+local t = {}
+print(t.x.y)
+print(undefined_global.field)
+local s = "text"
+s:nomethod()
@@ -0,0 +1,23 @@
+local a, b, c = 1 + 2, 3 * 4.5, 10 // 3
+local d = 7 % -3, -7 // 2, 7.5 % 2, 2 ^ 10, 1 / 2
+local e = 1 / 0, 0 / 0, 1 // 0, 1 % 0, 1.0 // 0.0, -0.0, 0.0 * -1
+local f = 1 - 1, 0.5 - 0.5, 3 & 5, 3 | 5, 3 ~ 5, 1 << 62, 1 << 64, -1 >> 1, ~0, ~5.0, 1.5 & 1, "a" + 1
+local g = -(2 ^ 63), - -1, -(-9223372036854775807 - 1), 9223372036854775807 + 1, math.pi * 2
+local h = x + 1 + 2, 1 + 2 + x, x * (2 * 3), (1 + 2) * x, -x, - 1, -1.5, -0, -(1 - 1), ~~x
+local i = not nil, not false, not true, not 1, not "s", not x, not not x, not (x == y), not (x and y)
+if not x then print(1) end
+if not (x < y) then print(2) end
+while not x do x = f() end
+if 1 then print(3) end
+if nil then print(4) else print(5) end
+if "x" and y then print(6) end
+if x or nil then print(7) end
+local j = x and 1 or 2
+local k = nil or x, false and x, 1 and x, true or x, 2 or x
+local l = (not x) and y, not x or y, x and not y
+repeat local m = not x until not m
+local n = 2^53 + 1, 1e308 * 10, -1e308 * 10, 5 // 0.0, -5 % math.huge, 3 % -0.0
+local o = 1 == 1, 1 < 2, x == 1 + 1
+local p = {1 + 1, [2 * 3] = 4 // 2, x = - - 2}
+for q = 1 + 1, 10 * 2, -(1) do end
+return 255 + 1, 300 * 300
@@ -0,0 +1,10 @@
+local count = 0
+local function add(a, b, ...)
+  local sum = a + b
+  count = count + 1
+  return sum
+end
+function greet(name)
+  return "hello " .. name
+end
+print(add(1, 2), greet("world"))
@@ -0,0 +1,5 @@
+local function r(n)
+  if n == 0 then return debug.traceback("x") end
+  return (r(n - 1))
+end
+print(r(30))